@@ -0,0 +1,6 @@
+//! Headless command-line entry point; see `zen_forge_lib::cli`.
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    std::process::exit(zen_forge_lib::cli::run(args));
+}
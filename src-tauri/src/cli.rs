@@ -0,0 +1,1085 @@
+//! Headless command-line interface to the build pipeline.
+//!
+//! Runs the same code as the Tauri commands without starting a webview, so games can be
+//! built from a terminal or CI. Entry point for the `zenforge-cli` binary.
+
+use crate::commands::game::{app_root, list_games};
+use crate::pipeline::build::{
+    build_game_targets, build_game_with_options, BuildOptions, BuildResult,
+};
+use crate::pipeline::cache::CacheStatus;
+use crate::pipeline::profile::{profile_path, ProfileOptions, ProfileReport, VM_SPEEDS};
+use crate::pipeline::release::{release_game, ReleaseOptions};
+use crate::pipeline::replay::{replay_and_compare, InputTrace, ReplayReport};
+use crate::pipeline::scripttest::{run_tests, TestRunReport};
+use crate::pipeline::{modules, obfuscate};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+/// Command succeeded
+pub const EXIT_OK: i32 = 0;
+/// Command ran but failed (build errors, invalid modules, I/O errors)
+pub const EXIT_FAILURE: i32 = 1;
+/// Invalid command-line usage
+pub const EXIT_USAGE: i32 = 2;
+
+const USAGE: &str = "\
+Usage: zenforge-cli <command> [options]
+
+Commands:
+  build <game_dir>         Preprocess main.gpc and write dist/<filename>.gpc, once
+                           per game.json target if the game declares any
+      --workspace <dir>    Workspace root; output goes to <dir>/dist
+      --plugins            Apply enabled plugins from the workspace
+      --profile <name>     Build profile from game.json build_profiles
+      --define NAME[=VAL]  Preprocessor symbol (repeatable, VAL defaults to 1)
+      --no-cache           Rebuild everything instead of reusing cached output
+      --tree-shake         Remove unused functions, combos, defines and globals
+      --optimize           Fold constants, inline defines used once and remove
+                           if (TRUE)/if (FALSE) branches
+      --target <name>      Build only this target from game.json targets
+  release <game_dir>       Build every target and package the outputs with a
+                           generated README, CHANGELOG.md and SHA256SUMS into
+                           dist/releases/<game>-v<version>.zip
+      --bump               Increase the game's version before building
+      --notes <text>       Add a CHANGELOG.md entry for the released version
+                           (also takes the build options above, except --target)
+  test <game_dir>          Build a game and run its tests/*.toml script tests on the
+                           simulator (takes --workspace, --plugins, --profile,
+                           --define and --target as for build)
+      --filter <text>      Only run tests whose name contains the text
+  replay <script> <trace>  Replay a JSON or CSV input trace on the simulator against
+                           a game directory (built first, with the options of
+                           build) or a .gpc file and print its output changes
+      --against <script>   Also replay against a second game or .gpc file and
+                           report every tick where their outputs differ
+      -o, --output <file>  Write the recorded output stream as JSON
+  profile <script> [trace] Run a game directory or .gpc file on the simulator, idle
+                           or driven by an input trace, and report the operations
+                           per tick, per main iteration and per function and combo
+      --vm-speed <ms>      Tick length to check against: 10, 8, 4, 2 or 1
+                           (default 10)
+      --ops-per-ms <n>     Estimated device operations per millisecond
+                           (default 1000)
+      --ticks <n>          Ticks to run (default: the trace, or 1000)
+      -o, --output <file>  Write the report as JSON
+      --folded <file>      Write folded stacks for flamegraph tools
+  obfuscate [file]         Obfuscate a GPC file (stdin if omitted or '-')
+      --level <1-5>        Obfuscation level (default 1)
+      -o, --output <file>  Write output to a file instead of stdout
+  modules validate         Check module definitions for errors
+      --workspace <dir>    Also check <dir>/modules (repeatable)
+  list-games <dir>...      List games found in workspace directories
+
+Global options:
+  --json                   Print machine-readable JSON to stdout
+  -h, --help               Show this help
+  -V, --version            Show version
+
+Exit codes: 0 success, 1 failure, 2 usage error";
+
+/// A parsed command line.
+#[derive(Debug, Clone, PartialEq)]
+enum Command {
+    Build {
+        game_dir: PathBuf,
+        workspace: Option<PathBuf>,
+        plugins: bool,
+        profile: Option<String>,
+        defines: BTreeMap<String, String>,
+        no_cache: bool,
+        tree_shake: bool,
+        optimize: bool,
+        target: Option<String>,
+    },
+    Release {
+        game_dir: PathBuf,
+        workspace: Option<PathBuf>,
+        plugins: bool,
+        profile: Option<String>,
+        defines: BTreeMap<String, String>,
+        no_cache: bool,
+        tree_shake: bool,
+        optimize: bool,
+        bump: bool,
+        notes: Option<String>,
+    },
+    Test {
+        game_dir: PathBuf,
+        workspace: Option<PathBuf>,
+        plugins: bool,
+        profile: Option<String>,
+        defines: BTreeMap<String, String>,
+        target: Option<String>,
+        filter: Option<String>,
+    },
+    Replay {
+        script: PathBuf,
+        trace: PathBuf,
+        against: Option<PathBuf>,
+        output: Option<PathBuf>,
+        workspace: Option<PathBuf>,
+        plugins: bool,
+        profile: Option<String>,
+        defines: BTreeMap<String, String>,
+        target: Option<String>,
+    },
+    Profile {
+        script: PathBuf,
+        trace: Option<PathBuf>,
+        options: ProfileOptions,
+        output: Option<PathBuf>,
+        folded: Option<PathBuf>,
+        workspace: Option<PathBuf>,
+        plugins: bool,
+        profile: Option<String>,
+        defines: BTreeMap<String, String>,
+        target: Option<String>,
+    },
+    Obfuscate {
+        input: Option<PathBuf>,
+        output: Option<PathBuf>,
+        level: u8,
+    },
+    ValidateModules {
+        workspaces: Vec<PathBuf>,
+    },
+    ListGames {
+        workspaces: Vec<PathBuf>,
+    },
+    Help,
+    Version,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Invocation {
+    command: Command,
+    json: bool,
+}
+
+/// Run the CLI with the given arguments (excluding the program name). Returns the exit code.
+pub fn run(args: Vec<String>) -> i32 {
+    let invocation = match parse_args(&args) {
+        Ok(inv) => inv,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return EXIT_USAGE;
+        }
+    };
+
+    let json = invocation.json;
+    match execute(invocation) {
+        Ok(code) => code,
+        Err(e) => {
+            if json {
+                println!("{}", serde_json::json!({ "success": false, "error": e }));
+            } else {
+                eprintln!("error: {}", e);
+            }
+            EXIT_FAILURE
+        }
+    }
+}
+
+/// Take the value following an option, or fail with a usage error.
+fn option_value<'a>(
+    name: &str,
+    iter: &mut impl Iterator<Item = &'a String>,
+) -> Result<&'a String, String> {
+    iter.next().ok_or_else(|| format!("{} requires a value", name))
+}
+
+fn parse_args(args: &[String]) -> Result<Invocation, String> {
+    let mut json = false;
+    let mut positional: Vec<&String> = Vec::new();
+    let mut workspaces: Vec<PathBuf> = Vec::new();
+    let mut plugins = false;
+    let mut profile = None;
+    let mut defines = BTreeMap::new();
+    let mut no_cache = false;
+    let mut tree_shake = false;
+    let mut optimize = false;
+    let mut target = None;
+    let mut level: Option<u8> = None;
+    let mut output = None;
+    let mut bump = false;
+    let mut notes = None;
+    let mut filter = None;
+    let mut against = None;
+    let mut profile_options = ProfileOptions::default();
+    let mut folded = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "-h" | "--help" => {
+                return Ok(Invocation { command: Command::Help, json })
+            }
+            "-V" | "--version" => {
+                return Ok(Invocation { command: Command::Version, json })
+            }
+            "--workspace" => workspaces.push(PathBuf::from(option_value(arg, &mut iter)?)),
+            "--plugins" => plugins = true,
+            "--no-cache" => no_cache = true,
+            "--tree-shake" => tree_shake = true,
+            "--optimize" => optimize = true,
+            "--profile" => profile = Some(option_value(arg, &mut iter)?.clone()),
+            "--target" => target = Some(option_value(arg, &mut iter)?.clone()),
+            "--bump" => bump = true,
+            "--notes" => notes = Some(option_value(arg, &mut iter)?.clone()),
+            "--filter" => filter = Some(option_value(arg, &mut iter)?.clone()),
+            "--against" => against = Some(PathBuf::from(option_value(arg, &mut iter)?)),
+            "--folded" => folded = Some(PathBuf::from(option_value(arg, &mut iter)?)),
+            "--vm-speed" => {
+                let raw = option_value(arg, &mut iter)?;
+                profile_options.vm_speed_ms = raw
+                    .parse()
+                    .ok()
+                    .filter(|ms| VM_SPEEDS.contains(ms))
+                    .ok_or_else(|| format!("VM speed must be 10, 8, 4, 2 or 1, got '{}'", raw))?;
+            }
+            "--ops-per-ms" => {
+                let raw = option_value(arg, &mut iter)?;
+                profile_options.ops_per_ms = raw
+                    .parse()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| format!("Invalid --ops-per-ms '{}'", raw))?;
+            }
+            "--ticks" => {
+                let raw = option_value(arg, &mut iter)?;
+                let ticks = raw.parse().map_err(|_| format!("Invalid --ticks '{}'", raw))?;
+                profile_options.ticks = Some(ticks);
+            }
+            "--define" | "-D" => {
+                let def = option_value(arg, &mut iter)?;
+                let (name, value) = def.split_once('=').unwrap_or((def, "1"));
+                if name.is_empty() {
+                    return Err(format!("Invalid define '{}'", def));
+                }
+                defines.insert(name.to_string(), value.to_string());
+            }
+            "--level" => {
+                let raw = option_value(arg, &mut iter)?;
+                let n: u8 = raw
+                    .parse()
+                    .ok()
+                    .filter(|n| (1..=5).contains(n))
+                    .ok_or_else(|| format!("Obfuscation level must be between 1 and 5, got '{}'", raw))?;
+                level = Some(n);
+            }
+            "-o" | "--output" => output = Some(PathBuf::from(option_value(arg, &mut iter)?)),
+            "-" => positional.push(arg),
+            other if other.starts_with('-') => return Err(format!("Unknown option '{}'", other)),
+            _ => positional.push(arg),
+        }
+    }
+
+    let (name, rest) = positional
+        .split_first()
+        .ok_or_else(|| "No command given".to_string())?;
+
+    let command = match name.as_str() {
+        "build" => {
+            let [game_dir] = rest else {
+                return Err("build expects exactly one game directory".to_string());
+            };
+            let workspace = build_workspace("build", workspaces, plugins)?;
+            Command::Build {
+                game_dir: PathBuf::from(game_dir),
+                workspace,
+                plugins,
+                profile,
+                defines,
+                no_cache,
+                tree_shake,
+                optimize,
+                target,
+            }
+        }
+        "release" => {
+            let [game_dir] = rest else {
+                return Err("release expects exactly one game directory".to_string());
+            };
+            if target.is_some() {
+                return Err("release builds every target; --target is not supported".to_string());
+            }
+            let workspace = build_workspace("release", workspaces, plugins)?;
+            Command::Release {
+                game_dir: PathBuf::from(game_dir),
+                workspace,
+                plugins,
+                profile,
+                defines,
+                no_cache,
+                tree_shake,
+                optimize,
+                bump,
+                notes,
+            }
+        }
+        "test" => {
+            let [game_dir] = rest else {
+                return Err("test expects exactly one game directory".to_string());
+            };
+            let workspace = build_workspace("test", workspaces, plugins)?;
+            Command::Test {
+                game_dir: PathBuf::from(game_dir),
+                workspace,
+                plugins,
+                profile,
+                defines,
+                target,
+                filter,
+            }
+        }
+        "replay" => {
+            let [script, trace] = rest else {
+                return Err("replay expects a game directory or script and a trace".to_string());
+            };
+            let workspace = build_workspace("replay", workspaces, plugins)?;
+            Command::Replay {
+                script: PathBuf::from(script),
+                trace: PathBuf::from(trace),
+                against,
+                output,
+                workspace,
+                plugins,
+                profile,
+                defines,
+                target,
+            }
+        }
+        "profile" => {
+            let (script, trace) = match rest {
+                [script] => (script, None),
+                [script, trace] => (script, Some(PathBuf::from(trace))),
+                _ => {
+                    return Err(
+                        "profile expects a game directory or script and an optional trace"
+                            .to_string(),
+                    )
+                }
+            };
+            let workspace = build_workspace("profile", workspaces, plugins)?;
+            Command::Profile {
+                script: PathBuf::from(script),
+                trace,
+                options: profile_options,
+                output,
+                folded,
+                workspace,
+                plugins,
+                profile,
+                defines,
+                target,
+            }
+        }
+        "obfuscate" => {
+            let input = match rest {
+                [] => None,
+                [file] if file.as_str() == "-" => None,
+                [file] => Some(PathBuf::from(file)),
+                _ => return Err("obfuscate accepts at most one input file".to_string()),
+            };
+            Command::Obfuscate {
+                input,
+                output,
+                level: level.unwrap_or(1),
+            }
+        }
+        "modules" => match rest {
+            [sub] if sub.as_str() == "validate" => Command::ValidateModules { workspaces },
+            _ => return Err("Expected 'modules validate'".to_string()),
+        },
+        "list-games" => {
+            workspaces.extend(rest.iter().map(PathBuf::from));
+            if workspaces.is_empty() {
+                return Err("list-games expects at least one workspace directory".to_string());
+            }
+            Command::ListGames { workspaces }
+        }
+        "help" => Command::Help,
+        other => return Err(format!("Unknown command '{}'", other)),
+    };
+
+    Ok(Invocation { command, json })
+}
+
+/// The single optional workspace of a build-like command.
+fn build_workspace(
+    command: &str,
+    mut workspaces: Vec<PathBuf>,
+    plugins: bool,
+) -> Result<Option<PathBuf>, String> {
+    if workspaces.len() > 1 {
+        return Err(format!("{} accepts a single --workspace", command));
+    }
+    let workspace = workspaces.pop();
+    if plugins && workspace.is_none() {
+        return Err("--plugins requires --workspace".to_string());
+    }
+    Ok(workspace)
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> Result<(), String> {
+    let text = serde_json::to_string_pretty(value)
+        .map_err(|e| format!("Could not serialize output: {}", e))?;
+    println!("{}", text);
+    Ok(())
+}
+
+/// Human-readable diagnostics and outcome of one build.
+fn print_build_result(result: &BuildResult) {
+    for warning in &result.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    for error in &result.errors {
+        eprintln!("error: {}", error);
+    }
+    for symbol in &result.removed {
+        match symbol.location {
+            Some(ref loc) => eprintln!(
+                "removed: {} {} ({}:{})",
+                symbol.kind.as_str(),
+                symbol.name,
+                loc.file,
+                loc.line
+            ),
+            None => eprintln!("removed: {} {}", symbol.kind.as_str(), symbol.name),
+        }
+    }
+    if let Some(ref report) = result.optimization {
+        eprintln!(
+            "optimized: {} constants folded, {} defines inlined, {} branches removed, \
+             {} bytes and {} lines saved",
+            report.folded,
+            report.inlined.len(),
+            report.branches,
+            report.bytes_saved,
+            report.lines_saved
+        );
+    }
+    if result.success && result.cache == CacheStatus::Hit {
+        println!("Up to date {}", result.output_path);
+    } else if result.success {
+        println!("Built {}", result.output_path);
+    }
+}
+
+/// Test outcomes with the diffs of failed tests, then a summary line.
+fn print_test_report(report: &TestRunReport) {
+    if !report.build.success {
+        print_build_result(&report.build);
+        return;
+    }
+    if let Some(ref error) = report.error {
+        eprintln!("error: {}", error);
+        return;
+    }
+    for test in &report.tests {
+        let status = if test.passed { "PASS" } else { "FAIL" };
+        println!("{} {}: {}", status, test.file, test.name);
+        if let Some(ref error) = test.error {
+            println!("  {}", error);
+        }
+        for diff in &test.diffs {
+            println!(
+                "  tick {}: {} expected {}, got {}",
+                diff.tick, diff.subject, diff.expected, diff.actual
+            );
+        }
+    }
+    println!("{} passed, {} failed", report.passed, report.failed);
+}
+
+fn print_replay_report(report: &ReplayReport) {
+    if let Some(ref error) = report.stream.error {
+        eprintln!("error: {}", error);
+    }
+    let Some(ref comparison) = report.comparison else {
+        for change in &report.stream.changes {
+            println!(
+                "tick {} ({} ms): {} = {}",
+                change.tick, change.time_ms, change.output, change.value
+            );
+        }
+        println!(
+            "{} ticks, {} output changes",
+            report.stream.ticks,
+            report.stream.changes.len()
+        );
+        return;
+    };
+    if let Some(error) = report.against.as_ref().and_then(|a| a.error.as_ref()) {
+        eprintln!("error: {}", error);
+    }
+    for diff in &comparison.diffs {
+        println!(
+            "  tick {}: {} {} vs {}",
+            diff.tick, diff.subject, diff.expected, diff.actual
+        );
+    }
+    if comparison.identical {
+        println!("Outputs identical over {} ticks", report.stream.ticks);
+    } else {
+        println!("Outputs differ on {} ticks", comparison.differing_ticks);
+    }
+}
+
+fn print_profile_report(report: &ProfileReport) {
+    if let Some(ref error) = report.error {
+        eprintln!("error: {}", error);
+    }
+    println!(
+        "{} ticks, init {} ops, main {} ops on average and {} at most (tick {})",
+        report.ticks,
+        report.init_ops,
+        report.main.average_ops,
+        report.main.max_ops,
+        report.main.max_tick
+    );
+    println!(
+        "Worst tick {}: {} ops, {}% of the {} ops a {} ms tick allows",
+        report.worst_tick.tick,
+        report.worst_tick.ops,
+        report.worst_tick.budget_percent,
+        report.budget_ops,
+        report.vm_speed_ms
+    );
+    for speed in &report.speeds {
+        let status = if speed.ticks_over_budget > 0 { "OVER" } else { "ok" };
+        println!(
+            "  {:>2} ms: {:>4}% {} ({} ticks over)",
+            speed.tick_ms, speed.worst_percent, status, speed.ticks_over_budget
+        );
+    }
+    println!("Frames (ops in their worst tick, itself/total):");
+    for frame in &report.frames {
+        let hot = if frame.hot { "  HOT" } else { "" };
+        let label = if frame.kind == frame.name {
+            frame.name.clone()
+        } else {
+            format!("{} {}", frame.kind, frame.name)
+        };
+        println!(
+            "  {}: {}/{} ops, {} calls{}",
+            label, frame.max_tick_self_ops, frame.max_tick_ops, frame.calls, hot
+        );
+    }
+}
+
+fn execute(invocation: Invocation) -> Result<i32, String> {
+    let json = invocation.json;
+    match invocation.command {
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(EXIT_OK)
+        }
+        Command::Version => {
+            println!("zenforge-cli {}", env!("CARGO_PKG_VERSION"));
+            Ok(EXIT_OK)
+        }
+        Command::Build {
+            game_dir,
+            workspace,
+            plugins,
+            profile,
+            defines,
+            no_cache,
+            tree_shake,
+            optimize,
+            target,
+        } => {
+            if !game_dir.is_dir() {
+                return Err(format!("Game directory not found: {}", game_dir.display()));
+            }
+            let root = app_root();
+            let dist_base = workspace.clone().unwrap_or_else(|| root.clone());
+            let workspace_str = workspace.as_ref().map(|w| w.to_string_lossy().to_string());
+            let plugin_workspace = if plugins { workspace_str.as_deref() } else { None };
+            let options = BuildOptions {
+                profile,
+                defines,
+                no_cache,
+                tree_shake,
+                optimize,
+                target,
+                ..Default::default()
+            };
+
+            if options.target.is_some() {
+                let result = build_game_with_options(
+                    &game_dir,
+                    &root,
+                    &dist_base,
+                    false,
+                    plugin_workspace,
+                    &options,
+                );
+                if json {
+                    print_json(&result)?;
+                } else {
+                    print_build_result(&result);
+                }
+                return Ok(if result.success { EXIT_OK } else { EXIT_FAILURE });
+            }
+
+            let matrix = build_game_targets(
+                &game_dir,
+                &root,
+                &dist_base,
+                false,
+                plugin_workspace,
+                &options,
+            );
+            match matrix.targets.as_slice() {
+                // A game without targets prints a plain build result, as before targets existed
+                [single] if single.target.is_none() => {
+                    if json {
+                        print_json(&single.result)?;
+                    } else {
+                        print_build_result(&single.result);
+                    }
+                }
+                targets => {
+                    if json {
+                        print_json(&matrix)?;
+                    } else {
+                        for target in targets {
+                            let name = target.target.as_deref().unwrap_or_default();
+                            eprintln!("[{}]", name);
+                            print_build_result(&target.result);
+                        }
+                        println!("Built {} of {} targets", matrix.built(), targets.len());
+                    }
+                }
+            }
+            Ok(if matrix.success { EXIT_OK } else { EXIT_FAILURE })
+        }
+        Command::Release {
+            game_dir,
+            workspace,
+            plugins,
+            profile,
+            defines,
+            no_cache,
+            tree_shake,
+            optimize,
+            bump,
+            notes,
+        } => {
+            if !game_dir.is_dir() {
+                return Err(format!("Game directory not found: {}", game_dir.display()));
+            }
+            let root = app_root();
+            let dist_base = workspace.clone().unwrap_or_else(|| root.clone());
+            let workspace_str = workspace.as_ref().map(|w| w.to_string_lossy().to_string());
+            let plugin_workspace = if plugins { workspace_str.as_deref() } else { None };
+            let options = ReleaseOptions {
+                bump_version: bump,
+                notes,
+                build: BuildOptions {
+                    profile,
+                    defines,
+                    no_cache,
+                    tree_shake,
+                    optimize,
+                    ..Default::default()
+                },
+            };
+
+            let result = release_game(&game_dir, &root, &dist_base, plugin_workspace, &options)?;
+            if json {
+                print_json(&result)?;
+            } else {
+                for target in &result.build.targets {
+                    if let Some(ref name) = target.target {
+                        eprintln!("[{}]", name);
+                    }
+                    print_build_result(&target.result);
+                }
+                if let Some(ref path) = result.bundle_path {
+                    for file in &result.files {
+                        println!("{}  {}", file.sha256, file.name);
+                    }
+                    println!("Released v{} to {}", result.version, path);
+                }
+            }
+            Ok(if result.success { EXIT_OK } else { EXIT_FAILURE })
+        }
+        Command::Test {
+            game_dir,
+            workspace,
+            plugins,
+            profile,
+            defines,
+            target,
+            filter,
+        } => {
+            if !game_dir.is_dir() {
+                return Err(format!("Game directory not found: {}", game_dir.display()));
+            }
+            let root = app_root();
+            let dist_base = workspace.clone().unwrap_or_else(|| root.clone());
+            let workspace_str = workspace.as_ref().map(|w| w.to_string_lossy().to_string());
+            let plugin_workspace = if plugins { workspace_str.as_deref() } else { None };
+            let options = BuildOptions {
+                profile,
+                defines,
+                target,
+                ..Default::default()
+            };
+
+            let report = run_tests(
+                &game_dir,
+                &root,
+                &dist_base,
+                plugin_workspace,
+                &options,
+                filter.as_deref(),
+            )?;
+            if json {
+                print_json(&report)?;
+            } else {
+                print_test_report(&report);
+            }
+            Ok(if report.success { EXIT_OK } else { EXIT_FAILURE })
+        }
+        Command::Replay {
+            script,
+            trace,
+            against,
+            output,
+            workspace,
+            plugins,
+            profile,
+            defines,
+            target,
+        } => {
+            let trace = InputTrace::load(&trace)?;
+            let root = app_root();
+            let dist_base = workspace.clone().unwrap_or_else(|| root.clone());
+            let workspace_str = workspace.as_ref().map(|w| w.to_string_lossy().to_string());
+            let plugin_workspace = if plugins { workspace_str.as_deref() } else { None };
+            let options = BuildOptions {
+                profile,
+                defines,
+                target,
+                ..Default::default()
+            };
+
+            let report = replay_and_compare(
+                &script,
+                against.as_deref(),
+                &root,
+                &dist_base,
+                plugin_workspace,
+                &options,
+                &trace,
+            )?;
+            if let Some(ref path) = output {
+                let text = serde_json::to_string_pretty(&report.stream)
+                    .map_err(|e| format!("Could not serialize output: {}", e))?;
+                std::fs::write(path, text)
+                    .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+            }
+            if json {
+                print_json(&report)?;
+            } else {
+                print_replay_report(&report);
+            }
+            Ok(if report.success { EXIT_OK } else { EXIT_FAILURE })
+        }
+        Command::Profile {
+            script,
+            trace,
+            options,
+            output,
+            folded,
+            workspace,
+            plugins,
+            profile,
+            defines,
+            target,
+        } => {
+            let trace = trace.as_deref().map(InputTrace::load).transpose()?;
+            let root = app_root();
+            let dist_base = workspace.clone().unwrap_or_else(|| root.clone());
+            let workspace_str = workspace.as_ref().map(|w| w.to_string_lossy().to_string());
+            let plugin_workspace = if plugins { workspace_str.as_deref() } else { None };
+            let build_options = BuildOptions {
+                profile,
+                defines,
+                target,
+                ..Default::default()
+            };
+
+            let report = profile_path(
+                &script,
+                &root,
+                &dist_base,
+                plugin_workspace,
+                &build_options,
+                trace.as_ref(),
+                &options,
+            )?;
+            if let Some(ref path) = output {
+                let text = serde_json::to_string_pretty(&report)
+                    .map_err(|e| format!("Could not serialize output: {}", e))?;
+                std::fs::write(path, text)
+                    .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+            }
+            if let Some(ref path) = folded {
+                std::fs::write(path, &report.folded)
+                    .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+            }
+            if json {
+                print_json(&report)?;
+            } else {
+                print_profile_report(&report);
+            }
+            Ok(if report.success { EXIT_OK } else { EXIT_FAILURE })
+        }
+        Command::Obfuscate { input, output, level } => {
+            let source = match input {
+                Some(ref path) => std::fs::read_to_string(path)
+                    .map_err(|e| format!("Could not read {}: {}", path.display(), e))?,
+                None => {
+                    let mut buf = String::new();
+                    std::io::stdin()
+                        .read_to_string(&mut buf)
+                        .map_err(|e| format!("Could not read stdin: {}", e))?;
+                    buf
+                }
+            };
+
+            let result = obfuscate::obfuscate(&source, level);
+
+            if let Some(ref path) = output {
+                std::fs::write(path, &result.output)
+                    .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+            }
+            if json {
+                print_json(&result)?;
+            } else {
+                if output.is_none() {
+                    std::io::stdout()
+                        .write_all(result.output.as_bytes())
+                        .map_err(|e| format!("Could not write stdout: {}", e))?;
+                }
+                let s = &result.stats;
+                eprintln!(
+                    "Obfuscated {} -> {} lines ({} identifiers renamed, {} comments removed, {} strings encoded, {} dead code blocks)",
+                    s.lines_before,
+                    s.lines_after,
+                    s.identifiers_renamed,
+                    s.comments_removed,
+                    s.strings_encoded,
+                    s.dead_code_blocks
+                );
+            }
+            Ok(EXIT_OK)
+        }
+        Command::ValidateModules { workspaces } => {
+            let issues = modules::validate_modules(&app_root(), &workspaces)?;
+            if json {
+                print_json(&serde_json::json!({
+                    "success": issues.is_empty(),
+                    "issues": issues,
+                }))?;
+            } else if issues.is_empty() {
+                println!("All modules are valid");
+            } else {
+                for issue in &issues {
+                    match issue.module_id {
+                        Some(ref id) => eprintln!("{} [{}]: {}", issue.path, id, issue.message),
+                        None => eprintln!("{}: {}", issue.path, issue.message),
+                    }
+                }
+                eprintln!("{} issue(s) found", issues.len());
+            }
+            Ok(if issues.is_empty() { EXIT_OK } else { EXIT_FAILURE })
+        }
+        Command::ListGames { workspaces } => {
+            let paths = workspaces
+                .iter()
+                .map(|w| w.to_string_lossy().to_string())
+                .collect();
+            let games = list_games(Some(paths))?;
+            if json {
+                print_json(&games)?;
+            } else {
+                for game in &games {
+                    println!(
+                        "{:<24} {:<6} {:<8} v{:<6} {}",
+                        game.name, game.game_type, game.console_type, game.version, game.path
+                    );
+                }
+            }
+            Ok(EXIT_OK)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::test_support::write_test_game;
+
+    fn parse(args: &[&str]) -> Result<Invocation, String> {
+        parse_args(&args.iter().map(|s| s.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_parse_build() {
+        let inv = parse(&[
+            "build", "Games/R6S", "--workspace", "ws", "--plugins", "--define", "LITE",
+            "-D", "SLOTS=4", "--profile", "lite", "--no-cache", "--target", "pc", "--json",
+        ])
+        .unwrap();
+        assert!(inv.json);
+        let mut defines = BTreeMap::new();
+        defines.insert("LITE".to_string(), "1".to_string());
+        defines.insert("SLOTS".to_string(), "4".to_string());
+        assert_eq!(
+            inv.command,
+            Command::Build {
+                game_dir: PathBuf::from("Games/R6S"),
+                workspace: Some(PathBuf::from("ws")),
+                plugins: true,
+                profile: Some("lite".to_string()),
+                defines,
+                no_cache: true,
+                tree_shake: false,
+                optimize: false,
+                target: Some("pc".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_release() {
+        let inv = parse(&["release", "Games/R6S", "--bump", "--notes", "- Fixes", "--optimize"])
+            .unwrap();
+        let Command::Release { bump, notes, workspace, optimize, .. } = inv.command else {
+            panic!("expected a release command");
+        };
+        assert!(bump && optimize && workspace.is_none());
+        assert_eq!(notes.as_deref(), Some("- Fixes"));
+        assert!(parse(&["release", "g", "--target", "pc"]).is_err());
+        assert!(parse(&["release", "g", "--plugins"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_obfuscate() {
+        let inv = parse(&["obfuscate", "--level", "3", "in.gpc", "-o", "out.gpc"]).unwrap();
+        assert_eq!(
+            inv.command,
+            Command::Obfuscate {
+                input: Some(PathBuf::from("in.gpc")),
+                output: Some(PathBuf::from("out.gpc")),
+                level: 3,
+            }
+        );
+        let inv = parse(&["obfuscate", "-"]).unwrap();
+        assert!(matches!(inv.command, Command::Obfuscate { input: None, level: 1, .. }));
+    }
+
+    #[test]
+    fn test_parse_test() {
+        let inv = parse(&["test", "Games/R6S", "--filter", "rapid", "-D", "LITE"]).unwrap();
+        assert_eq!(
+            inv.command,
+            Command::Test {
+                game_dir: PathBuf::from("Games/R6S"),
+                workspace: None,
+                plugins: false,
+                profile: None,
+                defines: BTreeMap::from([("LITE".to_string(), "1".to_string())]),
+                target: None,
+                filter: Some("rapid".to_string()),
+            }
+        );
+        assert!(parse(&["test"]).is_err());
+        assert!(parse(&["test", "g", "--plugins"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_replay() {
+        let inv = parse(&["replay", "Games/R6S", "ads.csv", "--against", "old.gpc"]).unwrap();
+        assert_eq!(
+            inv.command,
+            Command::Replay {
+                script: PathBuf::from("Games/R6S"),
+                trace: PathBuf::from("ads.csv"),
+                against: Some(PathBuf::from("old.gpc")),
+                output: None,
+                workspace: None,
+                plugins: false,
+                profile: None,
+                defines: BTreeMap::new(),
+                target: None,
+            }
+        );
+        assert!(parse(&["replay", "Games/R6S"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        let args = ["profile", "R6S.gpc", "--vm-speed", "4", "--folded", "out.folded"];
+        let inv = parse(&args).unwrap();
+        let Command::Profile {
+            script,
+            trace,
+            options,
+            folded,
+            ..
+        } = inv.command
+        else {
+            panic!("expected profile, got {:?}", inv.command);
+        };
+        assert_eq!((script, trace), (PathBuf::from("R6S.gpc"), None));
+        assert_eq!((options.vm_speed_ms, options.ops_per_ms), (4, 1000));
+        assert_eq!(folded, Some(PathBuf::from("out.folded")));
+        assert!(parse(&["profile", "R6S.gpc", "--vm-speed", "3"]).is_err());
+        assert!(parse(&["profile"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_usage_errors() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["build"]).is_err());
+        assert!(parse(&["build", "g", "--plugins"]).is_err());
+        assert!(parse(&["obfuscate", "--level", "9"]).is_err());
+        assert!(parse(&["modules"]).is_err());
+        assert!(parse(&["list-games"]).is_err());
+        assert!(parse(&["frobnicate"]).is_err());
+        assert!(parse(&["build", "g", "--bogus"]).is_err());
+        assert_eq!(run(vec!["frobnicate".to_string()]), EXIT_USAGE);
+    }
+
+    #[test]
+    fn test_build_exit_codes() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = write_test_game(dir.path(), "main {\n}\n");
+        let args = |extra: &[&str]| {
+            let mut v = vec![
+                "build".to_string(),
+                game_dir.to_string_lossy().to_string(),
+                "--workspace".to_string(),
+                dir.path().to_string_lossy().to_string(),
+            ];
+            v.extend(extra.iter().map(|s| s.to_string()));
+            v
+        };
+
+        assert_eq!(run(args(&[])), EXIT_OK);
+        assert!(dir.path().join("dist/Test.gpc").exists());
+
+        std::fs::write(game_dir.join("main.gpc"), "#if 1\nmain {\n}\n").unwrap();
+        assert_eq!(run(args(&[])), EXIT_FAILURE);
+    }
+}
@@ -0,0 +1,61 @@
+use crate::commands::game::app_root;
+use crate::pipeline::artifacts::{self, Artifact, ArtifactDiff};
+use std::path::{Path, PathBuf};
+
+/// List the recorded builds of a game, newest first
+#[tauri::command]
+pub fn list_artifacts(game_path: String) -> Result<Vec<Artifact>, String> {
+    Ok(artifacts::list(Path::new(&game_path)))
+}
+
+/// Get the built script of an artifact
+#[tauri::command]
+pub fn read_artifact(game_path: String, artifact_id: String) -> Result<String, String> {
+    artifacts::read(Path::new(&game_path), &artifact_id)
+}
+
+/// Compare two artifacts: both scripts plus what changed between their builds
+#[tauri::command]
+pub fn diff_artifacts(
+    game_path: String,
+    from_id: String,
+    to_id: String,
+) -> Result<ArtifactDiff, String> {
+    artifacts::diff(Path::new(&game_path), &from_id, &to_id)
+}
+
+/// Write an artifact back to {workspace}/dist/ under its original file name
+#[tauri::command]
+pub fn restore_artifact(
+    game_path: String,
+    artifact_id: String,
+    workspace_path: Option<String>,
+) -> Result<String, String> {
+    let dist_base = workspace_path
+        .map(PathBuf::from)
+        .unwrap_or_else(app_root);
+    let path = artifacts::restore(Path::new(&game_path), &dist_base, &artifact_id)?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// Save a copy of an artifact to a chosen path
+#[tauri::command]
+pub fn export_artifact(
+    game_path: String,
+    artifact_id: String,
+    dest_path: String,
+) -> Result<(), String> {
+    artifacts::export(Path::new(&game_path), &artifact_id, Path::new(&dest_path))
+}
+
+/// Name an artifact, or clear its name with an empty label
+#[tauri::command]
+pub fn label_artifact(game_path: String, artifact_id: String, label: String) -> Result<(), String> {
+    artifacts::set_label(Path::new(&game_path), &artifact_id, Some(label))
+}
+
+/// Delete an artifact from a game's build history
+#[tauri::command]
+pub fn delete_artifact(game_path: String, artifact_id: String) -> Result<(), String> {
+    artifacts::delete(Path::new(&game_path), &artifact_id)
+}
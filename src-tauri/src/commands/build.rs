@@ -1,10 +1,40 @@
 use crate::commands::game::app_root;
-use crate::pipeline::build::{BuildResult, build_game, build_game_with_plugins};
-use std::path::PathBuf;
+use crate::pipeline::build::{
+    BuildOptions, BuildResult, MacroPreview, MatrixBuildResult, build_game_targets,
+    build_game_with_options, preview_macros,
+};
+use crate::pipeline::profile::{profile_path, ProfileOptions, ProfileReport};
+use crate::pipeline::queue::{default_workers, BuildJob, BuildQueue, QueueHandle, QueueSummary};
+use crate::pipeline::release::{release_game, ReleaseOptions, ReleaseResult};
+use crate::pipeline::replay::{replay_and_compare, InputTrace, ReplayReport};
+use crate::pipeline::scripttest::{run_tests, TestRunReport};
+use crate::pipeline::sourcemap::{self, SourceLocation, SourceMap};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Emitter, State};
+
+/// The build queue currently running, if any.
+#[derive(Default)]
+pub struct BuildQueueState {
+    current: Mutex<Option<Arc<QueueHandle>>>,
+}
 
 /// Build a game by preprocessing its main.gpc and writing the output to {workspace}/dist/
+///
+/// `profile` selects an entry of game.json `build_profiles`; `defines` adds symbols for
+/// `#if`/`#ifdef` blocks and overrides every other symbol source. `no_cache` forces a
+/// full rebuild instead of reusing unchanged output from the game's build cache.
+/// `target` builds one entry of game.json `targets` instead of the game's own console.
 #[tauri::command]
-pub fn build_game_cmd(game_path: String, workspace_path: Option<String>) -> Result<BuildResult, String> {
+pub fn build_game_cmd(
+    game_path: String,
+    workspace_path: Option<String>,
+    profile: Option<String>,
+    defines: Option<BTreeMap<String, String>>,
+    no_cache: Option<bool>,
+    target: Option<String>,
+) -> Result<BuildResult, String> {
     let root = app_root();
     let game_dir = PathBuf::from(&game_path);
 
@@ -18,14 +48,282 @@ pub fn build_game_cmd(game_path: String, workspace_path: Option<String>) -> Resu
         .map(|p| PathBuf::from(p))
         .unwrap_or_else(|| root.clone());
 
-    // Use plugin-aware build when workspace path is available
-    let result = match workspace_path.as_deref() {
-        Some(ws) => build_game_with_plugins(&game_dir, &root, &dist_base, true, ws),
-        None => build_game(&game_dir, &root, &dist_base, true),
+    let options = BuildOptions {
+        profile,
+        defines: defines.unwrap_or_default(),
+        no_cache: no_cache.unwrap_or(false),
+        target,
+        ..Default::default()
     };
+
+    // Plugin hooks are applied when workspace path is available
+    let result = build_game_with_options(
+        &game_dir,
+        &root,
+        &dist_base,
+        true,
+        workspace_path.as_deref(),
+        &options,
+    );
     Ok(result)
 }
 
+/// Build every target declared in the game's game.json into {workspace}/dist/,
+/// or the game itself if it declares none. Options are as for `build_game_cmd`.
+#[tauri::command]
+pub fn build_game_targets_cmd(
+    game_path: String,
+    workspace_path: Option<String>,
+    profile: Option<String>,
+    defines: Option<BTreeMap<String, String>>,
+    no_cache: Option<bool>,
+) -> Result<MatrixBuildResult, String> {
+    let root = app_root();
+    let game_dir = PathBuf::from(&game_path);
+
+    if !game_dir.exists() {
+        return Err(format!("Game directory not found: {}", game_path));
+    }
+
+    let dist_base = workspace_path
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| root.clone());
+
+    let options = BuildOptions {
+        profile,
+        defines: defines.unwrap_or_default(),
+        no_cache: no_cache.unwrap_or(false),
+        ..Default::default()
+    };
+
+    Ok(build_game_targets(
+        &game_dir,
+        &root,
+        &dist_base,
+        true,
+        workspace_path.as_deref(),
+        &options,
+    ))
+}
+
+/// Build every target of a game and package the outputs with a generated README,
+/// the game's CHANGELOG.md and SHA-256 checksums into {workspace}/dist/releases/.
+///
+/// `bump_version` increases the game's version before building; `notes` become a new
+/// CHANGELOG.md entry for the released version.
+#[tauri::command]
+pub fn release_game_cmd(
+    game_path: String,
+    workspace_path: Option<String>,
+    profile: Option<String>,
+    bump_version: Option<bool>,
+    notes: Option<String>,
+) -> Result<ReleaseResult, String> {
+    let root = app_root();
+    let game_dir = PathBuf::from(&game_path);
+
+    if !game_dir.exists() {
+        return Err(format!("Game directory not found: {}", game_path));
+    }
+
+    let dist_base = workspace_path
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| root.clone());
+
+    let options = ReleaseOptions {
+        bump_version: bump_version.unwrap_or(false),
+        notes,
+        build: BuildOptions {
+            profile,
+            ..Default::default()
+        },
+    };
+
+    release_game(&game_dir, &root, &dist_base, workspace_path.as_deref(), &options)
+}
+
+/// Build a game and run the script tests in its tests/ directory on the simulator.
+///
+/// `filter` keeps only tests whose name contains it. Failed tests carry the ticks
+/// where an output, variable, combo or the OLED differed from what was expected.
+#[tauri::command]
+pub fn run_script_tests_cmd(
+    game_path: String,
+    workspace_path: Option<String>,
+    profile: Option<String>,
+    filter: Option<String>,
+) -> Result<TestRunReport, String> {
+    let root = app_root();
+    let game_dir = PathBuf::from(&game_path);
+
+    if !game_dir.exists() {
+        return Err(format!("Game directory not found: {}", game_path));
+    }
+
+    let dist_base = workspace_path
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| root.clone());
+    let options = BuildOptions {
+        profile,
+        ..Default::default()
+    };
+
+    run_tests(
+        &game_dir,
+        &root,
+        &dist_base,
+        workspace_path.as_deref(),
+        &options,
+        filter.as_deref(),
+    )
+}
+
+/// Replay a recorded input trace (JSON or CSV) on the simulator and record the outputs.
+///
+/// `script_path` is a game directory, which is built first, or a built `.gpc` file.
+/// With `against_path` the trace is also replayed against a second game or file and
+/// the report lists every tick where the two builds' outputs differ.
+#[tauri::command]
+pub fn replay_trace_cmd(
+    script_path: String,
+    trace_path: String,
+    against_path: Option<String>,
+    workspace_path: Option<String>,
+    profile: Option<String>,
+) -> Result<ReplayReport, String> {
+    let root = app_root();
+    let trace = InputTrace::load(Path::new(&trace_path))?;
+    let dist_base = workspace_path
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| root.clone());
+    let options = BuildOptions {
+        profile,
+        ..Default::default()
+    };
+
+    replay_and_compare(
+        Path::new(&script_path),
+        against_path.as_deref().map(Path::new),
+        &root,
+        &dist_base,
+        workspace_path.as_deref(),
+        &options,
+        &trace,
+    )
+}
+
+/// Save an input trace recorded in the emulator, as CSV if `path` ends in `.csv` and
+/// as JSON otherwise.
+#[tauri::command]
+pub fn save_input_trace_cmd(path: String, trace: InputTrace) -> Result<(), String> {
+    trace.save(Path::new(&path))
+}
+
+/// Profile a game directory, which is built first, or a built `.gpc` file on the
+/// simulator and report its operations per tick against the VM speed `vm_speed_ms`.
+///
+/// The script runs idle, or driven by the input trace at `trace_path`. With
+/// `folded_path` the call stacks of the run are also written as folded stacks for
+/// flamegraph tools.
+#[tauri::command]
+pub fn profile_script_cmd(
+    script_path: String,
+    trace_path: Option<String>,
+    vm_speed_ms: Option<u64>,
+    ops_per_ms: Option<u64>,
+    folded_path: Option<String>,
+    workspace_path: Option<String>,
+    profile: Option<String>,
+) -> Result<ProfileReport, String> {
+    let root = app_root();
+    let trace = trace_path
+        .as_deref()
+        .map(|path| InputTrace::load(Path::new(path)))
+        .transpose()?;
+    let dist_base = workspace_path
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| root.clone());
+    let build_options = BuildOptions {
+        profile,
+        ..Default::default()
+    };
+    let defaults = ProfileOptions::default();
+    let options = ProfileOptions {
+        vm_speed_ms: vm_speed_ms.unwrap_or(defaults.vm_speed_ms),
+        ops_per_ms: ops_per_ms.unwrap_or(defaults.ops_per_ms),
+        ticks: None,
+    };
+
+    let report = profile_path(
+        Path::new(&script_path),
+        &root,
+        &dist_base,
+        workspace_path.as_deref(),
+        &build_options,
+        trace.as_ref(),
+        &options,
+    )?;
+    if let Some(path) = folded_path {
+        std::fs::write(&path, &report.folded)
+            .map_err(|e| format!("Could not write {}: {}", path, e))?;
+    }
+    Ok(report)
+}
+
+/// Build several games concurrently, each with all of its targets, and return a
+/// summary once every job has finished or been cancelled.
+///
+/// Emits `build:progress` events as jobs are queued, move through the build phases
+/// and finish. `workers` defaults to one thread per CPU core. Flow games must have
+/// their main.gpc generated before they are queued. Only one queue runs at a time.
+#[tauri::command]
+pub async fn start_build_queue(
+    app: AppHandle,
+    state: State<'_, BuildQueueState>,
+    jobs: Vec<BuildJob>,
+    workers: Option<usize>,
+) -> Result<QueueSummary, String> {
+    let workers = workers.unwrap_or_else(|| default_workers(jobs.len()));
+    let queue = BuildQueue::new(jobs, move |progress| {
+        let _ = app.emit("build:progress", progress);
+    })?;
+    {
+        let mut current = state.current.lock().map_err(|e| e.to_string())?;
+        if current.is_some() {
+            return Err("A build queue is already running".to_string());
+        }
+        *current = Some(queue.handle());
+    }
+
+    let root = app_root();
+    let summary = tauri::async_runtime::spawn_blocking(move || queue.run(&root, workers)).await;
+    *state.current.lock().map_err(|e| e.to_string())? = None;
+    summary.map_err(|e| format!("Build queue stopped unexpectedly: {}", e))
+}
+
+/// Cancel one job of the running build queue by its index in the queue.
+#[tauri::command]
+pub fn cancel_build_job(state: State<'_, BuildQueueState>, job_id: usize) -> Result<(), String> {
+    match state.current.lock().map_err(|e| e.to_string())?.as_ref() {
+        Some(queue) => queue.cancel_job(job_id),
+        None => Err("No build queue is running".to_string()),
+    }
+}
+
+/// Cancel every unfinished job of the running build queue.
+#[tauri::command]
+pub fn cancel_build_queue(state: State<'_, BuildQueueState>) -> Result<(), String> {
+    if let Some(queue) = state.current.lock().map_err(|e| e.to_string())?.as_ref() {
+        queue.cancel_all();
+    }
+    Ok(())
+}
+
 /// Get the expected build output path for a game
 #[tauri::command]
 pub fn get_build_output_path(game_path: String, workspace_path: Option<String>) -> Result<String, String> {
@@ -54,3 +352,50 @@ pub fn get_build_output_path(game_path: String, workspace_path: Option<String>)
     let output_path = dist_base.join("dist").join(format!("{}{}.gpc", filename, version));
     Ok(output_path.to_string_lossy().to_string())
 }
+
+/// Resolve a 1-indexed line of a built output file back to the original file and line,
+/// using the source map written next to it.
+#[tauri::command]
+pub fn resolve_build_location(output_path: String, line: usize) -> Result<Option<SourceLocation>, String> {
+    let map_path = sourcemap::map_path_for(Path::new(&output_path));
+    if !map_path.exists() {
+        return Err(format!("No source map found for {}", output_path));
+    }
+    let map = SourceMap::load(&map_path)?;
+    Ok(map.resolve(line))
+}
+
+/// Expand the macros used in a file, for showing expansions inline in the editor.
+///
+/// `start_line`/`end_line` (1-indexed, inclusive) limit the preview to a selection.
+/// With `game_path`, imports are resolved like in a build of that game.
+#[tauri::command]
+pub fn preview_macro_expansion_cmd(
+    file_path: String,
+    game_path: Option<String>,
+    workspace_path: Option<String>,
+    start_line: Option<usize>,
+    end_line: Option<usize>,
+) -> Result<MacroPreview, String> {
+    let root = app_root();
+    let file = PathBuf::from(&file_path);
+    if !file.exists() {
+        return Err(format!("File not found: {}", file_path));
+    }
+
+    let workspace = workspace_path
+        .map(PathBuf::from)
+        .unwrap_or_else(|| root.clone());
+    let lines = match (start_line, end_line) {
+        (None, None) => None,
+        (start, end) => Some((start.unwrap_or(1), end.unwrap_or(usize::MAX))),
+    };
+
+    preview_macros(
+        &file,
+        game_path.as_deref().map(Path::new),
+        &root,
+        &workspace,
+        lines,
+    )
+}
@@ -9,6 +9,7 @@ pub mod templates;
 pub mod watcher;
 pub mod wizard;
 pub mod history;
+pub mod artifacts;
 pub mod plugins;
 pub mod search;
 pub mod workspace;
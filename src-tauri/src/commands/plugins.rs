@@ -29,12 +29,12 @@ pub struct PluginHooks {
     /// GPC include files to add
     #[serde(default)]
     pub includes: Option<Vec<String>>,
-    /// Extra variables to define
+    /// Extra variables to define, sorted by name so builds are reproducible
     #[serde(default)]
-    pub extra_vars: Option<std::collections::HashMap<String, String>>,
-    /// Extra defines
+    pub extra_vars: Option<std::collections::BTreeMap<String, String>>,
+    /// Extra defines, sorted by name so builds are reproducible
     #[serde(default)]
-    pub extra_defines: Option<std::collections::HashMap<String, String>>,
+    pub extra_defines: Option<std::collections::BTreeMap<String, String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -200,37 +200,50 @@ author = ""
     Ok(dir.to_string_lossy().to_string())
 }
 
-/// Collect merged hooks from all enabled plugins in a workspace.
-/// Used by the build pipeline to inject plugin code.
-pub fn collect_enabled_hooks(workspace_path: &str) -> PluginHooks {
+/// Manifest paths of all enabled plugins in a workspace, sorted by path.
+/// Used by the build cache to detect plugin changes.
+pub fn enabled_manifest_paths(workspace_path: &str) -> Vec<PathBuf> {
     let enabled_ids = load_enabled(workspace_path);
     if enabled_ids.is_empty() {
-        return PluginHooks::default();
+        return Vec::new();
     }
 
-    let dir = plugins_dir(workspace_path);
-    if !dir.exists() {
-        return PluginHooks::default();
-    }
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(plugins_dir(workspace_path))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path().join("plugin.toml"))
+        .filter(|path| {
+            load_manifest(path)
+                .map(|m| enabled_ids.contains(&m.id))
+                .unwrap_or(false)
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Manifests of the enabled plugins in a workspace, in the order of `enabled_manifest_paths`.
+pub fn enabled_manifests(workspace_path: &str) -> Vec<PluginManifest> {
+    enabled_manifest_paths(workspace_path)
+        .iter()
+        .filter_map(|path| load_manifest(path).ok())
+        .collect()
+}
 
+/// Collect merged hooks from all enabled plugins in a workspace.
+/// Used by the build pipeline to inject plugin code. Plugins are merged in the
+/// order of `enabled_manifest_paths`, so the result does not depend on the order
+/// the filesystem lists plugin directories in.
+pub fn collect_enabled_hooks(workspace_path: &str) -> PluginHooks {
     let mut merged = PluginHooks::default();
 
-    for entry in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
-        let path = entry.path();
-        if !path.is_dir() {
-            continue;
-        }
-        let manifest_path = path.join("plugin.toml");
-        if !manifest_path.exists() {
-            continue;
-        }
+    for manifest_path in enabled_manifest_paths(workspace_path) {
         let manifest = match load_manifest(&manifest_path) {
             Ok(m) => m,
             Err(_) => continue,
         };
-        if !enabled_ids.contains(&manifest.id) {
-            continue;
-        }
+        let path = manifest_path.parent().unwrap_or(&manifest_path);
 
         let hooks = &manifest.hooks;
         if let Some(ref code) = hooks.pre_build {
@@ -257,11 +270,11 @@ pub fn collect_enabled_hooks(workspace_path: &str) -> PluginHooks {
             }
         }
         if let Some(ref vars) = hooks.extra_vars {
-            let existing = merged.extra_vars.get_or_insert_with(std::collections::HashMap::new);
+            let existing = merged.extra_vars.get_or_insert_with(std::collections::BTreeMap::new);
             existing.extend(vars.iter().map(|(k, v)| (k.clone(), v.clone())));
         }
         if let Some(ref defines) = hooks.extra_defines {
-            let existing = merged.extra_defines.get_or_insert_with(std::collections::HashMap::new);
+            let existing = merged.extra_defines.get_or_insert_with(std::collections::BTreeMap::new);
             existing.extend(defines.iter().map(|(k, v)| (k.clone(), v.clone())));
         }
     }
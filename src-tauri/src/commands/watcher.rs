@@ -1,6 +1,14 @@
+use crate::commands::game::app_root;
+use crate::pipeline::build::BuildOptions;
+use crate::pipeline::watch::{watch_dirs, AutoBuild, AutoBuildResult, Debouncer, DEBOUNCE};
 use notify::{RecommendedWatcher, RecursiveMode, Watcher, EventKind};
+use std::collections::{BTreeSet, HashMap};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Instant;
 use tauri::{AppHandle, Emitter};
 
 pub struct WatcherState {
@@ -166,3 +174,149 @@ pub fn watch_workspaces(
     *guard = Some(watchers);
     Ok(())
 }
+
+/// Games in auto build mode, keyed by game path.
+#[derive(Default)]
+pub struct AutoBuildState {
+    games: Mutex<HashMap<String, AutoBuildWorker>>,
+}
+
+struct AutoBuildWorker {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+/// Rebuild a game whenever a file in its include closure, its configuration or an
+/// enabled plugin changes. Builds once right away, then after changes settle.
+/// Emits 'build:auto' with an `AutoBuildResult` after every build.
+#[tauri::command]
+pub fn start_auto_build(
+    game_path: String,
+    workspace_path: Option<String>,
+    profile: Option<String>,
+    target: Option<String>,
+    app: AppHandle,
+    state: tauri::State<'_, AutoBuildState>,
+) -> Result<(), String> {
+    let root = app_root();
+    let game_dir = PathBuf::from(&game_path);
+    if !game_dir.exists() {
+        return Err(format!("Game directory not found: {}", game_path));
+    }
+    let dist_base = workspace_path
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| root.clone());
+    let options = BuildOptions {
+        profile,
+        target,
+        ..Default::default()
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let watcher = notify::recommended_watcher(move |res| {
+        let _ = tx.send(res);
+    })
+    .map_err(|e| format!("Failed to create watcher: {}", e))?;
+
+    let mut games = state.games.lock().map_err(|e| e.to_string())?;
+    if let Some(previous) = games.remove(&game_path) {
+        previous.stop.store(true, Ordering::Relaxed);
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let auto = AutoBuild::new(&game_dir, &root, &dist_base, workspace_path, options);
+    let thread_stop = stop.clone();
+    let thread = std::thread::spawn(move || {
+        run_auto_build(auto, watcher, rx, &thread_stop, |result| {
+            let _ = app.emit("build:auto", result);
+        });
+    });
+    games.insert(game_path, AutoBuildWorker { stop, thread });
+    Ok(())
+}
+
+/// Leave auto build mode for a game.
+#[tauri::command]
+pub fn stop_auto_build(
+    game_path: String,
+    state: tauri::State<'_, AutoBuildState>,
+) -> Result<(), String> {
+    let mut games = state.games.lock().map_err(|e| e.to_string())?;
+    if let Some(worker) = games.remove(&game_path) {
+        worker.stop.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Paths of the games in auto build mode.
+#[tauri::command]
+pub fn list_auto_builds(state: tauri::State<'_, AutoBuildState>) -> Result<Vec<String>, String> {
+    let mut games = state.games.lock().map_err(|e| e.to_string())?;
+    games.retain(|_, worker| !worker.thread.is_finished());
+    let mut paths: Vec<String> = games.keys().cloned().collect();
+    paths.sort();
+    Ok(paths)
+}
+
+type WatchEvents = mpsc::Receiver<Result<notify::Event, notify::Error>>;
+
+fn run_auto_build(
+    mut auto: AutoBuild,
+    mut watcher: RecommendedWatcher,
+    events: WatchEvents,
+    stop: &AtomicBool,
+    emit: impl Fn(&AutoBuildResult),
+) {
+    let mut dirs = BTreeSet::new();
+    sync_watched_dirs(&mut watcher, &mut dirs, auto.watched());
+    emit(&auto.rebuild(Vec::new()));
+    sync_watched_dirs(&mut watcher, &mut dirs, auto.watched());
+
+    let mut debouncer = Debouncer::new(DEBOUNCE);
+    while !stop.load(Ordering::Relaxed) {
+        match events.recv_timeout(debouncer.wait(Instant::now())) {
+            Ok(Ok(event)) => {
+                if !matches!(
+                    event.kind,
+                    EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+                ) {
+                    continue;
+                }
+                for path in event.paths {
+                    if auto.is_relevant(&path) {
+                        debouncer.change(path, Instant::now());
+                    }
+                }
+            }
+            Ok(Err(e)) => log::warn!("Auto build watcher error: {}", e),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        if let Some(changed) = debouncer.take_due(Instant::now()) {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            emit(&auto.rebuild(changed));
+            sync_watched_dirs(&mut watcher, &mut dirs, auto.watched());
+        }
+    }
+}
+
+/// Watch the directories holding `files`, and stop watching those no longer needed.
+fn sync_watched_dirs(
+    watcher: &mut RecommendedWatcher,
+    dirs: &mut BTreeSet<PathBuf>,
+    files: &BTreeSet<PathBuf>,
+) {
+    let wanted = watch_dirs(files);
+    for dir in dirs.difference(&wanted) {
+        let _ = watcher.unwatch(dir);
+    }
+    for dir in wanted.difference(dirs) {
+        if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+            log::warn!("Failed to watch {}: {}", dir.display(), e);
+        }
+    }
+    *dirs = wanted;
+}
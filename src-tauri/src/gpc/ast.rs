@@ -0,0 +1,425 @@
+//! Typed syntax tree of a GPC script.
+//!
+//! Every node carries the span of its significant tokens. Trivia (whitespace,
+//! comments and preprocessor lines) stays in `File::tokens`, from which it can be
+//! looked up around any node.
+
+use super::lexer::Token;
+use super::{ParseError, Span};
+
+/// A parsed script: its items, every token of the source and the problems found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct File {
+    pub items: Vec<Item>,
+    /// All tokens of the source, trivia included; their texts join back to the source
+    pub tokens: Vec<Token>,
+    pub errors: Vec<ParseError>,
+}
+
+impl File {
+    /// Trivia between the previous significant token and the node at `span`.
+    pub fn leading_trivia(&self, span: Span) -> &[Token] {
+        let end = self.tokens.partition_point(|t| t.span.start < span.start);
+        let start = self.tokens[..end]
+            .iter()
+            .rposition(|t| !t.is_trivia())
+            .map_or(0, |i| i + 1);
+        &self.tokens[start..end]
+    }
+
+    /// Trivia after the node at `span` up to and including the end of its line.
+    pub fn trailing_trivia(&self, span: Span) -> &[Token] {
+        let start = self.tokens.partition_point(|t| t.span.start < span.end);
+        let mut end = start;
+        while let Some(token) = self.tokens.get(end).filter(|t| t.is_trivia()) {
+            end += 1;
+            if token.kind == super::lexer::TokenKind::Newline {
+                break;
+            }
+        }
+        &self.tokens[start..end]
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = &Function> {
+        self.items.iter().filter_map(|item| match &item.kind {
+            ItemKind::Function(function) => Some(function),
+            _ => None,
+        })
+    }
+
+    pub fn combos(&self) -> impl Iterator<Item = &Combo> {
+        self.items.iter().filter_map(|item| match &item.kind {
+            ItemKind::Combo(combo) => Some(combo),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    pub kind: ItemKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ItemKind {
+    /// `define NAME = value;`
+    Define(Define),
+    /// `data(1, 2, 3);`
+    Data(Vec<Expr>),
+    Enum(Enum),
+    /// Global variables and `const` arrays
+    Var(VarDecl),
+    Remap(Remap),
+    Init(Block),
+    Main(Block),
+    Combo(Combo),
+    Function(Function),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Define {
+    pub name: Ident,
+    pub value: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enum {
+    pub name: Option<Ident>,
+    pub members: Vec<EnumMember>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumMember {
+    pub name: Ident,
+    pub value: Option<Expr>,
+}
+
+/// `remap from -> to;`, or `unmap to;` when `from` is `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Remap {
+    pub from: Option<Expr>,
+    pub to: Expr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeName {
+    Int,
+    Int8,
+    Int16,
+    Int32,
+    Uint8,
+    Uint16,
+    Uint32,
+    Fix32,
+    String,
+    Image,
+    Ps5adt,
+}
+
+impl TypeName {
+    pub fn from_keyword(text: &str) -> Option<Self> {
+        Some(match text {
+            "int" => Self::Int,
+            "int8" => Self::Int8,
+            "int16" => Self::Int16,
+            "int32" => Self::Int32,
+            "uint8" => Self::Uint8,
+            "uint16" => Self::Uint16,
+            "uint32" => Self::Uint32,
+            "fix32" => Self::Fix32,
+            "string" => Self::String,
+            "image" => Self::Image,
+            "ps5adt" => Self::Ps5adt,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Type {
+    pub name: TypeName,
+    pub span: Span,
+}
+
+/// `[const] [type] a, b[2] = { 1, 2 };`
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarDecl {
+    pub constant: bool,
+    pub ty: Option<Type>,
+    pub declarators: Vec<Declarator>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Declarator {
+    pub name: Ident,
+    /// One entry per `[...]`; `None` for an empty `[]`
+    pub dims: Vec<Option<Expr>>,
+    pub init: Option<Initializer>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Initializer {
+    Expr(Expr),
+    List { items: Vec<Initializer>, span: Span },
+}
+
+impl Initializer {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Expr(expr) => expr.span,
+            Self::List { span, .. } => *span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Combo {
+    pub name: Ident,
+    pub body: Block,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: Ident,
+    pub params: Vec<Param>,
+    pub body: Block,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub ty: Option<Type>,
+    pub name: Ident,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub stmts: Vec<Stmt>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    Block(Block),
+    /// A lone `;`
+    Empty,
+    Expr(Expr),
+    Var(VarDecl),
+    If {
+        cond: Expr,
+        then: Box<Stmt>,
+        otherwise: Option<Box<Stmt>>,
+    },
+    While {
+        cond: Expr,
+        body: Box<Stmt>,
+    },
+    DoWhile {
+        body: Box<Stmt>,
+        cond: Expr,
+    },
+    For {
+        init: Option<ForInit>,
+        cond: Option<Expr>,
+        step: Vec<Expr>,
+        body: Box<Stmt>,
+    },
+    Switch {
+        value: Expr,
+        cases: Vec<Case>,
+    },
+    Return(Option<Expr>),
+    Break,
+    Continue,
+    Remap(Remap),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ForInit {
+    Var(VarDecl),
+    Exprs(Vec<Expr>),
+}
+
+/// A `case value:` or `default:` label and the statements up to the next label.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    /// `None` for `default`
+    pub value: Option<Expr>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Int(i32),
+    Fixed(f64),
+    /// String literal with escapes resolved
+    Str(String),
+    Char(char),
+    Name(String),
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    /// `x++` and `x--`
+    Postfix {
+        op: PostfixOp,
+        operand: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    Assign {
+        op: AssignOp,
+        target: Box<Expr>,
+        value: Box<Expr>,
+    },
+    Call {
+        callee: Ident,
+        args: Vec<Expr>,
+    },
+    Index {
+        base: Box<Expr>,
+        index: Box<Expr>,
+    },
+    /// `(fix32)x`
+    Cast {
+        ty: Type,
+        operand: Box<Expr>,
+    },
+    Paren(Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Plus,
+    Not,
+    BitNot,
+    PreInc,
+    PreDec,
+}
+
+impl UnaryOp {
+    pub fn from_token(text: &str) -> Option<Self> {
+        Some(match text {
+            "-" => Self::Neg,
+            "+" => Self::Plus,
+            "!" => Self::Not,
+            "~" => Self::BitNot,
+            "++" => Self::PreInc,
+            "--" => Self::PreDec,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostfixOp {
+    Inc,
+    Dec,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl BinaryOp {
+    pub fn from_token(text: &str) -> Option<Self> {
+        Some(match text {
+            "||" => Self::Or,
+            "&&" => Self::And,
+            "|" => Self::BitOr,
+            "^" => Self::BitXor,
+            "&" => Self::BitAnd,
+            "==" => Self::Eq,
+            "!=" => Self::Ne,
+            "<" => Self::Lt,
+            "<=" => Self::Le,
+            ">" => Self::Gt,
+            ">=" => Self::Ge,
+            "<<" => Self::Shl,
+            ">>" => Self::Shr,
+            "+" => Self::Add,
+            "-" => Self::Sub,
+            "*" => Self::Mul,
+            "/" => Self::Div,
+            "%" => Self::Rem,
+            _ => return None,
+        })
+    }
+
+    /// Binding power; higher binds tighter.
+    pub fn precedence(self) -> u8 {
+        match self {
+            Self::Or => 1,
+            Self::And => 2,
+            Self::BitOr => 3,
+            Self::BitXor => 4,
+            Self::BitAnd => 5,
+            Self::Eq | Self::Ne => 6,
+            Self::Lt | Self::Le | Self::Gt | Self::Ge => 7,
+            Self::Shl | Self::Shr => 8,
+            Self::Add | Self::Sub => 9,
+            Self::Mul | Self::Div | Self::Rem => 10,
+        }
+    }
+}
+
+/// `=` or a compound assignment, which applies `Some(op)` first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AssignOp(pub Option<BinaryOp>);
+
+impl AssignOp {
+    pub fn from_token(text: &str) -> Option<Self> {
+        if text == "=" {
+            return Some(Self(None));
+        }
+        let op = text.strip_suffix('=')?;
+        match op {
+            "+" | "-" | "*" | "/" | "%" | "&" | "|" | "^" | "<<" | ">>" => {
+                BinaryOp::from_token(op).map(|op| Self(Some(op)))
+            }
+            _ => None,
+        }
+    }
+}
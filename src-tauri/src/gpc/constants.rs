@@ -0,0 +1,260 @@
+//! Built-in constants of the device: controller identifiers, OLED and SPVAR values.
+
+/// Number of controller inputs and outputs the device tracks.
+pub const IO_COUNT: usize = 40;
+
+/// Identifier names per console prefix, in identifier order starting at 0.
+/// `None` marks an index the console has no name for.
+const PS: &[Option<&str>] = &[
+    Some("PS"),
+    Some("SHARE"),
+    Some("OPTIONS"),
+    Some("R1"),
+    Some("R2"),
+    Some("R3"),
+    Some("L1"),
+    Some("L2"),
+    Some("L3"),
+    Some("RX"),
+    Some("RY"),
+    Some("LX"),
+    Some("LY"),
+    Some("UP"),
+    Some("DOWN"),
+    Some("LEFT"),
+    Some("RIGHT"),
+    Some("TRIANGLE"),
+    Some("CIRCLE"),
+    Some("CROSS"),
+    Some("SQUARE"),
+    Some("ACCX"),
+    Some("ACCY"),
+    Some("ACCZ"),
+    Some("GYROX"),
+    Some("GYROY"),
+    Some("GYROZ"),
+    Some("TOUCH"),
+    Some("TOUCHX"),
+    Some("TOUCHY"),
+];
+
+const XB1: &[Option<&str>] = &[
+    Some("XBOX"),
+    Some("VIEW"),
+    Some("MENU"),
+    Some("RB"),
+    Some("RT"),
+    Some("RS"),
+    Some("LB"),
+    Some("LT"),
+    Some("LS"),
+    Some("RX"),
+    Some("RY"),
+    Some("LX"),
+    Some("LY"),
+    Some("UP"),
+    Some("DOWN"),
+    Some("LEFT"),
+    Some("RIGHT"),
+    Some("Y"),
+    Some("B"),
+    Some("A"),
+    Some("X"),
+    Some("SHARE"),
+    None,
+    None,
+    None,
+    None,
+    None,
+    Some("SYNC"),
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    Some("PR1"),
+    Some("PR2"),
+    Some("PL1"),
+    Some("PL2"),
+];
+
+const XB360: &[Option<&str>] = &[
+    Some("XBOX"),
+    Some("BACK"),
+    Some("START"),
+    Some("RB"),
+    Some("RT"),
+    Some("RS"),
+    Some("LB"),
+    Some("LT"),
+    Some("LS"),
+    Some("RX"),
+    Some("RY"),
+    Some("LX"),
+    Some("LY"),
+    Some("UP"),
+    Some("DOWN"),
+    Some("LEFT"),
+    Some("RIGHT"),
+    Some("Y"),
+    Some("B"),
+    Some("A"),
+    Some("X"),
+];
+
+const SWI: &[Option<&str>] = &[
+    Some("HOME"),
+    Some("MINUS"),
+    Some("PLUS"),
+    Some("R"),
+    Some("ZR"),
+    Some("R3"),
+    Some("L"),
+    Some("ZL"),
+    Some("L3"),
+    Some("RX"),
+    Some("LX"),
+    Some("RY"),
+    Some("LY"),
+    Some("UP"),
+    Some("DOWN"),
+    Some("LEFT"),
+    Some("RIGHT"),
+    Some("X"),
+    Some("A"),
+    Some("B"),
+    Some("Y"),
+    Some("ACCX"),
+    Some("ACCY"),
+    Some("ACCZ"),
+    Some("GYROX"),
+    Some("GYROY"),
+    Some("GYROZ"),
+    Some("CAPTURE"),
+];
+
+const WII: &[Option<&str>] = &[
+    Some("HOME"),
+    Some("MINUS"),
+    Some("PLUS"),
+    Some("RT"),
+    Some("ZR"),
+    Some("R3"),
+    Some("LT"),
+    Some("ZL"),
+    Some("TWO"),
+    Some("RX"),
+    Some("RY"),
+    Some("LX"),
+    Some("LY"),
+    Some("UP"),
+    Some("DOWN"),
+    Some("LEFT"),
+    Some("RIGHT"),
+    Some("X"),
+    Some("B"),
+    Some("A"),
+    Some("Y"),
+    Some("ACCX"),
+    Some("ACCY"),
+    Some("ACCZ"),
+    None,
+    Some("ACCNX"),
+    Some("ACCNY"),
+    Some("ACCNZ"),
+    Some("IRX"),
+    Some("IRY"),
+];
+
+const CONSOLES: &[(&str, &[Option<&str>])] = &[
+    ("PS5_", PS),
+    ("PS4_", PS),
+    ("XB1_", XB1),
+    ("XB360_", XB360),
+    ("SWI_", SWI),
+    ("WII_", WII),
+];
+
+/// Number of SPVAR slots; `SPVAR_1` is slot 128.
+pub const SPVAR_COUNT: i32 = 64;
+pub const SPVAR_FIRST: i32 = 128;
+
+/// Value of a built-in constant such as `PS5_R2`, `OLED_WHITE` or `SPVAR_3`.
+pub fn constant(name: &str) -> Option<i32> {
+    for (prefix, names) in CONSOLES {
+        if let Some(rest) = name.strip_prefix(prefix) {
+            return names.iter().position(|n| *n == Some(rest)).map(|i| i as i32);
+        }
+    }
+    if let Some(n) = name.strip_prefix("SPVAR_").and_then(|n| n.parse::<i32>().ok()) {
+        return (1..=SPVAR_COUNT).contains(&n).then_some(SPVAR_FIRST + n - 1);
+    }
+    Some(match name {
+        "TRUE" => 1,
+        "FALSE" => 0,
+        "OLED_BLACK" => 0,
+        "OLED_WHITE" => 1,
+        "OLED_WIDTH" => super::oled::WIDTH as i32,
+        "OLED_HEIGHT" => super::oled::HEIGHT as i32,
+        "OLED_FONT_SMALL" => 0,
+        "OLED_FONT_MEDIUM" => 1,
+        "OLED_FONT_LARGE" => 2,
+        "OLED_FONT_SMALL_WIDTH" => 7,
+        "OLED_FONT_SMALL_HEIGHT" => 10,
+        "OLED_FONT_MEDIUM_WIDTH" => 11,
+        "OLED_FONT_MEDIUM_HEIGHT" => 18,
+        "OLED_FONT_LARGE_WIDTH" => 16,
+        "OLED_FONT_LARGE_HEIGHT" => 26,
+        _ => return None,
+    })
+}
+
+/// Identifier index of a controller input name such as `PS5_R2`.
+pub fn io_index(name: &str) -> Option<usize> {
+    CONSOLES
+        .iter()
+        .any(|(prefix, _)| name.starts_with(prefix))
+        .then(|| constant(name))
+        .flatten()
+        .map(|value| value as usize)
+}
+
+/// Name of identifier `index` with a console prefix such as `PS5_`.
+pub fn io_name(prefix: &str, index: usize) -> Option<String> {
+    let (prefix, names) = CONSOLES.iter().find(|(p, _)| *p == prefix)?;
+    let name = names.get(index).copied().flatten()?;
+    Some(format!("{}{}", prefix, name))
+}
+
+/// Console prefix of an identifier name, e.g. `XB1_` for `XB1_RT`.
+pub fn io_prefix(name: &str) -> Option<&'static str> {
+    CONSOLES
+        .iter()
+        .map(|(prefix, _)| *prefix)
+        .find(|prefix| name.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constants() {
+        assert_eq!(constant("PS5_R2"), Some(4));
+        assert_eq!(constant("PS4_CROSS"), Some(19));
+        assert_eq!(constant("XB1_PL2"), Some(37));
+        assert_eq!(constant("SWI_CAPTURE"), Some(27));
+        assert_eq!(constant("XB1_NOPE"), None);
+        assert_eq!(constant("SPVAR_1"), Some(128));
+        assert_eq!(constant("SPVAR_64"), Some(191));
+        assert_eq!(constant("SPVAR_65"), None);
+        assert_eq!(constant("OLED_WHITE"), Some(1));
+        assert_eq!(io_index("XB360_A"), Some(19));
+        assert_eq!(io_index("OLED_WHITE"), None);
+        assert_eq!(io_name("XB1_", 4).as_deref(), Some("XB1_RT"));
+        assert_eq!(io_name("PS5_", 35), None);
+        assert_eq!(io_prefix("SWI_ZR"), Some("SWI_"));
+        assert!(CONSOLES.iter().all(|(_, names)| names.len() <= IO_COUNT));
+    }
+}
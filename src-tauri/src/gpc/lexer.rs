@@ -0,0 +1,260 @@
+//! Lossless GPC lexer.
+//!
+//! Every byte of the input belongs to exactly one token, trivia included, so the
+//! token texts concatenated give back the source unchanged. Compound operators
+//! such as `<<=` and `->` are single tokens.
+
+use super::{ParseError, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// Spaces, tabs and carriage returns
+    Whitespace,
+    Newline,
+    LineComment,
+    BlockComment,
+    /// A preprocessor line such as `#ifdef DEBUG`, up to the end of the line
+    Directive,
+    /// Names, keywords and type names
+    Ident,
+    /// Decimal, hexadecimal (`0x`), binary (`0b`) and fixed-point (`1.5`) numbers
+    Number,
+    Str,
+    Char,
+    Punct,
+    /// A character that cannot start any token, e.g. a non-ASCII letter
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Token {
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        self.span.text(source)
+    }
+
+    /// Whether the parser skips this token: whitespace, comments and directives.
+    pub fn is_trivia(&self) -> bool {
+        matches!(
+            self.kind,
+            TokenKind::Whitespace
+                | TokenKind::Newline
+                | TokenKind::LineComment
+                | TokenKind::BlockComment
+                | TokenKind::Directive
+        )
+    }
+}
+
+/// Operators longer than one character, longest first.
+const COMPOUND_PUNCT: &[&str] = &[
+    "<<=", ">>=", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "==", "!=", "<=", ">=", "&&",
+    "||", "<<", ">>", "++", "--", "->",
+];
+
+/// Split `source` into tokens covering all of it, plus unterminated comments and literals.
+pub fn lex(source: &str) -> (Vec<Token>, Vec<ParseError>) {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    let mut pos = 0;
+    // Only whitespace seen since the last newline, so `#` starts a directive
+    let mut line_start = true;
+
+    while pos < bytes.len() {
+        let start = pos;
+        let kind = match bytes[pos] {
+            b'\n' => {
+                pos += 1;
+                TokenKind::Newline
+            }
+            b' ' | b'\t' | b'\r' => {
+                pos = skip_while(bytes, pos, |b| matches!(b, b' ' | b'\t' | b'\r'));
+                TokenKind::Whitespace
+            }
+            b'/' if bytes.get(pos + 1) == Some(&b'/') => {
+                pos = skip_while(bytes, pos, |b| b != b'\n');
+                TokenKind::LineComment
+            }
+            b'/' if bytes.get(pos + 1) == Some(&b'*') => {
+                match source[pos + 2..].find("*/") {
+                    Some(end) => pos += end + 4,
+                    None => {
+                        pos = bytes.len();
+                        errors.push(ParseError::new(
+                            "Unterminated block comment",
+                            Span::new(start, start + 2),
+                        ));
+                    }
+                }
+                TokenKind::BlockComment
+            }
+            b'#' if line_start => {
+                pos = skip_while(bytes, pos, |b| b != b'\n');
+                TokenKind::Directive
+            }
+            b'"' | b'\'' => {
+                let quote = bytes[pos];
+                pos += 1;
+                let mut closed = false;
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    match bytes[pos] {
+                        b'\\' if pos + 1 < bytes.len() && bytes[pos + 1] != b'\n' => pos += 2,
+                        b if b == quote => {
+                            pos += 1;
+                            closed = true;
+                            break;
+                        }
+                        _ => pos += 1,
+                    }
+                }
+                if !closed {
+                    errors.push(ParseError::new(
+                        "Unterminated string literal",
+                        Span::new(start, start + 1),
+                    ));
+                }
+                if quote == b'"' {
+                    TokenKind::Str
+                } else {
+                    TokenKind::Char
+                }
+            }
+            b if b.is_ascii_alphabetic() || b == b'_' => {
+                pos = skip_while(bytes, pos, |b| b.is_ascii_alphanumeric() || b == b'_');
+                TokenKind::Ident
+            }
+            b if b.is_ascii_digit() => {
+                pos = skip_while(bytes, pos, |b| b.is_ascii_alphanumeric() || b == b'_');
+                if bytes.get(pos) == Some(&b'.')
+                    && bytes.get(pos + 1).is_some_and(u8::is_ascii_digit)
+                {
+                    pos = skip_while(bytes, pos + 1, |b| b.is_ascii_alphanumeric() || b == b'_');
+                }
+                TokenKind::Number
+            }
+            b if b.is_ascii() => {
+                let rest = &source[pos..];
+                let len = COMPOUND_PUNCT
+                    .iter()
+                    .find(|op| rest.starts_with(*op))
+                    .map_or(1, |op| op.len());
+                pos += len;
+                TokenKind::Punct
+            }
+            _ => {
+                pos += source[pos..].chars().next().map_or(1, char::len_utf8);
+                TokenKind::Unknown
+            }
+        };
+        line_start = match kind {
+            TokenKind::Newline => true,
+            TokenKind::Whitespace => line_start,
+            _ => false,
+        };
+        tokens.push(Token {
+            kind,
+            span: Span::new(start, pos),
+        });
+    }
+    (tokens, errors)
+}
+
+fn skip_while(bytes: &[u8], mut pos: usize, keep: impl Fn(u8) -> bool) -> usize {
+    while pos < bytes.len() && keep(bytes[pos]) {
+        pos += 1;
+    }
+    pos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<(TokenKind, &str)> {
+        lex(source)
+            .0
+            .iter()
+            .map(|t| (t.kind, t.text(source)))
+            .collect()
+    }
+
+    #[test]
+    fn test_lex_is_lossless() {
+        let source =
+            "#ifdef X\r\nint a = 0x1F; // é\n  /* b */ a <<= 1.5;\tprintf(\"%d\\\"\", 'c');\n";
+        let (tokens, errors) = lex(source);
+        assert!(errors.is_empty());
+        let joined: String = tokens.iter().map(|t| t.text(source)).collect();
+        assert_eq!(joined, source);
+        assert!(tokens.windows(2).all(|w| w[0].span.end == w[1].span.start));
+
+        let significant: Vec<_> = kinds(source)
+            .into_iter()
+            .filter(|(k, _)| !matches!(k, TokenKind::Whitespace | TokenKind::Newline))
+            .collect();
+        assert_eq!(
+            &significant[..4],
+            &[
+                (TokenKind::Directive, "#ifdef X\r"),
+                (TokenKind::Ident, "int"),
+                (TokenKind::Ident, "a"),
+                (TokenKind::Punct, "="),
+            ]
+        );
+        assert!(significant.contains(&(TokenKind::Number, "0x1F")));
+        assert!(significant.contains(&(TokenKind::LineComment, "// é")));
+        assert!(significant.contains(&(TokenKind::Punct, "<<=")));
+        assert!(significant.contains(&(TokenKind::Number, "1.5")));
+        assert!(significant.contains(&(TokenKind::Str, "\"%d\\\"\"")));
+        assert!(significant.contains(&(TokenKind::Char, "'c'")));
+    }
+
+    #[test]
+    fn test_lex_operators_and_hash() {
+        assert_eq!(
+            kinds("a->b&=c>>d"),
+            vec![
+                (TokenKind::Ident, "a"),
+                (TokenKind::Punct, "->"),
+                (TokenKind::Ident, "b"),
+                (TokenKind::Punct, "&="),
+                (TokenKind::Ident, "c"),
+                (TokenKind::Punct, ">>"),
+                (TokenKind::Ident, "d"),
+            ]
+        );
+        // `#` only starts a directive at the beginning of a line
+        assert_eq!(kinds("a #b")[2], (TokenKind::Punct, "#"));
+        assert_eq!(kinds("  #if A")[1], (TokenKind::Directive, "#if A"));
+        assert_eq!(kinds("é")[0], (TokenKind::Unknown, "é"));
+    }
+
+    #[test]
+    fn test_lex_unterminated() {
+        let source = "a = \"abc\nb; /* open";
+        let (tokens, errors) = lex(source);
+        let messages: Vec<_> = errors
+            .iter()
+            .map(|e| (e.message.as_str(), e.span.start))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                ("Unterminated string literal", 4),
+                ("Unterminated block comment", 12)
+            ]
+        );
+        assert_eq!(
+            tokens.iter().map(|t| t.text(source)).collect::<String>(),
+            source
+        );
+        assert!(tokens
+            .iter()
+            .any(|t| t.kind == TokenKind::Str && t.text(source) == "\"abc"));
+    }
+}
@@ -0,0 +1,91 @@
+//! GPC language support: a lossless lexer, a parser, a typed syntax tree and a
+//! headless virtual machine for simulating scripts.
+//!
+//! `parse` never gives up on a script: problems are collected in `File::errors`
+//! and parsing resumes at the next statement or top-level item, so tools get a
+//! tree for everything that does parse. The build's syntax check reports the same
+//! errors (see `pipeline::syntax`).
+
+pub mod ast;
+pub mod constants;
+pub mod lexer;
+pub mod oled;
+pub mod parser;
+pub mod profile;
+pub mod vm;
+
+pub use parser::parse;
+
+use serde::{Deserialize, Serialize};
+
+/// Byte range in the source text; `end` is exclusive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Span from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start, other.end.max(self.end))
+    }
+
+    pub fn text(self, source: &str) -> &str {
+        &source[self.start..self.end]
+    }
+
+    pub fn contains(self, offset: usize) -> bool {
+        self.start <= offset && offset < self.end
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+    /// Another location that explains the error, e.g. the bracket left unclosed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub related: Option<(Span, String)>,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            related: None,
+        }
+    }
+}
+
+/// Keywords that can never be used as names, type names included.
+pub fn is_keyword(text: &str) -> bool {
+    matches!(
+        text,
+        "if" | "else"
+            | "while"
+            | "do"
+            | "for"
+            | "switch"
+            | "case"
+            | "default"
+            | "return"
+            | "break"
+            | "continue"
+            | "function"
+            | "combo"
+            | "init"
+            | "main"
+            | "define"
+            | "const"
+            | "enum"
+            | "remap"
+            | "unmap"
+            | "import"
+    ) || ast::TypeName::from_keyword(text).is_some()
+}
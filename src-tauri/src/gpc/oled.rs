@@ -0,0 +1,216 @@
+//! Simulated 128x64 monochrome OLED display.
+//!
+//! Shapes and images are drawn into a 1-bit framebuffer laid out like the flow
+//! editor's previews: row-major, 16 bytes per row, most significant bit first.
+//! Text is not rasterized; each string drawn is kept with its position and font.
+
+use serde::Serialize;
+
+pub const WIDTH: usize = 128;
+pub const HEIGHT: usize = 64;
+
+/// Characters `putc_oled` can buffer for `puts_oled`.
+const STRING_BUFFER: usize = 64;
+
+/// A string drawn on the display.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OledText {
+    pub x: i32,
+    pub y: i32,
+    pub font: i32,
+    pub color: i32,
+    pub text: String,
+}
+
+/// Image data from a `const image` declaration: width, height and packed bits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// Row-major bits, most significant bit first, without row padding
+    pub bits: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Oled {
+    pub pixels: Vec<u8>,
+    /// Strings on the display, in drawing order; cleared by `cls_oled`
+    pub text: Vec<OledText>,
+    buffer: Vec<u8>,
+}
+
+impl Default for Oled {
+    fn default() -> Self {
+        Self {
+            pixels: vec![0; WIDTH * HEIGHT / 8],
+            text: Vec::new(),
+            buffer: vec![0; STRING_BUFFER],
+        }
+    }
+}
+
+impl Oled {
+    pub fn pixel(&self, x: i32, y: i32) -> bool {
+        match Self::bit(x, y) {
+            Some((byte, mask)) => self.pixels[byte] & mask != 0,
+            None => false,
+        }
+    }
+
+    /// All strings on the display joined by newlines, in drawing order.
+    pub fn text_content(&self) -> String {
+        let lines: Vec<&str> = self.text.iter().map(|t| t.text.as_str()).collect();
+        lines.join("\n")
+    }
+
+    fn bit(x: i32, y: i32) -> Option<(usize, u8)> {
+        if x < 0 || y < 0 || x as usize >= WIDTH || y as usize >= HEIGHT {
+            return None;
+        }
+        let (x, y) = (x as usize, y as usize);
+        Some((y * WIDTH / 8 + x / 8, 0x80 >> (x % 8)))
+    }
+
+    pub fn cls(&mut self, color: i32) {
+        self.pixels.fill(if color != 0 { 0xFF } else { 0 });
+        self.text.clear();
+    }
+
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: i32) {
+        if let Some((byte, mask)) = Self::bit(x, y) {
+            if color != 0 {
+                self.pixels[byte] |= mask;
+            } else {
+                self.pixels[byte] &= !mask;
+            }
+        }
+    }
+
+    pub fn line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, thickness: i32, color: i32) {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = (if x0 < x1 { 1 } else { -1 }, if y0 < y1 { 1 } else { -1 });
+        let (mut x, mut y, mut err) = (x0, y0, dx + dy);
+        let size = thickness.max(1);
+        loop {
+            self.rect(x - (size - 1) / 2, y - (size - 1) / 2, size, size, true, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    pub fn rect(&mut self, x: i32, y: i32, width: i32, height: i32, fill: bool, color: i32) {
+        for py in y..y.saturating_add(height).min(HEIGHT as i32) {
+            for px in x..x.saturating_add(width).min(WIDTH as i32) {
+                let edge = px == x || py == y || px == x + width - 1 || py == y + height - 1;
+                if fill || edge {
+                    self.set_pixel(px, py, color);
+                }
+            }
+        }
+    }
+
+    pub fn circle(&mut self, cx: i32, cy: i32, radius: i32, fill: bool, color: i32) {
+        let r2 = radius * radius;
+        let inner = (radius - 1) * (radius - 1);
+        for y in -radius..=radius {
+            for x in -radius..=radius {
+                let d = x * x + y * y;
+                if d <= r2 && (fill || d > inner) {
+                    self.set_pixel(cx + x, cy + y, color);
+                }
+            }
+        }
+    }
+
+    /// Store a character at 1-indexed `position` of the string buffer.
+    pub fn putc(&mut self, position: i32, ch: i32) {
+        if let Some(slot) = position
+            .checked_sub(1)
+            .and_then(|p| self.buffer.get_mut(usize::try_from(p).ok()?))
+        {
+            *slot = ch as u8;
+        }
+    }
+
+    /// Draw the first `length` buffered characters.
+    pub fn puts(&mut self, x: i32, y: i32, font: i32, length: i32, color: i32) {
+        let length = usize::try_from(length).unwrap_or(0).min(STRING_BUFFER);
+        let text = String::from_utf8_lossy(&self.buffer[..length]).to_string();
+        self.print(x, y, font, color, &text);
+    }
+
+    /// Draw a string, replacing any string drawn at the same position.
+    pub fn print(&mut self, x: i32, y: i32, font: i32, color: i32, text: &str) {
+        self.text.retain(|t| (t.x, t.y) != (x, y));
+        self.text.push(OledText {
+            x,
+            y,
+            font,
+            color,
+            text: text.to_string(),
+        });
+    }
+
+    /// Draw an image; transparent images leave unset pixels alone.
+    pub fn image(&mut self, x: i32, y: i32, transparent: bool, invert: bool, image: &Image) {
+        for py in 0..image.height {
+            for px in 0..image.width {
+                let index = py * image.width + px;
+                let set = image
+                    .bits
+                    .get(index / 8)
+                    .is_some_and(|byte| byte & (0x80 >> (index % 8)) != 0);
+                let on = set != invert;
+                if on || !transparent {
+                    self.set_pixel(x + px as i32, y + py as i32, on as i32);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oled_drawing() {
+        let mut oled = Oled::default();
+        oled.rect(0, 0, 4, 3, false, 1);
+        assert!(oled.pixel(0, 0) && oled.pixel(3, 2) && !oled.pixel(1, 1));
+        assert_eq!(oled.pixels[0], 0xF0);
+        oled.line(10, 10, 13, 10, 1, 1);
+        assert!((10..=13).all(|x| oled.pixel(x, 10)));
+        oled.circle(64, 32, 3, true, 1);
+        assert!(oled.pixel(64, 32) && oled.pixel(67, 32) && !oled.pixel(67, 35));
+        oled.set_pixel(200, 200, 1);
+
+        let image = Image {
+            width: 3,
+            height: 2,
+            bits: vec![0b1010_1100],
+        };
+        oled.image(100, 0, true, false, &image);
+        let row = |y| (100..103).map(|x| oled.pixel(x, y)).collect::<Vec<_>>();
+        assert_eq!((row(0), row(1)), (vec![true, false, true], vec![false, true, true]));
+
+        oled.putc(1, 'h' as i32);
+        oled.putc(2, 'i' as i32);
+        oled.puts(0, 20, 0, 2, 1);
+        oled.print(0, 40, 1, 1, "Menu");
+        oled.print(0, 20, 0, 1, "hey");
+        assert_eq!(oled.text_content(), "Menu\nhey");
+        oled.cls(0);
+        assert!(oled.text.is_empty() && oled.pixels.iter().all(|b| *b == 0));
+    }
+}
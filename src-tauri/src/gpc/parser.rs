@@ -0,0 +1,1143 @@
+//! Recursive-descent parser from tokens to the typed syntax tree.
+//!
+//! Trivia is skipped; the grammar follows the device compiler: top-level
+//! declarations, `init`, `main`, combos and functions, C statements and
+//! expressions with C precedence.
+
+use super::ast::*;
+use super::lexer::{lex, Token, TokenKind};
+use super::{is_keyword, ParseError, Span};
+
+type Parsed<T> = Result<T, ParseError>;
+
+/// Parse a whole script, collecting every problem instead of stopping at the first.
+///
+/// Unterminated literals and unbalanced brackets are reported on their own, since they
+/// would throw off everything after them. Otherwise each malformed item is reported once.
+pub fn parse(source: &str) -> File {
+    let (tokens, mut errors) = lex(source);
+    let mut parser = Parser {
+        source,
+        toks: tokens.iter().filter(|t| !t.is_trivia()).copied().collect(),
+        pos: 0,
+        errors: Vec::new(),
+    };
+    let items = parser.file();
+    if errors.is_empty() {
+        errors = check_brackets(source, &parser.toks);
+    }
+    if errors.is_empty() {
+        errors = parser.errors;
+    }
+    errors.sort_by_key(|e| e.span.start);
+    File {
+        items,
+        tokens,
+        errors,
+    }
+}
+
+fn closing_bracket(open: &str) -> Option<&'static str> {
+    match open {
+        "(" => Some(")"),
+        "[" => Some("]"),
+        "{" => Some("}"),
+        _ => None,
+    }
+}
+
+/// Report the first bracket closed by the wrong bracket or never opened, or else the
+/// innermost bracket never closed.
+fn check_brackets(source: &str, toks: &[Token]) -> Vec<ParseError> {
+    let mut stack: Vec<Token> = Vec::new();
+    for tok in toks.iter().filter(|t| t.kind == TokenKind::Punct) {
+        let text = tok.text(source);
+        if closing_bracket(text).is_some() {
+            stack.push(*tok);
+            continue;
+        }
+        if !matches!(text, ")" | "]" | "}") {
+            continue;
+        }
+        match stack.pop() {
+            Some(open) if closing_bracket(open.text(source)) == Some(text) => {}
+            Some(open) => {
+                let open_text = open.text(source);
+                let mut error = ParseError::new(
+                    format!(
+                        "Mismatched '{}': expected '{}' to close '{}'",
+                        text,
+                        closing_bracket(open_text).unwrap_or_default(),
+                        open_text
+                    ),
+                    tok.span,
+                );
+                error.related = Some((open.span, format!("'{}' opened here", open_text)));
+                return vec![error];
+            }
+            None => return vec![ParseError::new(format!("Unmatched '{}'", text), tok.span)],
+        }
+    }
+    stack
+        .last()
+        .map(|open| {
+            let message = format!("'{}' is never closed", open.text(source));
+            ParseError::new(message, open.span)
+        })
+        .into_iter()
+        .collect()
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    /// Significant tokens only
+    toks: Vec<Token>,
+    pos: usize,
+    errors: Vec<ParseError>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<Token> {
+        self.toks.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> &str {
+        self.toks
+            .get(self.pos + offset)
+            .filter(|t| matches!(t.kind, TokenKind::Ident | TokenKind::Punct))
+            .map_or("", |t| t.text(self.source))
+    }
+
+    /// Text of the next token if it is a name, keyword or operator.
+    fn peek_text(&self) -> &str {
+        self.peek_at(0)
+    }
+
+    fn at(&self, text: &str) -> bool {
+        self.peek().is_some() && self.peek_text() == text
+    }
+
+    fn at_eof(&self) -> bool {
+        self.pos >= self.toks.len()
+    }
+
+    fn bump(&mut self) -> Token {
+        let tok = self.peek().unwrap_or(Token {
+            kind: TokenKind::Punct,
+            span: self.eof_span(),
+        });
+        self.pos = (self.pos + 1).min(self.toks.len());
+        tok
+    }
+
+    fn eat(&mut self, text: &str) -> bool {
+        let found = self.at(text);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eof_span(&self) -> Span {
+        Span::new(self.source.len(), self.source.len())
+    }
+
+    fn current_span(&self) -> Span {
+        self.peek().map_or(self.eof_span(), |t| t.span)
+    }
+
+    /// Span from `start` to the end of the last consumed token.
+    fn since(&self, start: Span) -> Span {
+        match self.pos.checked_sub(1).map(|i| self.toks[i].span) {
+            Some(prev) if prev.end >= start.start => start.to(prev),
+            _ => start,
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Parsed<Span> {
+        if self.at(text) {
+            return Ok(self.bump().span);
+        }
+        // A missing terminator belongs to the end of what came before it
+        if text == ";" && self.pos > 0 {
+            let prev = self.toks[self.pos - 1];
+            let message = format!("Expected ';' after '{}'", prev.text(self.source));
+            return Err(ParseError::new(message, prev.span));
+        }
+        Err(self.unexpected(&format!("'{}'", text)))
+    }
+
+    fn unexpected(&self, expected: &str) -> ParseError {
+        let found = match self.peek() {
+            Some(tok) => format!("'{}'", tok.text(self.source)),
+            None => "end of file".to_string(),
+        };
+        ParseError::new(
+            format!("Expected {}, found {}", expected, found),
+            self.current_span(),
+        )
+    }
+
+    fn ident(&mut self, what: &str) -> Parsed<Ident> {
+        match self.peek() {
+            Some(tok) if tok.kind == TokenKind::Ident && !is_keyword(tok.text(self.source)) => {
+                self.pos += 1;
+                Ok(Ident {
+                    name: tok.text(self.source).to_string(),
+                    span: tok.span,
+                })
+            }
+            _ => Err(self.unexpected(what)),
+        }
+    }
+
+    fn ty(&mut self) -> Option<Type> {
+        let name = TypeName::from_keyword(self.peek_text())?;
+        Some(Type {
+            name,
+            span: self.bump().span,
+        })
+    }
+
+    // Items
+
+    /// Parse top-level items until the end, skipping past any item that fails.
+    fn file(&mut self) -> Vec<Item> {
+        let mut items = Vec::new();
+        while !self.at_eof() {
+            let start = self.pos;
+            if self.eat(";") {
+                continue;
+            }
+            match self.item() {
+                Ok(item) => items.push(item),
+                Err(error) => {
+                    // A statement error at the end of the file also ends its item there
+                    if self.errors.last().map(|e| e.span) != Some(error.span) {
+                        self.errors.push(error);
+                    }
+                    self.pos = self
+                        .item_end(start)
+                        .max(self.pos.min(self.toks.len() - 1) + 1);
+                }
+            }
+        }
+        items
+    }
+
+    /// Index just past the top-level item starting at `start`: its first `;` outside
+    /// brackets, or the bracket that closes its body.
+    fn item_end(&self, start: usize) -> usize {
+        let mut depth = 0usize;
+        for (i, tok) in self.toks.iter().enumerate().skip(start) {
+            if tok.kind != TokenKind::Punct {
+                continue;
+            }
+            match tok.text(self.source) {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" => depth = depth.saturating_sub(1),
+                "}" => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        return i + 1;
+                    }
+                }
+                ";" if depth == 0 => return i + 1,
+                _ => {}
+            }
+        }
+        self.toks.len()
+    }
+
+    fn item(&mut self) -> Parsed<Item> {
+        let start = self.current_span();
+        let kind = match self.peek_text() {
+            "define" => {
+                self.bump();
+                let name = self.ident("a name after 'define'")?;
+                self.expect("=")?;
+                let value = self.expr()?;
+                self.expect(";")?;
+                ItemKind::Define(Define { name, value })
+            }
+            "data" if self.peek_at(1) == "(" => {
+                self.bump();
+                self.bump();
+                let values = self.arguments()?;
+                self.expect(";")?;
+                ItemKind::Data(values)
+            }
+            "remap" | "unmap" => ItemKind::Remap(self.remap()?),
+            "enum" => ItemKind::Enum(self.enumeration()?),
+            "init" => {
+                self.bump();
+                ItemKind::Init(self.block()?)
+            }
+            "main" => {
+                self.bump();
+                ItemKind::Main(self.block()?)
+            }
+            "combo" => {
+                self.bump();
+                let name = self.ident("a combo name")?;
+                let body = self.block()?;
+                ItemKind::Combo(Combo { name, body })
+            }
+            "function" => ItemKind::Function(self.function()?),
+            text if text == "const" || TypeName::from_keyword(text).is_some() => {
+                let decl = self.declaration()?;
+                self.expect(";")?;
+                ItemKind::Var(decl)
+            }
+            _ => {
+                return Err(self.unexpected("a declaration, 'init', 'main', 'combo' or 'function'"))
+            }
+        };
+        Ok(Item {
+            kind,
+            span: self.since(start),
+        })
+    }
+
+    fn function(&mut self) -> Parsed<Function> {
+        self.bump();
+        let name = self.ident("a function name")?;
+        self.expect("(")?;
+        let mut params = Vec::new();
+        if !self.eat(")") {
+            loop {
+                let ty = self.ty();
+                let name = self.ident("a parameter name")?;
+                params.push(Param { ty, name });
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        let body = self.block()?;
+        Ok(Function { name, params, body })
+    }
+
+    fn remap(&mut self) -> Parsed<Remap> {
+        let from = if self.bump().text(self.source) == "remap" {
+            let from = self.expr()?;
+            self.expect("->")?;
+            Some(from)
+        } else {
+            None
+        };
+        let to = self.expr()?;
+        self.expect(";")?;
+        Ok(Remap { from, to })
+    }
+
+    fn enumeration(&mut self) -> Parsed<Enum> {
+        self.bump();
+        let name = match self.peek() {
+            Some(tok) if tok.kind == TokenKind::Ident => Some(self.ident("an enum name")?),
+            _ => None,
+        };
+        self.expect("{")?;
+        let mut members = Vec::new();
+        while !self.eat("}") {
+            let name = self.ident("an enum member")?;
+            let value = if self.eat("=") {
+                Some(self.expr()?)
+            } else {
+                None
+            };
+            members.push(EnumMember { name, value });
+            if !self.at("}") {
+                self.expect(",")?;
+            }
+        }
+        self.eat(";");
+        Ok(Enum { name, members })
+    }
+
+    /// `[const] type name[..] = init, ...` with the type optional after `const`,
+    /// without the closing `;`.
+    fn declaration(&mut self) -> Parsed<VarDecl> {
+        let constant = self.eat("const");
+        let ty = self.ty();
+        if ty.is_none() && !constant {
+            return Err(self.unexpected("a type"));
+        }
+        let mut declarators = Vec::new();
+        loop {
+            let name = self.ident("a variable name")?;
+            let mut dims = Vec::new();
+            while self.eat("[") {
+                dims.push(if self.at("]") {
+                    None
+                } else {
+                    Some(self.expr()?)
+                });
+                self.expect("]")?;
+            }
+            let init = if self.eat("=") {
+                Some(self.initializer()?)
+            } else {
+                None
+            };
+            let span = self.since(name.span);
+            declarators.push(Declarator {
+                name,
+                dims,
+                init,
+                span,
+            });
+            if !self.eat(",") {
+                break;
+            }
+        }
+        Ok(VarDecl {
+            constant,
+            ty,
+            declarators,
+        })
+    }
+
+    fn initializer(&mut self) -> Parsed<Initializer> {
+        let start = self.current_span();
+        if !self.eat("{") {
+            return self.expr().map(Initializer::Expr);
+        }
+        let mut items = Vec::new();
+        while !self.eat("}") {
+            items.push(self.initializer()?);
+            if !self.at("}") {
+                self.expect(",")?;
+            }
+        }
+        Ok(Initializer::List {
+            items,
+            span: self.since(start),
+        })
+    }
+
+    // Statements
+
+    /// A `{ ... }` block; a statement that fails is reported and skipped.
+    fn block(&mut self) -> Parsed<Block> {
+        let start = self.expect("{")?;
+        let mut stmts = Vec::new();
+        while !self.eat("}") {
+            if self.at_eof() {
+                return Err(self.unexpected("'}'"));
+            }
+            let first = self.pos;
+            match self.stmt() {
+                Ok(stmt) => stmts.push(stmt),
+                Err(error) => {
+                    self.errors.push(error);
+                    self.pos = self.stmt_end(first);
+                }
+            }
+        }
+        Ok(Block {
+            stmts,
+            span: self.since(start),
+        })
+    }
+
+    /// Index just past the statement starting at `start`, without leaving its block.
+    fn stmt_end(&self, start: usize) -> usize {
+        let mut depth = 0usize;
+        for (i, tok) in self.toks.iter().enumerate().skip(start) {
+            if tok.kind != TokenKind::Punct {
+                continue;
+            }
+            match tok.text(self.source) {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" => depth = depth.saturating_sub(1),
+                "}" if depth == 0 => return i.max(start + 1),
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return i + 1;
+                    }
+                }
+                ";" if depth == 0 => return i + 1,
+                _ => {}
+            }
+        }
+        self.toks.len()
+    }
+
+    fn stmt(&mut self) -> Parsed<Stmt> {
+        let start = self.current_span();
+        let kind = match self.peek_text() {
+            "{" => StmtKind::Block(self.block()?),
+            ";" => {
+                self.bump();
+                StmtKind::Empty
+            }
+            "if" => {
+                self.bump();
+                let cond = self.condition()?;
+                let then = Box::new(self.stmt()?);
+                let otherwise = if self.eat("else") {
+                    Some(Box::new(self.stmt()?))
+                } else {
+                    None
+                };
+                StmtKind::If {
+                    cond,
+                    then,
+                    otherwise,
+                }
+            }
+            "while" => {
+                self.bump();
+                let cond = self.condition()?;
+                let body = Box::new(self.stmt()?);
+                StmtKind::While { cond, body }
+            }
+            "do" => {
+                self.bump();
+                let body = Box::new(self.stmt()?);
+                self.expect("while")?;
+                let cond = self.condition()?;
+                self.expect(";")?;
+                StmtKind::DoWhile { body, cond }
+            }
+            "for" => self.for_loop()?,
+            "switch" => self.switch()?,
+            "return" => {
+                self.bump();
+                let value = if self.at(";") {
+                    None
+                } else {
+                    Some(self.expr()?)
+                };
+                self.expect(";")?;
+                StmtKind::Return(value)
+            }
+            "break" | "continue" => {
+                let brk = self.bump().text(self.source) == "break";
+                self.expect(";")?;
+                if brk {
+                    StmtKind::Break
+                } else {
+                    StmtKind::Continue
+                }
+            }
+            "remap" | "unmap" => StmtKind::Remap(self.remap()?),
+            text if text == "const" || TypeName::from_keyword(text).is_some() => {
+                let decl = self.declaration()?;
+                self.expect(";")?;
+                StmtKind::Var(decl)
+            }
+            "else" => {
+                return Err(ParseError::new("'else' without a matching 'if'", start));
+            }
+            "case" | "default" => {
+                let message = format!("'{}' outside of a 'switch'", self.peek_text());
+                return Err(ParseError::new(message, start));
+            }
+            _ => {
+                let expr = self.expr()?;
+                self.expect(";")?;
+                StmtKind::Expr(expr)
+            }
+        };
+        Ok(Stmt {
+            kind,
+            span: self.since(start),
+        })
+    }
+
+    fn for_loop(&mut self) -> Parsed<StmtKind> {
+        self.bump();
+        self.expect("(")?;
+        let init = if self.at("const") || TypeName::from_keyword(self.peek_text()).is_some() {
+            Some(ForInit::Var(self.declaration()?))
+        } else if self.at(";") {
+            None
+        } else {
+            Some(ForInit::Exprs(self.expr_list()?))
+        };
+        self.expect(";")?;
+        let cond = if self.at(";") {
+            None
+        } else {
+            Some(self.expr()?)
+        };
+        self.expect(";")?;
+        let step = if self.at(")") {
+            Vec::new()
+        } else {
+            self.expr_list()?
+        };
+        self.expect(")")?;
+        let body = Box::new(self.stmt()?);
+        Ok(StmtKind::For {
+            init,
+            cond,
+            step,
+            body,
+        })
+    }
+
+    fn switch(&mut self) -> Parsed<StmtKind> {
+        self.bump();
+        let value = self.condition()?;
+        self.expect("{")?;
+        let mut cases: Vec<Case> = Vec::new();
+        while !self.eat("}") {
+            let start = self.current_span();
+            if self.eat("case") {
+                let label = self.expr()?;
+                self.expect(":")?;
+                cases.push(Case {
+                    value: Some(label),
+                    body: Vec::new(),
+                    span: self.since(start),
+                });
+            } else if self.eat("default") {
+                self.expect(":")?;
+                cases.push(Case {
+                    value: None,
+                    body: Vec::new(),
+                    span: self.since(start),
+                });
+            } else {
+                let stmt = self.stmt()?;
+                let Some(case) = cases.last_mut() else {
+                    return Err(ParseError::new(
+                        "Statement before the first 'case'",
+                        stmt.span,
+                    ));
+                };
+                case.span = case.span.to(stmt.span);
+                case.body.push(stmt);
+            }
+        }
+        Ok(StmtKind::Switch { value, cases })
+    }
+
+    fn condition(&mut self) -> Parsed<Expr> {
+        self.expect("(")?;
+        let expr = self.expr()?;
+        self.expect(")")?;
+        Ok(expr)
+    }
+
+    fn expr_list(&mut self) -> Parsed<Vec<Expr>> {
+        let mut exprs = vec![self.expr()?];
+        while self.eat(",") {
+            exprs.push(self.expr()?);
+        }
+        Ok(exprs)
+    }
+
+    /// Call arguments after the opening `(`, up to and including the `)`.
+    fn arguments(&mut self) -> Parsed<Vec<Expr>> {
+        if self.eat(")") {
+            return Ok(Vec::new());
+        }
+        let args = self.expr_list()?;
+        self.expect(")")?;
+        Ok(args)
+    }
+
+    // Expressions
+
+    fn expr(&mut self) -> Parsed<Expr> {
+        let target = self.binary(1)?;
+        let Some(op) = AssignOp::from_token(self.peek_text()) else {
+            return Ok(target);
+        };
+        self.bump();
+        let value = self.expr()?;
+        Ok(Expr {
+            span: target.span.to(value.span),
+            kind: ExprKind::Assign {
+                op,
+                target: Box::new(target),
+                value: Box::new(value),
+            },
+        })
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Parsed<Expr> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match BinaryOp::from_token(self.peek_text()) {
+                Some(op) if op.precedence() >= min_precedence => op,
+                _ => return Ok(lhs),
+            };
+            self.bump();
+            let rhs = self.binary(op.precedence() + 1)?;
+            lhs = Expr {
+                span: lhs.span.to(rhs.span),
+                kind: ExprKind::Binary {
+                    op,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                },
+            };
+        }
+    }
+
+    fn unary(&mut self) -> Parsed<Expr> {
+        let start = self.current_span();
+        if let Some(op) = UnaryOp::from_token(self.peek_text()) {
+            self.bump();
+            let operand = self.unary()?;
+            return Ok(Expr {
+                span: start.to(operand.span),
+                kind: ExprKind::Unary {
+                    op,
+                    operand: Box::new(operand),
+                },
+            });
+        }
+        // Cast, e.g. `(fix32)x`
+        if self.at("(") && self.peek_at(2) == ")" {
+            if let Some(name) = TypeName::from_keyword(self.peek_at(1)) {
+                self.bump();
+                let ty = Type {
+                    name,
+                    span: self.bump().span,
+                };
+                self.bump();
+                let operand = self.unary()?;
+                return Ok(Expr {
+                    span: start.to(operand.span),
+                    kind: ExprKind::Cast {
+                        ty,
+                        operand: Box::new(operand),
+                    },
+                });
+            }
+        }
+        let mut expr = self.primary()?;
+        loop {
+            let kind = if self.eat("(") {
+                let ExprKind::Name(name) = &expr.kind else {
+                    return Err(ParseError::new(
+                        "Only named functions can be called",
+                        expr.span,
+                    ));
+                };
+                let callee = Ident {
+                    name: name.clone(),
+                    span: expr.span,
+                };
+                let args = self.arguments()?;
+                ExprKind::Call { callee, args }
+            } else if self.eat("[") {
+                let index = self.expr()?;
+                self.expect("]")?;
+                ExprKind::Index {
+                    base: Box::new(expr),
+                    index: Box::new(index),
+                }
+            } else if self.at("++") || self.at("--") {
+                let op = if self.bump().text(self.source) == "++" {
+                    PostfixOp::Inc
+                } else {
+                    PostfixOp::Dec
+                };
+                ExprKind::Postfix {
+                    op,
+                    operand: Box::new(expr),
+                }
+            } else {
+                return Ok(expr);
+            };
+            expr = Expr {
+                kind,
+                span: self.since(start),
+            };
+        }
+    }
+
+    fn primary(&mut self) -> Parsed<Expr> {
+        let Some(tok) = self.peek() else {
+            return Err(self.unexpected("an expression"));
+        };
+        let text = tok.text(self.source);
+        let kind = match tok.kind {
+            TokenKind::Number => number(text)
+                .ok_or_else(|| ParseError::new(format!("Invalid number '{}'", text), tok.span))?,
+            TokenKind::Str => ExprKind::Str(unescape(text)),
+            TokenKind::Char => {
+                let value = unescape(text);
+                let mut chars = value.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => ExprKind::Char(c),
+                    _ => {
+                        let message = format!("Invalid character literal {}", text);
+                        return Err(ParseError::new(message, tok.span));
+                    }
+                }
+            }
+            TokenKind::Ident if !is_keyword(text) => ExprKind::Name(text.to_string()),
+            _ if text == "(" => {
+                self.bump();
+                let inner = self.expr()?;
+                self.expect(")")?;
+                return Ok(Expr {
+                    kind: ExprKind::Paren(Box::new(inner)),
+                    span: self.since(tok.span),
+                });
+            }
+            _ => return Err(self.unexpected("an expression")),
+        };
+        self.bump();
+        Ok(Expr {
+            kind,
+            span: tok.span,
+        })
+    }
+}
+
+/// Value of a number literal. Integers are 32 bits; literals up to `0xFFFFFFFF`
+/// wrap to negative values as on the device.
+fn number(text: &str) -> Option<ExprKind> {
+    let lower = text.to_ascii_lowercase();
+    let value = if let Some(hex) = lower.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        u32::from_str_radix(bin, 2).ok()?
+    } else if text.contains('.') {
+        return text.parse().ok().map(ExprKind::Fixed);
+    } else {
+        text.parse::<u32>().ok()?
+    };
+    Some(ExprKind::Int(value as i32))
+}
+
+/// Contents of a string or char literal with its quotes removed and escapes resolved.
+fn unescape(literal: &str) -> String {
+    let mut chars = literal.chars();
+    let quote = chars.next();
+    let mut out = String::with_capacity(literal.len());
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('r') => out.push('\r'),
+                Some('0') => out.push('\0'),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            },
+            _ if Some(c) == quote => break,
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_ok(source: &str) -> File {
+        let file = parse(source);
+        assert!(file.errors.is_empty(), "{:?}", file.errors);
+        file
+    }
+
+    fn main_stmts(file: &File) -> &[Stmt] {
+        file.items
+            .iter()
+            .find_map(|item| match &item.kind {
+                ItemKind::Main(block) => Some(&block.stmts[..]),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_items() {
+        let source = r#"
+define FIRE = 40;
+data(20, 42);
+remap PS5_CROSS -> PS5_SQUARE;
+unmap PS5_TRIANGLE;
+enum Mode { MODE_A, MODE_B = 5, };
+int a, b = 2, c[10];
+const int8 TABLE[][2] = { { 1, -2 }, { 0x7F, 0 } };
+const string NAMES[] = { "one", "tw\"o" };
+
+init { a = 0; }
+
+main {
+    if (get_val(PS5_R2) > 50 && !b) combo_run(Fire); else a++;
+    for (int i = 0; i < 10; i++) c[i] <<= 1;
+    switch (a) { case 1: b = 1; break; default: b = (fix32)a * 1.5; }
+}
+
+combo Fire {
+    set_val(PS5_R2, 100);
+    wait(FIRE);
+}
+
+function add(x, int y) { return x + y; }
+"#;
+        let file = parse_ok(source);
+        let kinds: Vec<_> = file.items.iter().map(|i| i.span.text(source)).collect();
+        assert_eq!(kinds.len(), 12);
+        assert_eq!(kinds[0], "define FIRE = 40;");
+        assert_eq!(kinds[4], "enum Mode { MODE_A, MODE_B = 5, };");
+
+        let ItemKind::Var(table) = &file.items[6].kind else {
+            panic!()
+        };
+        assert!(table.constant);
+        assert_eq!(table.ty.as_ref().map(|t| t.name), Some(TypeName::Int8));
+        let declarator = &table.declarators[0];
+        assert_eq!(declarator.name.name, "TABLE");
+        assert_eq!(declarator.dims.len(), 2);
+        assert!(declarator.dims[0].is_none());
+        let Some(Initializer::List { items, .. }) = &declarator.init else {
+            panic!()
+        };
+        assert_eq!(items.len(), 2);
+
+        let ItemKind::Var(names) = &file.items[7].kind else {
+            panic!()
+        };
+        let Some(Initializer::List { items, .. }) = &names.declarators[0].init else {
+            panic!()
+        };
+        let Initializer::Expr(second) = &items[1] else {
+            panic!()
+        };
+        assert_eq!(second.kind, ExprKind::Str("tw\"o".to_string()));
+
+        let ItemKind::Remap(remap) = &file.items[2].kind else {
+            panic!()
+        };
+        assert_eq!(
+            remap.from.as_ref().map(|e| e.span.text(source)),
+            Some("PS5_CROSS")
+        );
+        let ItemKind::Remap(unmap) = &file.items[3].kind else {
+            panic!()
+        };
+        assert!(unmap.from.is_none());
+
+        let stmts = main_stmts(&file);
+        assert_eq!(stmts.len(), 3);
+        let StmtKind::Switch { cases, .. } = &stmts[2].kind else {
+            panic!()
+        };
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].body.len(), 2);
+        assert!(cases[1].value.is_none());
+
+        let combo = file.combos().next().unwrap();
+        assert_eq!(
+            (combo.name.name.as_str(), combo.body.stmts.len()),
+            ("Fire", 2)
+        );
+        let function = file.functions().next().unwrap();
+        assert_eq!(function.params.len(), 2);
+        assert_eq!(
+            function.params[1].ty.as_ref().map(|t| t.name),
+            Some(TypeName::Int)
+        );
+    }
+
+    #[test]
+    fn test_parse_expressions() {
+        let file = parse_ok("main { a = b = 1 + 2 * -3 << 1 == c[0]++ || !f(x, 0xFFFFFFFF); }");
+        let StmtKind::Expr(expr) = &main_stmts(&file)[0].kind else {
+            panic!()
+        };
+        let ExprKind::Assign { op, value, .. } = &expr.kind else {
+            panic!()
+        };
+        assert_eq!(*op, AssignOp(None));
+        // Assignment is right-associative
+        let ExprKind::Assign { value, .. } = &value.kind else {
+            panic!()
+        };
+        let ExprKind::Binary {
+            op: BinaryOp::Or,
+            lhs,
+            rhs,
+        } = &value.kind
+        else {
+            panic!()
+        };
+        let ExprKind::Binary {
+            op: BinaryOp::Eq,
+            lhs: shift,
+            rhs: index,
+        } = &lhs.kind
+        else {
+            panic!()
+        };
+        let ExprKind::Binary {
+            op: BinaryOp::Shl,
+            lhs: sum,
+            ..
+        } = &shift.kind
+        else {
+            panic!()
+        };
+        let ExprKind::Binary {
+            op: BinaryOp::Add,
+            rhs: product,
+            ..
+        } = &sum.kind
+        else {
+            panic!()
+        };
+        let ExprKind::Binary {
+            op: BinaryOp::Mul,
+            rhs: neg,
+            ..
+        } = &product.kind
+        else {
+            panic!()
+        };
+        assert!(matches!(
+            neg.kind,
+            ExprKind::Unary {
+                op: UnaryOp::Neg,
+                ..
+            }
+        ));
+        assert!(matches!(
+            index.kind,
+            ExprKind::Postfix {
+                op: PostfixOp::Inc,
+                ..
+            }
+        ));
+        let ExprKind::Unary { operand: call, .. } = &rhs.kind else {
+            panic!()
+        };
+        let ExprKind::Call { callee, args } = &call.kind else {
+            panic!()
+        };
+        assert_eq!(callee.name, "f");
+        assert_eq!(args[1].kind, ExprKind::Int(-1));
+
+        let file = parse_ok("main { x &= ~y; z = (fix32)(w) % 3; }");
+        let StmtKind::Expr(expr) = &main_stmts(&file)[0].kind else {
+            panic!()
+        };
+        let ExprKind::Assign { op, .. } = &expr.kind else {
+            panic!()
+        };
+        assert_eq!(*op, AssignOp(Some(BinaryOp::BitAnd)));
+        let StmtKind::Expr(expr) = &main_stmts(&file)[1].kind else {
+            panic!()
+        };
+        let ExprKind::Assign { value, .. } = &expr.kind else {
+            panic!()
+        };
+        let ExprKind::Binary {
+            op: BinaryOp::Rem,
+            lhs,
+            ..
+        } = &value.kind
+        else {
+            panic!()
+        };
+        assert!(matches!(
+            lhs.kind,
+            ExprKind::Cast {
+                ty: Type {
+                    name: TypeName::Fix32,
+                    ..
+                },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_parse_recovers_and_keeps_trivia() {
+        let source = "int a = ;\n// Fires\nmain {\n    a = 1\n    b = 2; // set b\n    c = 3;\n}\n";
+        let file = parse(source);
+        let messages: Vec<_> = file.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Expected an expression, found ';'",
+                "Expected ';' after '1'"
+            ]
+        );
+        // The broken statement is skipped, the rest of main still parses
+        let stmts = main_stmts(&file);
+        assert_eq!(stmts.len(), 1);
+        assert_eq!(stmts[0].span.text(source), "c = 3;");
+
+        let main = &file.items[0];
+        let leading: String = file
+            .leading_trivia(main.span)
+            .iter()
+            .map(|t| t.text(source))
+            .collect();
+        assert_eq!(leading, "\n// Fires\n");
+        let comments: Vec<_> = file
+            .tokens
+            .iter()
+            .filter(|t| t.kind == TokenKind::LineComment)
+            .map(|t| t.text(source))
+            .collect();
+        assert_eq!(comments, vec!["// Fires", "// set b"]);
+        assert_eq!(
+            file.tokens
+                .iter()
+                .map(|t| t.text(source))
+                .collect::<String>(),
+            source
+        );
+
+        let file = parse_ok("main {\n    a = 1; /* x */ // y\n    b = 2;\n}\n");
+        let stmt = &main_stmts(&file)[0];
+        let source = "main {\n    a = 1; /* x */ // y\n    b = 2;\n}\n";
+        let trailing: String = file
+            .trailing_trivia(stmt.span)
+            .iter()
+            .map(|t| t.text(source))
+            .collect();
+        assert_eq!(trailing, " /* x */ // y\n");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let errors = |source: &str| -> Vec<String> {
+            parse(source)
+                .errors
+                .into_iter()
+                .map(|e| e.message)
+                .collect()
+        };
+        assert_eq!(errors("main { f(1) }"), vec!["Expected ';' after ')'"]);
+        assert_eq!(
+            errors("function f(1) { }"),
+            vec!["Expected a parameter name, found '1'"]
+        );
+        assert_eq!(
+            errors("main { else a; }"),
+            vec!["'else' without a matching 'if'"]
+        );
+        assert_eq!(errors("main { a = 12ab; }"), vec!["Invalid number '12ab'"]);
+        // Broken literals and brackets hide the errors they cause further on
+        assert_eq!(errors("main { a = 1;"), vec!["'{' is never closed"]);
+        assert_eq!(
+            errors("main { printf(\"x); }"),
+            vec!["Unterminated string literal"]
+        );
+        let file = parse("main {\n    f(a];\n}\n");
+        assert_eq!(file.errors[0].message, "Mismatched ']': expected ')' to close '('");
+        assert_eq!(file.errors[0].related, Some((Span::new(12, 13), "'(' opened here".into())));
+        assert_eq!(
+            errors("else { }\nint ok;"),
+            vec!["Expected a declaration, 'init', 'main', 'combo' or 'function', found 'else'"]
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//! Operation counts of a running script, attributed to the call stack they ran in.
+//!
+//! Frames are `init`, `main`, combo steps, combos run with `call` and functions. Counts
+//! are kept for the whole run and for the current tick, and can be written as folded
+//! stacks (`main;fire;recoil 120`) for flamegraph tools.
+
+use std::rc::Rc;
+
+struct Node {
+    name: Rc<str>,
+    parent: usize,
+    children: Vec<usize>,
+    calls: u64,
+    /// Operations run in this frame itself, not in frames it called
+    ops: u64,
+    tick_ops: u64,
+}
+
+/// A call stack and the operations counted directly in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackOps {
+    /// Frame names from the outermost frame in
+    pub frames: Vec<Rc<str>>,
+    pub calls: u64,
+    pub ops: u64,
+    /// Operations in the last tick
+    pub tick_ops: u64,
+}
+
+pub struct CallTree {
+    /// Node 0 is the tick itself and has no name
+    nodes: Vec<Node>,
+    current: usize,
+}
+
+impl Default for CallTree {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node {
+                name: Rc::from(""),
+                parent: 0,
+                children: Vec::new(),
+                calls: 0,
+                ops: 0,
+                tick_ops: 0,
+            }],
+            current: 0,
+        }
+    }
+}
+
+impl CallTree {
+    pub(crate) fn start_tick(&mut self) {
+        for node in &mut self.nodes {
+            node.tick_ops = 0;
+        }
+        self.current = 0;
+    }
+
+    pub(crate) fn enter(&mut self, name: &str) {
+        let parent = self.current;
+        let child = self.nodes[parent]
+            .children
+            .iter()
+            .copied()
+            .find(|child| &*self.nodes[*child].name == name);
+        let child = child.unwrap_or_else(|| {
+            self.nodes.push(Node {
+                name: Rc::from(name),
+                parent,
+                children: Vec::new(),
+                calls: 0,
+                ops: 0,
+                tick_ops: 0,
+            });
+            let child = self.nodes.len() - 1;
+            self.nodes[parent].children.push(child);
+            child
+        });
+        self.nodes[child].calls += 1;
+        self.current = child;
+    }
+
+    pub(crate) fn exit(&mut self) {
+        self.current = self.nodes[self.current].parent;
+    }
+
+    pub(crate) fn count(&mut self) {
+        let node = &mut self.nodes[self.current];
+        node.ops += 1;
+        node.tick_ops += 1;
+    }
+
+    /// Every call stack seen so far, parents before their children.
+    pub fn stacks(&self) -> Vec<StackOps> {
+        let mut stacks: Vec<StackOps> = Vec::with_capacity(self.nodes.len());
+        // Index of each node's entry in `stacks`; the root has none
+        let mut entries = vec![usize::MAX; self.nodes.len()];
+        for (index, node) in self.nodes.iter().enumerate().skip(1) {
+            let mut frames = match entries[node.parent] {
+                usize::MAX => Vec::new(),
+                parent => stacks[parent].frames.clone(),
+            };
+            frames.push(Rc::clone(&node.name));
+            entries[index] = stacks.len();
+            stacks.push(StackOps {
+                frames,
+                calls: node.calls,
+                ops: node.ops,
+                tick_ops: node.tick_ops,
+            });
+        }
+        stacks
+    }
+
+    /// Folded stacks of the whole run, one `frame;frame ops` line per call stack that
+    /// ran operations itself.
+    pub fn folded(&self) -> String {
+        let mut out = String::new();
+        for stack in self.stacks().iter().filter(|s| s.ops > 0) {
+            out.push_str(&stack.frames.join(";"));
+            out.push_str(&format!(" {}\n", stack.ops));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_tree() {
+        let mut tree = CallTree::default();
+        tree.start_tick();
+        tree.enter("main");
+        tree.count();
+        tree.enter("fire");
+        tree.count();
+        tree.count();
+        tree.exit();
+        tree.enter("fire");
+        tree.count();
+        tree.exit();
+        tree.exit();
+        tree.enter("Rapid");
+        tree.count();
+        tree.exit();
+        assert_eq!(tree.folded(), "main 1\nmain;fire 3\nRapid 1\n");
+
+        tree.start_tick();
+        tree.enter("main");
+        tree.count();
+        tree.exit();
+        let stacks = tree.stacks();
+        let fire = &stacks[1];
+        assert_eq!(fire.frames.join(";"), "main;fire");
+        assert_eq!((fire.calls, fire.ops, fire.tick_ops), (2, 3, 0));
+        assert_eq!((stacks[0].ops, stacks[0].tick_ops), (2, 1));
+    }
+}
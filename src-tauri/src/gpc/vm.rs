@@ -0,0 +1,2110 @@
+//! Headless GPC virtual machine.
+//!
+//! Runs a script the way the device does: `init` once, then every VM tick `main`
+//! followed by each running combo, over a simulated controller. Inputs are given
+//! per tick; outputs are what `set_val`, `block`, remaps and combos leave behind.
+//!
+//! A combo runs until it reaches a `wait`, which may sit in a loop, a branch or a
+//! function the combo calls; the outputs it set are held until the wait time has
+//! passed and it continues from there on a later tick. `call` runs another combo to
+//! completion before the caller continues.
+//!
+//! `sensitivity` is approximated: the output is scaled linearly by the sensitivity
+//! percentage and the midpoint argument is ignored, so simulated stick curves can
+//! differ from the device's near the midpoint.
+
+use super::ast::*;
+use super::constants::{self, IO_COUNT};
+use super::oled::{Image, Oled};
+use super::profile::CallTree;
+use super::{parse, ParseError, Span};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// Length of a VM tick before any `vm_tctrl` call, in milliseconds.
+pub const DEFAULT_TICK_MS: i32 = 10;
+
+/// Operations one tick may execute, so a script stuck in a loop fails instead of hanging.
+pub const MAX_OPS_PER_TICK: u64 = 1_000_000;
+
+const MAX_CALL_DEPTH: usize = 64;
+
+/// Built-in functions the VM implements, with their argument counts.
+const BUILTINS: &[(&str, usize)] = &[
+    ("get_val", 1),
+    ("set_val", 2),
+    ("get_ival", 1),
+    ("get_lval", 1),
+    ("get_ptime", 1),
+    ("event_press", 1),
+    ("event_release", 1),
+    ("get_rtime", 0),
+    ("block", 2),
+    ("block_all_inputs", 0),
+    ("swap", 2),
+    ("sensitivity", 3),
+    ("abs", 1),
+    ("inv", 1),
+    ("min", 2),
+    ("max", 2),
+    ("clamp", 3),
+    ("pow", 2),
+    ("isqrt", 1),
+    ("random", 2),
+    ("get_pvar", 4),
+    ("set_pvar", 2),
+    ("combo_run", 1),
+    ("combo_stop", 1),
+    ("combo_running", 1),
+    ("combo_restart", 1),
+    ("combo_stop_all", 0),
+    ("wait", 1),
+    ("call", 1),
+    ("vm_tctrl", 1),
+    ("cls_oled", 1),
+    ("pixel_oled", 3),
+    ("line_oled", 6),
+    ("rect_oled", 6),
+    ("circle_oled", 5),
+    ("putc_oled", 2),
+    ("puts_oled", 5),
+    ("print", 5),
+    ("image_oled", 5),
+    ("duint8", 1),
+    ("dint8", 1),
+    ("duint16", 1),
+    ("dint16", 1),
+    ("dint32", 1),
+    // No keyboard is attached and LEDs and rumble have nothing to drive
+    ("get_keyboard", 1),
+    ("get_modifiers", 1),
+    ("set_rgb", 3),
+    ("set_hsb", 3),
+    ("set_led", 2),
+    ("reset_leds", 0),
+    ("set_rumble", 2),
+    ("block_rumble", 0),
+    ("reset_rumble", 0),
+];
+
+fn builtin_arity(name: &str) -> Option<usize> {
+    BUILTINS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, arity)| *arity)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuntimeError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl RuntimeError {
+    fn new(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+type Run<T> = Result<T, RuntimeError>;
+
+/// A variable or array; scalars have no dimensions.
+#[derive(Debug, Clone, PartialEq)]
+struct Var {
+    values: Vec<i32>,
+    dims: Vec<usize>,
+    ty: Option<TypeName>,
+    constant: bool,
+}
+
+impl Var {
+    fn scalar(value: i32) -> Self {
+        Self {
+            values: vec![value],
+            dims: Vec::new(),
+            ty: None,
+            constant: false,
+        }
+    }
+}
+
+/// Wrap a value to the range of the variable's element type.
+fn truncate(ty: Option<TypeName>, value: i32) -> i32 {
+    match ty {
+        Some(TypeName::Int8) => value as i8 as i32,
+        Some(TypeName::Uint8) => value as u8 as i32,
+        Some(TypeName::Int16) => value as i16 as i32,
+        Some(TypeName::Uint16) => value as u16 as i32,
+        _ => value,
+    }
+}
+
+/// A parsed and checked script, ready to run.
+#[derive(Debug, Clone)]
+pub struct Program {
+    init: Option<Block>,
+    main: Option<Block>,
+    functions: HashMap<String, Function>,
+    combos: Vec<Combo>,
+    combo_index: HashMap<String, usize>,
+    /// Defines and enum members with constant values
+    constants: HashMap<String, i32>,
+    /// Defines whose value is only known at run time
+    defines: HashMap<String, Expr>,
+    globals: HashMap<String, Var>,
+    remaps: Vec<(Option<Expr>, Expr)>,
+    strings: Vec<String>,
+    images: Vec<Image>,
+    data: Vec<u8>,
+}
+
+impl Program {
+    /// Parse `source` and check that the VM can run it.
+    pub fn load(source: &str) -> Result<Program, Vec<ParseError>> {
+        let file = parse(source);
+        if !file.errors.is_empty() {
+            return Err(file.errors);
+        }
+        let mut program = Program {
+            init: None,
+            main: None,
+            functions: HashMap::new(),
+            combos: Vec::new(),
+            combo_index: HashMap::new(),
+            constants: HashMap::new(),
+            defines: HashMap::new(),
+            globals: HashMap::new(),
+            remaps: Vec::new(),
+            strings: Vec::new(),
+            images: Vec::new(),
+            data: Vec::new(),
+        };
+        let mut errors = Vec::new();
+        let mut defines = Vec::new();
+        let mut decls = Vec::new();
+
+        for item in file.items {
+            match item.kind {
+                ItemKind::Define(define) => defines.push(define),
+                ItemKind::Enum(enumeration) => defines.extend(enum_defines(enumeration)),
+                ItemKind::Data(values) => decls.push(Err(values)),
+                ItemKind::Var(decl) => decls.push(Ok(decl)),
+                ItemKind::Remap(remap) => program.remaps.push((remap.from, remap.to)),
+                ItemKind::Init(block) => program.init = Some(block),
+                ItemKind::Main(block) => program.main = Some(block),
+                ItemKind::Combo(combo) => {
+                    program
+                        .combo_index
+                        .insert(combo.name.name.clone(), program.combos.len());
+                    program.combos.push(combo);
+                }
+                ItemKind::Function(function) => {
+                    program
+                        .functions
+                        .insert(function.name.name.clone(), function);
+                }
+            }
+        }
+
+        // Defines may refer to later ones; settle constant values until nothing changes
+        loop {
+            let before = program.constants.len();
+            for define in &defines {
+                if !program.constants.contains_key(&define.name.name) {
+                    if let Some(value) = const_eval(&define.value, &program.constants) {
+                        program.constants.insert(define.name.name.clone(), value);
+                    }
+                }
+            }
+            if program.constants.len() == before {
+                break;
+            }
+        }
+        for define in defines {
+            if !program.constants.contains_key(&define.name.name) {
+                program.defines.insert(define.name.name, define.value);
+            }
+        }
+
+        for decl in decls {
+            let result = match decl {
+                Ok(decl) => program.declare_global(&decl),
+                Err(values) => program.declare_data(&values),
+            };
+            if let Err(error) = result {
+                errors.push(error);
+            }
+        }
+
+        Checker {
+            program: &program,
+            errors: &mut errors,
+            locals: HashSet::new(),
+            suspendable: false,
+        }
+        .check();
+        if errors.is_empty() {
+            Ok(program)
+        } else {
+            errors.sort_by_key(|e| e.span.start);
+            Err(errors)
+        }
+    }
+
+    fn declare_global(&mut self, decl: &VarDecl) -> Result<(), ParseError> {
+        if let Some(ty) = decl.ty.as_ref().filter(|t| t.name == TypeName::Fix32) {
+            return Err(unsupported_fix32(ty.span));
+        }
+        let ty = decl.ty.as_ref().map(|t| t.name);
+        for declarator in &decl.declarators {
+            let var = match ty {
+                Some(TypeName::String) => self.string_array(declarator)?,
+                Some(TypeName::Image) => self.image(declarator)?,
+                _ => self.int_var(declarator, ty, decl.constant)?,
+            };
+            self.globals.insert(declarator.name.name.clone(), var);
+        }
+        Ok(())
+    }
+
+    fn int_var(
+        &self,
+        declarator: &Declarator,
+        ty: Option<TypeName>,
+        constant: bool,
+    ) -> Result<Var, ParseError> {
+        let mut leaves = Vec::new();
+        let dims = array_dims(declarator, &self.constants)?;
+        if let Some(init) = &declarator.init {
+            flatten(init, &dims, &mut leaves)?;
+        }
+        let size = dims.iter().product::<usize>();
+        if leaves.len() > size {
+            return Err(ParseError::new(
+                format!("Too many initializers for '{}'", declarator.name.name),
+                declarator.span,
+            ));
+        }
+        let mut values = Vec::with_capacity(size);
+        for leaf in leaves {
+            let value = leaf.map_or(Some(0), |expr| const_eval(expr, &self.constants));
+            let Some(value) = value else {
+                let span = leaf.map_or(declarator.span, |e| e.span);
+                return Err(ParseError::new("Initializer is not a constant", span));
+            };
+            values.push(truncate(ty, value));
+        }
+        values.resize(size, 0);
+        Ok(Var {
+            values,
+            dims,
+            ty,
+            constant,
+        })
+    }
+
+    fn string_array(&mut self, declarator: &Declarator) -> Result<Var, ParseError> {
+        let Some(Initializer::List { items, .. }) = &declarator.init else {
+            return Err(ParseError::new(
+                "Expected a list of strings",
+                declarator.span,
+            ));
+        };
+        let mut values = Vec::new();
+        for item in items {
+            let Initializer::Expr(Expr {
+                kind: ExprKind::Str(text),
+                ..
+            }) = item
+            else {
+                return Err(ParseError::new("Expected a string", item.span()));
+            };
+            values.push(self.strings.len() as i32);
+            self.strings.push(text.clone());
+        }
+        Ok(Var {
+            dims: vec![values.len()],
+            values,
+            ty: Some(TypeName::String),
+            constant: true,
+        })
+    }
+
+    /// `const image name = { width, height, bits... };`, referred to as `name[0]`.
+    fn image(&mut self, declarator: &Declarator) -> Result<Var, ParseError> {
+        let mut leaves = Vec::new();
+        if let Some(init) = &declarator.init {
+            flatten(init, &[usize::MAX], &mut leaves)?;
+        }
+        let mut values = Vec::new();
+        for leaf in leaves.into_iter().flatten() {
+            let value = const_eval(leaf, &self.constants)
+                .ok_or_else(|| ParseError::new("Image data is not a constant", leaf.span))?;
+            values.push(value);
+        }
+        if values.len() < 2 || values[0] < 0 || values[1] < 0 {
+            return Err(ParseError::new(
+                "An image starts with its width and height",
+                declarator.span,
+            ));
+        }
+        let handle = self.images.len() as i32;
+        self.images.push(Image {
+            width: values[0] as usize,
+            height: values[1] as usize,
+            bits: values[2..].iter().map(|v| *v as u8).collect(),
+        });
+        Ok(Var {
+            values: vec![handle],
+            dims: vec![1],
+            ty: Some(TypeName::Image),
+            constant: true,
+        })
+    }
+
+    fn declare_data(&mut self, values: &[Expr]) -> Result<(), ParseError> {
+        for value in values {
+            match &value.kind {
+                ExprKind::Str(text) => self.data.extend(text.bytes()),
+                _ => {
+                    let byte = const_eval(value, &self.constants)
+                        .ok_or_else(|| ParseError::new("Data is not a constant", value.span))?;
+                    self.data.push(byte as u8);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn combo_names(&self) -> impl Iterator<Item = &str> {
+        self.combos.iter().map(|c| c.name.name.as_str())
+    }
+}
+
+fn unsupported_fix32(span: Span) -> ParseError {
+    ParseError::new("fix32 values are not supported by the simulator", span)
+}
+
+/// Enum members as defines counting up from the previous value.
+fn enum_defines(enumeration: Enum) -> Vec<Define> {
+    let mut next: Option<Expr> = None;
+    let mut offset = 0;
+    let mut defines = Vec::new();
+    for member in enumeration.members {
+        let span = member.name.span;
+        let value = match member.value {
+            Some(value) => {
+                next = Some(value.clone());
+                offset = 0;
+                value
+            }
+            None => match &next {
+                Some(base) => Expr {
+                    kind: ExprKind::Binary {
+                        op: BinaryOp::Add,
+                        lhs: Box::new(base.clone()),
+                        rhs: Box::new(Expr {
+                            kind: ExprKind::Int(offset),
+                            span,
+                        }),
+                    },
+                    span,
+                },
+                None => Expr {
+                    kind: ExprKind::Int(offset),
+                    span,
+                },
+            },
+        };
+        offset += 1;
+        defines.push(Define {
+            name: member.name,
+            value,
+        });
+    }
+    defines
+}
+
+/// Array sizes, with an empty `[]` taking its size from the initializer.
+fn array_dims(
+    declarator: &Declarator,
+    constants: &HashMap<String, i32>,
+) -> Result<Vec<usize>, ParseError> {
+    let mut dims = Vec::new();
+    for (i, dim) in declarator.dims.iter().enumerate() {
+        let size = match dim {
+            Some(expr) => const_eval(expr, constants)
+                .filter(|size| *size > 0)
+                .ok_or_else(|| {
+                    ParseError::new("Array size must be a positive constant", expr.span)
+                })? as usize,
+            None => match (i, &declarator.init) {
+                (0, Some(Initializer::List { items, .. })) => items.len(),
+                _ => {
+                    let message = "Only the first array size can be left out";
+                    return Err(ParseError::new(message, declarator.span));
+                }
+            },
+        };
+        dims.push(size);
+    }
+    Ok(dims)
+}
+
+/// Initializer leaves in storage order; `None` pads a short nested list.
+fn flatten<'a>(
+    init: &'a Initializer,
+    dims: &[usize],
+    out: &mut Vec<Option<&'a Expr>>,
+) -> Result<(), ParseError> {
+    match init {
+        Initializer::Expr(expr) => {
+            out.push(Some(expr));
+            Ok(())
+        }
+        Initializer::List { span, .. } if dims.is_empty() => Err(ParseError::new(
+            "A list cannot initialize a single value",
+            *span,
+        )),
+        Initializer::List { items, span } => {
+            let row: usize = dims[1..].iter().product();
+            let start = out.len();
+            for item in items {
+                match item {
+                    Initializer::List { .. } => {
+                        let item_start = out.len();
+                        flatten(item, &dims[1..], out)?;
+                        if out.len() < item_start + row {
+                            out.resize(item_start + row, None);
+                        }
+                    }
+                    Initializer::Expr(expr) => out.push(Some(expr)),
+                }
+            }
+            if dims[0] != usize::MAX && out.len() - start > dims[0].saturating_mul(row) {
+                return Err(ParseError::new("Too many initializers", *span));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Value of an expression made only of literals and known constants.
+fn const_eval(expr: &Expr, constants: &HashMap<String, i32>) -> Option<i32> {
+    match &expr.kind {
+        ExprKind::Int(value) => Some(*value),
+        ExprKind::Char(c) => Some(*c as i32),
+        ExprKind::Name(name) => constants
+            .get(name)
+            .copied()
+            .or_else(|| constants::constant(name)),
+        ExprKind::Paren(inner) => const_eval(inner, constants),
+        ExprKind::Unary { op, operand } => {
+            let value = const_eval(operand, constants)?;
+            match op {
+                UnaryOp::Neg => Some(value.wrapping_neg()),
+                UnaryOp::Plus => Some(value),
+                UnaryOp::Not => Some((value == 0) as i32),
+                UnaryOp::BitNot => Some(!value),
+                UnaryOp::PreInc | UnaryOp::PreDec => None,
+            }
+        }
+        ExprKind::Binary { op, lhs, rhs } => binary(
+            *op,
+            const_eval(lhs, constants)?,
+            const_eval(rhs, constants)?,
+        )
+        .ok(),
+        _ => None,
+    }
+}
+
+/// 32-bit wrapping arithmetic as on the device.
+fn binary(op: BinaryOp, a: i32, b: i32) -> Result<i32, &'static str> {
+    Ok(match op {
+        BinaryOp::Or => (a != 0 || b != 0) as i32,
+        BinaryOp::And => (a != 0 && b != 0) as i32,
+        BinaryOp::BitOr => a | b,
+        BinaryOp::BitXor => a ^ b,
+        BinaryOp::BitAnd => a & b,
+        BinaryOp::Eq => (a == b) as i32,
+        BinaryOp::Ne => (a != b) as i32,
+        BinaryOp::Lt => (a < b) as i32,
+        BinaryOp::Le => (a <= b) as i32,
+        BinaryOp::Gt => (a > b) as i32,
+        BinaryOp::Ge => (a >= b) as i32,
+        BinaryOp::Shl => a.wrapping_shl(b as u32),
+        BinaryOp::Shr => a.wrapping_shr(b as u32),
+        BinaryOp::Add => a.wrapping_add(b),
+        BinaryOp::Sub => a.wrapping_sub(b),
+        BinaryOp::Mul => a.wrapping_mul(b),
+        BinaryOp::Div if b == 0 => return Err("Division by zero"),
+        BinaryOp::Rem if b == 0 => return Err("Modulo by zero"),
+        BinaryOp::Div => a.wrapping_div(b),
+        BinaryOp::Rem => a.wrapping_rem(b),
+    })
+}
+
+/// Built-ins that suspend a combo: `wait(ms)` and `call(combo)`.
+fn is_suspension(name: &str) -> bool {
+    matches!(name, "wait" | "call")
+}
+
+/// Load-time checks: every name and function resolves, `wait` and `call` are statements
+/// of their own outside `init` and `main`, and nothing uses fix32.
+struct Checker<'a> {
+    program: &'a Program,
+    errors: &'a mut Vec<ParseError>,
+    locals: HashSet<String>,
+    /// Whether the body being checked may suspend, i.e. is a combo or function
+    suspendable: bool,
+}
+
+impl Checker<'_> {
+    fn check(&mut self) {
+        let program = self.program;
+        for block in program.init.iter().chain(&program.main) {
+            self.body(&[], block, false);
+        }
+        for function in program.functions.values() {
+            self.body(&function.params, &function.body, true);
+        }
+        for combo in &program.combos {
+            self.body(&[], &combo.body, true);
+        }
+        for expr in program.defines.values() {
+            self.locals.clear();
+            self.expr(expr);
+        }
+        for (from, to) in &program.remaps {
+            self.locals.clear();
+            for expr in from.iter().chain([to]) {
+                self.expr(expr);
+            }
+        }
+    }
+
+    fn body(&mut self, params: &[Param], block: &Block, suspendable: bool) {
+        self.locals = params.iter().map(|p| p.name.name.clone()).collect();
+        self.suspendable = suspendable;
+        for param in params {
+            if let Some(ty) = param.ty.as_ref().filter(|t| t.name == TypeName::Fix32) {
+                self.errors.push(unsupported_fix32(ty.span));
+            }
+        }
+        block.stmts.iter().for_each(|s| self.stmt(s));
+    }
+
+    /// A `wait` or `call` statement.
+    fn suspension(&mut self, expr: &Expr, callee: &Ident, args: &[Expr]) {
+        if !self.suspendable {
+            let message = format!("'{}' can only be used in combos and functions", callee.name);
+            self.errors.push(ParseError::new(message, callee.span));
+        } else if args.len() != 1 {
+            self.arity(&callee.name, 1, args.len(), expr.span);
+        } else if callee.name == "call" {
+            self.combo_arg(&args[0]);
+        } else {
+            self.expr(&args[0]);
+        }
+    }
+
+    fn combo_arg(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Name(name) if self.program.combo_index.contains_key(name) => {}
+            _ => self
+                .errors
+                .push(ParseError::new("Expected a combo name", expr.span)),
+        }
+    }
+
+    fn arity(&mut self, name: &str, expected: usize, found: usize, span: Span) {
+        let message = format!("'{}' takes {} arguments, found {}", name, expected, found);
+        self.errors.push(ParseError::new(message, span));
+    }
+
+    fn decl(&mut self, decl: &VarDecl) {
+        if let Some(ty) = decl.ty.as_ref().filter(|t| t.name == TypeName::Fix32) {
+            self.errors.push(unsupported_fix32(ty.span));
+        }
+        for declarator in &decl.declarators {
+            self.locals.insert(declarator.name.name.clone());
+            for dim in declarator.dims.iter().flatten() {
+                self.expr(dim);
+            }
+            let mut leaves = Vec::new();
+            if let Some(init) = &declarator.init {
+                if flatten(init, &[usize::MAX], &mut leaves).is_err() {
+                    self.errors
+                        .push(ParseError::new("Invalid initializer", init.span()));
+                }
+            }
+            for leaf in leaves.into_iter().flatten() {
+                self.expr(leaf);
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Block(block) => block.stmts.iter().for_each(|s| self.stmt(s)),
+            StmtKind::Empty | StmtKind::Break | StmtKind::Continue => {}
+            StmtKind::Expr(expr) => match &expr.kind {
+                ExprKind::Call { callee, args } if is_suspension(&callee.name) => {
+                    self.suspension(expr, callee, args)
+                }
+                _ => self.expr(expr),
+            },
+            StmtKind::Var(decl) => self.decl(decl),
+            StmtKind::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                self.expr(cond);
+                self.stmt(then);
+                if let Some(otherwise) = otherwise {
+                    self.stmt(otherwise);
+                }
+            }
+            StmtKind::While { cond, body } | StmtKind::DoWhile { body, cond } => {
+                self.expr(cond);
+                self.stmt(body);
+            }
+            StmtKind::For {
+                init,
+                cond,
+                step,
+                body,
+            } => {
+                match init {
+                    Some(ForInit::Var(decl)) => self.decl(decl),
+                    Some(ForInit::Exprs(exprs)) => exprs.iter().for_each(|e| self.expr(e)),
+                    None => {}
+                }
+                cond.iter().for_each(|e| self.expr(e));
+                step.iter().for_each(|e| self.expr(e));
+                self.stmt(body);
+            }
+            StmtKind::Switch { value, cases } => {
+                self.expr(value);
+                for case in cases {
+                    case.value.iter().for_each(|e| self.expr(e));
+                    case.body.iter().for_each(|s| self.stmt(s));
+                }
+            }
+            StmtKind::Return(value) => value.iter().for_each(|e| self.expr(e)),
+            StmtKind::Remap(remap) => {
+                remap.from.iter().for_each(|e| self.expr(e));
+                self.expr(&remap.to);
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Int(_) | ExprKind::Char(_) => {}
+            ExprKind::Fixed(_) => self.errors.push(unsupported_fix32(expr.span)),
+            ExprKind::Str(_) => self.errors.push(ParseError::new(
+                "Strings can only initialize const string arrays",
+                expr.span,
+            )),
+            ExprKind::Name(name) => {
+                let program = self.program;
+                let known = self.locals.contains(name)
+                    || program.globals.contains_key(name)
+                    || program.constants.contains_key(name)
+                    || program.defines.contains_key(name)
+                    || program.combo_index.contains_key(name)
+                    || constants::constant(name).is_some();
+                if !known {
+                    let message = format!("Unknown name '{}'", name);
+                    self.errors.push(ParseError::new(message, expr.span));
+                }
+            }
+            ExprKind::Unary { operand, .. }
+            | ExprKind::Postfix { operand, .. }
+            | ExprKind::Cast { operand, .. } => self.expr(operand),
+            ExprKind::Paren(inner) => self.expr(inner),
+            ExprKind::Binary { lhs, rhs, .. } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            ExprKind::Assign { target, value, .. } => {
+                self.expr(target);
+                self.expr(value);
+            }
+            ExprKind::Index { base, index } => {
+                self.expr(base);
+                self.expr(index);
+            }
+            ExprKind::Call { callee, args } => {
+                let name = callee.name.as_str();
+                let expected = match self.program.functions.get(name) {
+                    Some(function) => Some(function.params.len()),
+                    None => builtin_arity(name),
+                };
+                match expected {
+                    None => {
+                        let message = format!("Unknown function '{}'", name);
+                        self.errors.push(ParseError::new(message, callee.span));
+                    }
+                    Some(_) if is_suspension(name) => {
+                        let message = format!("'{}' can only be a statement of its own", name);
+                        self.errors.push(ParseError::new(message, expr.span));
+                    }
+                    Some(expected) if expected != args.len() => {
+                        self.arity(name, expected, args.len(), expr.span)
+                    }
+                    Some(_) if name.starts_with("combo_") && !args.is_empty() => {
+                        self.combo_arg(&args[0])
+                    }
+                    Some(_) => args.iter().for_each(|a| self.expr(a)),
+                }
+            }
+        }
+    }
+}
+
+/// Where a suspended combo continues: the statement indexes from the combo body down
+/// to the `wait`, and the locals of the combo and of the functions and called combos
+/// on the way, both innermost first.
+#[derive(Debug, Clone, Default)]
+struct Resume {
+    path: Vec<usize>,
+    frames: Vec<HashMap<String, Var>>,
+}
+
+#[derive(Debug, Clone)]
+struct ComboRun {
+    /// `None` until the combo first waits
+    resume: Option<Resume>,
+    wait_left: i32,
+    /// Outputs set by the current step, held while it waits
+    held: Vec<(usize, i32)>,
+    /// Changes when the combo is restarted, so a step in progress can tell
+    generation: u64,
+}
+
+enum Flow {
+    Next,
+    Break,
+    Continue,
+    Return(i32),
+    /// A `wait` was reached and the combo gives up the rest of the tick
+    Suspend,
+}
+
+/// A running script and the controller it is attached to.
+pub struct Vm {
+    program: Rc<Program>,
+    globals: HashMap<String, Var>,
+    /// Local variables of the running function, combo step, `init` or `main`
+    frames: Vec<HashMap<String, Var>>,
+    input: [i32; IO_COUNT],
+    prev_input: [i32; IO_COUNT],
+    output: [i32; IO_COUNT],
+    press_time: [i32; IO_COUNT],
+    /// Inputs that changed state on the last tick
+    state_changed: [bool; IO_COUNT],
+    /// Time each output stays blocked by `block`, while its input is held
+    blocked: [i32; IO_COUNT],
+    /// Output each identifier's value is sent to; `None` when unmapped
+    remaps: [Option<usize>; IO_COUNT],
+    combos: Vec<Option<ComboRun>>,
+    generation: u64,
+    /// Combo whose step is running
+    combo: Option<usize>,
+    /// Whether a `wait` may suspend here: in a combo, outside expressions
+    suspendable: bool,
+    /// Set while a combo fast-forwards to where it waited
+    resuming: Option<Resume>,
+    /// Wait time and resume point collected while a suspended step unwinds
+    suspension: Option<(i32, Resume)>,
+    pvars: HashMap<i32, i32>,
+    oled: Oled,
+    tick_ms: i32,
+    /// Length of the current tick, returned by `get_rtime`
+    rtime: i32,
+    time_ms: u64,
+    ticks: u64,
+    ops: u64,
+    total_ops: u64,
+    /// Operations per call stack, when profiling
+    profile: Option<CallTree>,
+    rng: u32,
+}
+
+impl Vm {
+    pub fn new(program: Program) -> Self {
+        let mut remaps = [None; IO_COUNT];
+        for (i, remap) in remaps.iter_mut().enumerate() {
+            *remap = Some(i);
+        }
+        Self {
+            globals: program.globals.clone(),
+            frames: Vec::new(),
+            input: [0; IO_COUNT],
+            prev_input: [0; IO_COUNT],
+            output: [0; IO_COUNT],
+            press_time: [0; IO_COUNT],
+            state_changed: [false; IO_COUNT],
+            blocked: [0; IO_COUNT],
+            remaps,
+            combos: vec![None; program.combos.len()],
+            generation: 0,
+            combo: None,
+            suspendable: false,
+            resuming: None,
+            suspension: None,
+            pvars: HashMap::new(),
+            oled: Oled::default(),
+            tick_ms: DEFAULT_TICK_MS,
+            rtime: DEFAULT_TICK_MS,
+            time_ms: 0,
+            ticks: 0,
+            ops: 0,
+            total_ops: 0,
+            profile: None,
+            rng: 1,
+            program: Rc::new(program),
+        }
+    }
+
+    /// Load `source` and attach it to an idle controller.
+    pub fn load(source: &str) -> Result<Vm, Vec<ParseError>> {
+        Program::load(source).map(Vm::new)
+    }
+
+    /// Run one VM tick with the given controller inputs and return the outputs.
+    ///
+    /// `input` is indexed by identifier (e.g. `PS5_R2` is 4); missing entries are 0.
+    pub fn tick(&mut self, input: &[i32]) -> Run<&[i32; IO_COUNT]> {
+        let program = Rc::clone(&self.program);
+        self.ops = 0;
+        if let Some(profile) = &mut self.profile {
+            profile.start_tick();
+        }
+        self.rtime = self.tick_ms;
+        let tick = self.tick_ms;
+
+        self.prev_input = self.input;
+        for (io, value) in self.input.iter_mut().enumerate() {
+            *value = input.get(io).copied().unwrap_or(0);
+        }
+        // On the tick an input changes, get_ptime still reports how long the previous
+        // state lasted, so `event_release(X) && get_ptime(X) < 200` detects a tap
+        for io in 0..IO_COUNT {
+            let changed = (self.input[io] != 0) != (self.prev_input[io] != 0);
+            if self.ticks == 0 {
+                self.press_time[io] = 0;
+            } else if self.state_changed[io] {
+                self.press_time[io] = tick;
+            } else {
+                self.press_time[io] = self.press_time[io].saturating_add(tick);
+            }
+            self.state_changed[io] = changed;
+        }
+        self.output = self.input;
+
+        if self.ticks == 0 {
+            for (from, to) in &program.remaps {
+                self.remap(from.as_ref(), to)?;
+            }
+            if let Some(init) = &program.init {
+                self.profile_enter("init");
+                let result = self.run_block(init);
+                self.profile_exit();
+                result?;
+            }
+        }
+        if let Some(main) = &program.main {
+            self.profile_enter("main");
+            let result = self.run_block(main);
+            self.profile_exit();
+            result?;
+        }
+        for index in 0..self.combos.len() {
+            if self.combos[index].is_some() {
+                self.run_combo(index)?;
+            }
+        }
+
+        for io in 0..IO_COUNT {
+            if self.blocked[io] > 0 {
+                if self.input[io] == 0 {
+                    self.blocked[io] = 0;
+                } else {
+                    self.output[io] = 0;
+                    self.blocked[io] -= tick;
+                }
+            }
+        }
+        let mut remapped = [0i32; IO_COUNT];
+        for (io, target) in self.remaps.iter().enumerate() {
+            if let Some(target) = target {
+                // A button sent to an output that is also pressed keeps the stronger value
+                if self.output[io].abs() > remapped[*target].abs() {
+                    remapped[*target] = self.output[io];
+                }
+            }
+        }
+        self.output = remapped;
+
+        self.time_ms += tick as u64;
+        self.ticks += 1;
+        Ok(&self.output)
+    }
+
+    /// Outputs of the last tick.
+    pub fn output(&self) -> &[i32; IO_COUNT] {
+        &self.output
+    }
+
+    pub fn oled(&self) -> &Oled {
+        &self.oled
+    }
+
+    /// Current value of a global variable, or all elements of a global array.
+    pub fn variable(&self, name: &str) -> Option<&[i32]> {
+        self.globals.get(name).map(|var| &var.values[..])
+    }
+
+    pub fn combo_names(&self) -> impl Iterator<Item = &str> {
+        self.program.combo_names()
+    }
+
+    pub fn combo_running(&self, name: &str) -> bool {
+        self.program
+            .combo_index
+            .get(name)
+            .is_some_and(|index| self.combos[*index].is_some())
+    }
+
+    pub fn pvar(&self, slot: i32) -> Option<i32> {
+        self.pvars.get(&slot).copied()
+    }
+
+    /// Preset a persistent variable, e.g. a saved SPVAR, before the first tick.
+    pub fn set_pvar(&mut self, slot: i32, value: i32) {
+        self.pvars.insert(slot, value);
+    }
+
+    /// Milliseconds simulated so far.
+    pub fn time_ms(&self) -> u64 {
+        self.time_ms
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Length of the next tick, as set by `vm_tctrl`.
+    pub fn tick_ms(&self) -> i32 {
+        self.tick_ms
+    }
+
+    /// Operations executed by the last tick.
+    pub fn tick_ops(&self) -> u64 {
+        self.ops
+    }
+
+    pub fn total_ops(&self) -> u64 {
+        self.total_ops
+    }
+
+    /// Attribute operations to the function, combo or block they run in from now on.
+    pub fn enable_profiling(&mut self) {
+        self.profile.get_or_insert_with(CallTree::default);
+    }
+
+    /// Operations per call stack since profiling was enabled.
+    pub fn profile(&self) -> Option<&CallTree> {
+        self.profile.as_ref()
+    }
+
+    fn profile_enter(&mut self, name: &str) {
+        if let Some(profile) = &mut self.profile {
+            profile.enter(name);
+        }
+    }
+
+    fn profile_exit(&mut self) {
+        if let Some(profile) = &mut self.profile {
+            profile.exit();
+        }
+    }
+
+    fn count(&mut self, span: Span) -> Run<()> {
+        self.ops += 1;
+        self.total_ops += 1;
+        if let Some(profile) = &mut self.profile {
+            profile.count();
+        }
+        if self.ops > MAX_OPS_PER_TICK {
+            let message = format!(
+                "Tick did not finish within {} operations; is a loop endless?",
+                MAX_OPS_PER_TICK
+            );
+            return Err(RuntimeError::new(message, span));
+        }
+        Ok(())
+    }
+
+    fn run_block(&mut self, block: &Block) -> Run<()> {
+        self.frames.push(HashMap::new());
+        let result = self.exec_stmts(&block.stmts);
+        self.frames.pop();
+        result.map(|_| ())
+    }
+
+    // Combos
+
+    /// Run a combo's current step, or hold its outputs while the step waits.
+    fn run_combo(&mut self, index: usize) -> Run<()> {
+        let program = Rc::clone(&self.program);
+        let Some(run) = &mut self.combos[index] else {
+            return Ok(());
+        };
+        if run.resume.is_some() && run.wait_left > 0 {
+            run.wait_left -= self.rtime;
+            for (io, value) in &run.held {
+                self.output[*io] = *value;
+            }
+            return Ok(());
+        }
+        let generation = run.generation;
+        run.held.clear();
+        let mut frame = HashMap::new();
+        if let Some(mut resume) = run.resume.take() {
+            frame = resume.frames.pop().unwrap_or_default();
+            self.resuming = Some(resume);
+        }
+
+        self.frames.push(frame);
+        self.combo = Some(index);
+        self.suspendable = true;
+        self.profile_enter(&program.combos[index].name.name);
+        let result = self.exec_stmts(&program.combos[index].body.stmts);
+        self.profile_exit();
+        self.combo = None;
+        self.suspendable = false;
+        self.resuming = None;
+        let frame = self.frames.pop().unwrap_or_default();
+        let suspension = self.suspension.take();
+        let flow = result?;
+
+        // The step may have stopped or restarted its own combo
+        let Some(run) = self.combos[index].as_mut() else {
+            return Ok(());
+        };
+        if run.generation != generation {
+            return Ok(());
+        }
+        match (flow, suspension) {
+            (Flow::Suspend, Some((wait, mut resume))) => {
+                resume.frames.push(frame);
+                run.resume = Some(resume);
+                run.wait_left = wait.saturating_sub(self.rtime);
+            }
+            _ => self.combos[index] = None,
+        }
+        Ok(())
+    }
+
+    fn start_combo(&mut self, index: usize) {
+        self.generation += 1;
+        self.combos[index] = Some(ComboRun {
+            resume: None,
+            wait_left: 0,
+            held: Vec::new(),
+            generation: self.generation,
+        });
+    }
+
+    /// Next index on the way down to a suspended `wait`, while resuming.
+    fn resume_index(&mut self) -> Option<usize> {
+        self.resuming.as_mut().and_then(|resume| resume.path.pop())
+    }
+
+    /// Record the index of the statement a suspension passes through.
+    fn suspended_at(&mut self, index: usize) {
+        if let Some((_, resume)) = &mut self.suspension {
+            resume.path.push(index);
+        }
+    }
+
+    /// `wait(ms)` or `call(combo)` as a statement.
+    fn suspend(&mut self, callee: &Ident, args: &[Expr], span: Span) -> Run<Flow> {
+        if !self.suspendable {
+            let message = format!(
+                "'{}' can only run in a combo or a function called from one as a statement",
+                callee.name
+            );
+            return Err(RuntimeError::new(message, span));
+        }
+        if callee.name == "call" {
+            let program = Rc::clone(&self.program);
+            let index = if self.resuming.is_some() {
+                self.resume_index().unwrap_or_default()
+            } else {
+                let value = self.eval(&args[0])?;
+                self.combo(value, args[0].span)?
+            };
+            self.profile_enter(&program.combos[index].name.name);
+            let flow = self.enter(HashMap::new(), &program.combos[index].body.stmts, span);
+            self.profile_exit();
+            let flow = flow?;
+            if let Flow::Suspend = flow {
+                self.suspended_at(index);
+                return Ok(Flow::Suspend);
+            }
+            return Ok(Flow::Next);
+        }
+        let wait = self.eval(&args[0])?;
+        self.suspension = Some((wait, Resume::default()));
+        Ok(Flow::Suspend)
+    }
+
+    /// Run a function or called combo body in its own frame, resuming inside it if it
+    /// was suspended there.
+    fn enter(&mut self, frame: HashMap<String, Var>, stmts: &[Stmt], span: Span) -> Run<Flow> {
+        if self.frames.len() > MAX_CALL_DEPTH {
+            let message = format!("Calls nested deeper than {} levels", MAX_CALL_DEPTH);
+            return Err(RuntimeError::new(message, span));
+        }
+        let frame = match &mut self.resuming {
+            Some(resume) => resume.frames.pop().unwrap_or_default(),
+            None => frame,
+        };
+        self.frames.push(frame);
+        let result = self.exec_stmts(stmts);
+        let frame = self.frames.pop().unwrap_or_default();
+        let flow = result?;
+        if let (Flow::Suspend, Some((_, resume))) = (&flow, &mut self.suspension) {
+            resume.frames.push(frame);
+        }
+        Ok(flow)
+    }
+
+    // Statements
+
+    fn exec_stmts(&mut self, stmts: &[Stmt]) -> Run<Flow> {
+        let start = self.resume_index().unwrap_or(0);
+        for (i, stmt) in stmts.iter().enumerate().skip(start) {
+            let flow = self.exec(stmt)?;
+            if let Flow::Suspend = flow {
+                self.suspended_at(i);
+            }
+            if !matches!(flow, Flow::Next) {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn exec(&mut self, stmt: &Stmt) -> Run<Flow> {
+        // Resuming reached the `wait` the combo was suspended at, which is now over
+        if self.resuming.as_ref().is_some_and(|r| r.path.is_empty()) {
+            self.resuming = None;
+            return Ok(Flow::Next);
+        }
+        self.count(stmt.span)?;
+        match &stmt.kind {
+            StmtKind::Block(block) => self.exec_stmts(&block.stmts),
+            StmtKind::Empty => Ok(Flow::Next),
+            StmtKind::Expr(expr) => match &expr.kind {
+                ExprKind::Call { callee, args } if is_suspension(&callee.name) => {
+                    self.suspend(callee, args, expr.span)
+                }
+                ExprKind::Call { callee, args }
+                    if self.program.functions.contains_key(&callee.name) =>
+                {
+                    self.call_function(callee, args, expr.span, true)
+                }
+                _ => self.eval(expr).map(|_| Flow::Next),
+            },
+            StmtKind::Var(decl) => {
+                self.declare_local(decl)?;
+                Ok(Flow::Next)
+            }
+            StmtKind::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let branch = match self.resume_index() {
+                    Some(branch) => branch,
+                    None => (self.eval(cond)? == 0) as usize,
+                };
+                let flow = match (branch, otherwise) {
+                    (0, _) => self.exec(then)?,
+                    (_, Some(otherwise)) => self.exec(otherwise)?,
+                    _ => Flow::Next,
+                };
+                if let Flow::Suspend = flow {
+                    self.suspended_at(branch);
+                }
+                Ok(flow)
+            }
+            StmtKind::While { cond, body } => {
+                let mut resuming = self.resuming.is_some();
+                loop {
+                    if !resuming && self.eval(cond)? == 0 {
+                        break;
+                    }
+                    resuming = false;
+                    match self.exec(body)? {
+                        Flow::Break => break,
+                        Flow::Next | Flow::Continue => {}
+                        flow => return Ok(flow),
+                    }
+                }
+                Ok(Flow::Next)
+            }
+            StmtKind::DoWhile { body, cond } => {
+                loop {
+                    match self.exec(body)? {
+                        Flow::Break => break,
+                        Flow::Next | Flow::Continue => {}
+                        flow => return Ok(flow),
+                    }
+                    if self.eval(cond)? == 0 {
+                        break;
+                    }
+                }
+                Ok(Flow::Next)
+            }
+            StmtKind::For {
+                init,
+                cond,
+                step,
+                body,
+            } => {
+                let mut resuming = self.resuming.is_some();
+                match init {
+                    _ if resuming => {}
+                    Some(ForInit::Var(decl)) => self.declare_local(decl)?,
+                    Some(ForInit::Exprs(exprs)) => {
+                        for expr in exprs {
+                            self.eval(expr)?;
+                        }
+                    }
+                    None => {}
+                }
+                loop {
+                    if let (false, Some(cond)) = (resuming, cond) {
+                        if self.eval(cond)? == 0 {
+                            break;
+                        }
+                    }
+                    resuming = false;
+                    match self.exec(body)? {
+                        Flow::Break => break,
+                        Flow::Next | Flow::Continue => {}
+                        flow => return Ok(flow),
+                    }
+                    for expr in step {
+                        self.eval(expr)?;
+                    }
+                }
+                Ok(Flow::Next)
+            }
+            StmtKind::Switch { value, cases } => {
+                let start = match self.resume_index() {
+                    Some(case) => Some(case),
+                    None => {
+                        let value = self.eval(value)?;
+                        let mut start = None;
+                        for (i, case) in cases.iter().enumerate() {
+                            if let Some(label) = &case.value {
+                                if self.eval(label)? == value {
+                                    start = Some(i);
+                                    break;
+                                }
+                            }
+                        }
+                        start.or_else(|| cases.iter().position(|c| c.value.is_none()))
+                    }
+                };
+                // Cases fall through to the next one until a `break`
+                for (i, case) in cases.iter().enumerate().skip(start.unwrap_or(cases.len())) {
+                    match self.exec_stmts(&case.body)? {
+                        Flow::Break => break,
+                        Flow::Next => {}
+                        Flow::Suspend => {
+                            self.suspended_at(i);
+                            return Ok(Flow::Suspend);
+                        }
+                        flow => return Ok(flow),
+                    }
+                }
+                Ok(Flow::Next)
+            }
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.eval(value)?,
+                    None => 0,
+                };
+                Ok(Flow::Return(value))
+            }
+            StmtKind::Break => Ok(Flow::Break),
+            StmtKind::Continue => Ok(Flow::Continue),
+            StmtKind::Remap(remap) => {
+                self.remap(remap.from.as_ref(), &remap.to)?;
+                Ok(Flow::Next)
+            }
+        }
+    }
+
+    fn remap(&mut self, from: Option<&Expr>, to: &Expr) -> Run<()> {
+        let target = self.io(to)?;
+        match from {
+            Some(from) => {
+                let source = self.io(from)?;
+                self.remaps[source] = Some(target);
+            }
+            None => self.remaps[target] = None,
+        }
+        Ok(())
+    }
+
+    fn declare_local(&mut self, decl: &VarDecl) -> Run<()> {
+        let ty = decl.ty.as_ref().map(|t| t.name);
+        for declarator in &decl.declarators {
+            let mut dims = Vec::new();
+            for dim in &declarator.dims {
+                let size = match dim {
+                    Some(expr) => self.eval(expr)?,
+                    None => match &declarator.init {
+                        Some(Initializer::List { items, .. }) => items.len() as i32,
+                        _ => 0,
+                    },
+                };
+                if size <= 0 {
+                    return Err(RuntimeError::new(
+                        "Array size must be positive",
+                        declarator.span,
+                    ));
+                }
+                dims.push(size as usize);
+            }
+            let mut leaves = Vec::new();
+            if let Some(init) = &declarator.init {
+                flatten(init, &dims, &mut leaves)
+                    .map_err(|e| RuntimeError::new(e.message, e.span))?;
+            }
+            let mut values = Vec::new();
+            for leaf in leaves {
+                values.push(match leaf {
+                    Some(expr) => truncate(ty, self.eval(expr)?),
+                    None => 0,
+                });
+            }
+            values.resize(dims.iter().product::<usize>().max(values.len()), 0);
+            let var = Var {
+                values,
+                dims,
+                ty,
+                constant: decl.constant,
+            };
+            if let Some(frame) = self.frames.last_mut() {
+                frame.insert(declarator.name.name.clone(), var);
+            }
+        }
+        Ok(())
+    }
+
+    // Expressions
+
+    fn var(&self, name: &str) -> Option<&Var> {
+        self.frames
+            .last()
+            .and_then(|frame| frame.get(name))
+            .or_else(|| self.globals.get(name))
+    }
+
+    fn var_mut(&mut self, name: &str) -> Option<&mut Var> {
+        match self.frames.last_mut() {
+            Some(frame) if frame.contains_key(name) => frame.get_mut(name),
+            _ => self.globals.get_mut(name),
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Run<i32> {
+        self.count(expr.span)?;
+        match &expr.kind {
+            ExprKind::Int(value) => Ok(*value),
+            ExprKind::Char(c) => Ok(*c as i32),
+            ExprKind::Fixed(_) | ExprKind::Str(_) => {
+                Err(RuntimeError::new("Unsupported value", expr.span))
+            }
+            ExprKind::Name(name) => self.name(name, expr.span),
+            ExprKind::Paren(inner) => self.eval(inner),
+            ExprKind::Cast { operand, .. } => self.eval(operand),
+            ExprKind::Unary { op, operand } => match op {
+                UnaryOp::Neg => Ok(self.eval(operand)?.wrapping_neg()),
+                UnaryOp::Plus => self.eval(operand),
+                UnaryOp::Not => Ok((self.eval(operand)? == 0) as i32),
+                UnaryOp::BitNot => Ok(!self.eval(operand)?),
+                UnaryOp::PreInc | UnaryOp::PreDec => {
+                    let delta = if *op == UnaryOp::PreInc { 1 } else { -1 };
+                    let (name, index) = self.place(operand)?;
+                    let value = self.read(&name, index, operand.span)?.wrapping_add(delta);
+                    self.store(&name, index, value, operand.span)
+                }
+            },
+            ExprKind::Postfix { op, operand } => {
+                let delta = if *op == PostfixOp::Inc { 1 } else { -1 };
+                let (name, index) = self.place(operand)?;
+                let old = self.read(&name, index, operand.span)?;
+                self.store(&name, index, old.wrapping_add(delta), operand.span)?;
+                Ok(old)
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                let a = self.eval(lhs)?;
+                // Logical operators only evaluate their right side when needed
+                match op {
+                    BinaryOp::And if a == 0 => return Ok(0),
+                    BinaryOp::Or if a != 0 => return Ok(1),
+                    _ => {}
+                }
+                let b = self.eval(rhs)?;
+                binary(*op, a, b).map_err(|message| RuntimeError::new(message, expr.span))
+            }
+            ExprKind::Assign { op, target, value } => {
+                let (name, index) = self.place(target)?;
+                let mut value = self.eval(value)?;
+                if let AssignOp(Some(op)) = op {
+                    let old = self.read(&name, index, target.span)?;
+                    value = binary(*op, old, value)
+                        .map_err(|message| RuntimeError::new(message, expr.span))?;
+                }
+                self.store(&name, index, value, target.span)
+            }
+            ExprKind::Index { .. } => {
+                let (name, index) = self.place(expr)?;
+                self.read(&name, index, expr.span)
+            }
+            ExprKind::Call { callee, args } => self.call(callee, args, expr.span),
+        }
+    }
+
+    fn name(&mut self, name: &str, span: Span) -> Run<i32> {
+        if let Some(var) = self.var(name) {
+            return Ok(var.values.first().copied().unwrap_or(0));
+        }
+        let program = Rc::clone(&self.program);
+        if let Some(value) = program.constants.get(name) {
+            return Ok(*value);
+        }
+        if let Some(expr) = program.defines.get(name) {
+            return self.eval(expr);
+        }
+        if let Some(index) = program.combo_index.get(name) {
+            return Ok(*index as i32);
+        }
+        constants::constant(name)
+            .ok_or_else(|| RuntimeError::new(format!("Unknown name '{}'", name), span))
+    }
+
+    /// Variable and element index an assignable expression refers to.
+    fn place(&mut self, expr: &Expr) -> Run<(String, usize)> {
+        let mut indices = Vec::new();
+        let mut base = expr;
+        while let ExprKind::Index { base: inner, index } = &base.kind {
+            indices.push(self.eval(index)?);
+            base = inner;
+        }
+        indices.reverse();
+        let ExprKind::Name(name) = &base.kind else {
+            return Err(RuntimeError::new("Expected a variable", expr.span));
+        };
+        let Some(var) = self.var(name) else {
+            let message = format!("'{}' is not a variable", name);
+            return Err(RuntimeError::new(message, expr.span));
+        };
+        if indices.len() != var.dims.len() && !(indices.is_empty() && var.dims.is_empty()) {
+            let message = format!(
+                "'{}' has {} dimensions, indexed with {}",
+                name,
+                var.dims.len(),
+                indices.len()
+            );
+            return Err(RuntimeError::new(message, expr.span));
+        }
+        let mut flat = 0usize;
+        for (index, size) in indices.iter().zip(&var.dims) {
+            if *index < 0 || *index as usize >= *size {
+                let message = format!(
+                    "Index {} out of bounds for '{}' of size {}",
+                    index, name, size
+                );
+                return Err(RuntimeError::new(message, expr.span));
+            }
+            flat = flat * size + *index as usize;
+        }
+        Ok((name.clone(), flat))
+    }
+
+    fn read(&self, name: &str, index: usize, span: Span) -> Run<i32> {
+        self.var(name)
+            .and_then(|var| var.values.get(index).copied())
+            .ok_or_else(|| RuntimeError::new(format!("'{}' is not a variable", name), span))
+    }
+
+    fn store(&mut self, name: &str, index: usize, value: i32, span: Span) -> Run<i32> {
+        let Some(var) = self.var_mut(name) else {
+            return Err(RuntimeError::new(
+                format!("'{}' is not a variable", name),
+                span,
+            ));
+        };
+        if var.constant {
+            let message = format!("Cannot assign to constant '{}'", name);
+            return Err(RuntimeError::new(message, span));
+        }
+        let value = truncate(var.ty, value);
+        var.values[index] = value;
+        Ok(value)
+    }
+
+    /// Call a user function; as a statement it may suspend the combo it runs in.
+    fn call_function(
+        &mut self,
+        callee: &Ident,
+        args: &[Expr],
+        span: Span,
+        statement: bool,
+    ) -> Run<Flow> {
+        let program = Rc::clone(&self.program);
+        let function = &program.functions[&callee.name];
+        let mut frame = HashMap::new();
+        if self.resuming.is_none() {
+            for (param, arg) in function.params.iter().zip(args) {
+                frame.insert(param.name.name.clone(), Var::scalar(self.eval(arg)?));
+            }
+        }
+        let suspendable = self.suspendable;
+        self.suspendable = suspendable && statement;
+        self.profile_enter(&callee.name);
+        let result = self.enter(frame, &function.body.stmts, span);
+        self.profile_exit();
+        self.suspendable = suspendable;
+        Ok(match result? {
+            Flow::Return(value) if !statement => Flow::Return(value),
+            Flow::Suspend => Flow::Suspend,
+            _ if statement => Flow::Next,
+            _ => Flow::Return(0),
+        })
+    }
+
+    fn call(&mut self, callee: &Ident, args: &[Expr], span: Span) -> Run<i32> {
+        if self.program.functions.contains_key(&callee.name) {
+            return match self.call_function(callee, args, span, false)? {
+                Flow::Return(value) => Ok(value),
+                _ => Ok(0),
+            };
+        }
+
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+            values.push(self.eval(arg)?);
+        }
+        self.builtin(&callee.name, &values, args, span)
+    }
+
+    fn io(&mut self, expr: &Expr) -> Run<usize> {
+        let value = self.eval(expr)?;
+        usize::try_from(value)
+            .ok()
+            .filter(|io| *io < IO_COUNT)
+            .ok_or_else(|| {
+                let message = format!("{} is not a controller identifier", value);
+                RuntimeError::new(message, expr.span)
+            })
+    }
+
+    fn io_value(&self, value: i32, span: Span) -> Run<usize> {
+        usize::try_from(value)
+            .ok()
+            .filter(|io| *io < IO_COUNT)
+            .ok_or_else(|| {
+                let message = format!("{} is not a controller identifier", value);
+                RuntimeError::new(message, span)
+            })
+    }
+
+    fn combo(&self, value: i32, span: Span) -> Run<usize> {
+        usize::try_from(value)
+            .ok()
+            .filter(|index| *index < self.combos.len())
+            .ok_or_else(|| RuntimeError::new(format!("{} is not a combo", value), span))
+    }
+
+    fn data(&self, offset: i32, width: usize, span: Span) -> Run<u32> {
+        let data = &self.program.data;
+        let start = usize::try_from(offset)
+            .ok()
+            .filter(|s| s + width <= data.len());
+        let Some(start) = start else {
+            let message = format!("Data offset {} out of bounds", offset);
+            return Err(RuntimeError::new(message, span));
+        };
+        let mut bytes = [0u8; 4];
+        bytes[..width].copy_from_slice(&data[start..start + width]);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn builtin(&mut self, name: &str, v: &[i32], args: &[Expr], span: Span) -> Run<i32> {
+        let arg_span = |i: usize| args.get(i).map_or(span, |a| a.span);
+        let result = match name {
+            "get_val" => self.output[self.io_value(v[0], arg_span(0))?],
+            "get_ival" => self.input[self.io_value(v[0], arg_span(0))?],
+            "get_lval" => self.prev_input[self.io_value(v[0], arg_span(0))?],
+            "get_ptime" => self.press_time[self.io_value(v[0], arg_span(0))?],
+            "set_val" => {
+                let io = self.io_value(v[0], arg_span(0))?;
+                let value = v[1].clamp(-100, 100);
+                self.output[io] = value;
+                if let Some(Some(run)) = self.combo.map(|index| &mut self.combos[index]) {
+                    run.held.retain(|(held, _)| *held != io);
+                    run.held.push((io, value));
+                }
+                0
+            }
+            "event_press" => {
+                let io = self.io_value(v[0], arg_span(0))?;
+                (self.input[io] != 0 && self.prev_input[io] == 0) as i32
+            }
+            "event_release" => {
+                let io = self.io_value(v[0], arg_span(0))?;
+                (self.input[io] == 0 && self.prev_input[io] != 0) as i32
+            }
+            "get_rtime" => self.rtime,
+            "block" => {
+                let io = self.io_value(v[0], arg_span(0))?;
+                if self.input[io] != 0 && self.blocked[io] <= 0 {
+                    self.blocked[io] = v[1];
+                }
+                0
+            }
+            "block_all_inputs" => {
+                self.output = [0; IO_COUNT];
+                0
+            }
+            "swap" => {
+                let a = self.io_value(v[0], arg_span(0))?;
+                let b = self.io_value(v[1], arg_span(1))?;
+                self.output.swap(a, b);
+                0
+            }
+            "sensitivity" => {
+                let io = self.io_value(v[0], arg_span(0))?;
+                // Linear scaling only; the midpoint in v[1] is not modelled
+                let scaled = self.output[io] as i64 * v[2] as i64 / 100;
+                self.output[io] = scaled.clamp(-100, 100) as i32;
+                0
+            }
+            "abs" => v[0].wrapping_abs(),
+            "inv" => v[0].wrapping_neg(),
+            "min" => v[0].min(v[1]),
+            "max" => v[0].max(v[1]),
+            "clamp" => v[0].max(v[1]).min(v[2]),
+            "pow" => v[0].wrapping_pow(v[1].max(0) as u32),
+            "isqrt" => (v[0].max(0) as f64).sqrt() as i32,
+            "random" => {
+                self.rng = self.rng.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let range = (v[1] as i64 - v[0] as i64 + 1).max(1);
+                (v[0] as i64 + (self.rng >> 8) as i64 % range) as i32
+            }
+            "get_pvar" => match self.pvars.get(&v[0]) {
+                Some(value) if (v[1]..=v[2]).contains(value) => *value,
+                _ => v[3],
+            },
+            "set_pvar" => {
+                self.pvars.insert(v[0], v[1]);
+                0
+            }
+            "combo_run" => {
+                let index = self.combo(v[0], arg_span(0))?;
+                if self.combos[index].is_none() {
+                    self.start_combo(index);
+                }
+                0
+            }
+            "combo_restart" => {
+                let index = self.combo(v[0], arg_span(0))?;
+                self.start_combo(index);
+                0
+            }
+            "combo_stop" => {
+                let index = self.combo(v[0], arg_span(0))?;
+                self.combos[index] = None;
+                0
+            }
+            "combo_running" => self.combos[self.combo(v[0], arg_span(0))?].is_some() as i32,
+            "combo_stop_all" => {
+                self.combos.iter_mut().for_each(|c| *c = None);
+                0
+            }
+            "vm_tctrl" => {
+                self.tick_ms = DEFAULT_TICK_MS.saturating_add(v[0]).max(1);
+                0
+            }
+            "cls_oled" => {
+                self.oled.cls(v[0]);
+                0
+            }
+            "pixel_oled" => {
+                self.oled.set_pixel(v[0], v[1], v[2]);
+                0
+            }
+            "line_oled" => {
+                self.oled.line(v[0], v[1], v[2], v[3], v[4], v[5]);
+                0
+            }
+            "rect_oled" => {
+                self.oled.rect(v[0], v[1], v[2], v[3], v[4] != 0, v[5]);
+                0
+            }
+            "circle_oled" => {
+                self.oled.circle(v[0], v[1], v[2], v[3] != 0, v[4]);
+                0
+            }
+            "putc_oled" => {
+                self.oled.putc(v[0], v[1]);
+                0
+            }
+            "puts_oled" => {
+                self.oled.puts(v[0], v[1], v[2], v[3], v[4]);
+                0
+            }
+            "print" => {
+                let program = Rc::clone(&self.program);
+                let text = usize::try_from(v[4])
+                    .ok()
+                    .and_then(|i| program.strings.get(i));
+                let Some(text) = text else {
+                    return Err(RuntimeError::new("Expected a string", arg_span(4)));
+                };
+                self.oled.print(v[0], v[1], v[2], v[3], text);
+                0
+            }
+            "image_oled" => {
+                let program = Rc::clone(&self.program);
+                let image = usize::try_from(v[4])
+                    .ok()
+                    .and_then(|i| program.images.get(i));
+                let Some(image) = image else {
+                    return Err(RuntimeError::new("Expected an image", arg_span(4)));
+                };
+                self.oled.image(v[0], v[1], v[2] != 0, v[3] != 0, image);
+                0
+            }
+            "duint8" => self.data(v[0], 1, span)? as i32,
+            "dint8" => self.data(v[0], 1, span)? as u8 as i8 as i32,
+            "duint16" => self.data(v[0], 2, span)? as i32,
+            "dint16" => self.data(v[0], 2, span)? as u16 as i16 as i32,
+            "dint32" => self.data(v[0], 4, span)? as i32,
+            "get_keyboard" | "get_modifiers" | "set_rgb" | "set_hsb" | "set_led" | "reset_leds"
+            | "set_rumble" | "block_rumble" | "reset_rumble" => 0,
+            _ => {
+                let message = format!("'{}' is not supported by the simulator", name);
+                return Err(RuntimeError::new(message, span));
+            }
+        };
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::test_support::write_test_game;
+
+    const R2: usize = 4;
+    const CROSS: usize = 19;
+
+    fn input(pressed: &[(usize, i32)]) -> Vec<i32> {
+        let mut values = vec![0; IO_COUNT];
+        for (io, value) in pressed {
+            values[*io] = *value;
+        }
+        values
+    }
+
+    fn load(source: &str) -> Vm {
+        Vm::load(source).unwrap_or_else(|errors| panic!("{:?}", errors))
+    }
+
+    #[test]
+    fn test_main_and_controller() {
+        let mut vm = load(
+            r#"
+define BOOST = 20;
+int presses, held, last;
+init { held = -1; }
+main {
+    if (event_press(PS5_CROSS)) presses++;
+    if (event_release(PS5_CROSS)) last = get_ptime(PS5_CROSS);
+    held = get_ptime(PS5_CROSS);
+    set_val(PS5_R2, get_val(PS5_R2) + BOOST);
+    swap(PS5_L1, PS5_R1);
+}
+"#,
+        );
+        let out = vm.tick(&input(&[(R2, 50)])).unwrap();
+        assert_eq!(out[R2], 70);
+        assert_eq!(vm.tick(&input(&[(R2, 95), (3, 100)])).unwrap()[R2], 100);
+        assert_eq!((vm.output()[3], vm.output()[6]), (0, 100));
+
+        for _ in 0..3 {
+            vm.tick(&input(&[(CROSS, 100)])).unwrap();
+        }
+        vm.tick(&input(&[])).unwrap();
+        assert_eq!(vm.variable("presses"), Some(&[1][..]));
+        // Held for three 10 ms ticks before the release
+        assert_eq!(vm.variable("last"), Some(&[30][..]));
+        vm.tick(&input(&[])).unwrap();
+        assert_eq!(vm.variable("held"), Some(&[10][..]));
+        assert_eq!(vm.time_ms(), 70);
+    }
+
+    #[test]
+    fn test_combos_wait_and_call() {
+        let mut vm = load(
+            r#"
+int fired, tail;
+main {
+    if (get_val(PS5_R2)) combo_run(Burst);
+}
+combo Burst {
+    set_val(PS5_CROSS, 100);
+    wait(30);
+    fired++;
+    call(Tail);
+    set_val(PS5_CROSS, 50);
+    wait(10);
+}
+combo Tail {
+    tail++;
+    wait(20);
+}
+"#,
+        );
+        let mut cross = Vec::new();
+        for tick in 0..10 {
+            let r2 = if tick == 0 { 100 } else { 0 };
+            let out = vm.tick(&input(&[(R2, r2)])).unwrap();
+            cross.push(out[CROSS]);
+        }
+        // Step one holds for 30 ms, Tail waits 20 ms, then the last step for 10 ms
+        assert_eq!(cross, vec![100, 100, 100, 0, 0, 50, 0, 0, 0, 0]);
+        // Each step runs once; its outputs are held while it waits
+        assert_eq!(vm.variable("fired"), Some(&[1][..]));
+        assert_eq!(vm.variable("tail"), Some(&[1][..]));
+        assert!(!vm.combo_running("Burst"));
+
+        // combo_run leaves a running combo alone; combo_stop ends it
+        vm.tick(&input(&[(R2, 100)])).unwrap();
+        assert!(vm.combo_running("Burst"));
+        vm.tick(&input(&[(R2, 100)])).unwrap();
+        vm.tick(&input(&[(R2, 100)])).unwrap();
+        assert_eq!(vm.tick(&input(&[(R2, 100)])).unwrap()[CROSS], 0);
+        assert_eq!(vm.variable("fired"), Some(&[2][..]));
+    }
+
+    #[test]
+    fn test_wait_in_functions_and_loops() {
+        let mut vm = load(
+            r#"
+int pulses;
+function tap(button, delay) {
+    set_val(button, 100);
+    if (delay) { wait(delay); }
+    set_val(button, 0);
+}
+init { combo_run(Pulse); }
+main { }
+combo Pulse {
+    for (pulses = 0; pulses < 2; pulses++) {
+        tap(PS5_CROSS, 20);
+        wait(10);
+    }
+}
+"#,
+        );
+        let cross: Vec<i32> = (0..7).map(|_| vm.tick(&[]).unwrap()[CROSS]).collect();
+        assert_eq!(cross, vec![100, 100, 0, 100, 100, 0, 0]);
+        assert_eq!(vm.variable("pulses"), Some(&[2][..]));
+        assert!(!vm.combo_running("Pulse"));
+
+        let mut vm = load("function pause() { wait(10); }\nmain { pause(); }");
+        assert_eq!(
+            vm.tick(&[]).unwrap_err().message,
+            "'wait' can only run in a combo or a function called from one as a statement"
+        );
+    }
+
+    #[test]
+    fn test_functions_arrays_and_spvars() {
+        let mut vm = load(
+            r#"
+enum { LOW, MID = 5, HIGH };
+const int8 TABLE[][2] = { { 1, -2 }, { 3 } };
+uint8 wrap;
+int total, slots[3], fact, saved;
+function factorial(n) {
+    if (n <= 1) return 1;
+    return n * factorial(n - 1);
+}
+init {
+    saved = get_pvar(SPVAR_1, 0, 100, 42);
+    set_pvar(SPVAR_2, HIGH);
+}
+main {
+    int i;
+    for (i = 0; i < 3; i++) {
+        slots[i] = TABLE[i % 2][1] + i;
+        switch (i) {
+            case 0: total += 1;
+            case 1: total += 10; break;
+            default: total += 100;
+        }
+    }
+    wrap = 250 + MID;
+    fact = factorial(5);
+}
+"#,
+        );
+        vm.set_pvar(128, 7);
+        vm.tick(&[]).unwrap();
+        assert_eq!(vm.variable("slots"), Some(&[-2, 1, 0][..]));
+        assert_eq!(vm.variable("total"), Some(&[121][..]));
+        assert_eq!(vm.variable("wrap"), Some(&[255][..]));
+        assert_eq!(vm.variable("fact"), Some(&[120][..]));
+        assert_eq!(vm.variable("saved"), Some(&[7][..]));
+        assert_eq!(vm.pvar(129), Some(6));
+    }
+
+    #[test]
+    fn test_oled_and_timing() {
+        let mut vm = load(
+            r#"
+const string LABELS[] = { "Rapid fire", "Off" };
+const image DOT = { 2, 1, 0xC0 };
+int elapsed;
+init { vm_tctrl(-6); }
+main {
+    elapsed += get_rtime();
+    cls_oled(OLED_BLACK);
+    rect_oled(0, 0, 10, 10, 1, OLED_WHITE);
+    image_oled(20, 0, TRUE, FALSE, DOT[0]);
+    print(2, 20, OLED_FONT_SMALL, OLED_WHITE, LABELS[elapsed > 10]);
+}
+"#,
+        );
+        vm.tick(&[]).unwrap();
+        assert_eq!(vm.oled().text_content(), "Rapid fire");
+        assert!(vm.oled().pixel(9, 9) && vm.oled().pixel(21, 0) && !vm.oled().pixel(22, 0));
+        // vm_tctrl in init takes effect from the next tick
+        vm.tick(&[]).unwrap();
+        vm.tick(&[]).unwrap();
+        assert_eq!(vm.variable("elapsed"), Some(&[18][..]));
+        assert_eq!(vm.oled().text_content(), "Off");
+        assert_eq!(vm.time_ms(), 18);
+
+        let mut vm = load("init { vm_tctrl(2147483647); }\nmain { }\n");
+        vm.tick(&[]).unwrap();
+        assert_eq!(vm.tick_ms(), i32::MAX);
+    }
+
+    #[test]
+    fn test_remap_and_block() {
+        let mut vm = load(concat!(
+            "remap PS5_CROSS -> PS5_R2;\nunmap PS5_L1;\n",
+            "main { if (event_press(PS5_SQUARE)) block(PS5_SQUARE, 20); }\n",
+        ));
+        let out = vm
+            .tick(&input(&[(CROSS, 100), (6, 100), (20, 100)]))
+            .unwrap();
+        assert_eq!((out[R2], out[CROSS], out[6], out[20]), (100, 0, 0, 0));
+        vm.tick(&input(&[(20, 100)])).unwrap();
+        assert_eq!(vm.tick(&input(&[(20, 100)])).unwrap()[20], 100);
+    }
+
+    #[test]
+    fn test_runs_built_game() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = write_test_game(
+            dir.path(),
+            "import common/rapid;\nmain {\n    if (get_val(PS5_R2)) combo_run(Rapid);\n}\n",
+        );
+        std::fs::create_dir_all(game_dir.join("common")).unwrap();
+        std::fs::write(
+            game_dir.join("common/rapid.gpc"),
+            concat!(
+                "define RATE = 20;\ncombo Rapid {\n    set_val(PS5_R2, 100);\n    wait(RATE);\n",
+                "    set_val(PS5_R2, 0);\n    wait(RATE);\n}\n",
+            ),
+        )
+        .unwrap();
+
+        let result = crate::pipeline::build::build_game(&game_dir, dir.path(), dir.path(), false);
+        assert!(result.success, "{:?}", result.errors);
+        let output = std::fs::read_to_string(&result.output_path).unwrap();
+        let mut vm = load(&output);
+        let r2: Vec<i32> = (0..8)
+            .map(|_| vm.tick(&input(&[(R2, 100)])).unwrap()[R2])
+            .collect();
+        // The held trigger passes through on the tick the combo ends, before main restarts it
+        assert_eq!(r2, vec![100, 100, 0, 0, 100, 100, 100, 0]);
+    }
+
+    #[test]
+    fn test_load_and_runtime_errors() {
+        let messages = |source: &str| -> Vec<String> {
+            match Vm::load(source) {
+                Ok(_) => Vec::new(),
+                Err(errors) => errors.into_iter().map(|e| e.message).collect(),
+            }
+        };
+        assert_eq!(
+            messages(
+                "main { nope(1); x = 1; set_val(PS5_R2); wait(10); }\ncombo C { x = call(C); }"
+            ),
+            vec![
+                "Unknown function 'nope'",
+                "Unknown name 'x'",
+                "'set_val' takes 2 arguments, found 1",
+                "'wait' can only be used in combos and functions",
+                "Unknown name 'x'",
+                "'call' can only be a statement of its own",
+            ]
+        );
+        assert_eq!(
+            messages("fix32 f;\nmain { }"),
+            vec!["fix32 values are not supported by the simulator"]
+        );
+        assert_eq!(
+            messages("main { int a = 1 }"),
+            vec!["Expected ';' after '1'"]
+        );
+
+        let mut vm = load("int a[2], i;\nmain { while (1) { } }");
+        let error = vm.tick(&[]).unwrap_err();
+        assert!(error.message.starts_with("Tick did not finish"));
+        let mut vm = load("int a[2], i;\nmain { i = 2; a[i] = 1; }");
+        assert_eq!(
+            vm.tick(&[]).unwrap_err().message,
+            "Index 2 out of bounds for 'a' of size 2"
+        );
+        let mut vm = load("const int A[] = { 1 };\nmain { A[0] = 2 / (A[0] - 1); }");
+        assert_eq!(vm.tick(&[]).unwrap_err().message, "Division by zero");
+    }
+}
@@ -1,4 +1,6 @@
+pub mod cli;
 mod commands;
+pub mod gpc;
 mod lsp;
 mod models;
 mod pipeline;
@@ -9,8 +11,10 @@ pub fn run() {
         .manage(commands::lsp::LspState::default())
         .manage(commands::watcher::WatcherState::default())
         .manage(commands::watcher::WorkspaceWatcherState::default())
+        .manage(commands::watcher::AutoBuildState::default())
         .manage(commands::runner::RunnerState::default())
         .manage(commands::server::FileServerState::default())
+        .manage(commands::build::BuildQueueState::default())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_opener::init())
         .setup(|app| {
@@ -55,7 +59,18 @@ pub fn run() {
             commands::module::import_module_toml,
             commands::wizard::create_game,
             commands::build::build_game_cmd,
+            commands::build::build_game_targets_cmd,
+            commands::build::release_game_cmd,
+            commands::build::run_script_tests_cmd,
+            commands::build::replay_trace_cmd,
+            commands::build::save_input_trace_cmd,
+            commands::build::profile_script_cmd,
+            commands::build::start_build_queue,
+            commands::build::cancel_build_job,
+            commands::build::cancel_build_queue,
             commands::build::get_build_output_path,
+            commands::build::resolve_build_location,
+            commands::build::preview_macro_expansion_cmd,
             commands::config::save_game_config,
             commands::config::read_file,
             commands::config::write_file,
@@ -71,6 +86,9 @@ pub fn run() {
             commands::watcher::watch_directory,
             commands::watcher::unwatch_directory,
             commands::watcher::watch_workspaces,
+            commands::watcher::start_auto_build,
+            commands::watcher::stop_auto_build,
+            commands::watcher::list_auto_builds,
             commands::workspace::pick_workspace_directory,
             commands::workspace::get_default_workspace,
             commands::templates::list_templates,
@@ -93,6 +111,13 @@ pub fn run() {
             commands::history::rollback_snapshot,
             commands::history::delete_snapshot,
             commands::history::rename_snapshot,
+            commands::artifacts::list_artifacts,
+            commands::artifacts::read_artifact,
+            commands::artifacts::diff_artifacts,
+            commands::artifacts::restore_artifact,
+            commands::artifacts::export_artifact,
+            commands::artifacts::label_artifact,
+            commands::artifacts::delete_artifact,
             commands::obfuscate::obfuscate_gpc,
             commands::flow::save_flow_project,
             commands::flow::load_flow_project,
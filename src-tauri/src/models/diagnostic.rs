@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+
+/// Diagnostic codes emitted by the build pipeline.
+pub mod codes {
+    /// Imported or included file does not exist
+    pub const IMPORT_NOT_FOUND: &str = "P001";
+    /// Source file exists but could not be read
+    pub const READ_FAILED: &str = "P002";
+    /// `#elif`/`#else`/`#endif` without a matching `#if`, or out of order
+    pub const CONDITIONAL_MISMATCH: &str = "P003";
+    /// `#if` block not closed before the end of its file
+    pub const CONDITIONAL_UNTERMINATED: &str = "P004";
+    /// `#if`/`#elif` expression could not be evaluated
+    pub const INVALID_CONDITION: &str = "P005";
+    /// Import found in more than one search root with different contents
+    pub const AMBIGUOUS_IMPORT: &str = "P006";
+    /// Macro with a `%0` placeholder called without a body block
+    pub const MACRO_BODY_REQUIRED: &str = "M001";
+    /// Macro called with the wrong number of arguments
+    pub const MACRO_ARITY: &str = "M002";
+    /// Macro expands into itself, directly or through other macros
+    pub const MACRO_RECURSION: &str = "M003";
+    /// Macro expansion kept growing past the expansion limit
+    pub const MACRO_EXPANSION_LIMIT: &str = "M004";
+    /// Plugin include file does not exist
+    pub const PLUGIN_INCLUDE_NOT_FOUND: &str = "L001";
+    /// Problem inside code injected by a plugin hook
+    pub const PLUGIN_CODE: &str = "L002";
+    /// Missing or invalid game configuration (game.json, config.toml, build profile)
+    pub const BUILD_CONFIG: &str = "B001";
+    /// Filesystem error while producing build output
+    pub const BUILD_IO: &str = "B002";
+    /// Unknown placeholder, filter or unbalanced section in a filename or header template
+    pub const TEMPLATE: &str = "B003";
+    /// Build stopped before it finished because it was cancelled
+    pub const CANCELLED: &str = "B004";
+    /// Top-level name declared more than once with different definitions
+    pub const DUPLICATE_SYMBOL: &str = "S001";
+    /// Top-level declaration repeated verbatim (e.g. the same file copied into two places)
+    pub const IDENTICAL_REDECLARATION: &str = "S002";
+    /// Built script uses too much of a device resource (variables, combos, bytecode, ...)
+    pub const RESOURCE_LIMIT: &str = "R001";
+    /// Built script is not valid GPC (unbalanced brackets, malformed statement, ...)
+    pub const SYNTAX_ERROR: &str = "G001";
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+/// A range on a single line of a source file.
+///
+/// Lines and columns are 1-indexed; columns count characters and `end_col` is exclusive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Span {
+    pub file: String,
+    pub line: usize,
+    pub start_col: usize,
+    pub end_col: usize,
+}
+
+impl Span {
+    /// Span covering the non-whitespace content of `text`, which is line `line` of `file`.
+    pub fn line(file: &str, line: usize, text: &str) -> Self {
+        let leading = text.chars().take_while(|c| c.is_whitespace()).count();
+        let content = text.trim().chars().count();
+        Self {
+            file: file.to_string(),
+            line,
+            start_col: leading + 1,
+            end_col: leading + content + 1,
+        }
+    }
+
+    /// Span covering the first occurrence of `needle` in `text`, falling back to the whole line.
+    pub fn find(file: &str, line: usize, text: &str, needle: &str) -> Self {
+        match text.find(needle) {
+            Some(pos) if !needle.is_empty() => {
+                let start = text[..pos].chars().count() + 1;
+                Self {
+                    file: file.to_string(),
+                    line,
+                    start_col: start,
+                    end_col: start + needle.chars().count(),
+                }
+            }
+            _ => Self::line(file, line, text),
+        }
+    }
+}
+
+/// A secondary location that explains a diagnostic (e.g. where a file was imported from).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelatedSpan {
+    pub span: Span,
+    pub message: String,
+}
+
+/// A replacement of the text covered by `span`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TextEdit {
+    pub span: Span,
+    pub new_text: String,
+}
+
+/// A suggested fix; applying all `edits` resolves the diagnostic.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fix {
+    pub message: String,
+    pub edits: Vec<TextEdit>,
+}
+
+/// A structured build error, warning or note.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub code: String,
+    pub severity: Severity,
+    pub message: String,
+    /// Where the problem is; `None` for problems without a source location
+    pub span: Option<Span>,
+    /// Supporting locations, innermost first (e.g. the include chain)
+    #[serde(default)]
+    pub related: Vec<RelatedSpan>,
+    #[serde(default)]
+    pub fixes: Vec<Fix>,
+}
+
+impl Diagnostic {
+    pub fn new(code: &str, severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            code: code.to_string(),
+            severity,
+            message: message.into(),
+            span: None,
+            related: Vec::new(),
+            fixes: Vec::new(),
+        }
+    }
+
+    pub fn error(code: &str, message: impl Into<String>) -> Self {
+        Self::new(code, Severity::Error, message)
+    }
+
+    pub fn warning(code: &str, message: impl Into<String>) -> Self {
+        Self::new(code, Severity::Warning, message)
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_related(mut self, related: Vec<RelatedSpan>) -> Self {
+        self.related.extend(related);
+        self
+    }
+
+    pub fn with_fix(mut self, fix: Fix) -> Self {
+        self.fixes.push(fix);
+        self
+    }
+}
+
+/// `file:line:col: message [code]`, the form printed by the CLI.
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(ref span) = self.span {
+            write!(f, "{}:{}:{}: ", span.file, span.line, span.start_col)?;
+        }
+        write!(f, "{} [{}]", self.message, self.code)?;
+        for related in &self.related {
+            write!(
+                f,
+                "\n  {} {}:{}",
+                related.message, related.span.file, related.span.line
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// Lightweight game metadata stored as game.json in game directories.
 /// Replaces config.toml as the primary game metadata source for flow-based games.
@@ -22,6 +23,83 @@ pub struct GameMeta {
     /// Auto-generate module info block in header comments
     #[serde(default)]
     pub generate_module_info: Option<bool>,
+    /// Stamp flow-generated scripts with the time the flows were last edited. Off by
+    /// default so rebuilding unchanged sources gives byte-identical output.
+    #[serde(default)]
+    pub header_timestamp: Option<bool>,
+    /// Named sets of preprocessor symbols selectable at build time (e.g. "lite", "full")
+    #[serde(default)]
+    pub build_profiles: Option<BTreeMap<String, BuildProfile>>,
+    /// Target firmware and thresholds for the resource usage check after each build
+    #[serde(default)]
+    pub resources: Option<ResourceSettings>,
+    /// Remove functions, combos, defines and globals that nothing uses from the build output
+    #[serde(default)]
+    pub tree_shake: Option<bool>,
+    /// Fold constant expressions, inline defines used once and drop constant branches
+    #[serde(default)]
+    pub optimize: Option<bool>,
+    /// Build variants produced together by one build, e.g. one per console
+    #[serde(default)]
+    pub targets: Option<Vec<BuildTarget>>,
+}
+
+impl GameMeta {
+    pub fn target(&self, name: &str) -> Option<&BuildTarget> {
+        self.targets.as_ref()?.iter().find(|t| t.name == name)
+    }
+
+    /// This game as built for the named target: its console replaces the game's own.
+    pub fn for_target(&self, name: &str) -> Result<GameMeta, String> {
+        let target = self
+            .target(name)
+            .ok_or_else(|| format!("Build target '{}' not found in game.json", name))?;
+        let mut meta = self.clone();
+        if let Some(ref console) = target.console_type {
+            meta.console_type = console.clone();
+        }
+        Ok(meta)
+    }
+}
+
+/// A build profile: extra symbols for `#if`/`#ifdef` blocks when the profile is selected.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BuildProfile {
+    #[serde(default)]
+    pub defines: BTreeMap<String, String>,
+}
+
+/// A build variant of a game, written to its own file in dist/.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BuildTarget {
+    /// Exposed to `#if` as `TARGET` and `TARGET_<NAME>`
+    pub name: String,
+    /// Console for this target; defaults to the game's `console_type`
+    #[serde(default)]
+    pub console_type: Option<String>,
+    /// Output filename template; takes the same placeholders as `filename` plus
+    /// `{filename}`, `{target}` and `{console}`. Defaults to `{filename}_{target}`.
+    #[serde(default)]
+    pub filename: Option<String>,
+    #[serde(default)]
+    pub defines: BTreeMap<String, String>,
+}
+
+/// How built output is checked against device limits.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResourceSettings {
+    /// Firmware whose limits apply (default "zen")
+    #[serde(default)]
+    pub firmware: Option<String>,
+    /// Warn when a resource reaches this percentage of its limit (default 90)
+    #[serde(default)]
+    pub warn_percent: Option<u32>,
+    /// Fail the build when a resource reaches this percentage of its limit (default 100)
+    #[serde(default)]
+    pub error_percent: Option<u32>,
+    /// Per-resource limit overrides, keyed like "global_slots" or "bytecode_bytes"
+    #[serde(default)]
+    pub limits: BTreeMap<String, u64>,
 }
 
 fn default_generation_mode() -> String {
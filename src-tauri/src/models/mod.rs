@@ -1,4 +1,6 @@
 pub mod config;
+pub mod diagnostic;
 pub mod flow;
 pub mod game_meta;
 pub mod module;
+pub mod workspace;
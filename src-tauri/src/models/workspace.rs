@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Name of the optional workspace configuration file in the workspace root.
+pub const WORKSPACE_CONFIG_FILE: &str = "zenforge.toml";
+
+/// Workspace-wide settings shared by every game in the workspace.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorkspaceConfig {
+    #[serde(default)]
+    pub imports: ImportSettings,
+}
+
+/// `[imports]` section: where imports are looked up besides the built-in roots.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportSettings {
+    /// Extra search roots, searched after the built-in ones in this order.
+    /// Relative paths are resolved against the workspace root.
+    #[serde(default)]
+    pub paths: Vec<ImportPath>,
+}
+
+/// An extra search root: a plain path (named after its last component) or a named one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ImportPath {
+    Path(String),
+    Named { name: String, path: String },
+}
+
+impl ImportPath {
+    pub fn path(&self) -> &str {
+        match self {
+            ImportPath::Path(path) | ImportPath::Named { path, .. } => path,
+        }
+    }
+
+    /// Name used by `import <name>/file;`
+    pub fn name(&self) -> String {
+        match self {
+            ImportPath::Named { name, .. } => name.clone(),
+            ImportPath::Path(path) => Path::new(path)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.clone()),
+        }
+    }
+}
+
+impl WorkspaceConfig {
+    /// Read `zenforge.toml` from the workspace root; a missing file means defaults.
+    pub fn load(workspace: &Path) -> Result<Self, String> {
+        let path = workspace.join(WORKSPACE_CONFIG_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read {}: {}", WORKSPACE_CONFIG_FILE, e))?;
+        toml::from_str(&content)
+            .map_err(|e| format!("Could not parse {}: {}", WORKSPACE_CONFIG_FILE, e))
+    }
+}
@@ -0,0 +1,454 @@
+//! Registry of the builds a game has written to `dist/`.
+//!
+//! Every successful build is recorded with its output and the metadata needed to
+//! tell builds apart (game version, plugins, modules, sources), so an earlier
+//! output can still be listed, compared, restored or exported after `dist/` has
+//! been overwritten. Outputs are stored once per content hash.
+
+use crate::pipeline::cache::{self, FileHash};
+use crate::pipeline::sourcemap;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const INDEX_FILE: &str = "index.json";
+
+/// Held while an index is read and rewritten; the targets of a game build in parallel.
+static INDEX_LOCK: Mutex<()> = Mutex::new(());
+
+/// Artifact directory of a game.
+pub fn artifacts_dir(game_dir: &Path) -> PathBuf {
+    game_dir.join(".zenforge").join("artifacts")
+}
+
+/// A plugin that was enabled for a build.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PluginRef {
+    pub id: String,
+    pub version: String,
+}
+
+/// What a build was made from.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    /// Build target from game.json `targets`, if one was selected
+    pub target: Option<String>,
+    pub game_version: f64,
+    pub console_type: String,
+    /// Build profile from game.json `build_profiles`, if one was selected
+    pub profile: Option<String>,
+    pub plugins: Vec<PluginRef>,
+    /// Ids of the modules the game uses, sorted
+    pub modules: Vec<String>,
+    /// Hash of every source file the build read; builds of identical sources share it
+    pub source_snapshot: String,
+}
+
+/// A recorded build output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Artifact {
+    pub id: String,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// File name the build wrote in `dist/`
+    pub file_name: String,
+    pub output_hash: String,
+    /// Output size in bytes
+    pub size: u64,
+    pub label: Option<String>,
+    #[serde(flatten)]
+    pub info: BuildInfo,
+}
+
+/// Two artifacts side by side, with a summary of what differs between their builds.
+#[derive(Debug, Clone, Serialize)]
+pub struct ArtifactDiff {
+    pub from: Artifact,
+    pub to: Artifact,
+    /// Human-readable differences in metadata, e.g. "Plugin 'aim' added"
+    pub changes: Vec<String>,
+    pub from_content: String,
+    pub to_content: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ArtifactIndex {
+    artifacts: Vec<Artifact>,
+}
+
+impl ArtifactIndex {
+    fn load(game_dir: &Path) -> Self {
+        std::fs::read_to_string(artifacts_dir(game_dir).join(INDEX_FILE))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, game_dir: &Path) -> Result<(), String> {
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Could not serialize artifact index: {}", e))?;
+        std::fs::write(artifacts_dir(game_dir).join(INDEX_FILE), content)
+            .map_err(|e| format!("Could not write artifact index: {}", e))
+    }
+
+    fn get(&self, id: &str) -> Result<&Artifact, String> {
+        self.artifacts
+            .iter()
+            .find(|a| a.id == id)
+            .ok_or_else(|| format!("Artifact '{}' not found", id))
+    }
+}
+
+fn content_path(game_dir: &Path, output_hash: &str) -> PathBuf {
+    artifacts_dir(game_dir).join(format!("{}.gpc", output_hash))
+}
+
+/// Id for the sources of a build, from the hashes of the files it read.
+///
+/// Paths inside the game directory are taken relative to it, so moving the
+/// workspace does not change the id.
+pub fn source_snapshot(game_dir: &Path, inputs: &[FileHash]) -> String {
+    let root = game_dir.canonicalize().unwrap_or_else(|_| game_dir.to_path_buf());
+    let mut entries: Vec<String> = inputs
+        .iter()
+        .map(|file| {
+            let path = Path::new(&file.path);
+            let path = path.strip_prefix(&root).unwrap_or(path);
+            format!("{}\0{}", path.to_string_lossy(), file.hash.as_deref().unwrap_or("-"))
+        })
+        .collect();
+    entries.sort();
+    entries.dedup();
+    cache::hash_bytes(entries.join("\n").as_bytes())[..16].to_string()
+}
+
+/// Ids of the modules a game uses: module nodes of flows.json for flow games,
+/// menu items with a `module` for config.toml games.
+pub fn game_modules(game_dir: &Path) -> Vec<String> {
+    let mut modules: Vec<String> = Vec::new();
+    if let Ok(content) = std::fs::read_to_string(game_dir.join("flows.json")) {
+        let project: serde_json::Value = serde_json::from_str(&content).unwrap_or_default();
+        let nodes = project["flows"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .flat_map(|flow| flow["nodes"].as_array().into_iter().flatten());
+        modules.extend(
+            nodes.filter_map(|node| node["moduleData"]["moduleId"].as_str().map(String::from)),
+        );
+    } else if let Ok(content) = std::fs::read_to_string(game_dir.join("config.toml")) {
+        if let Ok(config) = toml::from_str::<crate::models::config::GameConfig>(&content) {
+            modules.extend(config.menu.into_iter().filter_map(|item| item.module));
+        }
+    }
+    modules.sort();
+    modules.dedup();
+    modules
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Record a build output. A build identical to the newest artifact of the same
+/// file (same output and build info) is not recorded twice; that artifact is returned.
+pub fn record(
+    game_dir: &Path,
+    file_name: &str,
+    content: &str,
+    info: BuildInfo,
+) -> Result<Artifact, String> {
+    let dir = artifacts_dir(game_dir);
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Could not create artifact directory {}: {}", dir.display(), e))?;
+
+    let output_hash = cache::hash_bytes(content.as_bytes());
+    let _guard = INDEX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut index = ArtifactIndex::load(game_dir);
+    // The index is in build order
+    let latest = index.artifacts.iter().rev().find(|a| a.file_name == file_name);
+    if let Some(latest) = latest {
+        if latest.output_hash == output_hash && latest.info == info {
+            return Ok(latest.clone());
+        }
+    }
+
+    let path = content_path(game_dir, &output_hash);
+    if !path.exists() {
+        std::fs::write(&path, content)
+            .map_err(|e| format!("Could not write artifact {}: {}", path.display(), e))?;
+    }
+
+    let timestamp = now_secs();
+    let base_id = format!("{}-{}", timestamp, &output_hash[..8]);
+    let mut id = base_id.clone();
+    let mut n = 1;
+    while index.artifacts.iter().any(|a| a.id == id) {
+        n += 1;
+        id = format!("{}-{}", base_id, n);
+    }
+    let artifact = Artifact {
+        id,
+        timestamp,
+        file_name: file_name.to_string(),
+        output_hash,
+        size: content.len() as u64,
+        label: None,
+        info,
+    };
+    index.artifacts.push(artifact.clone());
+    index.save(game_dir)?;
+    Ok(artifact)
+}
+
+/// Every recorded artifact of a game, newest first.
+pub fn list(game_dir: &Path) -> Vec<Artifact> {
+    let mut artifacts = ArtifactIndex::load(game_dir).artifacts;
+    artifacts.reverse();
+    artifacts
+}
+
+/// The built script stored for an artifact.
+pub fn read(game_dir: &Path, id: &str) -> Result<String, String> {
+    let index = ArtifactIndex::load(game_dir);
+    let artifact = index.get(id)?;
+    std::fs::read_to_string(content_path(game_dir, &artifact.output_hash))
+        .map_err(|e| format!("Could not read artifact '{}': {}", id, e))
+}
+
+/// Compare two artifacts of a game.
+pub fn diff(game_dir: &Path, from_id: &str, to_id: &str) -> Result<ArtifactDiff, String> {
+    let index = ArtifactIndex::load(game_dir);
+    let from = index.get(from_id)?.clone();
+    let to = index.get(to_id)?.clone();
+    Ok(ArtifactDiff {
+        changes: describe_changes(&from, &to),
+        from_content: read(game_dir, from_id)?,
+        to_content: read(game_dir, to_id)?,
+        from,
+        to,
+    })
+}
+
+fn describe_changes(from: &Artifact, to: &Artifact) -> Vec<String> {
+    let (a, b) = (&from.info, &to.info);
+    let mut changes = Vec::new();
+    if a.game_version != b.game_version {
+        changes.push(format!("Version {} -> {}", a.game_version, b.game_version));
+    }
+    if a.console_type != b.console_type {
+        changes.push(format!("Console {} -> {}", a.console_type, b.console_type));
+    }
+    if a.target != b.target {
+        changes.push(format!(
+            "Target {} -> {}",
+            a.target.as_deref().unwrap_or("(none)"),
+            b.target.as_deref().unwrap_or("(none)")
+        ));
+    }
+    if a.profile != b.profile {
+        changes.push(format!(
+            "Profile {} -> {}",
+            a.profile.as_deref().unwrap_or("(none)"),
+            b.profile.as_deref().unwrap_or("(none)")
+        ));
+    }
+    for plugin in &a.plugins {
+        match b.plugins.iter().find(|p| p.id == plugin.id) {
+            None => changes.push(format!("Plugin '{}' removed", plugin.id)),
+            Some(other) if other.version != plugin.version => changes.push(format!(
+                "Plugin '{}' {} -> {}",
+                plugin.id, plugin.version, other.version
+            )),
+            Some(_) => {}
+        }
+    }
+    for plugin in b.plugins.iter().filter(|p| !a.plugins.iter().any(|o| o.id == p.id)) {
+        changes.push(format!("Plugin '{}' added", plugin.id));
+    }
+    for module in a.modules.iter().filter(|m| !b.modules.contains(m)) {
+        changes.push(format!("Module '{}' removed", module));
+    }
+    for module in b.modules.iter().filter(|m| !a.modules.contains(m)) {
+        changes.push(format!("Module '{}' added", module));
+    }
+    if a.source_snapshot != b.source_snapshot {
+        changes.push("Sources changed".to_string());
+    }
+    if from.output_hash == to.output_hash {
+        changes.push("Output is identical".to_string());
+    }
+    changes
+}
+
+/// Put an artifact back in `{dist_base}/dist/` under the file name it was built as.
+/// Returns the restored path.
+pub fn restore(game_dir: &Path, dist_base: &Path, id: &str) -> Result<PathBuf, String> {
+    let content = read(game_dir, id)?;
+    let index = ArtifactIndex::load(game_dir);
+    let dist_dir = dist_base.join("dist");
+    std::fs::create_dir_all(&dist_dir)
+        .map_err(|e| format!("Could not create dist directory: {}", e))?;
+    let path = dist_dir.join(&index.get(id)?.file_name);
+    std::fs::write(&path, content)
+        .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+    // The source map describes whatever was built last, not this output
+    let _ = std::fs::remove_file(sourcemap::map_path_for(&path));
+    Ok(path)
+}
+
+/// Copy an artifact to `dest`.
+pub fn export(game_dir: &Path, id: &str, dest: &Path) -> Result<(), String> {
+    let content = read(game_dir, id)?;
+    std::fs::write(dest, content).map_err(|e| format!("Could not write {}: {}", dest.display(), e))
+}
+
+/// Name an artifact (e.g. "sent to testers"), or clear its name.
+pub fn set_label(game_dir: &Path, id: &str, label: Option<String>) -> Result<(), String> {
+    let _guard = INDEX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut index = ArtifactIndex::load(game_dir);
+    let artifact = index
+        .artifacts
+        .iter_mut()
+        .find(|a| a.id == id)
+        .ok_or_else(|| format!("Artifact '{}' not found", id))?;
+    artifact.label = label.filter(|l| !l.trim().is_empty());
+    index.save(game_dir)
+}
+
+/// Forget an artifact, deleting its output unless another artifact shares it.
+pub fn delete(game_dir: &Path, id: &str) -> Result<(), String> {
+    let _guard = INDEX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut index = ArtifactIndex::load(game_dir);
+    let hash = index.get(id)?.output_hash.clone();
+    index.artifacts.retain(|a| a.id != id);
+    if !index.artifacts.iter().any(|a| a.output_hash == hash) {
+        let _ = std::fs::remove_file(content_path(game_dir, &hash));
+    }
+    index.save(game_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(version: f64, plugins: &[(&str, &str)], modules: &[&str]) -> BuildInfo {
+        BuildInfo {
+            game_version: version,
+            console_type: "ps5".to_string(),
+            plugins: plugins
+                .iter()
+                .map(|(id, version)| PluginRef {
+                    id: id.to_string(),
+                    version: version.to_string(),
+                })
+                .collect(),
+            modules: modules.iter().map(|m| m.to_string()).collect(),
+            source_snapshot: "abc".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_record_restore_and_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("game");
+
+        let first = record(&game_dir, "Game1.gpc", "main { }\n", info(1.0, &[], &[])).unwrap();
+        // Rebuilding the same thing does not add an entry
+        let again = record(&game_dir, "Game1.gpc", "main { }\n", info(1.0, &[], &[])).unwrap();
+        assert_eq!(first.id, again.id);
+        let second = record(&game_dir, "Game1.gpc", "int a;\nmain { }\n", info(1.1, &[], &[]))
+            .unwrap();
+        // Same output as the first, but built for another version
+        let third = record(&game_dir, "Game1.gpc", "main { }\n", info(1.2, &[], &[])).unwrap();
+        let ids: Vec<String> = list(&game_dir).into_iter().map(|a| a.id).collect();
+        assert_eq!(ids, [third.id.clone(), second.id.clone(), first.id.clone()]);
+        assert_eq!(read(&game_dir, &second.id).unwrap(), "int a;\nmain { }\n");
+
+        let dist = dir.path().join("dist/Game1.gpc");
+        std::fs::create_dir_all(dist.parent().unwrap()).unwrap();
+        std::fs::write(&dist, "newer").unwrap();
+        std::fs::write(sourcemap::map_path_for(&dist), "{}").unwrap();
+        let restored = restore(&game_dir, dir.path(), &second.id).unwrap();
+        assert_eq!(restored, dist);
+        assert_eq!(std::fs::read_to_string(&dist).unwrap(), "int a;\nmain { }\n");
+        assert!(!sourcemap::map_path_for(&dist).exists());
+
+        let exported = dir.path().join("tester.gpc");
+        export(&game_dir, &first.id, &exported).unwrap();
+        assert_eq!(std::fs::read_to_string(&exported).unwrap(), "main { }\n");
+
+        // The first and third artifact share their output file
+        delete(&game_dir, &first.id).unwrap();
+        assert_eq!(read(&game_dir, &third.id).unwrap(), "main { }\n");
+        delete(&game_dir, &third.id).unwrap();
+        assert!(!content_path(&game_dir, &first.output_hash).exists());
+        assert!(read(&game_dir, &first.id).is_err());
+    }
+
+    #[test]
+    fn test_record_in_parallel() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("game");
+        std::thread::scope(|scope| {
+            for n in 0..8 {
+                let game_dir = &game_dir;
+                scope.spawn(move || {
+                    let name = format!("Game{}.gpc", n);
+                    let content = format!("int a{};\n", n);
+                    record(game_dir, &name, &content, info(1.0, &[], &[])).unwrap();
+                });
+            }
+        });
+        assert_eq!(list(&game_dir).len(), 8);
+    }
+
+    #[test]
+    fn test_diff_describes_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = record(
+            dir.path(),
+            "G.gpc",
+            "a",
+            info(1.0, &[("aim", "1.0"), ("old", "1.0")], &["antirecoil"]),
+        )
+        .unwrap();
+        let mut newer = info(1.1, &[("aim", "1.1"), ("new", "0.1")], &["rapidfire"]);
+        newer.source_snapshot = "def".to_string();
+        let to = record(dir.path(), "G.gpc", "b", newer).unwrap();
+        set_label(dir.path(), &to.id, Some("sent to testers".to_string())).unwrap();
+
+        let diff = diff(dir.path(), &from.id, &to.id).unwrap();
+        assert_eq!(
+            diff.changes,
+            [
+                "Version 1 -> 1.1",
+                "Plugin 'aim' 1.0 -> 1.1",
+                "Plugin 'old' removed",
+                "Plugin 'new' added",
+                "Module 'antirecoil' removed",
+                "Module 'rapidfire' added",
+                "Sources changed",
+            ]
+        );
+        assert_eq!((diff.from_content.as_str(), diff.to_content.as_str()), ("a", "b"));
+        assert_eq!(diff.to.label.as_deref(), Some("sent to testers"));
+    }
+
+    #[test]
+    fn test_game_modules() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("flows.json"),
+            r#"{"flows":[{"nodes":[{"moduleData":{"moduleId":"rapidfire"}},{"label":"x"},
+                {"moduleData":{"moduleId":"antirecoil"}}]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(game_modules(dir.path()), ["antirecoil", "rapidfire"]);
+    }
+}
@@ -1,7 +1,28 @@
 use crate::models::config::GameConfig;
+use crate::models::diagnostic::{
+    codes, Diagnostic, Fix, RelatedSpan, Severity, Span, TextEdit,
+};
 use crate::models::game_meta::GameMeta;
-use std::collections::HashSet;
+use crate::models::workspace::{WorkspaceConfig, WORKSPACE_CONFIG_FILE};
+use crate::pipeline::artifacts::{self, BuildInfo, PluginRef};
+use crate::pipeline::cache::{
+    self, BuildRecord, CacheStatus, FileHash, Fragment, FragmentCache, FragmentLine, KeyHasher,
+};
+use crate::pipeline::conditional::{self, ConditionalError, ConditionalStack};
+use crate::pipeline::manifest::{self, BuildManifest, ManifestEntry};
+use crate::pipeline::optimize::{self, OptimizeReport};
+use crate::pipeline::resources::{self, ResourceReport};
+use crate::pipeline::syntax::{self, SyntaxError, TextRange};
+use crate::pipeline::template::{self, TemplateContext};
+use crate::pipeline::sourcemap::{
+    self, compose_traces, identity_trace, LineIndex, LineOrigin, LineTrace, SourceLocation,
+    SourceMap, SourceTable, TracedString,
+};
+use crate::pipeline::treeshake::{self, RemovedSymbol};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
 /// Strip the Windows `\\?\` extended-length path prefix for cleaner display.
 fn display_path(path: &Path) -> String {
@@ -14,48 +35,537 @@ fn display_path(path: &Path) -> String {
 pub struct BuildResult {
     pub output_path: String,
     pub success: bool,
-    pub errors: Vec<String>,
-    pub warnings: Vec<String>,
+    pub errors: Vec<Diagnostic>,
+    pub warnings: Vec<Diagnostic>,
+    /// Path of the line-level source map written next to the output
+    pub source_map_path: Option<String>,
+    pub cache: CacheStatus,
+    /// Resource usage of the output against the target firmware's limits
+    pub resources: Option<ResourceReport>,
+    /// Unused declarations removed by tree shaking
+    pub removed: Vec<RemovedSymbol>,
+    /// What the optimizer changed, if it ran
+    pub optimization: Option<OptimizeReport>,
+}
+
+impl BuildResult {
+    /// A failed build that produced no output.
+    fn failure(output_path: &Path, error: Diagnostic) -> Self {
+        Self {
+            output_path: output_path.to_string_lossy().to_string(),
+            success: false,
+            errors: vec![error],
+            warnings: Vec::new(),
+            source_map_path: None,
+            cache: CacheStatus::Miss,
+            resources: None,
+            removed: Vec::new(),
+            optimization: None,
+        }
+    }
+
+    fn cancelled(output_path: &Path) -> Self {
+        Self::failure(output_path, Diagnostic::error(codes::CANCELLED, "Build cancelled"))
+    }
 }
 
 /// Build log entry emitted during preprocessing
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct BuildLogEntry {
-    pub level: String, // "info", "warn", "error"
+    pub level: Severity,
     pub message: String,
 }
 
+/// Output of the preprocessor, with the origin of every output line.
+pub struct Preprocessed {
+    pub content: String,
+    /// Every file that contributed lines, indexed by `LineOrigin::source`
+    pub sources: SourceTable,
+    /// One entry per line of `content`
+    pub origins: Vec<LineOrigin>,
+    /// Verbose progress messages
+    pub logs: Vec<BuildLogEntry>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Every file read or looked for, with its content hash
+    pub files: Vec<FileHash>,
+    /// The import directive that pulled in each imported file
+    pub imports: Vec<ImportEdge>,
+    pub success: bool,
+}
+
+/// An imported file and the import directive that first pulled it in.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ImportEdge {
+    pub file: String,
+    pub site: Span,
+    /// Search root the file was found in; `None` if it was next to the importing file
+    #[serde(default)]
+    pub root: Option<String>,
+}
+
+/// A directory imports are looked up in when they are not next to the importing file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchRoot {
+    /// Name used by `import <name>/file;`
+    pub name: String,
+    pub dir: PathBuf,
+}
+
+/// Options controlling the preprocessor.
+#[derive(Debug, Clone, Default)]
+pub struct PreprocessOptions {
+    pub verbose: bool,
+    /// Symbols visible to `#if`/`#ifdef` conditions
+    pub defines: BTreeMap<String, String>,
+    /// Roots searched in order for imports not found next to the importing file
+    pub search_roots: Vec<SearchRoot>,
+    /// Told about every imported file as it is processed
+    pub monitor: BuildMonitor,
+}
+
+/// Import search roots for a game, in lookup order: the game directory, the
+/// workspace `lib/`, the app-bundled `common/`, then `[imports] paths` from the
+/// workspace's zenforge.toml.
+pub fn import_search_roots(
+    game_dir: &Path,
+    project_root: &Path,
+    workspace: &Path,
+) -> Result<Vec<SearchRoot>, String> {
+    let config = WorkspaceConfig::load(workspace)?;
+    let mut roots = vec![
+        SearchRoot {
+            name: "game".to_string(),
+            dir: game_dir.to_path_buf(),
+        },
+        SearchRoot {
+            name: "lib".to_string(),
+            dir: workspace.join("lib"),
+        },
+        SearchRoot {
+            name: "common".to_string(),
+            dir: project_root.join("common"),
+        },
+    ];
+    for path in &config.imports.paths {
+        let name = path.name();
+        if roots.iter().any(|r| r.name == name) {
+            return Err(format!(
+                "Import path '{}' in {} is named '{}', which is already taken; \
+                 name it with {{ name = \"...\", path = \"{}\" }}",
+                path.path(),
+                WORKSPACE_CONFIG_FILE,
+                name,
+                path.path()
+            ));
+        }
+        roots.push(SearchRoot {
+            name,
+            dir: workspace.join(path.path()),
+        });
+    }
+    Ok(roots)
+}
+
 /// Preprocess a GPC file by recursively expanding import directives.
 ///
-/// Returns (processed_content, log_entries, success).
+/// Returns (processed_content, diagnostics, success).
 pub fn preprocess(
     file_path: &Path,
     verbose: bool,
-) -> (String, Vec<BuildLogEntry>, bool) {
-    let mut processed_files: HashSet<PathBuf> = HashSet::new();
-    let mut logs = Vec::new();
-    let mut success = true;
-
-    let content = preprocess_recursive(
-        file_path,
-        &mut processed_files,
-        &mut Vec::new(),
-        &mut logs,
-        &mut success,
+) -> (String, Vec<Diagnostic>, bool) {
+    let options = PreprocessOptions {
         verbose,
-    );
+        ..Default::default()
+    };
+    let result = preprocess_mapped(file_path, &options);
+    (result.content, result.diagnostics, result.success)
+}
+
+/// Preprocess a GPC file, recording which file and line produced each output line.
+pub fn preprocess_mapped(file_path: &Path, options: &PreprocessOptions) -> Preprocessed {
+    run_preprocessor(file_path, options, None)
+}
 
-    (content, logs, success)
+/// Like `preprocess_mapped`, but reuses imported subtrees from `cache` when none of
+/// their files changed, and stores newly processed subtrees in it.
+pub fn preprocess_cached(
+    file_path: &Path,
+    options: &PreprocessOptions,
+    cache: &mut FragmentCache,
+) -> Preprocessed {
+    run_preprocessor(file_path, options, Some(cache))
 }
 
-fn preprocess_recursive(
+fn run_preprocessor(
     file_path: &Path,
-    processed_files: &mut HashSet<PathBuf>,
-    include_stack: &mut Vec<PathBuf>,
-    logs: &mut Vec<BuildLogEntry>,
-    success: &mut bool,
+    options: &PreprocessOptions,
+    cache: Option<&mut FragmentCache>,
+) -> Preprocessed {
+    let mut ctx = PreprocessContext {
+        processed_files: HashSet::new(),
+        include_stack: Vec::new(),
+        import_sites: Vec::new(),
+        logs: Vec::new(),
+        diagnostics: Vec::new(),
+        success: true,
+        verbose: options.verbose,
+        defines: &options.defines,
+        search_roots: &options.search_roots,
+        monitor: &options.monitor,
+        sources: SourceTable::default(),
+        origins: Vec::new(),
+        files: Vec::new(),
+        imports: Vec::new(),
+        cache,
+    };
+
+    let content = preprocess_recursive(file_path, &mut ctx);
+
+    Preprocessed {
+        content,
+        sources: ctx.sources,
+        origins: ctx.origins,
+        logs: ctx.logs,
+        diagnostics: ctx.diagnostics,
+        files: ctx.files,
+        imports: ctx.imports,
+        success: ctx.success,
+    }
+}
+
+/// An import directive currently being expanded.
+struct ImportSite {
+    span: Span,
+    /// Full text of the directive line, for building fix suggestions
+    line_text: String,
+    /// Search root the imported file was found in
+    root: Option<String>,
+}
+
+/// Describe where an import was found, for messages.
+fn root_label(root: Option<&str>) -> String {
+    match root {
+        Some(name) => format!("the <{}> root", name),
+        None => "the importing file's directory".to_string(),
+    }
+}
+
+/// State shared across the recursive walk of the include tree.
+struct PreprocessContext<'a> {
+    processed_files: HashSet<PathBuf>,
+    include_stack: Vec<PathBuf>,
+    /// Import directives leading to the file being processed, outermost first
+    import_sites: Vec<ImportSite>,
+    logs: Vec<BuildLogEntry>,
+    diagnostics: Vec<Diagnostic>,
+    success: bool,
     verbose: bool,
-) -> String {
+    defines: &'a BTreeMap<String, String>,
+    search_roots: &'a [SearchRoot],
+    monitor: &'a BuildMonitor,
+    sources: SourceTable,
+    origins: Vec<LineOrigin>,
+    files: Vec<FileHash>,
+    imports: Vec<ImportEdge>,
+    cache: Option<&'a mut FragmentCache>,
+}
+
+impl PreprocessContext<'_> {
+    fn log(&mut self, message: String) {
+        self.logs.push(BuildLogEntry {
+            level: Severity::Info,
+            message,
+        });
+    }
+
+    /// The include chain as related spans, innermost first, skipping the `skip` innermost sites.
+    fn include_chain(&self, skip: usize) -> Vec<RelatedSpan> {
+        self.import_sites
+            .iter()
+            .rev()
+            .skip(skip)
+            .map(|site| RelatedSpan {
+                span: site.span.clone(),
+                message: match site.root {
+                    Some(ref name) => format!("included via <{}> from", name),
+                    None => "included from".to_string(),
+                },
+            })
+            .collect()
+    }
+
+    /// Record a diagnostic raised inside the file currently being processed.
+    fn report(&mut self, diagnostic: Diagnostic) {
+        let diagnostic = diagnostic.with_related(self.include_chain(0));
+        self.push_diagnostic(diagnostic);
+    }
+
+    /// Record a diagnostic about the innermost import directive itself (missing or unreadable file).
+    fn report_at_import(&mut self, diagnostic: Diagnostic) {
+        let diagnostic = match self.import_sites.last() {
+            Some(site) => diagnostic
+                .with_span(site.span.clone())
+                .with_related(self.include_chain(1)),
+            None => diagnostic,
+        };
+        self.push_diagnostic(diagnostic);
+    }
+
+    fn push_diagnostic(&mut self, diagnostic: Diagnostic) {
+        if diagnostic.severity == Severity::Error {
+            self.success = false;
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Report a missing file at the innermost import, listing the roots that were searched too.
+    fn report_missing(&mut self, path: &Path, searched: &[&SearchRoot]) {
+        self.files.push(FileHash {
+            path: path.to_string_lossy().to_string(),
+            hash: None,
+        });
+        let mut message = format!("File not found: {}", display_path(path));
+        if !searched.is_empty() {
+            let roots: Vec<String> = searched
+                .iter()
+                .map(|r| format!("<{}> {}", r.name, display_path(&r.dir)))
+                .collect();
+            message.push_str(&format!(" (also searched {})", roots.join(", ")));
+        }
+        let mut diagnostic = Diagnostic::error(codes::IMPORT_NOT_FOUND, message);
+        if let Some(fix) = self
+            .import_sites
+            .last()
+            .and_then(|site| suggest_import_case_fix(path, site))
+        {
+            diagnostic = diagnostic.with_fix(fix);
+        }
+        self.report_at_import(diagnostic);
+    }
+
+    /// Find the file an import refers to and the search root it came from.
+    ///
+    /// `<name>/file` looks only in the named root. Other paths are tried next to the
+    /// importing file first, then in each search root; paths starting with `./` or
+    /// `../` and absolute paths are only tried next to the importing file. Missing
+    /// and ambiguous imports are reported at the innermost import site.
+    fn resolve_import(
+        &mut self,
+        import_path: &str,
+        base_dir: &Path,
+    ) -> Option<(PathBuf, Option<String>)> {
+        if let Some((name, rest)) = split_root_prefix(import_path) {
+            let Some(root) = self.search_roots.iter().find(|r| r.name == name) else {
+                let names: Vec<String> =
+                    self.search_roots.iter().map(|r| format!("<{}>", r.name)).collect();
+                self.report_at_import(Diagnostic::error(
+                    codes::IMPORT_NOT_FOUND,
+                    format!(
+                        "Unknown import root <{}> (available: {})",
+                        name,
+                        if names.is_empty() { "none".to_string() } else { names.join(", ") }
+                    ),
+                ));
+                return None;
+            };
+            // A missing file is reported when it is opened
+            return Some((normalize_path(&root.dir.join(rest)), Some(root.name.clone())));
+        }
+
+        let local = normalize_path(&base_dir.join(import_path));
+        let explicit = import_path.starts_with("./")
+            || import_path.starts_with("../")
+            || Path::new(import_path).is_absolute();
+        if explicit || self.search_roots.is_empty() {
+            return Some((local, None));
+        }
+
+        let search_roots = self.search_roots;
+        let candidates = std::iter::once((None, local.clone())).chain(
+            search_roots
+                .iter()
+                .map(|r| (Some(r), normalize_path(&r.dir.join(import_path)))),
+        );
+        // Distinct files the import matches, in lookup order
+        let mut found: Vec<(Option<&SearchRoot>, PathBuf)> = Vec::new();
+        for (root, path) in candidates {
+            match path.canonicalize() {
+                Ok(abs) if abs.is_file() => {
+                    if !found.iter().any(|(_, p)| *p == abs) {
+                        found.push((root, abs));
+                    }
+                }
+                // Creating the file later may change which one is used
+                _ => self.files.push(FileHash {
+                    path: path.to_string_lossy().to_string(),
+                    hash: None,
+                }),
+            }
+        }
+        if found.is_empty() {
+            let searched: Vec<&SearchRoot> = search_roots.iter().collect();
+            self.report_missing(&local, &searched);
+            return None;
+        }
+
+        let (root, path) = found.remove(0);
+        let root_name = root.map(|r| r.name.clone());
+        // Copies of the same library (e.g. common/ copied into a game) are not ambiguous
+        let content = std::fs::read(&path).ok();
+        let shadowed: Vec<(Option<&SearchRoot>, PathBuf)> = found
+            .into_iter()
+            .filter(|(_, other)| std::fs::read(other).ok() != content)
+            .collect();
+        if self.verbose {
+            self.log(format!(
+                "Resolved '{}' from {}: {}",
+                import_path,
+                root_label(root_name.as_deref()),
+                display_path(&path)
+            ));
+        }
+        if !shadowed.is_empty() {
+            let mut diagnostic = Diagnostic::warning(
+                codes::AMBIGUOUS_IMPORT,
+                format!(
+                    "'{}' matches {} different files; using {} from {}",
+                    import_path,
+                    shadowed.len() + 1,
+                    display_path(&path),
+                    root_label(root_name.as_deref())
+                ),
+            );
+            let site = self.import_sites.last();
+            let stem = import_path.strip_suffix(".gpc").unwrap_or(import_path);
+            for (other_root, other) in &shadowed {
+                diagnostic.related.push(RelatedSpan {
+                    span: Span {
+                        file: display_path(other),
+                        line: 1,
+                        start_col: 1,
+                        end_col: 1,
+                    },
+                    message: format!(
+                        "also found in {}",
+                        root_label(other_root.map(|r| r.name.as_str()))
+                    ),
+                });
+            }
+            // Offer to pin the import to each root explicitly (`#include` paths keep
+            // their extension, so only `import` directives can be rewritten this way)
+            if let Some(site) = site.filter(|s| {
+                s.line_text.trim_start().starts_with("import") && s.line_text.contains(stem)
+            }) {
+                let chosen = root.into_iter();
+                for pinned in chosen.chain(shadowed.iter().filter_map(|(r, _)| *r)) {
+                    diagnostic = diagnostic.with_fix(Fix {
+                        message: format!("Import from <{}> explicitly", pinned.name),
+                        edits: vec![TextEdit {
+                            span: Span::find(
+                                &site.span.file,
+                                site.span.line,
+                                &site.line_text,
+                                stem,
+                            ),
+                            new_text: format!("<{}>/{}", pinned.name, stem),
+                        }],
+                    });
+                }
+            }
+            self.report_at_import(diagnostic);
+        }
+        Some((path, root_name))
+    }
+
+    /// Cache key for the subtree rooted at `abs_path`. Its output depends on the
+    /// symbols, the search roots and on which files were already included (those are skipped).
+    fn fragment_key(&self, abs_path: &Path) -> String {
+        let mut processed: Vec<String> = self
+            .processed_files
+            .iter()
+            .map(|p| p.to_string_lossy().to_string())
+            .collect();
+        processed.sort();
+        let mut key = KeyHasher::new();
+        key.add("file", abs_path.to_string_lossy().as_bytes())
+            .add("defines", format!("{:?}", self.defines).as_bytes())
+            .add("roots", format!("{:?}", self.search_roots).as_bytes())
+            .add("processed", processed.join("\n").as_bytes());
+        key.finish()
+    }
+
+    /// Splice a cached subtree into the output as if it had just been processed.
+    fn reuse_fragment(&mut self, fragment: Fragment) -> String {
+        for file in &fragment.files {
+            if file.hash.is_some() {
+                self.processed_files.insert(PathBuf::from(&file.path));
+            }
+        }
+        let ids: Vec<usize> = fragment.sources.iter().map(|s| self.sources.id(s)).collect();
+        self.origins.extend(fragment.lines.iter().map(|l| LineOrigin {
+            source: ids[l.source],
+            line: l.line,
+            macros: Vec::new(),
+        }));
+        self.files.extend(fragment.files);
+        self.imports.extend(fragment.imports);
+        fragment.content
+    }
+
+    /// Capture everything produced since the given marks as a cacheable fragment.
+    fn make_fragment(
+        &self,
+        content: &str,
+        origins_from: usize,
+        files_from: usize,
+        imports_from: usize,
+    ) -> Fragment {
+        let mut local = SourceTable::default();
+        let lines = self.origins[origins_from..]
+            .iter()
+            .map(|o| FragmentLine {
+                source: local.id(&self.sources.sources[o.source]),
+                line: o.line,
+            })
+            .collect();
+        Fragment {
+            content: content.to_string(),
+            sources: local.sources,
+            lines,
+            files: self.files[files_from..].to_vec(),
+            imports: self.imports[imports_from..].to_vec(),
+        }
+    }
+}
+
+/// Suggest a fix for an import of a missing file when a file with the same name but
+/// different letter case exists next to it (imports are case-sensitive outside Windows).
+fn suggest_import_case_fix(missing: &Path, site: &ImportSite) -> Option<Fix> {
+    let missing_name = missing.file_name()?.to_str()?;
+    let dir = missing.parent()?;
+    let actual = std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .filter_map(|e| e.file_name().into_string().ok())
+        .find(|name| name != missing_name && name.eq_ignore_ascii_case(missing_name))?;
+
+    // Imports may omit the .gpc extension, so replace only the file stem
+    let missing_stem = missing_name.strip_suffix(".gpc").unwrap_or(missing_name);
+    let actual_stem = actual.strip_suffix(".gpc").unwrap_or(&actual);
+    if !site.line_text.contains(missing_stem) {
+        return None;
+    }
+    let span = Span::find(&site.span.file, site.span.line, &site.line_text, missing_stem);
+    Some(Fix {
+        message: format!("Change the import to '{}'", actual),
+        edits: vec![TextEdit {
+            span,
+            new_text: actual_stem.to_string(),
+        }],
+    })
+}
+
+fn preprocess_recursive(file_path: &Path, ctx: &mut PreprocessContext) -> String {
     let abs_path = match file_path.canonicalize() {
         Ok(p) => p,
         Err(_) => {
@@ -67,92 +577,174 @@ fn preprocess_recursive(
                     .unwrap_or_default()
                     .join(file_path)
             };
-            logs.push(BuildLogEntry {
-                level: "error".to_string(),
-                message: format!("File not found: {}", display_path(&abs)),
-            });
-            *success = false;
+            ctx.report_missing(&abs, &[]);
             return format!("// Error: Missing file {}\n", file_path.display());
         }
     };
 
     // Skip already processed files (prevents double-inclusion)
-    if processed_files.contains(&abs_path) || include_stack.contains(&abs_path) {
-        if verbose {
-            logs.push(BuildLogEntry {
-                level: "info".to_string(),
-                message: format!("Skipping (already included): {}", file_path.display()),
-            });
+    if ctx.processed_files.contains(&abs_path) || ctx.include_stack.contains(&abs_path) {
+        if ctx.verbose {
+            ctx.log(format!("Skipping (already included): {}", file_path.display()));
         }
         return String::new();
     }
 
-    if verbose {
-        let indent = "  ".repeat(include_stack.len());
-        logs.push(BuildLogEntry {
-            level: "info".to_string(),
-            message: format!("{}Processing: {}", indent, file_path.display()),
+    if let Some(site) = ctx.import_sites.last() {
+        ctx.monitor.report(BuildPhase::Preprocessing, Some(&display_path(&abs_path)));
+        ctx.imports.push(ImportEdge {
+            file: display_path(&abs_path),
+            site: site.span.clone(),
+            root: site.root.clone(),
         });
     }
 
-    processed_files.insert(abs_path.clone());
-    include_stack.push(abs_path.clone());
+    // Imported subtrees are cacheable; the root file is not (it is rebuilt every time)
+    let fragment_key = match ctx.cache {
+        Some(_) if !ctx.import_sites.is_empty() => Some(ctx.fragment_key(&abs_path)),
+        _ => None,
+    };
+    if let Some(ref key) = fragment_key {
+        let cached = ctx.cache.as_deref_mut().and_then(|c| c.get(key)).cloned();
+        if let Some(fragment) = cached {
+            if ctx.verbose {
+                let indent = "  ".repeat(ctx.include_stack.len());
+                ctx.log(format!("{}Reusing cached: {}", indent, file_path.display()));
+            }
+            return ctx.reuse_fragment(fragment);
+        }
+    }
+    let origins_at_entry = ctx.origins.len();
+    let files_at_entry = ctx.files.len();
+    let imports_at_entry = ctx.imports.len();
+    let diagnostics_at_entry = ctx.diagnostics.len();
+
+    if ctx.verbose {
+        let indent = "  ".repeat(ctx.include_stack.len());
+        ctx.log(format!("{}Processing: {}", indent, file_path.display()));
+    }
+
+    ctx.processed_files.insert(abs_path.clone());
+    ctx.include_stack.push(abs_path.clone());
 
     let content = match std::fs::read_to_string(&abs_path) {
-        Ok(c) => c,
-        Err(e) => {
-            logs.push(BuildLogEntry {
-                level: "error".to_string(),
-                message: format!("Could not read {}: {}", display_path(&abs_path), e),
+        Ok(c) => {
+            ctx.files.push(FileHash {
+                path: abs_path.to_string_lossy().to_string(),
+                hash: Some(cache::hash_bytes(c.as_bytes())),
             });
-            *success = false;
-            include_stack.pop();
+            c
+        }
+        Err(e) => {
+            ctx.report_at_import(Diagnostic::error(
+                codes::READ_FAILED,
+                format!("Could not read {}: {}", display_path(&abs_path), e),
+            ));
+            ctx.include_stack.pop();
             return format!("// Error: Could not read {}\n", file_path.display());
         }
     };
 
+    let file_display = display_path(&abs_path);
+    let source_id = ctx.sources.id(&file_display);
     let base_dir = abs_path.parent().unwrap_or(Path::new("."));
     let mut output = String::with_capacity(content.len() * 2);
+    // Conditional blocks never span files
+    let mut conditions = ConditionalStack::default();
 
     for (line_num, line) in content.lines().enumerate() {
         let line_num = line_num + 1; // 1-indexed
+        let origin = LineOrigin {
+            source: source_id,
+            line: line_num,
+            macros: Vec::new(),
+        };
+        let trimmed = line.trim_start();
+
+        // Conditional directives are consumed here and never reach the output
+        if let Some(directive) = conditional::parse_directive(trimmed) {
+            if let Err(e) = conditions.apply(&directive, line_num, ctx.defines) {
+                let code = match e {
+                    ConditionalError::Mismatched(_) => codes::CONDITIONAL_MISMATCH,
+                    ConditionalError::InvalidExpression(_) => codes::INVALID_CONDITION,
+                };
+                ctx.report(
+                    Diagnostic::error(code, e.to_string())
+                        .with_span(Span::line(&file_display, line_num, line)),
+                );
+            }
+            continue;
+        }
+
+        // Lines in inactive branches are dropped, including their imports
+        if !conditions.is_active() {
+            continue;
+        }
 
         // Skip commented lines - don't process their import directives
-        let trimmed = line.trim_start();
         if trimmed.starts_with("//") {
             output.push_str(line);
             output.push('\n');
+            ctx.origins.push(origin);
             continue;
         }
 
         // Check for import directive
         if let Some(import_path) = parse_import(trimmed) {
-            let full_import_path = normalize_path(&base_dir.join(&import_path));
-
-            let included_content = preprocess_recursive(
-                &full_import_path,
-                processed_files,
-                include_stack,
-                logs,
-                success,
-                verbose,
-            );
+            ctx.import_sites.push(ImportSite {
+                span: Span::line(&file_display, line_num, line),
+                line_text: line.to_string(),
+                root: None,
+            });
+            let origins_before = ctx.origins.len();
+            let included_content = match ctx.resolve_import(&import_path, base_dir) {
+                Some((full_import_path, root)) => {
+                    if let Some(site) = ctx.import_sites.last_mut() {
+                        site.root = root;
+                    }
+                    preprocess_recursive(&full_import_path, ctx)
+                }
+                None => format!("// Error: Missing file {}\n", import_path),
+            };
+            ctx.import_sites.pop();
 
-            if !*success {
-                logs.push(BuildLogEntry {
-                    level: "error".to_string(),
-                    message: format!("  Referenced from: {}:{}", display_path(&abs_path), line_num),
-                });
+            // Lines the included file could not account for (error placeholders)
+            // are attributed to the import directive itself
+            let included_lines = included_content.matches('\n').count();
+            while ctx.origins.len() < origins_before + included_lines {
+                ctx.origins.push(origin.clone());
             }
 
             output.push_str(&included_content);
         } else {
             output.push_str(line);
             output.push('\n');
+            ctx.origins.push(origin);
+        }
+    }
+
+    for frame in conditions.unterminated() {
+        let text = content.lines().nth(frame.line - 1).unwrap_or_default();
+        ctx.report(
+            Diagnostic::error(
+                codes::CONDITIONAL_UNTERMINATED,
+                "Unterminated conditional block (missing #endif)",
+            )
+            .with_span(Span::line(&file_display, frame.line, text)),
+        );
+    }
+
+    // Only clean subtrees are cached, so reused output never hides a diagnostic
+    if let Some(key) = fragment_key {
+        if ctx.diagnostics.len() == diagnostics_at_entry {
+            let fragment = ctx.make_fragment(&output, origins_at_entry, files_at_entry, imports_at_entry);
+            if let Some(cache) = ctx.cache.as_deref_mut() {
+                cache.insert(key, fragment);
+            }
         }
     }
 
-    include_stack.pop();
+    ctx.include_stack.pop();
     output
 }
 
@@ -160,6 +752,7 @@ fn preprocess_recursive(
 ///
 /// Supports:
 ///   import common/helper;          -> common/helper.gpc
+///   import <lib>/helper;           -> <lib>/helper.gpc  (see `split_root_prefix`)
 ///   import "common/helper.gpc";    -> common/helper.gpc
 ///   import "common/helper";        -> common/helper.gpc
 ///   #include "common/helper.gpc"   -> common/helper.gpc  (legacy)
@@ -206,6 +799,16 @@ fn parse_import(line: &str) -> Option<String> {
     None
 }
 
+/// Split an import path of the form `<name>/rest` into the search root name and the rest.
+fn split_root_prefix(path: &str) -> Option<(&str, &str)> {
+    let rest = path.strip_prefix('<')?;
+    let (name, rest) = rest.split_once(">/")?;
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return None;
+    }
+    Some((name, rest))
+}
+
 /// Normalize a path (resolve ../ and ./ components) without requiring the file to exist.
 fn normalize_path(path: &Path) -> PathBuf {
     let mut components = Vec::new();
@@ -228,14 +831,36 @@ fn normalize_path(path: &Path) -> PathBuf {
 // ============================================================
 
 /// A parsed macro definition from `define! name(params) { body }`.
-#[derive(Debug, Clone)]
+///
+/// Parameters may have a type prefix (`int x`), a default value (`x = 1`), and
+/// the last one may be variadic (`rest...`).
+#[derive(Debug, Clone, Default)]
 struct MacroDef {
     name: String,
     params: Vec<String>,
+    /// Value used when an argument is omitted or left empty, by parameter index
+    defaults: Vec<Option<String>>,
+    /// The last parameter collects all remaining arguments
+    variadic: bool,
     body: String,
     has_placeholder: bool,
     /// Byte range of the entire `define! ... { ... }` block in the source.
     byte_range: std::ops::Range<usize>,
+    /// 0-indexed line of the `define!` keyword in the source
+    line: usize,
+}
+
+impl MacroDef {
+    /// Fewest and most arguments a call may pass (`None` for variadic macros).
+    fn arity(&self) -> (usize, Option<usize>) {
+        let fixed = self.params.len() - usize::from(self.variadic);
+        let required = (0..fixed)
+            .rev()
+            .find(|&i| self.defaults.get(i).cloned().flatten().is_none())
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        (required, (!self.variadic).then_some(fixed))
+    }
 }
 
 /// Return the byte length of the UTF-8 character starting at the given byte.
@@ -251,12 +876,33 @@ fn utf8_char_len(b: u8) -> usize {
     }
 }
 
+/// Parse one declared parameter: `name`, `int name`, `name = default` or `name...`.
+/// Returns the name, the default value and whether it is variadic.
+fn parse_macro_param(text: &str) -> Option<(String, Option<String>, bool)> {
+    let (decl, default) = match text.split_once('=') {
+        Some((decl, value)) => (decl.trim(), Some(value.trim().to_string())),
+        None => (text.trim(), None),
+    };
+    let (decl, variadic) = match decl.strip_suffix("...") {
+        Some(decl) => (decl.trim_end(), true),
+        None => (decl, false),
+    };
+    // An optional type keyword comes before the name
+    let name = decl.split_whitespace().last()?;
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then(|| (name.to_string(), default, variadic))
+}
+
 /// Extract all `define!` macro definitions from fully-preprocessed source.
 fn extract_macro_definitions(source: &str) -> Vec<MacroDef> {
     let mut macros = Vec::new();
     let bytes = source.as_bytes();
     let len = bytes.len();
     let mut i = 0;
+    // Newlines before `counted`, for definition line numbers
+    let mut line = 0;
+    let mut counted = 0;
 
     while i < len {
         // Skip to potential "define!" keyword (only ASCII, safe to check byte)
@@ -300,48 +946,17 @@ fn extract_macro_definitions(source: &str) -> Vec<MacroDef> {
         }
         i += 1; // skip '('
 
-        // Parse params until ')'
+        // Parse params until ')'; defaults may contain parentheses and commas inside them
+        let Some(declared) = parse_balanced_args(source, &mut i) else {
+            continue;
+        };
         let mut params = Vec::new();
-        loop {
-            while i < len && bytes[i].is_ascii_whitespace() {
-                i += 1;
-            }
-            if i >= len {
-                break;
-            }
-            if bytes[i] == b')' {
-                i += 1;
-                break;
-            }
-            if bytes[i] == b',' {
-                i += 1;
-                continue;
-            }
-            // Skip optional type keyword (e.g., "int")
-            let token_start = i;
-            while i < len && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
-                i += 1;
-            }
-            let token = &source[token_start..i];
-
-            // Peek ahead: if there's another identifier after whitespace before , or ),
-            // this token is a type and the next is the param name
-            let saved = i;
-            while i < len && bytes[i].is_ascii_whitespace() {
-                i += 1;
-            }
-            if i < len && (bytes[i].is_ascii_alphabetic() || bytes[i] == b'_') {
-                // Next token is the real param name
-                let pname_start = i;
-                while i < len && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
-                    i += 1;
-                }
-                params.push(source[pname_start..i].to_string());
-            } else {
-                // This token is the param name (no type prefix)
-                i = saved;
-                params.push(token.to_string());
-            }
+        let mut defaults = Vec::new();
+        let mut variadic = false;
+        for param in declared.iter().filter_map(|p| parse_macro_param(p)) {
+            params.push(param.0);
+            defaults.push(param.1);
+            variadic = param.2;
         }
 
         // Skip whitespace to opening brace
@@ -379,13 +994,18 @@ fn extract_macro_definitions(source: &str) -> Vec<MacroDef> {
         let body_end = i - 1; // before the closing '}'
         let body = source[body_start..body_end].to_string();
         let has_placeholder = body.contains("%0");
+        line += source[counted..block_start].matches('\n').count();
+        counted = block_start;
 
         macros.push(MacroDef {
             name,
             params,
+            defaults,
+            variadic,
             body,
             has_placeholder,
             byte_range: block_start..i,
+            line,
         });
     }
 
@@ -394,24 +1014,54 @@ fn extract_macro_definitions(source: &str) -> Vec<MacroDef> {
 
 /// Strip macro definitions from the source, returning content without `define!` blocks.
 fn strip_macro_definitions(source: &str, macros: &[MacroDef]) -> String {
+    strip_macro_definitions_traced(source, macros).0
+}
+
+/// Strip macro definitions, tracking which source line each remaining line came from.
+fn strip_macro_definitions_traced(source: &str, macros: &[MacroDef]) -> (String, Vec<LineTrace>) {
     if macros.is_empty() {
-        return source.to_string();
+        return (source.to_string(), identity_trace(source));
     }
 
-    let mut result = String::with_capacity(source.len());
+    let index = LineIndex::new(source);
+    let mut result = TracedString::new(&index, source.len());
     let mut pos = 0;
     for mac in macros {
-        result.push_str(&source[pos..mac.byte_range.start]);
+        result.push_copied(&source[pos..mac.byte_range.start], pos);
         pos = mac.byte_range.end;
         // Skip trailing newline if present
         if pos < source.len() && source.as_bytes()[pos] == b'\n' {
             pos += 1;
         }
     }
-    result.push_str(&source[pos..]);
-    result
+    result.push_copied(&source[pos..], pos);
+    result.finish(source.len().saturating_sub(1))
+}
+
+/// A problem with a macro call found during expansion.
+struct MacroError {
+    code: &'static str,
+    message: String,
+    /// 0-indexed line of the outermost call in the text passed to the expander
+    line: usize,
+    /// 1-indexed character columns of the outermost call's macro name
+    columns: Option<(usize, usize)>,
+    /// Macros being expanded when the problem was found, outermost first
+    trace: Vec<String>,
+}
+
+/// Result of macro expansion with line tracking.
+struct MacroExpansion {
+    text: String,
+    /// Origin of each output line in the text passed to the expander
+    lines: Vec<LineTrace>,
+    errors: Vec<MacroError>,
 }
 
+/// Most macro calls expanded in one source, so expansions that multiply (each macro
+/// calling the next several times) fail instead of exhausting memory.
+const MAX_MACRO_EXPANSIONS: usize = 10_000;
+
 /// Expand macro calls in the source. Returns the expanded content and any errors.
 ///
 /// Handles `name(args)!` and `name(args)! { body }` call syntax.
@@ -420,172 +1070,485 @@ fn expand_macro_calls(
     macros: &[MacroDef],
     errors: &mut Vec<String>,
 ) -> String {
+    let expansion = expand_macro_calls_traced(source, macros);
+    errors.extend(expansion.errors.into_iter().map(|e| e.message));
+    expansion.text
+}
+
+/// Expand macro calls, tracking the input line and macro chain behind every output line.
+///
+/// Macros used inside a macro body are expanded as part of the call, so a macro
+/// that reaches itself again is reported instead of expanding forever. Arguments
+/// and caller bodies are expanded where they were written, before substitution.
+fn expand_macro_calls_traced(source: &str, macros: &[MacroDef]) -> MacroExpansion {
     if macros.is_empty() {
-        return source.to_string();
+        return MacroExpansion {
+            text: source.to_string(),
+            lines: identity_trace(source),
+            errors: Vec::new(),
+        };
+    }
+
+    let mut expander = MacroExpander {
+        macros,
+        errors: Vec::new(),
+        expansions: 0,
+    };
+    let segments = expander.scan(source, &mut Vec::new(), None);
+
+    let index = LineIndex::new(source);
+    let mut result = TracedString::new(&index, source.len());
+    for segment in &segments {
+        match segment {
+            Segment::Copied(text, pos) => result.push_copied(text, *pos),
+            Segment::Generated { text, pos, chains } => result.push_expansion(text, *pos, chains),
+        }
+    }
+    let (text, lines) = result.finish(source.len().saturating_sub(1));
+    MacroExpansion {
+        text,
+        lines,
+        errors: expander.errors,
+    }
+}
+
+/// The outermost macro call being expanded; errors in nested expansions are reported here.
+#[derive(Debug, Clone, Copy)]
+struct CallSite {
+    line: usize,
+    columns: Option<(usize, usize)>,
+}
+
+/// A piece of expander output.
+enum Segment<'t> {
+    /// Input text starting at the given byte offset, copied unchanged
+    Copied(&'t str, usize),
+    /// Expansion of the macro call at the given byte offset; line `i` of `text`
+    /// was produced by the macros in `chains[i]`, outermost first
+    Generated {
+        text: String,
+        pos: usize,
+        chains: Vec<Vec<String>>,
+    },
+}
+
+/// Join expander output, with the macro chain of each line (one entry per `\n`, plus one).
+fn flatten_segments(segments: &[Segment]) -> (String, Vec<Vec<String>>) {
+    let mut text = String::new();
+    let mut chains = Vec::new();
+    let mut pending: Vec<String> = Vec::new();
+    for segment in segments {
+        match segment {
+            Segment::Copied(copied, _) => {
+                for _ in copied.matches('\n') {
+                    chains.push(std::mem::take(&mut pending));
+                }
+                text.push_str(copied);
+            }
+            Segment::Generated {
+                text: generated,
+                chains: generated_chains,
+                ..
+            } => {
+                let pieces = generated.split('\n').count();
+                for (idx, piece) in generated.split('\n').enumerate() {
+                    if !piece.is_empty() || idx + 1 < pieces {
+                        if let Some(chain) = generated_chains.get(idx) {
+                            merge_chain(&mut pending, chain);
+                        }
+                    }
+                    if idx + 1 < pieces {
+                        chains.push(std::mem::take(&mut pending));
+                    }
+                }
+                text.push_str(generated);
+            }
+        }
+    }
+    chains.push(pending);
+    (text, chains)
+}
+
+/// Add the macros of `chain` to `into`, skipping ones already there.
+fn merge_chain(into: &mut Vec<String>, chain: &[String]) {
+    for name in chain {
+        if !into.contains(name) {
+            into.push(name.clone());
+        }
     }
+}
 
-    let mut result = source.to_string();
+/// Macro expansion state shared by nested expansions.
+struct MacroExpander<'m> {
+    macros: &'m [MacroDef],
+    errors: Vec<MacroError>,
+    /// Calls expanded so far; also numbers hygienic identifiers
+    expansions: usize,
+}
 
-    // Iterate expansion passes (macros may produce other macro calls)
-    for _pass in 0..16 {
-        let mut any_expanded = false;
-        let mut new_result = String::with_capacity(result.len());
-        let bytes = result.as_bytes();
+impl<'m> MacroExpander<'m> {
+    /// Expand every macro call in `text`. `stack` holds the macros whose expansion
+    /// `text` is part of; `outer` is the call being expanded, if any.
+    fn scan<'t>(
+        &mut self,
+        text: &'t str,
+        stack: &mut Vec<&'m MacroDef>,
+        outer: Option<CallSite>,
+    ) -> Vec<Segment<'t>> {
+        let bytes = text.as_bytes();
         let len = bytes.len();
+        let index = outer.is_none().then(|| LineIndex::new(text));
+        let mut segments = Vec::new();
+        // Start of input not yet emitted
+        let mut copied_to = 0;
         let mut i = 0;
 
         while i < len {
-            // Non-ASCII byte: copy full UTF-8 character and continue
+            // Non-ASCII byte: skip the full UTF-8 character
             if bytes[i] > 0x7F {
-                let clen = utf8_char_len(bytes[i]);
-                new_result.push_str(&result[i..i + clen.min(len - i)]);
-                i += clen;
+                i += utf8_char_len(bytes[i]);
                 continue;
             }
 
-            // Check if we're in a comment
+            // Skip line comments
             if bytes[i] == b'/' && i + 1 < len && bytes[i + 1] == b'/' {
-                // Copy rest of line (line content may have non-ASCII)
-                let line_end = result[i..].find('\n').map(|p| i + p).unwrap_or(len);
-                new_result.push_str(&result[i..line_end]);
-                i = line_end;
+                i = text[i..].find('\n').map(|p| i + p).unwrap_or(len);
                 continue;
             }
 
-            // Look for identifier followed by ( ... )!
-            if bytes[i].is_ascii_alphabetic() || bytes[i] == b'_' {
-                let ident_start = i;
-                while i < len && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
-                    i += 1;
-                }
-                let ident = &result[ident_start..i];
+            if !(bytes[i].is_ascii_alphabetic() || bytes[i] == b'_') {
+                i += 1;
+                continue;
+            }
 
-                // Check if this identifier matches a macro name
-                let mac = macros.iter().find(|m| m.name == ident);
-                if mac.is_none() {
-                    new_result.push_str(ident);
-                    continue;
-                }
-                let mac = mac.unwrap();
+            // Look for identifier followed by ( ... )!
+            let ident_start = i;
+            while i < len && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            let ident = &text[ident_start..i];
+            let Some(mac) = self.macros.iter().find(|m| m.name == ident) else {
+                continue;
+            };
+            let after_ident = i;
 
-                // Skip whitespace
-                let saved_i = i;
-                while i < len && bytes[i].is_ascii_whitespace() && bytes[i] != b'\n' {
-                    i += 1;
-                }
+            // Skip whitespace, expect '('
+            while i < len && bytes[i].is_ascii_whitespace() && bytes[i] != b'\n' {
+                i += 1;
+            }
+            if i >= len || bytes[i] != b'(' {
+                i = after_ident;
+                continue;
+            }
+            i += 1; // skip '('
+            let Some(args) = parse_balanced_args(text, &mut i) else {
+                i = after_ident;
+                continue;
+            };
 
-                // Expect '('
-                if i >= len || bytes[i] != b'(' {
-                    new_result.push_str(ident);
-                    i = saved_i;
-                    continue;
-                }
+            // Expect '!'; otherwise this is a regular function call sharing the name
+            while i < len && bytes[i].is_ascii_whitespace() && bytes[i] != b'\n' {
+                i += 1;
+            }
+            if i >= len || bytes[i] != b'!' {
+                i = after_ident;
+                continue;
+            }
+            i += 1; // skip '!'
 
-                // Parse arguments inside ( ... )
-                i += 1; // skip '('
-                let args = match parse_balanced_args(&result, &mut i) {
-                    Some(a) => a,
-                    None => {
-                        new_result.push_str(ident);
-                        i = saved_i;
-                        continue;
+            // Optional body block { ... }
+            let mut caller_body = String::new();
+            let after_bang = i;
+            while i < len && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            if i < len && bytes[i] == b'{' {
+                i += 1; // skip '{'
+                let body_start = i;
+                let mut depth = 1;
+                while i < len && depth > 0 {
+                    match bytes[i] {
+                        b'{' => depth += 1,
+                        b'}' => depth -= 1,
+                        _ => {}
                     }
-                };
-
-                // Skip whitespace
-                while i < len && bytes[i].is_ascii_whitespace() && bytes[i] != b'\n' {
                     i += 1;
                 }
-
-                // Expect '!'
-                if i >= len || bytes[i] != b'!' {
-                    // Not a macro call, just a regular function call
-                    new_result.push_str(ident);
-                    new_result.push('(');
-                    new_result.push_str(&args.join(", "));
-                    new_result.push(')');
-                    continue;
+                if depth == 0 {
+                    caller_body = text[body_start..i - 1].trim().to_string();
                 }
-                i += 1; // skip '!'
+            } else {
+                i = after_bang;
+            }
 
-                // Optional body block { ... }
-                let mut caller_body = String::new();
-                let saved_after_bang = i;
-                // Skip whitespace (including newlines) to find optional body
-                while i < len && bytes[i].is_ascii_whitespace() {
-                    i += 1;
-                }
-                if i < len && bytes[i] == b'{' {
-                    i += 1; // skip '{'
-                    let body_start = i;
-                    let mut depth = 1;
-                    while i < len && depth > 0 {
-                        match bytes[i] {
-                            b'{' => depth += 1,
-                            b'}' => depth -= 1,
-                            _ => {}
-                        }
-                        i += 1;
-                    }
-                    if depth == 0 {
-                        caller_body = result[body_start..i - 1].trim().to_string();
-                    }
-                } else {
-                    // No body block
-                    i = saved_after_bang;
+            let site = outer.unwrap_or_else(|| {
+                let line_start = text[..ident_start].rfind('\n').map(|p| p + 1).unwrap_or(0);
+                let col = text[line_start..ident_start].chars().count() + 1;
+                CallSite {
+                    line: index.as_ref().map(|x| x.line_of(ident_start)).unwrap_or(0),
+                    columns: Some((col, col + ident.chars().count())),
                 }
+            });
+            if copied_to < ident_start {
+                segments.push(Segment::Copied(&text[copied_to..ident_start], copied_to));
+            }
+            let (expanded, chains) = self.expand_call(mac, &args, &caller_body, stack, site);
+            segments.push(Segment::Generated {
+                text: expanded,
+                pos: ident_start,
+                chains,
+            });
+            copied_to = i;
+        }
 
-                // Validate: if macro has %0 placeholder, caller must provide a body
-                if mac.has_placeholder && caller_body.is_empty() {
-                    errors.push(format!(
-                        "Macro '{}' requires a body block because it contains a %0 placeholder",
-                        mac.name
-                    ));
-                    new_result.push_str(&format!("/* Error: macro '{}' requires body */", mac.name));
-                    any_expanded = true;
-                    continue;
-                }
+        if copied_to < len {
+            segments.push(Segment::Copied(&text[copied_to..], copied_to));
+        }
+        segments
+    }
 
-                // Validate argument count
-                if args.len() != mac.params.len() && !(args.len() == 1 && args[0].is_empty() && mac.params.is_empty()) {
-                    errors.push(format!(
-                        "Macro '{}' expects {} arguments but got {}",
-                        mac.name,
-                        mac.params.len(),
-                        args.len()
-                    ));
-                }
+    /// Expand `text` and return the result without line tracking.
+    fn expand_nested(
+        &mut self,
+        text: &str,
+        stack: &mut Vec<&'m MacroDef>,
+        site: CallSite,
+    ) -> String {
+        flatten_segments(&self.scan(text, stack, Some(site))).0
+    }
 
-                // Expand: substitute params and %0
-                let mut expanded = mac.body.clone();
+    fn error(&mut self, code: &'static str, message: String, site: CallSite, stack: &[&MacroDef]) {
+        self.errors.push(MacroError {
+            code,
+            message,
+            line: site.line,
+            columns: site.columns,
+            trace: stack.iter().map(|m| m.name.clone()).collect(),
+        });
+    }
 
-                // Substitute parameters (word-boundary aware)
-                for (idx, param) in mac.params.iter().enumerate() {
-                    if let Some(arg) = args.get(idx) {
-                        expanded = replace_word(&expanded, param, arg.trim());
-                    }
-                }
+    /// Expand one call of `mac`, including every macro call its expansion contains.
+    fn expand_call(
+        &mut self,
+        mac: &'m MacroDef,
+        args: &[String],
+        caller_body: &str,
+        stack: &mut Vec<&'m MacroDef>,
+        site: CallSite,
+    ) -> (String, Vec<Vec<String>>) {
+        let failed = |text: String| (text, vec![vec![mac.name.clone()]]);
+
+        if stack.iter().any(|m| m.name == mac.name) {
+            let mut path: Vec<&str> = stack.iter().map(|m| m.name.as_str()).collect();
+            path.push(&mac.name);
+            self.error(
+                codes::MACRO_RECURSION,
+                format!("Macro '{}' expands into itself: {}", mac.name, path.join(" -> ")),
+                site,
+                stack,
+            );
+            return failed(format!("/* Error: recursive macro '{}' */", mac.name));
+        }
 
-                // Substitute %0 placeholder with caller body
-                if mac.has_placeholder {
-                    expanded = expanded.replace("%0", &caller_body);
-                }
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            // Report once; every later call fails the same way
+            if self.expansions == MAX_MACRO_EXPANSIONS + 1 {
+                let mut path: Vec<&str> = stack.iter().map(|m| m.name.as_str()).collect();
+                path.push(&mac.name);
+                self.error(
+                    codes::MACRO_EXPANSION_LIMIT,
+                    format!(
+                        "Macro expansion stopped after {} calls while expanding {}",
+                        MAX_MACRO_EXPANSIONS,
+                        path.join(" -> ")
+                    ),
+                    site,
+                    stack,
+                );
+            }
+            return failed(format!("/* Error: macro '{}' not expanded */", mac.name));
+        }
 
-                new_result.push_str(expanded.trim());
-                any_expanded = true;
-            } else {
-                // ASCII non-identifier character
-                new_result.push(bytes[i] as char);
-                i += 1;
+        // Validate: if macro has %0 placeholder, caller must provide a body
+        if mac.has_placeholder && caller_body.is_empty() {
+            self.error(
+                codes::MACRO_BODY_REQUIRED,
+                format!(
+                    "Macro '{}' requires a body block because it contains a %0 placeholder",
+                    mac.name
+                ),
+                site,
+                stack,
+            );
+            return failed(format!("/* Error: macro '{}' requires body */", mac.name));
+        }
+
+        // `name()!` passes no arguments rather than one empty one
+        let args: &[String] = if args.len() == 1 && args[0].is_empty() { &[] } else { args };
+        let (required, max) = mac.arity();
+        if args.len() < required || max.is_some_and(|max| args.len() > max) {
+            let expected = match max {
+                Some(max) if max == required => format!("{}", required),
+                Some(max) => format!("{} to {}", required, max),
+                None => format!("at least {}", required),
+            };
+            self.error(
+                codes::MACRO_ARITY,
+                format!(
+                    "Macro '{}' expects {} arguments but got {}",
+                    mac.name,
+                    expected,
+                    args.len()
+                ),
+                site,
+                stack,
+            );
+        }
+
+        // Arguments and the caller's body belong to the caller, so they are expanded
+        // in its context and do not count as recursion of this macro
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| self.expand_nested(arg.trim(), stack, site))
+            .collect();
+        let caller_body = self.expand_nested(caller_body, stack, site);
+
+        let expanded = substitute_macro_body(mac, &args, &caller_body, self.expansions);
+        let expanded = expanded.trim();
+        stack.push(mac);
+        let segments = self.scan(expanded, stack, Some(site));
+        stack.pop();
+
+        let (text, inner) = flatten_segments(&segments);
+        let chains = inner
+            .into_iter()
+            .map(|chain| {
+                let mut full = vec![mac.name.clone()];
+                merge_chain(&mut full, &chain);
+                full
+            })
+            .collect();
+        (text, chains)
+    }
+}
+
+/// Fill in a macro body for one call: hygienic locals, `%for` repetitions,
+/// parameters (with defaults and the variadic rest) and the `%0` caller body.
+///
+/// `$name` in the body becomes an identifier unique to this expansion, so
+/// temporaries of two expansions never collide. `%for(x in rest) { ... }` repeats
+/// its block once per argument collected by the variadic `rest`, with `x` bound
+/// to the argument. A bare `rest` becomes the remaining arguments joined by commas.
+fn substitute_macro_body(
+    mac: &MacroDef,
+    args: &[String],
+    caller_body: &str,
+    expansion: usize,
+) -> String {
+    let mut expanded = rename_macro_locals(&mac.body, &mac.name, expansion);
+
+    let fixed = mac.params.len() - usize::from(mac.variadic);
+    let rest: &[String] = if mac.variadic { args.get(fixed..).unwrap_or(&[]) } else { &[] };
+    let values: Vec<Vec<String>> = mac
+        .params
+        .iter()
+        .enumerate()
+        .map(|(idx, _)| {
+            if mac.variadic && idx == fixed {
+                return rest.to_vec();
+            }
+            let default = mac.defaults.get(idx).cloned().flatten();
+            match args.get(idx) {
+                Some(arg) if !arg.is_empty() => vec![arg.clone()],
+                Some(arg) => vec![default.unwrap_or_else(|| arg.clone())],
+                None => default.into_iter().collect(),
             }
+        })
+        .collect();
+    expanded = expand_macro_repetitions(&expanded, &mac.params, &values);
+
+    // Substitute parameters (word-boundary aware); ones without a value stay as written
+    for (idx, (param, value)) in mac.params.iter().zip(&values).enumerate() {
+        if value.is_empty() && !(mac.variadic && idx == fixed) {
+            continue;
         }
+        expanded = replace_word(&expanded, param, value.join(", ").trim());
+    }
 
-        result = new_result;
-        if !any_expanded {
-            break;
+    // Substitute %0 placeholder with caller body
+    if mac.has_placeholder {
+        expanded = expanded.replace("%0", caller_body);
+    }
+    expanded
+}
+
+/// Replace `$name` identifiers with names unique to one expansion of `macro_name`.
+fn rename_macro_locals(body: &str, macro_name: &str, expansion: usize) -> String {
+    let mut result = String::with_capacity(body.len());
+    let mut rest = body;
+    while let Some(pos) = rest.find('$') {
+        result.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        let ident_len = after
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(after.len());
+        if ident_len == 0 || after.starts_with(|c: char| c.is_ascii_digit()) {
+            result.push('$');
+        } else {
+            result.push_str(&format!("_{}_{}_{}", macro_name, &after[..ident_len], expansion));
         }
+        rest = &after[ident_len..];
     }
+    result.push_str(rest);
+    result
+}
 
+/// Expand `%for(item in param) { block }` into one copy of `block` per value of
+/// `param`, with `item` replaced by the value. Copies are separated by a space.
+fn expand_macro_repetitions(body: &str, params: &[String], values: &[Vec<String>]) -> String {
+    const KEYWORD: &str = "%for(";
+    let mut result = String::with_capacity(body.len());
+    let mut rest = body;
+    while let Some(pos) = rest.find(KEYWORD) {
+        result.push_str(&rest[..pos]);
+        let after = &rest[pos + KEYWORD.len()..];
+        let parsed = after.find(')').and_then(|close| {
+            let (item, list) = after[..close].split_once(" in ")?;
+            let block = after[close + 1..].trim_start().strip_prefix('{')?;
+            let mut depth = 1;
+            let end = block.char_indices().find_map(|(i, c)| {
+                match c {
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    _ => {}
+                }
+                (depth == 0).then_some(i)
+            })?;
+            Some((item.trim(), list.trim(), &block[..end], &block[end + 1..]))
+        });
+        let Some((item, list, block, remainder)) = parsed else {
+            // Not a well-formed repetition; leave it for the compiler to reject
+            result.push_str(KEYWORD);
+            rest = after;
+            continue;
+        };
+        let items = params
+            .iter()
+            .position(|p| p == list)
+            .map(|idx| values[idx].as_slice())
+            .unwrap_or(&[]);
+        let copies: Vec<String> = items
+            .iter()
+            .map(|value| {
+                let copy = replace_word(block.trim(), item, value.trim());
+                expand_macro_repetitions(&copy, params, values)
+            })
+            .collect();
+        result.push_str(&copies.join(" "));
+        rest = remainder;
+    }
+    result.push_str(rest);
     result
 }
 
@@ -640,28 +1603,19 @@ fn parse_balanced_args(source: &str, pos: &mut usize) -> Option<Vec<String>> {
     let bytes = source.as_bytes();
     let len = bytes.len();
     let mut args = Vec::new();
-    let mut current = String::new();
+    // Parentheses are ASCII, so slicing at them keeps multi-byte characters intact
+    let mut start = *pos;
     let mut depth = 1;
 
     while *pos < len && depth > 0 {
         match bytes[*pos] {
-            b'(' => {
-                depth += 1;
-                current.push('(');
-            }
-            b')' => {
-                depth -= 1;
-                if depth > 0 {
-                    current.push(')');
-                }
-            }
+            b'(' => depth += 1,
+            b')' => depth -= 1,
             b',' if depth == 1 => {
-                args.push(current.trim().to_string());
-                current = String::new();
-            }
-            c => {
-                current.push(c as char);
+                args.push(source[start..*pos].trim().to_string());
+                start = *pos + 1;
             }
+            _ => {}
         }
         *pos += 1;
     }
@@ -670,7 +1624,7 @@ fn parse_balanced_args(source: &str, pos: &mut usize) -> Option<Vec<String>> {
         return None;
     }
 
-    let trimmed = current.trim().to_string();
+    let trimmed = source[start..*pos - 1].trim().to_string();
     if !trimmed.is_empty() || !args.is_empty() {
         args.push(trimmed);
     }
@@ -678,6 +1632,159 @@ fn parse_balanced_args(source: &str, pos: &mut usize) -> Option<Vec<String>> {
     Some(args)
 }
 
+/// Options for a single build beyond the defaults.
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    /// Name of a profile in game.json `build_profiles` whose defines to apply
+    pub profile: Option<String>,
+    /// Extra preprocessor symbols (e.g. from the command line); these override all others
+    pub defines: BTreeMap<String, String>,
+    /// Rebuild everything, ignoring and not updating `.zenforge/cache`
+    pub no_cache: bool,
+    /// Remove unreachable declarations even if game.json does not enable `tree_shake`
+    pub tree_shake: bool,
+    /// Optimize the output even if game.json does not enable `optimize`
+    pub optimize: bool,
+    /// Name of an entry of game.json `targets` to build instead of the game's own console
+    pub target: Option<String>,
+    /// Progress reporting and cancellation for builds run in the background
+    pub monitor: BuildMonitor,
+    /// Value of the `{build}` placeholder if the build writes output, so every target
+    /// of one build shares it; the number after the game's build counter if not set
+    pub build_number: Option<u64>,
+}
+
+/// Step a build is working on, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildPhase {
+    /// Reading game.json, plugins and the build cache
+    Preparing,
+    /// Expanding imports and conditional blocks; reported once per file
+    Preprocessing,
+    /// Expanding macros, checking declarations and tree shaking
+    Expanding,
+    /// Checking syntax and device resource limits
+    Checking,
+    /// Writing the output, source map, manifest and artifact
+    Writing,
+}
+
+impl BuildPhase {
+    /// Rough share of a build done once this phase starts, in percent.
+    pub fn percent(self) -> u8 {
+        match self {
+            BuildPhase::Preparing => 0,
+            BuildPhase::Preprocessing => 10,
+            BuildPhase::Expanding => 60,
+            BuildPhase::Checking => 75,
+            BuildPhase::Writing => 90,
+        }
+    }
+}
+
+type ProgressFn = dyn Fn(BuildPhase, Option<&str>) + Send + Sync;
+
+/// Watches a build from another thread: receives each phase (and the file being
+/// preprocessed) and can cancel the build, which then stops at the next phase.
+/// Clones share the same cancellation flag.
+#[derive(Clone, Default)]
+pub struct BuildMonitor {
+    cancelled: Arc<AtomicBool>,
+    on_progress: Option<Arc<ProgressFn>>,
+}
+
+impl std::fmt::Debug for BuildMonitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BuildMonitor")
+            .field("cancelled", &self.is_cancelled())
+            .finish_non_exhaustive()
+    }
+}
+
+impl BuildMonitor {
+    pub fn new(on_progress: impl Fn(BuildPhase, Option<&str>) + Send + Sync + 'static) -> Self {
+        Self {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            on_progress: Some(Arc::new(on_progress)),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn report(&self, phase: BuildPhase, file: Option<&str>) {
+        if let Some(ref on_progress) = self.on_progress {
+            on_progress(phase, file);
+        }
+    }
+}
+
+/// Turn a value like "xbox-one" into a symbol suffix like "XBOX_ONE".
+fn symbol_suffix(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect()
+}
+
+/// Compute the symbols visible to `#if`/`#ifdef` for a build.
+///
+/// Sources, lowest precedence first: game metadata (`CONSOLE_TYPE`, `GAME_TYPE`,
+/// `GAME_VERSION` plus flags like `CONSOLE_PS5` and `GAME_TYPE_FPS`), plugin
+/// `extra_defines`, the selected build target (also sets `TARGET` and
+/// `TARGET_<NAME>`), the selected build profile (also sets `PROFILE` and
+/// `PROFILE_<NAME>`), then explicit defines from `options`.
+///
+/// When a target is selected, `meta` should already be the target's view of the
+/// game (see `GameMeta::for_target`) so the console symbols match it.
+pub fn build_symbols(
+    meta: Option<&GameMeta>,
+    plugin_defines: Option<&BTreeMap<String, String>>,
+    options: &BuildOptions,
+) -> Result<BTreeMap<String, String>, String> {
+    let mut symbols = BTreeMap::new();
+
+    if let Some(m) = meta {
+        symbols.insert("CONSOLE_TYPE".to_string(), format!("\"{}\"", m.console_type));
+        symbols.insert("GAME_TYPE".to_string(), format!("\"{}\"", m.game_type));
+        symbols.insert("GAME_VERSION".to_string(), m.version.to_string());
+        symbols.insert(format!("CONSOLE_{}", symbol_suffix(&m.console_type)), "1".to_string());
+        symbols.insert(format!("GAME_TYPE_{}", symbol_suffix(&m.game_type)), "1".to_string());
+    }
+
+    if let Some(defines) = plugin_defines {
+        symbols.extend(defines.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+
+    if let Some(ref name) = options.target {
+        let target = meta
+            .and_then(|m| m.target(name))
+            .ok_or_else(|| format!("Build target '{}' not found in game.json", name))?;
+        symbols.insert("TARGET".to_string(), format!("\"{}\"", name));
+        symbols.insert(format!("TARGET_{}", symbol_suffix(name)), "1".to_string());
+        symbols.extend(target.defines.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+
+    if let Some(ref name) = options.profile {
+        let profile = meta
+            .and_then(|m| m.build_profiles.as_ref())
+            .and_then(|profiles| profiles.get(name))
+            .ok_or_else(|| format!("Build profile '{}' not found in game.json", name))?;
+        symbols.insert("PROFILE".to_string(), format!("\"{}\"", name));
+        symbols.insert(format!("PROFILE_{}", symbol_suffix(name)), "1".to_string());
+        symbols.extend(profile.defines.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+
+    symbols.extend(options.defines.iter().map(|(k, v)| (k.clone(), v.clone())));
+    Ok(symbols)
+}
+
 /// Build a game: preprocess main.gpc and write the output to dist/.
 ///
 /// - `game_dir`: path to the game directory (e.g., Games/Shooter/R6S)
@@ -690,7 +1797,7 @@ pub fn build_game(
     dist_base: &Path,
     verbose: bool,
 ) -> BuildResult {
-    build_game_impl(game_dir, project_root, dist_base, verbose, None)
+    build_game_impl(game_dir, project_root, dist_base, verbose, None, &BuildOptions::default())
 }
 
 /// Build a game with plugin support from the given workspace path.
@@ -701,55 +1808,241 @@ pub fn build_game_with_plugins(
     verbose: bool,
     workspace_path: &str,
 ) -> BuildResult {
-    build_game_impl(game_dir, project_root, dist_base, verbose, Some(workspace_path))
+    build_game_impl(
+        game_dir,
+        project_root,
+        dist_base,
+        verbose,
+        Some(workspace_path),
+        &BuildOptions::default(),
+    )
+}
+
+/// Build a game with an optional plugin workspace and a build profile and/or extra defines.
+pub fn build_game_with_options(
+    game_dir: &Path,
+    project_root: &Path,
+    dist_base: &Path,
+    verbose: bool,
+    workspace_path: Option<&str>,
+    options: &BuildOptions,
+) -> BuildResult {
+    build_game_impl(game_dir, project_root, dist_base, verbose, workspace_path, options)
+}
+
+/// Build result of one target of a game.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TargetBuildResult {
+    /// `None` when the game declares no targets and was built as-is
+    pub target: Option<String>,
+    pub console_type: Option<String>,
+    pub result: BuildResult,
+}
+
+/// Results of building every target of a game.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MatrixBuildResult {
+    /// Whether every target built
+    pub success: bool,
+    pub targets: Vec<TargetBuildResult>,
+}
+
+impl MatrixBuildResult {
+    /// Number of targets that built successfully.
+    pub fn built(&self) -> usize {
+        self.targets.iter().filter(|t| t.result.success).count()
+    }
+}
+
+/// Build every entry of game.json `targets` into its own file in dist/, or the
+/// game itself if it declares no targets. `options.target` is ignored.
+pub fn build_game_targets(
+    game_dir: &Path,
+    project_root: &Path,
+    dist_base: &Path,
+    verbose: bool,
+    workspace_path: Option<&str>,
+    options: &BuildOptions,
+) -> MatrixBuildResult {
+    let meta = read_game_meta(game_dir);
+    let targets = meta
+        .as_ref()
+        .and_then(|m| m.targets.clone())
+        .unwrap_or_default();
+    let default_console = meta.as_ref().map(|m| m.console_type.clone());
+    let options = BuildOptions {
+        build_number: Some(
+            options.build_number.unwrap_or_else(|| last_build_number(game_dir) + 1),
+        ),
+        ..options.clone()
+    };
+    let mut results = Vec::new();
+
+    if targets.is_empty() {
+        let options = BuildOptions {
+            target: None,
+            ..options.clone()
+        };
+        results.push(TargetBuildResult {
+            target: None,
+            console_type: default_console.clone(),
+            result: build_game_impl(
+                game_dir,
+                project_root,
+                dist_base,
+                verbose,
+                workspace_path,
+                &options,
+            ),
+        });
+    }
+
+    // Targets must not overwrite each other's output
+    let mut claimed: HashMap<String, String> = HashMap::new();
+    for target in targets {
+        let console_type = target.console_type.clone().or_else(|| default_console.clone());
+        let clash = match resolve_output_filename(
+            game_dir,
+            Some(&target.name),
+            options.profile.as_deref(),
+            options.build_number.unwrap_or_default(),
+        ) {
+            Ok((filename, _)) => match claimed.get(&filename) {
+                Some(other) if *other == target.name => {
+                    Some(format!("Build target '{}' is declared twice", target.name))
+                }
+                Some(other) => Some(format!(
+                    "Build targets '{}' and '{}' both write {}",
+                    other, target.name, filename
+                )),
+                None => {
+                    claimed.insert(filename, target.name.clone());
+                    None
+                }
+            },
+            // Reported by the build itself
+            Err(_) => None,
+        };
+        let result = match clash {
+            Some(message) => BuildResult::failure(
+                Path::new(""),
+                Diagnostic::error(codes::BUILD_CONFIG, message),
+            ),
+            None => {
+                let options = BuildOptions {
+                    target: Some(target.name.clone()),
+                    ..options.clone()
+                };
+                build_game_impl(game_dir, project_root, dist_base, verbose, workspace_path, &options)
+            }
+        };
+        results.push(TargetBuildResult {
+            target: Some(target.name),
+            console_type,
+            result,
+        });
+    }
+
+    MatrixBuildResult {
+        success: results.iter().all(|t| t.result.success),
+        targets: results,
+    }
+}
+
+/// What the artifact registry records about a build besides its output.
+fn artifact_info(
+    game_dir: &Path,
+    game_meta: Option<&GameMeta>,
+    workspace_path: Option<&str>,
+    options: &BuildOptions,
+    inputs: &[FileHash],
+) -> BuildInfo {
+    let (game_version, console_type) = match game_meta {
+        Some(meta) => (meta.version, meta.console_type.clone()),
+        None => std::fs::read_to_string(game_dir.join("config.toml"))
+            .ok()
+            .and_then(|content| toml::from_str::<GameConfig>(&content).ok())
+            .map(|config| (config.version, config.console_type.unwrap_or_default()))
+            .unwrap_or_default(),
+    };
+    let plugins = workspace_path
+        .map(crate::commands::plugins::enabled_manifests)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|manifest| PluginRef {
+            id: manifest.id,
+            version: manifest.version,
+        })
+        .collect();
+    BuildInfo {
+        target: options.target.clone(),
+        game_version,
+        console_type,
+        profile: options.profile.clone(),
+        plugins,
+        modules: artifacts::game_modules(game_dir),
+        source_snapshot: artifacts::source_snapshot(game_dir, inputs),
+    }
+}
+
+/// Read game.json, if the game has one and it parses.
+fn read_game_meta(game_dir: &Path) -> Option<GameMeta> {
+    let meta_path = game_dir.join("game.json");
+    if meta_path.exists() {
+        std::fs::read_to_string(&meta_path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<GameMeta>(&content).ok())
+    } else {
+        None
+    }
 }
 
 fn build_game_impl(
     game_dir: &Path,
-    _project_root: &Path,
+    project_root: &Path,
     dist_base: &Path,
     verbose: bool,
     workspace_path: Option<&str>,
+    options: &BuildOptions,
 ) -> BuildResult {
     let main_path = game_dir.join("main.gpc");
+    let monitor = &options.monitor;
+    monitor.report(BuildPhase::Preparing, None);
+    let build_number = options.build_number.unwrap_or_else(|| last_build_number(game_dir) + 1);
 
     // Resolve output filename: try game.json first, fall back to config.toml
-    let output_filename = match resolve_output_filename(game_dir) {
-        Ok(f) => f,
+    let (output_filename, filename_warnings) = match resolve_output_filename(
+        game_dir,
+        options.target.as_deref(),
+        options.profile.as_deref(),
+        build_number,
+    ) {
+        Ok(resolved) => resolved,
         Err(e) => {
-            return BuildResult {
-                output_path: String::new(),
-                success: false,
-                errors: vec![e],
-                warnings: Vec::new(),
-            };
+            return BuildResult::failure(Path::new(""), Diagnostic::error(codes::BUILD_CONFIG, e))
         }
     };
     let dist_dir = dist_base.join("dist");
 
     // Ensure dist directory exists
     if let Err(e) = std::fs::create_dir_all(&dist_dir) {
-        return BuildResult {
-            output_path: String::new(),
-            success: false,
-            errors: vec![format!("Could not create dist directory: {}", e)],
-            warnings: Vec::new(),
-        };
+        return BuildResult::failure(
+            Path::new(""),
+            Diagnostic::error(codes::BUILD_IO, format!("Could not create dist directory: {}", e)),
+        );
     }
 
     let output_path = dist_dir.join(&output_filename);
 
     // Check main.gpc exists
     if !main_path.exists() {
-        return BuildResult {
-            output_path: output_path.to_string_lossy().to_string(),
-            success: false,
-            errors: vec![format!(
-                "main.gpc not found at {}",
-                main_path.display()
-            )],
-            warnings: Vec::new(),
-        };
+        return BuildResult::failure(
+            &output_path,
+            Diagnostic::error(
+                codes::BUILD_CONFIG,
+                format!("main.gpc not found at {}", main_path.display()),
+            ),
+        );
     }
 
     // Collect plugin hooks if workspace path is provided
@@ -757,6 +2050,43 @@ fn build_game_impl(
         .map(crate::commands::plugins::collect_enabled_hooks)
         .unwrap_or_default();
 
+    // Read optional game.json (header comments, conditional compilation symbols),
+    // as seen by the selected target
+    let game_meta = match (read_game_meta(game_dir), options.target.as_deref()) {
+        (Some(meta), Some(target)) => match meta.for_target(target) {
+            Ok(meta) => Some(meta),
+            Err(e) => {
+                return BuildResult::failure(
+                    &output_path,
+                    Diagnostic::error(codes::BUILD_CONFIG, e),
+                )
+            }
+        },
+        (meta, _) => meta,
+    };
+    let cache_dir = cache::cache_dir(game_dir, options.target.as_deref());
+
+    let defines = match build_symbols(
+        game_meta.as_ref(),
+        plugin_hooks.extra_defines.as_ref(),
+        options,
+    ) {
+        Ok(d) => d,
+        Err(e) => {
+            return BuildResult::failure(&output_path, Diagnostic::error(codes::BUILD_CONFIG, e))
+        }
+    };
+
+    // The workspace is where dist/ goes
+    let search_roots = match import_search_roots(game_dir, project_root, dist_base) {
+        Ok(roots) => roots,
+        Err(e) => {
+            return BuildResult::failure(&output_path, Diagnostic::error(codes::BUILD_CONFIG, e))
+        }
+    };
+
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
     // Build source with plugin injections (pre_build, defines, vars, includes)
     let mut source = std::fs::read_to_string(&main_path)
         .unwrap_or_default();
@@ -774,6 +2104,12 @@ fn build_game_impl(
     }
     if let Some(ref includes) = plugin_hooks.includes {
         for inc_path in includes {
+            // Report missing plugin includes against the plugin manifest instead of
+            // letting the preprocessor point at the generated import line
+            if !Path::new(inc_path).exists() {
+                diagnostics.push(missing_plugin_include(inc_path));
+                continue;
+            }
             plugin_prefix.push_str(&format!("import {};\n", inc_path));
         }
     }
@@ -781,44 +2117,339 @@ fn build_game_impl(
         plugin_prefix.push_str(pre);
         plugin_prefix.push('\n');
     }
+    // Lines of the temp build file that precede the real main.gpc content
+    let mut prefix_lines = 0;
     if !plugin_prefix.is_empty() {
+        prefix_lines = plugin_prefix.matches('\n').count() + 1;
         source = format!("{}\n{}", plugin_prefix, source);
     }
 
+    let resource_settings = game_meta.as_ref().and_then(|m| m.resources.as_ref());
+    let tree_shake = options.tree_shake
+        || game_meta.as_ref().and_then(|m| m.tree_shake).unwrap_or(false);
+    let optimize = options.optimize
+        || game_meta.as_ref().and_then(|m| m.optimize).unwrap_or(false);
+
+    // Everything besides source files that affects the output
+    let key_base = {
+        let mut key = KeyHasher::new();
+        key.add("zenforge", env!("CARGO_PKG_VERSION").as_bytes())
+            .add("defines", format!("{:?}", defines).as_bytes())
+            .add("prefix", plugin_prefix.as_bytes())
+            .add("post_build", plugin_hooks.post_build.as_deref().unwrap_or("").as_bytes())
+            .add("tree_shake", &[tree_shake as u8])
+            .add("optimize", &[optimize as u8])
+            .add("roots", format!("{:?}", search_roots).as_bytes());
+        for name in ["game.json", "config.toml"] {
+            key.add(name, &std::fs::read(game_dir.join(name)).unwrap_or_default());
+        }
+        key.add(
+            WORKSPACE_CONFIG_FILE,
+            &std::fs::read(dist_base.join(WORKSPACE_CONFIG_FILE)).unwrap_or_default(),
+        );
+        if let Some(ws) = workspace_path {
+            for manifest in crate::commands::plugins::enabled_manifest_paths(ws) {
+                key.add("plugin", &std::fs::read(manifest).unwrap_or_default());
+            }
+        }
+        key
+    };
+    // The key of the output written under `filename` with the rendered `header`
+    let build_key = |filename: &str, header: &str| {
+        let mut key = key_base.clone();
+        key.add("output", filename.as_bytes())
+            .add("header", header.as_bytes());
+        key.finish()
+    };
+
+    // The last build is still current if rendering its templates with its own number
+    // gives the same output, e.g. no `{date}` has moved on since
+    let last_build = last_build_number(game_dir);
+    let cached = if options.no_cache || !diagnostics.is_empty() {
+        None
+    } else {
+        resolve_output_filename(
+            game_dir,
+            options.target.as_deref(),
+            options.profile.as_deref(),
+            last_build,
+        )
+        .ok()
+    };
+    if let Some((cached_filename, _)) = cached {
+        let cached_path = dist_dir.join(&cached_filename);
+        let (header, _) =
+            render_header(game_dir, game_meta.as_ref(), options, last_build, &cached_filename);
+        if let Some(record) = BuildRecord::load(&cache_dir) {
+            // An output the manifest does not describe is rebuilt so the manifest catches up
+            let described = BuildManifest::load(&dist_dir)
+                .covers(&cached_filename, &record.output_hash);
+            if described && record.is_fresh(&build_key(&cached_filename, &header), &cached_path) {
+                let resources = std::fs::read_to_string(&cached_path)
+                    .ok()
+                    .and_then(|content| {
+                        resources::check(resources::analyze(&content), resource_settings).ok()
+                    })
+                    .map(|(report, _)| report);
+                return BuildResult {
+                    output_path: record.output_path,
+                    success: true,
+                    errors: Vec::new(),
+                    warnings: record.warnings,
+                    source_map_path: record.source_map_path,
+                    cache: CacheStatus::Hit,
+                    resources,
+                    removed: record.removed,
+                    optimization: record.optimization,
+                };
+            }
+        }
+    }
+
+    // Filename template warnings are only reported by a real build; cache hits return
+    // the diagnostics stored with their record
+    diagnostics.extend(
+        filename_warnings
+            .into_iter()
+            .map(|w| Diagnostic::warning(codes::TEMPLATE, w)),
+    );
+
+    if monitor.is_cancelled() {
+        return BuildResult::cancelled(&output_path);
+    }
+    monitor.report(BuildPhase::Preprocessing, Some(&display_path(&main_path)));
+
     // Write augmented source to a temp file for preprocessing
     let temp_main = game_dir.join(".main_build.gpc");
     if let Err(e) = std::fs::write(&temp_main, &source) {
-        return BuildResult {
-            output_path: output_path.to_string_lossy().to_string(),
-            success: false,
-            errors: vec![format!("Could not write temp build file: {}", e)],
-            warnings: Vec::new(),
-        };
+        return BuildResult::failure(
+            &output_path,
+            Diagnostic::error(codes::BUILD_IO, format!("Could not write temp build file: {}", e)),
+        );
     }
 
-    // Run preprocessor (import expansion)
-    let (processed, logs, preprocess_success) = preprocess(&temp_main, verbose);
+    // Run preprocessor (conditional blocks and import expansion)
+    let preprocess_options = PreprocessOptions {
+        verbose,
+        defines: defines.clone(),
+        search_roots,
+        monitor: monitor.clone(),
+    };
+    let mut cache_status = CacheStatus::Disabled;
+    let preprocessed = if options.no_cache {
+        preprocess_mapped(&temp_main, &preprocess_options)
+    } else {
+        let mut fragments = FragmentCache::load(&cache_dir);
+        let preprocessed = preprocess_cached(&temp_main, &preprocess_options, &mut fragments);
+        if let Err(e) = fragments.save(&cache_dir) {
+            log::warn!("{}", e);
+        }
+        cache_status = if fragments.reused > 0 {
+            CacheStatus::Partial
+        } else {
+            CacheStatus::Miss
+        };
+        preprocessed
+    };
+    let processed = preprocessed.content;
+    for entry in &preprocessed.logs {
+        log::info!("{}", entry.message);
+    }
+    // The temp file is regenerated every build; main.gpc stands in for it
+    let temp_abs = temp_main.canonicalize().unwrap_or(temp_main.clone());
+    let mut inputs: Vec<FileHash> = preprocessed
+        .files
+        .iter()
+        .filter(|f| Path::new(&f.path) != temp_abs)
+        .cloned()
+        .collect();
+    // Canonical like the imported files, so records of the inputs do not depend on
+    // the directory the build was started from
+    let main_canonical = main_path.canonicalize().unwrap_or(main_path.clone());
+    inputs.push(FileHash::of(&main_canonical));
+
+    // Point lines of the temp file back at main.gpc; injected plugin lines have no source
+    let temp_display = temp_main
+        .canonicalize()
+        .map(|p| display_path(&p))
+        .unwrap_or_else(|_| display_path(&temp_main));
+    let main_display = display_path(&main_canonical);
+    let temp_source = preprocessed.sources.sources.iter().position(|s| *s == temp_display);
+    let mut sources = preprocessed.sources.sources;
+    if let Some(id) = temp_source {
+        sources[id] = main_display.clone();
+    }
+    let origins: Vec<Option<LineOrigin>> = preprocessed
+        .origins
+        .into_iter()
+        .map(|origin| {
+            if Some(origin.source) != temp_source {
+                return Some(origin);
+            }
+            if origin.line <= prefix_lines {
+                return None;
+            }
+            Some(LineOrigin {
+                line: origin.line - prefix_lines,
+                ..origin
+            })
+        })
+        .collect();
 
     // Clean up temp file
     let _ = std::fs::remove_file(&temp_main);
+    if monitor.is_cancelled() {
+        return BuildResult::cancelled(&output_path);
+    }
+    monitor.report(BuildPhase::Expanding, None);
 
-    let mut errors: Vec<String> = logs
-        .iter()
-        .filter(|l| l.level == "error")
-        .map(|l| l.message.clone())
-        .collect();
-    let warnings: Vec<String> = logs
-        .iter()
-        .filter(|l| l.level == "warn")
-        .map(|l| l.message.clone())
-        .collect();
+    let temp_file = TempFileMapping {
+        temp: &temp_display,
+        main: &main_display,
+        prefix_lines,
+    };
+    diagnostics.extend(
+        preprocessed
+            .diagnostics
+            .into_iter()
+            .map(|d| temp_file.remap(d)),
+    );
 
     // Macro expansion pass
     let macro_defs = extract_macro_definitions(&processed);
-    let stripped = strip_macro_definitions(&processed, &macro_defs);
-    let mut macro_errors = Vec::new();
-    let mut expanded = expand_macro_calls(&stripped, &macro_defs, &mut macro_errors);
-    errors.extend(macro_errors);
+    let (stripped, strip_trace) = strip_macro_definitions_traced(&processed, &macro_defs);
+    let expansion = expand_macro_calls_traced(&stripped, &macro_defs);
+    let mut expanded_trace = compose_traces(&strip_trace, &expansion.lines);
+    let origin_at = |line: usize| origins.get(line).cloned().flatten();
+    let macro_source = MacroSource {
+        processed: &processed,
+        origins: &origins,
+        sources: &sources,
+        macro_defs: &macro_defs,
+        strip_trace: &strip_trace,
+    };
+    diagnostics.extend(expansion.errors.into_iter().map(|e| macro_source.diagnostic(e)));
+    let mut expanded = expansion.text;
+
+    // The same function, combo or global declared twice only fails in Zen Studio,
+    // so report it here with both sites and how each file was imported
+    let mut imported_from: HashMap<&str, &ImportEdge> = HashMap::new();
+    for edge in &preprocessed.imports {
+        imported_from.entry(edge.file.as_str()).or_insert(edge);
+    }
+    let site_of = |line: usize, name: &str| {
+        let origin = expanded_trace.get(line).and_then(|trace| origin_at(trace.line))?;
+        let file = &sources[origin.source];
+        let text = expanded.lines().nth(line).unwrap_or_default();
+        Some(Span::find(file, origin.line, text, name))
+    };
+    let chain_of = |span: &Option<Span>| match span {
+        Some(span) => import_chain(&span.file, &imported_from, &temp_file),
+        None => Vec::new(),
+    };
+    for (first, second) in duplicate_declarations(&expanded) {
+        let identical = first.normalized == second.normalized;
+        let (code, message) = if first.kind != second.kind {
+            (
+                codes::DUPLICATE_SYMBOL,
+                format!(
+                    "'{}' is declared as a {} but is already a {}",
+                    second.name,
+                    second.kind.as_str(),
+                    first.kind.as_str()
+                ),
+            )
+        } else if identical {
+            (
+                codes::IDENTICAL_REDECLARATION,
+                format!(
+                    "{} '{}' is declared twice with identical definitions",
+                    capitalize(second.kind.as_str()),
+                    second.name
+                ),
+            )
+        } else {
+            (
+                codes::DUPLICATE_SYMBOL,
+                format!(
+                    "{} '{}' is declared twice with different definitions",
+                    capitalize(second.kind.as_str()),
+                    second.name
+                ),
+            )
+        };
+
+        let first_span = site_of(first.line, &first.name);
+        let second_span = site_of(second.line, &second.name);
+        let mut diagnostic = Diagnostic::error(code, message);
+        let mut related = chain_of(&second_span);
+        match first_span {
+            Some(ref span) => related.push(RelatedSpan {
+                span: span.clone(),
+                message: "first declared here".to_string(),
+            }),
+            None => diagnostic.message.push_str(" (first declared in code injected by plugins)"),
+        }
+        related.extend(chain_of(&first_span));
+        match second_span {
+            Some(span) => diagnostic = diagnostic.with_span(span),
+            None => {
+                diagnostic.message = format!("In code injected by plugins: {}", diagnostic.message)
+            }
+        }
+        diagnostics.push(diagnostic.with_related(related));
+    }
+
+    // post_build code is appended after tree shaking and optimizing, so the names it
+    // uses must survive both
+    let post_build_names = plugin_hooks
+        .post_build
+        .as_deref()
+        .map(treeshake::referenced_names)
+        .unwrap_or_default();
+
+    // Drop declarations nothing reaches
+    let mut removed = Vec::new();
+    if tree_shake {
+        let shaken = treeshake::shake(&expanded, &post_build_names);
+        removed = shaken
+            .removed
+            .into_iter()
+            .map(|symbol| RemovedSymbol {
+                location: expanded_trace
+                    .get(symbol.line)
+                    .and_then(|trace| origin_at(trace.line))
+                    .map(|origin| SourceLocation {
+                        file: sources[origin.source].clone(),
+                        line: origin.line,
+                        macros: Vec::new(),
+                    }),
+                kind: symbol.kind,
+                name: symbol.name,
+            })
+            .collect();
+        if verbose && !removed.is_empty() {
+            log::info!("Tree shaking removed {} unused declarations", removed.len());
+        }
+        expanded_trace = compose_traces(&expanded_trace, &shaken.lines);
+        expanded = shaken.text;
+    }
+
+    // Fold constants, inline defines used once and drop branches that never run
+    let mut optimization = None;
+    if optimize {
+        let optimized = optimize::optimize(&expanded, &post_build_names);
+        if verbose {
+            log::info!(
+                "Optimizer saved {} bytes and {} lines",
+                optimized.report.bytes_saved,
+                optimized.report.lines_saved
+            );
+        }
+        expanded_trace = compose_traces(&expanded_trace, &optimized.lines);
+        expanded = optimized.text;
+        optimization = Some(optimized.report);
+    }
 
     // Append post_build plugin code after all processing
     if let Some(ref post) = plugin_hooks.post_build {
@@ -827,89 +2458,674 @@ fn build_game_impl(
         expanded.push('\n');
     }
 
-    // Read optional header comments from game.json
-    let game_meta = {
-        let meta_path = game_dir.join("game.json");
-        if meta_path.exists() {
-            std::fs::read_to_string(&meta_path)
-                .ok()
-                .and_then(|content| serde_json::from_str::<GameMeta>(&content).ok())
-        } else {
+    // Write output
+    let (header, header_warnings) =
+        render_header(game_dir, game_meta.as_ref(), options, build_number, &output_filename);
+    diagnostics.extend(header_warnings.into_iter().map(|w| {
+        Diagnostic::warning(codes::TEMPLATE, format!("Header comments: {}", w))
+    }));
+    let final_content = format!("{}{}", header, expanded);
+    let header_lines = header.matches('\n').count();
+
+    // Plugin snippets and macros are spliced in as text and can leave broken code
+    // behind; fail here rather than when the script is compiled for the device
+    monitor.report(BuildPhase::Checking, None);
+    let syntax_errors = syntax::check(&final_content);
+    let syntax_ok = syntax_errors.is_empty();
+    let trace_at = |line: usize| line.checked_sub(header_lines).and_then(|l| expanded_trace.get(l));
+    for error in syntax_errors {
+        diagnostics.push(macro_source.syntax_diagnostic(error, trace_at));
+    }
+
+    // Check the output against device limits
+    let resources = match resources::check(resources::analyze(&final_content), resource_settings) {
+        Ok((report, resource_diagnostics)) => {
+            diagnostics.extend(resource_diagnostics);
+            Some(report)
+        }
+        Err(e) => {
+            diagnostics.push(Diagnostic::error(codes::BUILD_CONFIG, e));
             None
         }
     };
-    // For flow-based games, header comments are already handled by codegen-merged.ts
-    // Only prepend header comments for legacy config-based games
-    let is_flow_game = game_meta.as_ref()
-        .map(|m| m.generation_mode == "flow")
-        .unwrap_or(false);
-    let header_comments = if is_flow_game {
-        None
-    } else {
-        game_meta.as_ref().and_then(|m| m.header_comments.clone())
-    };
 
-    // Write output
-    let mut header = "".to_string();
-    if let Some(ref comments) = header_comments {
-        let substituted = substitute_header_vars(comments, game_meta.as_ref());
-        for line in substituted.lines() {
-            header.push_str(&format!("// {}\n", line));
+    if !syntax_ok {
+        if !options.no_cache {
+            BuildRecord::remove(&cache_dir);
         }
+        let (errors, warnings) = split_diagnostics(diagnostics);
+        return BuildResult {
+            output_path: output_path.to_string_lossy().to_string(),
+            success: false,
+            errors,
+            warnings,
+            source_map_path: None,
+            cache: cache_status,
+            resources,
+            removed,
+            optimization,
+        };
     }
-    if !header.is_empty() {
-        header.push('\n');
-    }
-    let final_content = format!("{}{}", header, expanded);
 
-    if let Err(e) = std::fs::write(&output_path, &final_content) {
-        errors.push(format!("Could not write output file: {}", e));
+    // Last point a cancelled build can stop without leaving a partial output behind
+    if monitor.is_cancelled() {
+        return BuildResult::cancelled(&output_path);
+    }
+    monitor.report(BuildPhase::Writing, Some(&display_path(&output_path)));
+    // Leave an identical output untouched so file watchers and timestamps only see real changes
+    let unchanged = std::fs::read(&output_path).is_ok_and(|old| old == final_content.as_bytes());
+    let written = if unchanged {
+        Ok(())
+    } else {
+        std::fs::write(&output_path, &final_content)
+    };
+    if let Err(e) = written {
+        diagnostics.push(Diagnostic::error(
+            codes::BUILD_IO,
+            format!("Could not write output file: {}", e),
+        ));
+        let (errors, warnings) = split_diagnostics(diagnostics);
         return BuildResult {
             output_path: output_path.to_string_lossy().to_string(),
             success: false,
             errors,
             warnings,
+            source_map_path: None,
+            cache: cache_status,
+            resources,
+            removed,
+            optimization,
         };
     }
 
-    BuildResult {
-        output_path: output_path.to_string_lossy().to_string(),
-        success: preprocess_success && errors.is_empty(),
-        errors,
-        warnings,
+    // Source map: header lines, then expanded lines, then generated post_build lines
+    let mut source_map = SourceMap::new(&output_filename);
+    source_map.sources = sources;
+    source_map.lines = vec![None; header_lines];
+    source_map.lines.extend(expanded_trace.iter().map(|trace| {
+        origin_at(trace.line).map(|mut origin| {
+            origin.macros.extend(trace.macros.iter().cloned());
+            origin
+        })
+    }));
+    source_map.lines.resize(final_content.lines().count(), None);
+
+    let map_path = sourcemap::map_path_for(&output_path);
+    let source_map_path = match source_map.save(&map_path) {
+        Ok(()) => Some(map_path.to_string_lossy().to_string()),
+        Err(e) => {
+            log::warn!("{}", e);
+            None
+        }
+    };
+
+    let output_hash = cache::hash_bytes(final_content.as_bytes());
+    let (errors, warnings) = split_diagnostics(diagnostics);
+    if errors.is_empty() {
+        record_build_number(game_dir, build_number);
+        let info = artifact_info(game_dir, game_meta.as_ref(), workspace_path, options, &inputs);
+        let entry = ManifestEntry {
+            output_hash: output_hash.clone(),
+            toolchain: manifest::toolchain(),
+            target: options.target.clone(),
+            profile: options.profile.clone(),
+            git_commit: template::git_commit(game_dir),
+            defines,
+            inputs: manifest::relative_inputs(dist_base, &inputs),
+            plugins: info.plugins.clone(),
+        };
+        if let Err(e) = manifest::record(&dist_dir, &output_filename, entry) {
+            log::warn!("{}", e);
+        }
+        if let Err(e) = artifacts::record(game_dir, &output_filename, &final_content, info) {
+            log::warn!("{}", e);
+        }
+    }
+    if !options.no_cache {
+        if errors.is_empty() {
+            let mut record = BuildRecord::new(
+                build_key(&output_filename, &header),
+                inputs,
+                output_path.to_string_lossy().to_string(),
+                output_hash,
+                source_map_path.clone(),
+                warnings.clone(),
+                removed.clone(),
+            );
+            record.optimization = optimization.clone();
+            if let Err(e) = record.save(&cache_dir) {
+                log::warn!("{}", e);
+            }
+        } else {
+            BuildRecord::remove(&cache_dir);
+        }
+    }
+    BuildResult {
+        output_path: output_path.to_string_lossy().to_string(),
+        success: errors.is_empty(),
+        errors,
+        warnings,
+        source_map_path,
+        cache: cache_status,
+        resources,
+        removed,
+        optimization,
+    }
+}
+
+/// Preprocessed source that macros were expanded in, for locating macro problems.
+struct MacroSource<'a> {
+    processed: &'a str,
+    /// Origin of each line of `processed`; `None` for code injected by plugins
+    origins: &'a [Option<LineOrigin>],
+    sources: &'a [String],
+    macro_defs: &'a [MacroDef],
+    strip_trace: &'a [LineTrace],
+}
+
+impl MacroSource<'_> {
+    /// Turn a macro expansion problem into a diagnostic at its call site.
+    fn diagnostic(&self, error: MacroError) -> Diagnostic {
+        let origin_at = |line: usize| self.origins.get(line).cloned().flatten();
+        let processed_line = self.strip_trace.get(error.line).map(|t| t.line).unwrap_or(error.line);
+        let mut diagnostic = Diagnostic::error(error.code, error.message);
+        match origin_at(processed_line) {
+            Some(origin) => {
+                // Lines outside the plugin prefix are verbatim copies of their source line
+                let file = &self.sources[origin.source];
+                let text = self.processed.lines().nth(processed_line).unwrap_or_default();
+                let span = match error.columns {
+                    Some((start_col, end_col)) => Span {
+                        file: file.clone(),
+                        line: origin.line,
+                        start_col,
+                        end_col,
+                    },
+                    None => Span::line(file, origin.line, text),
+                };
+                diagnostic = diagnostic.with_span(span);
+            }
+            None => {
+                diagnostic.code = codes::PLUGIN_CODE.to_string();
+                diagnostic.message = format!("In code injected by plugins: {}", diagnostic.message);
+            }
+        }
+        // Problems inside nested expansions: show each enclosing macro, innermost first
+        diagnostic.with_related(self.expansion_notes(&error.trace))
+    }
+
+    /// Locate a syntax error in the built output. `trace_at` maps an output line to
+    /// the expanded line it came from; `None` means generated or plugin code.
+    fn syntax_diagnostic<'t>(
+        &self,
+        error: SyntaxError,
+        trace_at: impl Fn(usize) -> Option<&'t LineTrace>,
+    ) -> Diagnostic {
+        let locate = |range: &TextRange| {
+            let trace = trace_at(range.line)?;
+            let origin = self.origins.get(trace.line).cloned().flatten()?;
+            let file = &self.sources[origin.source];
+            Some(match trace.macros.first() {
+                // Columns of expanded code mean nothing in the source; point at the call
+                Some(name) => {
+                    let text = self.processed.lines().nth(trace.line).unwrap_or_default();
+                    Span::find(file, origin.line, text, name)
+                }
+                None => Span {
+                    file: file.clone(),
+                    line: origin.line,
+                    start_col: range.start_col,
+                    end_col: range.end_col,
+                },
+            })
+        };
+
+        let mut diagnostic = Diagnostic::error(codes::SYNTAX_ERROR, error.message.clone());
+        match locate(&error.range) {
+            Some(span) => diagnostic = diagnostic.with_span(span),
+            None => {
+                diagnostic.code = codes::PLUGIN_CODE.to_string();
+                diagnostic.message = format!("In code injected by plugins: {}", error.message);
+            }
+        }
+        let mut related: Vec<RelatedSpan> = error
+            .related
+            .iter()
+            .filter_map(|(range, message)| {
+                Some(RelatedSpan {
+                    span: locate(range)?,
+                    message: message.clone(),
+                })
+            })
+            .collect();
+        if let Some(trace) = trace_at(error.range.line) {
+            related.extend(self.expansion_notes(&trace.macros));
+        }
+        diagnostic.with_related(related)
+    }
+
+    /// "in expansion of macro" notes at the definitions of `chain` (outermost first),
+    /// innermost first.
+    fn expansion_notes(&self, chain: &[String]) -> Vec<RelatedSpan> {
+        chain
+            .iter()
+            .rev()
+            .filter_map(|name| {
+                let def = self.macro_defs.iter().find(|m| &m.name == name)?;
+                let origin = self.origins.get(def.line).cloned().flatten()?;
+                let text = self.processed.lines().nth(def.line).unwrap_or_default();
+                Some(RelatedSpan {
+                    span: Span::find(&self.sources[origin.source], origin.line, text, name),
+                    message: format!("in expansion of macro '{}'", name),
+                })
+            })
+            .collect()
+    }
+}
+
+/// A file with its imports resolved and macro calls expanded, for previewing macros.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MacroPreview {
+    /// Lines of the file (or the selected lines) after expansion; imported files and
+    /// `define!` blocks are not included
+    pub text: String,
+    /// Runs of lines in `text` produced by macro calls, in order
+    pub regions: Vec<ExpandedRegion>,
+    /// Problems found while resolving imports and expanding macros
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Lines of a macro preview produced by one macro call.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ExpandedRegion {
+    /// First and last line of the region in the preview text, 1-indexed
+    pub start_line: usize,
+    pub end_line: usize,
+    /// The line of the call, with the macros that produced the region, outermost first
+    pub call: SourceLocation,
+}
+
+/// Expand the macros used in `file`, optionally only on lines `lines` (1-indexed,
+/// inclusive).
+///
+/// Imports are resolved first so macros defined in imported files apply. When
+/// `game_dir` is given, imports are searched like in a build of that game and its
+/// game.json symbols are visible to `#if` blocks.
+pub fn preview_macros(
+    file: &Path,
+    game_dir: Option<&Path>,
+    project_root: &Path,
+    workspace: &Path,
+    lines: Option<(usize, usize)>,
+) -> Result<MacroPreview, String> {
+    let mut options = PreprocessOptions::default();
+    if let Some(game_dir) = game_dir {
+        options.search_roots = import_search_roots(game_dir, project_root, workspace)?;
+        options.defines =
+            build_symbols(read_game_meta(game_dir).as_ref(), None, &BuildOptions::default())?;
+    }
+    let file_display = file
+        .canonicalize()
+        .map(|p| display_path(&p))
+        .map_err(|e| format!("Could not open {}: {}", file.display(), e))?;
+
+    let preprocessed = preprocess_mapped(file, &options);
+    let processed = preprocessed.content;
+    let origins: Vec<Option<LineOrigin>> = preprocessed.origins.into_iter().map(Some).collect();
+    let sources = preprocessed.sources.sources;
+    let macro_defs = extract_macro_definitions(&processed);
+    let (stripped, strip_trace) = strip_macro_definitions_traced(&processed, &macro_defs);
+    let expansion = expand_macro_calls_traced(&stripped, &macro_defs);
+    let trace = compose_traces(&strip_trace, &expansion.lines);
+
+    let macro_source = MacroSource {
+        processed: &processed,
+        origins: &origins,
+        sources: &sources,
+        macro_defs: &macro_defs,
+        strip_trace: &strip_trace,
+    };
+    let mut diagnostics = preprocessed.diagnostics;
+    diagnostics.extend(expansion.errors.into_iter().map(|e| macro_source.diagnostic(e)));
+
+    let file_id = sources.iter().position(|s| *s == file_display);
+    let mut text = String::new();
+    let mut regions: Vec<ExpandedRegion> = Vec::new();
+    let mut line_count = 0;
+    for (line, content) in expansion.text.lines().enumerate() {
+        let Some(trace) = trace.get(line) else { continue };
+        let Some(origin) = origins.get(trace.line).cloned().flatten() else { continue };
+        if Some(origin.source) != file_id {
+            continue;
+        }
+        if let Some((start, end)) = lines {
+            if !(start..=end).contains(&origin.line) {
+                continue;
+            }
+        }
+        text.push_str(content);
+        text.push('\n');
+        line_count += 1;
+        if trace.macros.is_empty() {
+            continue;
+        }
+        let call = SourceLocation {
+            file: file_display.clone(),
+            line: origin.line,
+            macros: trace.macros.clone(),
+        };
+        match regions.last_mut() {
+            Some(region) if region.end_line + 1 == line_count && region.call.line == call.line => {
+                region.end_line = line_count;
+                merge_chain(&mut region.call.macros, &call.macros);
+            }
+            _ => regions.push(ExpandedRegion {
+                start_line: line_count,
+                end_line: line_count,
+                call,
+            }),
+        }
+    }
+
+    Ok(MacroPreview {
+        text,
+        regions,
+        diagnostics,
+    })
+}
+
+/// Every later declaration of an already declared top-level name, paired with the first.
+fn duplicate_declarations(source: &str) -> Vec<(treeshake::Declaration, treeshake::Declaration)> {
+    let mut first_seen: HashMap<String, treeshake::Declaration> = HashMap::new();
+    let mut duplicates = Vec::new();
+    for decl in treeshake::declarations(source) {
+        match first_seen.get(&decl.name) {
+            Some(first) => duplicates.push((first.clone(), decl)),
+            None => {
+                first_seen.insert(decl.name.clone(), decl);
+            }
+        }
+    }
+    duplicates
+}
+
+/// How `file` was reached from main.gpc, innermost import first.
+fn import_chain(
+    file: &str,
+    imported_from: &HashMap<&str, &ImportEdge>,
+    temp_file: &TempFileMapping,
+) -> Vec<RelatedSpan> {
+    let mut chain = Vec::new();
+    let mut current = file;
+    while let Some(edge) = imported_from.get(current) {
+        let mut span = edge.site.clone();
+        // Imports made by plugin-injected code have no location to show
+        if !temp_file.remap_span(&mut span) || chain.len() > imported_from.len() {
+            break;
+        }
+        current = edge.site.file.as_str();
+        chain.push(RelatedSpan {
+            span,
+            message: match edge.root {
+                Some(ref name) => format!("included via <{}> from", name),
+                None => "included from".to_string(),
+            },
+        });
+    }
+    chain
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Split diagnostics into (errors, warnings); informational diagnostics are dropped.
+fn split_diagnostics(diagnostics: Vec<Diagnostic>) -> (Vec<Diagnostic>, Vec<Diagnostic>) {
+    let (errors, rest): (Vec<_>, Vec<_>) = diagnostics
+        .into_iter()
+        .partition(|d| d.severity == Severity::Error);
+    let warnings = rest
+        .into_iter()
+        .filter(|d| d.severity == Severity::Warning)
+        .collect();
+    (errors, warnings)
+}
+
+/// Maps locations in the temporary `.main_build.gpc` back to main.gpc.
+struct TempFileMapping<'a> {
+    temp: &'a str,
+    main: &'a str,
+    /// Lines of injected plugin code at the top of the temp file
+    prefix_lines: usize,
+}
+
+impl TempFileMapping<'_> {
+    /// Remap a span; returns false if it points into injected plugin code.
+    fn remap_span(&self, span: &mut Span) -> bool {
+        if span.file != self.temp {
+            return true;
+        }
+        if span.line <= self.prefix_lines {
+            return false;
+        }
+        span.file = self.main.to_string();
+        span.line -= self.prefix_lines;
+        true
+    }
+
+    fn remap(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+        let in_plugin_code = match diagnostic.span {
+            Some(ref mut span) => !self.remap_span(span),
+            None => false,
+        };
+        if in_plugin_code {
+            diagnostic.span = None;
+            diagnostic.code = codes::PLUGIN_CODE.to_string();
+            diagnostic.message = format!("In code injected by plugins: {}", diagnostic.message);
+        }
+        diagnostic.related.retain_mut(|r| self.remap_span(&mut r.span));
+        diagnostic.fixes.retain_mut(|fix| fix.edits.iter_mut().all(|e| self.remap_span(&mut e.span)));
+        diagnostic
+    }
+}
+
+/// Diagnostic for a plugin include that does not exist, pointing at the plugin manifest
+/// line that names it when the manifest can be found.
+fn missing_plugin_include(inc_path: &str) -> Diagnostic {
+    let diagnostic = Diagnostic::error(
+        codes::PLUGIN_INCLUDE_NOT_FOUND,
+        format!("Plugin include not found: {}", inc_path),
+    );
+    let path = Path::new(inc_path);
+    let Some(plugin_dir) = path.ancestors().skip(1).find(|dir| dir.join("plugin.toml").exists())
+    else {
+        return diagnostic;
+    };
+    let manifest_path = plugin_dir.join("plugin.toml");
+    let relative = path
+        .strip_prefix(plugin_dir)
+        .map(|p| p.to_string_lossy().replace('\\', "/"))
+        .unwrap_or_default();
+    let manifest = std::fs::read_to_string(&manifest_path).unwrap_or_default();
+    let manifest_display = display_path(&manifest_path);
+    let span = manifest
+        .lines()
+        .enumerate()
+        .find(|(_, line)| !relative.is_empty() && line.contains(&relative))
+        .map(|(idx, line)| Span::find(&manifest_display, idx + 1, line, &relative))
+        .unwrap_or(Span {
+            file: manifest_display,
+            line: 1,
+            start_col: 1,
+            end_col: 1,
+        });
+    diagnostic.with_span(span)
+}
+
+/// Values for the filename and header templates of a build (see `pipeline::template`).
+///
+/// `meta` should already be the selected target's view of the game. `{filename}` is
+/// left for the caller, since it is itself resolved from a template.
+fn template_context(
+    game_dir: &Path,
+    meta: Option<&GameMeta>,
+    config: Option<&GameConfig>,
+    target: Option<&str>,
+    profile: Option<&str>,
+    build_number: u64,
+) -> TemplateContext {
+    let mut ctx = TemplateContext::new().with_git(game_dir);
+    if let Some(m) = meta {
+        ctx.set("name", &m.name)
+            .set("game", &m.name)
+            .set("version", m.version.to_string())
+            .set("game_type", &m.game_type)
+            .set("type", &m.game_type)
+            .set("console", &m.console_type)
+            .set("username", m.username.as_deref().unwrap_or(""));
+    } else if let Some(c) = config {
+        let name = c.name.as_deref().unwrap_or("");
+        let game_type = c.r#type.as_deref().unwrap_or("fps");
+        ctx.set("name", name)
+            .set("game", name)
+            .set("version", c.version.to_string())
+            .set("game_type", game_type)
+            .set("type", game_type)
+            .set("console", c.console_type.as_deref().unwrap_or(""))
+            .set("username", c.username.as_deref().unwrap_or(""));
+    }
+    let name = ctx.render("{name}").0;
+    let modules = artifacts::game_modules(game_dir);
+    ctx.set("gameabbr", template::abbreviate(&name))
+        .set("target", target.unwrap_or(""))
+        .set("profile", profile.unwrap_or(""))
+        .set("build", build_number.to_string())
+        .set("module_count", modules.len().to_string())
+        .set("modules", modules.join(", "));
+    ctx
+}
+
+fn build_number_path(game_dir: &Path) -> PathBuf {
+    game_dir.join(".zenforge").join("build-number")
+}
+
+/// Held while a build counter is updated, so builds finishing at the same time never
+/// lower it.
+static BUILD_NUMBER_LOCK: Mutex<()> = Mutex::new(());
+
+/// Number of the last build of a game that wrote its output; 0 before the first.
+fn last_build_number(game_dir: &Path) -> u64 {
+    std::fs::read_to_string(build_number_path(game_dir))
+        .ok()
+        .and_then(|n| n.trim().parse::<u64>().ok())
+        .unwrap_or(0)
+}
+
+/// Count a build that wrote its output as build `number`.
+fn record_build_number(game_dir: &Path, number: u64) {
+    let _guard = BUILD_NUMBER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if last_build_number(game_dir) >= number {
+        return;
+    }
+    // Replaced in one step, so the counter is never read half-written
+    let path = build_number_path(game_dir);
+    let temp = path.with_extension("tmp");
+    let written = std::fs::create_dir_all(game_dir.join(".zenforge"))
+        .and_then(|_| std::fs::write(&temp, number.to_string()))
+        .and_then(|_| std::fs::rename(&temp, &path));
+    if let Err(e) = written {
+        log::warn!("Could not update build number {}: {}", path.display(), e);
     }
 }
 
-/// Resolve the output filename for a game build.
-/// Tries game.json first (flow-based), then falls back to config.toml (legacy).
-/// Substitute template variables in header comments.
-/// Supports: {name}, {filename}, {version}, {game_type}, {console}, {username}
-fn substitute_header_vars(template: &str, meta: Option<&GameMeta>) -> String {
-    match meta {
-        Some(m) => {
-            let gameabbr = normalize_for_filename(&m.name);
-            template
-                .replace("{name}", &m.name)
-                .replace("{filename}", &m.filename)
-                .replace("{version}", &m.version.to_string())
-                .replace("{game_type}", &m.game_type)
-                .replace("{console}", &m.console_type)
-                .replace("{username}", m.username.as_deref().unwrap_or(""))
-                .replace("{gameabbr}", &gameabbr)
-        }
-        None => template.to_string(),
+/// Header comments of the output, ending with a blank line, plus template warnings.
+fn render_header(
+    game_dir: &Path,
+    game_meta: Option<&GameMeta>,
+    options: &BuildOptions,
+    build_number: u64,
+    output_filename: &str,
+) -> (String, Vec<String>) {
+    // For flow-based games, header comments are already handled by codegen-merged.ts
+    // Only prepend header comments for legacy config-based games
+    let comments = game_meta
+        .filter(|m| m.generation_mode != "flow")
+        .and_then(|m| m.header_comments.as_ref());
+    let Some(comments) = comments else {
+        return (String::new(), Vec::new());
+    };
+    let mut ctx = template_context(
+        game_dir,
+        game_meta,
+        None,
+        options.target.as_deref(),
+        options.profile.as_deref(),
+        build_number,
+    );
+    ctx.set("filename", output_filename.trim_end_matches(".gpc"));
+    let (substituted, warnings) = ctx.render(comments);
+    let mut header = String::new();
+    for line in substituted.lines() {
+        header.push_str(&format!("// {}\n", line));
     }
+    if !header.is_empty() {
+        header.push('\n');
+    }
+    (header, warnings)
 }
 
-fn resolve_output_filename(game_dir: &Path) -> Result<String, String> {
+/// Resolve the output filename for a game build from the game.json `filename` (or the
+/// target's own filename template), falling back to config.toml for legacy games.
+/// Also returns warnings about the templates.
+fn resolve_output_filename(
+    game_dir: &Path,
+    target: Option<&str>,
+    profile: Option<&str>,
+    build_number: u64,
+) -> Result<(String, Vec<String>), String> {
     let meta_path = game_dir.join("game.json");
     if meta_path.exists() {
         let content = std::fs::read_to_string(&meta_path)
             .map_err(|e| format!("Could not read game.json: {}", e))?;
         let meta: GameMeta = serde_json::from_str(&content)
             .map_err(|e| format!("Could not parse game.json: {}", e))?;
-        let resolved = resolve_filename_template(&meta.filename, &meta);
-        return Ok(format!("{}.gpc", resolved));
+        let (base, mut warnings) =
+            template_context(game_dir, Some(&meta), None, None, profile, build_number)
+                .render(&meta.filename);
+        let resolved = match target {
+            Some(name) => {
+                let template = meta
+                    .target(name)
+                    .and_then(|t| t.filename.clone())
+                    .unwrap_or_else(|| "{filename}_{target}".to_string());
+                let target_meta = meta.for_target(name)?;
+                let mut ctx = template_context(
+                    game_dir,
+                    Some(&target_meta),
+                    None,
+                    Some(name),
+                    profile,
+                    build_number,
+                );
+                ctx.set("filename", base);
+                let (resolved, target_warnings) = ctx.render(&template);
+                warnings.extend(target_warnings);
+                resolved
+            }
+            None => base,
+        };
+        let warnings = warnings
+            .into_iter()
+            .map(|w| format!("Output filename: {}", w))
+            .collect();
+        return Ok((format!("{}.gpc", resolved), warnings));
+    }
+    if let Some(name) = target {
+        return Err(format!("Build target '{}' needs a game.json with `targets`", name));
     }
 
     let config_path = game_dir.join("config.toml");
@@ -918,50 +3134,23 @@ fn resolve_output_filename(game_dir: &Path) -> Result<String, String> {
             .map_err(|e| format!("Could not read config.toml: {}", e))?;
         let config: GameConfig = toml::from_str(&content)
             .map_err(|e| format!("Could not parse config.toml: {}", e))?;
-        let resolved = resolve_config_filename_template(&config.filename, &config);
-        return Ok(format!("{}.gpc", resolved));
+        let (resolved, warnings) =
+            template_context(game_dir, None, Some(&config), None, profile, build_number)
+                .render(&config.filename);
+        let warnings = warnings
+            .into_iter()
+            .map(|w| format!("Output filename: {}", w))
+            .collect();
+        return Ok((format!("{}.gpc", resolved), warnings));
     }
 
     Err("No game.json or config.toml found in game directory".to_string())
 }
 
-/// Resolve template variables in a filename string using GameMeta.
-/// Supports: {version}, {game}, {gameabbr}, {username}, {type}
-fn resolve_filename_template(template: &str, meta: &GameMeta) -> String {
-    let gameabbr = normalize_for_filename(&meta.name);
-    let username = meta.username.as_deref().unwrap_or("");
-
-    template
-        .replace("{version}", &meta.version.to_string())
-        .replace("{game}", &meta.name)
-        .replace("{gameabbr}", &gameabbr)
-        .replace("{username}", username)
-        .replace("{type}", &meta.game_type)
-}
-
-/// Resolve template variables in a filename string using legacy GameConfig.
-fn resolve_config_filename_template(template: &str, config: &GameConfig) -> String {
-    let game_name = config.name.as_deref().unwrap_or("");
-    let gameabbr = normalize_for_filename(game_name);
-    let username = config.username.as_deref().unwrap_or("");
-
-    template
-        .replace("{version}", &config.version.to_string())
-        .replace("{game}", game_name)
-        .replace("{gameabbr}", &gameabbr)
-        .replace("{username}", username)
-        .replace("{type}", config.r#type.as_deref().unwrap_or("fps"))
-}
-
-fn normalize_for_filename(s: &str) -> String {
-    s.chars()
-        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
-        .collect()
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::pipeline::test_support::write_test_game;
 
     #[test]
     fn test_parse_import() {
@@ -979,6 +3168,12 @@ mod tests {
         assert_eq!(parse_import("  #include \"bar.gpc\""), Some("bar.gpc".to_string()));
         assert_eq!(parse_import("#include \"../Common/helper.gpc\""), Some("../Common/helper.gpc".to_string()));
 
+        // Search root prefix
+        assert_eq!(parse_import("import <lib>/helper;"), Some("<lib>/helper.gpc".to_string()));
+        assert_eq!(split_root_prefix("<lib>/ui/menu.gpc"), Some(("lib", "ui/menu.gpc")));
+        assert_eq!(split_root_prefix("lib/menu.gpc"), None);
+        assert_eq!(split_root_prefix("<>/menu.gpc"), None);
+
         // Negative cases
         assert_eq!(parse_import("// import foo;"), None);
         assert_eq!(parse_import("int x = 5;"), None);
@@ -1108,6 +3303,7 @@ define! wrapper() {
             body: "\n    set_val(a, b);\n".to_string(),
             has_placeholder: false,
             byte_range: 0..0,
+            ..Default::default()
         }];
         let mut errors = Vec::new();
         let result = expand_macro_calls(source, &macros, &mut errors);
@@ -1125,6 +3321,7 @@ define! wrapper() {
                 .to_string(),
             has_placeholder: true,
             byte_range: 0..0,
+            ..Default::default()
         }];
         let mut errors = Vec::new();
         let result = expand_macro_calls(source, &macros, &mut errors);
@@ -1143,6 +3340,7 @@ define! wrapper() {
             body: "\n    set_val(TRACE_1, x);\n".to_string(),
             has_placeholder: false,
             byte_range: 0..0,
+            ..Default::default()
         }];
         let mut errors = Vec::new();
         let result = expand_macro_calls(source, &macros, &mut errors);
@@ -1150,6 +3348,92 @@ define! wrapper() {
         assert!(result.contains("set_val(TRACE_1, 5)"));
     }
 
+    #[test]
+    fn test_extract_macro_params() {
+        let source =
+            "// define! commented(x) { }\ndefine! m(int a, b = f(1, 2), rest...) {\n    g(a);\n}\n";
+        let macros = extract_macro_definitions(source);
+        assert_eq!(macros.len(), 1);
+        assert_eq!(macros[0].params, vec!["a", "b", "rest"]);
+        assert_eq!(macros[0].defaults, vec![None, Some("f(1, 2)".to_string()), None]);
+        assert!(macros[0].variadic);
+        assert_eq!(macros[0].line, 1);
+        assert_eq!(macros[0].arity(), (1, None));
+    }
+
+    #[test]
+    fn test_expand_macro_variadic_defaults_and_hygiene() {
+        let source = r#"define! press(btn, ms = 40) {
+    set_val(btn, 100); wait(ms);
+}
+define! each(first, rest...) {
+    %for(b in rest) { set_val(b, 0); } call(first, rest);
+}
+define! swap(a, b) {
+    $tmp = a; a = b; b = $tmp;
+}
+press(PS4_CROSS)!
+press(PS4_CIRCLE, 80)!
+each(1, X, Y)!
+swap(p, q)! swap(r, s)!
+"#;
+        let macros = extract_macro_definitions(source);
+        let stripped = strip_macro_definitions(source, &macros);
+        let expansion = expand_macro_calls_traced(&stripped, &macros);
+        assert!(expansion.errors.is_empty());
+        let text = expansion.text;
+        assert!(text.contains("set_val(PS4_CROSS, 100); wait(40);"));
+        assert!(text.contains("set_val(PS4_CIRCLE, 100); wait(80);"));
+        assert!(text.contains("set_val(X, 0); set_val(Y, 0); call(1, X, Y);"));
+        // Each expansion gets its own temporary
+        assert!(text.contains("_swap_tmp_4 = p; p = q; q = _swap_tmp_4;"));
+        assert!(text.contains("_swap_tmp_5 = r; r = s; s = _swap_tmp_5;"));
+
+        let calls = "press()!\neach()!\n";
+        let expansion = expand_macro_calls_traced(calls, &macros);
+        let messages: Vec<&str> = expansion.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Macro 'press' expects 1 to 2 arguments but got 0",
+                "Macro 'each' expects at least 1 arguments but got 0",
+            ]
+        );
+        assert_eq!(expansion.errors[1].line, 1);
+        assert_eq!(expansion.errors[1].columns, Some((1, 5)));
+    }
+
+    #[test]
+    fn test_expand_macro_recursion() {
+        let source = "define! a(x) {\n    b(x)!\n}\ndefine! b(x) {\n    a(x)!\n}\n\
+            define! wrap() {\n    { %0 }\n}\nint y;\na(1)!\nwrap()! { wrap()! { go(); } }\n";
+        let macros = extract_macro_definitions(source);
+        let stripped = strip_macro_definitions(source, &macros);
+        let expansion = expand_macro_calls_traced(&stripped, &macros);
+        assert_eq!(expansion.errors.len(), 1);
+        let error = &expansion.errors[0];
+        assert_eq!(error.code, codes::MACRO_RECURSION);
+        assert_eq!(error.message, "Macro 'a' expands into itself: a -> b -> a");
+        assert_eq!(error.trace, vec!["a", "b"]);
+        assert_eq!(error.line, 1);
+        // A macro in a caller body is not recursion
+        assert!(expansion.text.contains("{ { go(); } }"));
+        // Lines keep the full chain of macros that produced them
+        assert_eq!(expansion.lines[1].macros, vec!["a", "b"]);
+
+        // Exponential expansion hits the limit instead of running away
+        let mut source = String::from("define! m0() {\n    x;\n}\n");
+        for n in 1..16 {
+            source.push_str(&format!("define! m{}() {{\n    m{}()! m{}()!\n}}\n", n, n - 1, n - 1));
+        }
+        source.push_str("m15()!\n");
+        let macros = extract_macro_definitions(&source);
+        let stripped = strip_macro_definitions(&source, &macros);
+        let expansion = expand_macro_calls_traced(&stripped, &macros);
+        assert_eq!(expansion.errors.len(), 1);
+        assert_eq!(expansion.errors[0].code, codes::MACRO_EXPANSION_LIMIT);
+    }
+
     #[test]
     fn test_strip_macro_definitions() {
         let source = "int x = 1;\ndefine! myMacro(a) {\n    set_val(a, 0);\n}\nint y = 2;\n";
@@ -1179,6 +3463,810 @@ int x = 1;
         assert!(!result.contains("define!"));
     }
 
+    #[test]
+    fn test_preprocess_mapped_origins() {
+        let dir = tempfile::tempdir().unwrap();
+        let main_path = dir.path().join("main.gpc");
+        std::fs::write(dir.path().join("helper.gpc"), "int a;\nint b;\n").unwrap();
+        std::fs::write(&main_path, "int x;\nimport helper;\nint y;\n").unwrap();
+
+        let result = preprocess_mapped(&main_path, &PreprocessOptions::default());
+        assert!(result.success);
+        assert_eq!(result.content.lines().count(), result.origins.len());
+        let lines: Vec<(String, usize)> = result
+            .origins
+            .iter()
+            .map(|o| {
+                let file = &result.sources.sources[o.source];
+                (file.rsplit(['/', '\\']).next().unwrap().to_string(), o.line)
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                ("main.gpc".to_string(), 1),
+                ("helper.gpc".to_string(), 1),
+                ("helper.gpc".to_string(), 2),
+                ("main.gpc".to_string(), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand_macro_traced_lines() {
+        let source = "int x;\nwrap()!\nint y;\n";
+        let macros = vec![MacroDef {
+            name: "wrap".to_string(),
+            params: vec![],
+            body: "\n    a();\n    b();\n".to_string(),
+            has_placeholder: false,
+            byte_range: 0..0,
+            ..Default::default()
+        }];
+        let expansion = expand_macro_calls_traced(source, &macros);
+        assert_eq!(expansion.text, "int x;\na();\n    b();\nint y;\n");
+        let lines: Vec<usize> = expansion.lines.iter().map(|t| t.line).collect();
+        assert_eq!(lines, vec![0, 1, 1, 2]);
+        assert!(expansion.lines[0].macros.is_empty());
+        assert_eq!(expansion.lines[1].macros, vec!["wrap"]);
+    }
+
+    #[test]
+    fn test_build_writes_source_map() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = write_test_game(
+            dir.path(),
+            "import common/helper;\nmain {\n    trace(1)!\n}\n",
+        );
+        std::fs::create_dir_all(game_dir.join("common")).unwrap();
+        std::fs::write(
+            game_dir.join("common/helper.gpc"),
+            "define! trace(v) {\n    set_val(TRACE_1, v);\n}\nint helper;\n",
+        )
+        .unwrap();
+
+        let result = build_game(&game_dir, dir.path(), dir.path(), false);
+        assert!(result.success, "{:?}", result.errors);
+        let map_path = result.source_map_path.expect("source map written");
+        let map = SourceMap::load(Path::new(&map_path)).unwrap();
+
+        let output = std::fs::read_to_string(&result.output_path).unwrap();
+        assert_eq!(map.lines.len(), output.lines().count());
+        let set_val_line = output.lines().position(|l| l.contains("set_val(TRACE_1, 1)")).unwrap() + 1;
+        let location = map.resolve(set_val_line).unwrap();
+        assert!(location.file.ends_with("main.gpc"));
+        assert_eq!(location.line, 3);
+        assert_eq!(location.macros, vec!["trace"]);
+
+        let helper_line = output.lines().position(|l| l == "int helper;").unwrap() + 1;
+        let location = map.resolve(helper_line).unwrap();
+        assert!(location.file.ends_with("helper.gpc"));
+        assert_eq!(location.line, 4);
+    }
+
+    #[test]
+    fn test_preprocess_conditional_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let main_path = dir.path().join("main.gpc");
+        std::fs::write(dir.path().join("lite.gpc"), "int lite;\n").unwrap();
+        std::fs::write(dir.path().join("full.gpc"), "int full;\n").unwrap();
+        std::fs::write(
+            &main_path,
+            "#if CONSOLE_TYPE == \"ps5\" && LITE\nimport lite;\n#else\nimport full;\n#endif\n#ifndef LITE\nint extra;\n#endif\nint y;\n",
+        )
+        .unwrap();
+
+        let mut defines = BTreeMap::new();
+        defines.insert("CONSOLE_TYPE".to_string(), "\"ps5\"".to_string());
+        defines.insert("LITE".to_string(), "1".to_string());
+        let options = PreprocessOptions { defines, ..Default::default() };
+        let result = preprocess_mapped(&main_path, &options);
+        assert!(result.success, "{:?}", result.logs.iter().map(|l| &l.message).collect::<Vec<_>>());
+        assert_eq!(result.content, "int lite;\nint y;\n");
+        assert_eq!(result.origins.len(), 2);
+        assert_eq!(result.origins[1].line, 9);
+
+        let result = preprocess_mapped(&main_path, &PreprocessOptions::default());
+        assert!(result.success);
+        assert_eq!(result.content, "int full;\nint extra;\nint y;\n");
+    }
+
+    #[test]
+    fn test_preprocess_conditional_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let main_path = dir.path().join("main.gpc");
+        std::fs::write(&main_path, "int x;\n#endif\n#ifdef A\nint y;\n").unwrap();
+
+        let result = preprocess_mapped(&main_path, &PreprocessOptions::default());
+        assert!(!result.success);
+        let found: Vec<(&str, usize)> = result
+            .diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.span.as_ref().unwrap().line))
+            .collect();
+        assert_eq!(
+            found,
+            vec![(codes::CONDITIONAL_MISMATCH, 2), (codes::CONDITIONAL_UNTERMINATED, 3)]
+        );
+        let span = result.diagnostics[1].span.as_ref().unwrap();
+        assert!(span.file.ends_with("main.gpc"));
+        assert_eq!((span.start_col, span.end_col), (1, 9));
+    }
+
+    #[test]
+    fn test_preprocess_diagnostic_include_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let main_path = dir.path().join("main.gpc");
+        std::fs::write(dir.path().join("helper.gpc"), "// helper\n  import Util;\n").unwrap();
+        std::fs::write(dir.path().join("util.gpc"), "int u;\n").unwrap();
+        std::fs::write(&main_path, "int x;\nimport helper;\n").unwrap();
+
+        let result = preprocess_mapped(&main_path, &PreprocessOptions::default());
+        assert!(!result.success);
+        assert_eq!(result.diagnostics.len(), 1);
+        let diagnostic = &result.diagnostics[0];
+        assert_eq!(diagnostic.code, codes::IMPORT_NOT_FOUND);
+        assert_eq!(diagnostic.severity, Severity::Error);
+
+        // Primary span is the failing import line, related spans walk up the include chain
+        let span = diagnostic.span.as_ref().unwrap();
+        assert!(span.file.ends_with("helper.gpc"));
+        assert_eq!((span.line, span.start_col, span.end_col), (2, 3, 15));
+        assert_eq!(diagnostic.related.len(), 1);
+        assert!(diagnostic.related[0].span.file.ends_with("main.gpc"));
+        assert_eq!(diagnostic.related[0].span.line, 2);
+
+        if cfg!(not(any(windows, target_os = "macos"))) {
+            let fix = &diagnostic.fixes[0];
+            assert_eq!(fix.edits[0].new_text, "util");
+            assert_eq!((fix.edits[0].span.start_col, fix.edits[0].span.end_col), (10, 14));
+        }
+    }
+
+    #[test]
+    fn test_preprocess_search_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let game = dir.path().join("game");
+        let lib = dir.path().join("lib");
+        std::fs::create_dir_all(&game).unwrap();
+        std::fs::create_dir_all(&lib).unwrap();
+        std::fs::write(lib.join("shared.gpc"), "int shared;\n").unwrap();
+        std::fs::write(lib.join("same.gpc"), "int same;\n").unwrap();
+        std::fs::write(game.join("same.gpc"), "int same;\n").unwrap();
+        std::fs::write(lib.join("menu.gpc"), "int lib_menu;\n").unwrap();
+        std::fs::write(game.join("menu.gpc"), "int game_menu;\n").unwrap();
+        let main_path = game.join("main.gpc");
+        let options = PreprocessOptions {
+            search_roots: vec![
+                SearchRoot { name: "game".to_string(), dir: game.clone() },
+                SearchRoot { name: "lib".to_string(), dir: lib.clone() },
+            ],
+            ..Default::default()
+        };
+
+        // Found only in a root, pinned to a root, and identical copies
+        std::fs::write(&main_path, "import shared;\nimport <lib>/menu;\nimport same;\n").unwrap();
+        let result = preprocess_mapped(&main_path, &options);
+        assert!(result.success);
+        assert!(result.diagnostics.is_empty());
+        assert!(result.content.contains("int shared;"));
+        assert!(result.content.contains("int lib_menu;"));
+        let roots: Vec<Option<&str>> = result.imports.iter().map(|e| e.root.as_deref()).collect();
+        assert_eq!(roots, vec![Some("lib"), Some("lib"), None]);
+
+        // Different files in two places: the importing file's directory wins, with a warning
+        std::fs::write(&main_path, "import menu;\n").unwrap();
+        let result = preprocess_mapped(&main_path, &options);
+        assert!(result.success);
+        assert!(result.content.contains("int game_menu;"));
+        let diagnostic = &result.diagnostics[0];
+        assert_eq!(diagnostic.code, codes::AMBIGUOUS_IMPORT);
+        assert_eq!(diagnostic.severity, Severity::Warning);
+        assert!(diagnostic.related[0].span.file.ends_with("menu.gpc"));
+        assert_eq!(diagnostic.related[0].message, "also found in the <lib> root");
+        assert_eq!(diagnostic.fixes[0].edits[0].new_text, "<lib>/menu");
+
+        // Missing everywhere, and an unknown root
+        std::fs::write(&main_path, "import nowhere;\nimport <vendor>/x;\n").unwrap();
+        let result = preprocess_mapped(&main_path, &options);
+        assert!(!result.success);
+        assert_eq!(result.diagnostics.len(), 2);
+        assert!(result.diagnostics[0].message.contains("also searched <game>"));
+        assert!(result.diagnostics[1].message.contains("Unknown import root <vendor>"));
+    }
+
+    #[test]
+    fn test_import_search_roots_from_workspace_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let game = dir.path().join("Games/Test");
+        std::fs::write(
+            dir.path().join(WORKSPACE_CONFIG_FILE),
+            "[imports]\npaths = [\"vendor/gpc-utils\", { name = \"extra\", path = \"/opt/gpc\" }]\n",
+        )
+        .unwrap();
+
+        let roots = import_search_roots(&game, Path::new("/app"), dir.path()).unwrap();
+        let names: Vec<&str> = roots.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["game", "lib", "common", "gpc-utils", "extra"]);
+        assert_eq!(roots[2].dir, Path::new("/app/common"));
+        assert_eq!(roots[3].dir, dir.path().join("vendor/gpc-utils"));
+        assert_eq!(roots[4].dir, Path::new("/opt/gpc"));
+
+        let clashing = "[imports]\npaths = [\"vendor/lib\"]\n";
+        std::fs::write(dir.path().join(WORKSPACE_CONFIG_FILE), clashing).unwrap();
+        assert!(import_search_roots(&game, Path::new("/app"), dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_build_symbols_precedence() {
+        let meta: GameMeta = serde_json::from_str(
+            r#"{"name":"Test","filename":"Test","version":2,"game_type":"fps","console_type":"xbox-one",
+                "build_profiles":{"lite":{"defines":{"LITE":"1","MAX_SLOTS":"2"}}}}"#,
+        )
+        .unwrap();
+        let mut plugin = BTreeMap::new();
+        plugin.insert("MAX_SLOTS".to_string(), "8".to_string());
+        plugin.insert("GAME_VERSION".to_string(), "9".to_string());
+
+        let mut options = BuildOptions {
+            profile: Some("lite".to_string()),
+            ..Default::default()
+        };
+        options.defines.insert("LITE".to_string(), "0".to_string());
+        let symbols = build_symbols(Some(&meta), Some(&plugin), &options).unwrap();
+        assert_eq!(symbols["CONSOLE_XBOX_ONE"], "1");
+        assert_eq!(symbols["GAME_TYPE_FPS"], "1");
+        assert_eq!(symbols["GAME_VERSION"], "9");
+        assert_eq!(symbols["MAX_SLOTS"], "2");
+        assert_eq!(symbols["PROFILE_LITE"], "1");
+        assert_eq!(symbols["LITE"], "0");
+
+        options.profile = Some("full".to_string());
+        assert!(build_symbols(Some(&meta), None, &options).is_err());
+    }
+
+    #[test]
+    fn test_build_macro_diagnostic_span() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = write_test_game(
+            dir.path(),
+            "define! set(v) {\n    x = v;\n}\nmain {\n    set(1, 2)!\n}\n",
+        );
+
+        let result = build_game(&game_dir, dir.path(), dir.path(), false);
+        assert!(!result.success);
+        assert_eq!(result.errors.len(), 1);
+        let error = &result.errors[0];
+        assert_eq!(error.code, codes::MACRO_ARITY);
+        let span = error.span.as_ref().unwrap();
+        assert!(span.file.ends_with("main.gpc"));
+        assert_eq!((span.line, span.start_col, span.end_col), (5, 5, 8));
+
+        // A bad call inside a macro body is reported at the outermost call site,
+        // with the macro it was expanded from
+        std::fs::write(
+            game_dir.join("main.gpc"),
+            "define! set(v) {\n    x = v;\n}\ndefine! reset() {\n    set()!\n}\n\
+             main {\n    reset()!\n}\n",
+        )
+        .unwrap();
+        let result = build_game(&game_dir, dir.path(), dir.path(), false);
+        let error = &result.errors[0];
+        assert_eq!(error.code, codes::MACRO_ARITY);
+        let span = error.span.as_ref().unwrap();
+        assert_eq!((span.line, span.start_col, span.end_col), (8, 5, 10));
+        assert_eq!(error.related[0].message, "in expansion of macro 'reset'");
+        assert_eq!((error.related[0].span.line, error.related[0].span.start_col), (4, 9));
+    }
+
+    #[test]
+    fn test_build_syntax_errors_block_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = write_test_game(
+            dir.path(),
+            "import lib;\ndefine! set(v) {\n    x = v\n}\nmain {\n    set(1)!\n    y = 2;\n}\n",
+        );
+        std::fs::write(game_dir.join("lib.gpc"), "int x, y;\nfunction f() {\n    x = 1 +;\n}\n")
+            .unwrap();
+
+        let result = build_game(&game_dir, dir.path(), dir.path(), false);
+        assert!(!result.success);
+        assert!(!Path::new(&result.output_path).exists());
+        assert_eq!(result.errors.len(), 2);
+        let error = &result.errors[0];
+        assert_eq!(error.code, codes::SYNTAX_ERROR);
+        assert_eq!(error.message, "Expected an expression, found ';'");
+        let span = error.span.as_ref().unwrap();
+        assert!(span.file.ends_with("lib.gpc"));
+        assert_eq!((span.line, span.start_col, span.end_col), (3, 12, 13));
+
+        // Inside a macro expansion the call is blamed, with the macro as a note
+        let error = &result.errors[1];
+        assert_eq!(error.message, "Expected ';' after '1'");
+        let span = error.span.as_ref().unwrap();
+        assert!(span.file.ends_with("main.gpc"));
+        assert_eq!((span.line, span.start_col, span.end_col), (6, 5, 8));
+        assert_eq!(error.related[0].message, "in expansion of macro 'set'");
+        assert_eq!(error.related[0].span.line, 2);
+
+        std::fs::write(game_dir.join("lib.gpc"), "int x, y;\n").unwrap();
+        std::fs::write(game_dir.join("main.gpc"), "import lib;\nmain {\n    x = 1;\n}\n").unwrap();
+        let result = build_game(&game_dir, dir.path(), dir.path(), false);
+        assert!(result.success, "{:?}", result.errors);
+        assert!(Path::new(&result.output_path).exists());
+    }
+
+    #[test]
+    fn test_preview_macros() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("game");
+        std::fs::create_dir_all(&game_dir).unwrap();
+        std::fs::write(
+            game_dir.join("macros.gpc"),
+            "define! set(v) {\n    x = v;\n    y = v;\n}\ndefine! reset() {\n    set(0)!\n}\n",
+        )
+        .unwrap();
+        let main = game_dir.join("main.gpc");
+        std::fs::write(
+            &main,
+            "import macros;\nint x;\nmain {\n    set(1)!\n    reset()!\n    set()!\n}\n",
+        )
+        .unwrap();
+
+        let preview = preview_macros(&main, Some(&game_dir), dir.path(), dir.path(), None).unwrap();
+        let lines: Vec<&str> = preview.text.lines().collect();
+        assert_eq!(lines[0], "int x;");
+        assert_eq!(&lines[2..4], ["    x = 1;", "    y = 1;"]);
+        assert_eq!(preview.regions.len(), 3);
+        let first = &preview.regions[0];
+        assert_eq!((first.start_line, first.end_line), (3, 4));
+        assert_eq!((first.call.line, first.call.macros.clone()), (4, vec!["set".to_string()]));
+        let nested = &preview.regions[1];
+        assert_eq!((nested.start_line, nested.end_line), (5, 6));
+        assert_eq!(nested.call.macros, ["reset", "set"]);
+        assert!(nested.call.file.ends_with("main.gpc"));
+
+        // The bad call is still expanded but reported, and a selection only
+        // previews its own lines
+        assert_eq!(preview.regions[2].call.line, 6);
+        assert_eq!(preview.diagnostics.len(), 1);
+        assert_eq!(preview.diagnostics[0].code, codes::MACRO_ARITY);
+        let preview =
+            preview_macros(&main, Some(&game_dir), dir.path(), dir.path(), Some((5, 5))).unwrap();
+        assert_eq!(preview.text, "    x = 0;\n    y = 0;\n");
+        assert_eq!((preview.regions[0].start_line, preview.regions[0].call.line), (1, 5));
+    }
+
+    #[test]
+    fn test_build_duplicate_symbols() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = write_test_game(
+            dir.path(),
+            "import common/a;\nimport other/b;\nmain {\n}\n",
+        );
+        std::fs::create_dir_all(game_dir.join("common")).unwrap();
+        std::fs::create_dir_all(game_dir.join("other")).unwrap();
+        std::fs::write(
+            game_dir.join("common/a.gpc"),
+            "function helper() { return 1; }\nint shared;\n",
+        )
+        .unwrap();
+        std::fs::write(
+            game_dir.join("other/b.gpc"),
+            "int  shared; // same\nfunction helper() { return 2; }\n",
+        )
+        .unwrap();
+
+        let result = build_game(&game_dir, dir.path(), dir.path(), false);
+        assert!(!result.success);
+        assert_eq!(result.errors.len(), 2, "{:?}", result.errors);
+
+        let identical = &result.errors[0];
+        assert_eq!(identical.code, codes::IDENTICAL_REDECLARATION);
+        let span = identical.span.as_ref().unwrap();
+        assert!(span.file.ends_with("b.gpc"));
+        assert_eq!((span.line, span.start_col, span.end_col), (1, 6, 12));
+        let related: Vec<(&str, usize, &str)> = identical
+            .related
+            .iter()
+            .map(|r| {
+                let name = Path::new(&r.span.file).file_name().unwrap().to_str().unwrap();
+                (name, r.span.line, r.message.as_str())
+            })
+            .collect();
+        assert_eq!(
+            related,
+            vec![
+                ("main.gpc", 2, "included from"),
+                ("a.gpc", 2, "first declared here"),
+                ("main.gpc", 1, "included from"),
+            ]
+        );
+
+        let conflicting = &result.errors[1];
+        assert_eq!(conflicting.code, codes::DUPLICATE_SYMBOL);
+        assert_eq!(
+            conflicting.message,
+            "Function 'helper' is declared twice with different definitions"
+        );
+    }
+
+    #[test]
+    fn test_build_tree_shake() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("game");
+        std::fs::create_dir_all(game_dir.join("common")).unwrap();
+        std::fs::write(
+            game_dir.join("game.json"),
+            r#"{"name":"Test","filename":"Test","version":1,"game_type":"fps","console_type":"ps5","tree_shake":true}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            game_dir.join("common/helper.gpc"),
+            "function used() {\n    return 1;\n}\nfunction unused() {\n    return 2;\n}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            game_dir.join("main.gpc"),
+            "import common/helper;\nint x;\nmain {\n    x = used();\n}\n",
+        )
+        .unwrap();
+
+        let result = build_game(&game_dir, dir.path(), dir.path(), false);
+        assert!(result.success, "{:?}", result.errors);
+        assert_eq!(result.removed.len(), 1);
+        let symbol = &result.removed[0];
+        assert_eq!((symbol.kind, symbol.name.as_str()), (treeshake::SymbolKind::Function, "unused"));
+        let location = symbol.location.as_ref().unwrap();
+        assert!(location.file.ends_with("helper.gpc"));
+        assert_eq!(location.line, 4);
+
+        let output = std::fs::read_to_string(&result.output_path).unwrap();
+        assert!(!output.contains("unused"));
+        let map = SourceMap::load(Path::new(result.source_map_path.as_ref().unwrap())).unwrap();
+        assert_eq!(map.lines.len(), output.lines().count());
+        let main_line = output.lines().position(|l| l == "main {").unwrap() + 1;
+        let location = map.resolve(main_line).unwrap();
+        assert!(location.file.ends_with("main.gpc"));
+        assert_eq!(location.line, 3);
+
+        // Cache hits still report what was removed
+        let again = build_game(&game_dir, dir.path(), dir.path(), false);
+        assert_eq!(again.cache, CacheStatus::Hit);
+        assert_eq!(again.removed, result.removed);
+    }
+
+    #[test]
+    fn test_build_optimize() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("game");
+        std::fs::create_dir_all(&game_dir).unwrap();
+        std::fs::write(
+            game_dir.join("game.json"),
+            r#"{"name":"Test","filename":"Test","version":1,"game_type":"fps","console_type":"ps5","optimize":true}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            game_dir.join("main.gpc"),
+            "define DELAY = 40;\ndefine DEBUG = FALSE;\nint x;\nmain {\n    if (DEBUG) {\n        x = 0;\n    }\n    x = DELAY * 10 / 10;\n}\n",
+        )
+        .unwrap();
+
+        let result = build_game(&game_dir, dir.path(), dir.path(), false);
+        assert!(result.success, "{:?}", result.errors);
+        let report = result.optimization.clone().unwrap();
+        assert_eq!((report.folded, report.branches), (1, 1));
+        assert_eq!(report.inlined, vec!["DELAY", "DEBUG"]);
+        assert!(report.bytes_saved > 0 && report.lines_saved == 5);
+
+        let output = std::fs::read_to_string(&result.output_path).unwrap();
+        assert!(output.contains("x = 40;"));
+        assert!(!output.contains("DEBUG"));
+        let map = SourceMap::load(Path::new(result.source_map_path.as_ref().unwrap())).unwrap();
+        let line = output.lines().position(|l| l.trim() == "x = 40;").unwrap() + 1;
+        assert_eq!(map.resolve(line).unwrap().line, 8);
+
+        // Cache hits still report what the optimizer did
+        let again = build_game(&game_dir, dir.path(), dir.path(), false);
+        assert_eq!(again.cache, CacheStatus::Hit);
+        assert_eq!(again.optimization, result.optimization);
+    }
+
+    #[test]
+    fn test_build_resource_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("game");
+        std::fs::create_dir_all(&game_dir).unwrap();
+        std::fs::write(
+            game_dir.join("game.json"),
+            r#"{"name":"Test","filename":"Test","version":1,"game_type":"fps","console_type":"ps5",
+                "resources":{"warn_percent":50,"limits":{"combos":2,"functions":1}}}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            game_dir.join("main.gpc"),
+            "int a, b[3];\ncombo C { wait(10); }\nfunction f() { return 1; }\nfunction g() { return 2; }\nmain {\n}\n",
+        )
+        .unwrap();
+
+        let result = build_game(&game_dir, dir.path(), dir.path(), false);
+        let report = result.resources.expect("resource report");
+        assert_eq!(report.firmware, "zen");
+        assert_eq!(report.counts.global_slots, 4);
+        assert!(!result.success);
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].code, codes::RESOURCE_LIMIT);
+        assert!(result.errors[0].message.starts_with("Functions: 2 of 1"));
+        assert_eq!(result.warnings.len(), 1);
+        assert!(result.warnings[0].message.starts_with("Combos: 1 of 2"));
+    }
+
+    #[test]
+    fn test_build_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("game");
+        std::fs::create_dir_all(&game_dir).unwrap();
+        std::fs::write(
+            game_dir.join("game.json"),
+            r#"{"name":"Rainbow Six","filename":"{name|slug}-b{build}","version":2,
+                "game_type":"fps","console_type":"ps5","generation_mode":"config",
+                "header_comments":"{name|upper} build {build}{#username} by {username}{/username}\n{oops}"}"#,
+        )
+        .unwrap();
+        std::fs::write(game_dir.join("main.gpc"), "main {\n}\n").unwrap();
+        let build = || {
+            build_game_with_options(&game_dir, dir.path(), dir.path(), false, None, &BuildOptions::default())
+        };
+
+        let first = build();
+        assert!(first.success);
+        assert!(first.output_path.ends_with("rainbow-six-b1.gpc"));
+        let output = std::fs::read_to_string(&first.output_path).unwrap();
+        assert!(output.starts_with("// RAINBOW SIX build 1\n\nmain"));
+        assert_eq!(first.warnings.len(), 1);
+        assert_eq!(first.warnings[0].code, codes::TEMPLATE);
+        assert!(first.warnings[0].message.contains("{oops}"));
+
+        // A build that writes output takes the next number
+        std::fs::write(game_dir.join("main.gpc"), "int a;\nmain {\n}\n").unwrap();
+        assert!(build().output_path.ends_with("rainbow-six-b2.gpc"));
+    }
+
+    #[test]
+    fn test_build_number_matches_cached_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("game");
+        std::fs::create_dir_all(&game_dir).unwrap();
+        std::fs::write(
+            game_dir.join("game.json"),
+            r#"{"name":"Test","filename":"Test","version":1,"game_type":"fps","console_type":"ps5",
+                "generation_mode":"config","header_comments":"build {build}"}"#,
+        )
+        .unwrap();
+        std::fs::write(game_dir.join("main.gpc"), "main {\n}\n").unwrap();
+        let build = || build_game(&game_dir, dir.path(), dir.path(), false);
+        let header = |result: &BuildResult| {
+            let output = std::fs::read_to_string(&result.output_path).unwrap();
+            output.lines().next().unwrap_or_default().to_string()
+        };
+
+        let first = build();
+        assert_eq!(first.cache, CacheStatus::Miss);
+        assert_eq!((header(&first), last_build_number(&game_dir)), ("// build 1".into(), 1));
+
+        // Nothing changed: the cached output is still build 1 and the counter stays
+        let again = build();
+        assert_eq!(again.cache, CacheStatus::Hit);
+        assert_eq!((header(&again), last_build_number(&game_dir)), ("// build 1".into(), 1));
+
+        // A failed build is not counted
+        std::fs::write(game_dir.join("main.gpc"), "main {\n    a = ;\n}\n").unwrap();
+        assert!(!build().success);
+        assert_eq!(last_build_number(&game_dir), 1);
+
+        std::fs::write(game_dir.join("main.gpc"), "int a;\nmain {\n}\n").unwrap();
+        let next = build();
+        assert_eq!((header(&next), last_build_number(&game_dir)), ("// build 2".into(), 2));
+    }
+
+    #[test]
+    fn test_build_targets_share_build_number() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("game");
+        std::fs::create_dir_all(&game_dir).unwrap();
+        std::fs::write(
+            game_dir.join("game.json"),
+            r#"{"name":"Test","filename":"Test-b{build}","version":1,"game_type":"fps",
+                "console_type":"ps5","generation_mode":"config","header_comments":"build {build}",
+                "targets":[{"name":"ps5"},{"name":"xb1","console_type":"xb1"}]}"#,
+        )
+        .unwrap();
+        std::fs::write(game_dir.join("main.gpc"), "main {\n}\n").unwrap();
+        let build_all = || {
+            build_game_targets(&game_dir, dir.path(), dir.path(), false, None, &BuildOptions::default())
+        };
+
+        for number in 1..=2 {
+            let main = format!("int build{};\nmain {{\n}}\n", number);
+            std::fs::write(game_dir.join("main.gpc"), main).unwrap();
+            let matrix = build_all();
+            assert!(matrix.success);
+            for target in ["ps5", "xb1"] {
+                let path = dir.path().join(format!("dist/Test-b{}_{}.gpc", number, target));
+                let output = std::fs::read_to_string(path).unwrap();
+                assert!(output.starts_with(&format!("// build {}\n", number)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_build_reproducible_with_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = write_test_game(dir.path(), "main {\n    set_val(0, SLOTS);\n}\n");
+        for (id, defines) in [("zeta", "SLOTS = \"2\"\nB = \"1\""), ("alpha", "A = \"1\"")] {
+            let plugin_dir = dir.path().join("plugins").join(id);
+            std::fs::create_dir_all(&plugin_dir).unwrap();
+            std::fs::write(
+                plugin_dir.join("plugin.toml"),
+                format!(
+                    "id = \"{id}\"\nname = \"{id}\"\nversion = \"1.0\"\n\
+                     [hooks.extra_vars]\n{id}_y = \"int\"\n{id}_x = \"int\"\n\
+                     [hooks.extra_defines]\n{defines}\n"
+                ),
+            )
+            .unwrap();
+        }
+        std::fs::write(dir.path().join("plugins/.enabled.json"), r#"["zeta","alpha"]"#).unwrap();
+        let workspace = dir.path().to_string_lossy().to_string();
+        let options = BuildOptions {
+            no_cache: true,
+            ..Default::default()
+        };
+        let build = || {
+            build_game_with_options(&game_dir, dir.path(), dir.path(), false, Some(&workspace), &options)
+        };
+
+        let first = build();
+        assert!(first.success, "{:?}", first.errors);
+        let output = std::fs::read(&first.output_path).unwrap();
+        let manifest_path = dir.path().join("dist").join(manifest::MANIFEST_FILE);
+        let manifest = std::fs::read(&manifest_path).unwrap();
+        assert!(build().success);
+        assert_eq!(std::fs::read(&first.output_path).unwrap(), output);
+        assert_eq!(std::fs::read(&manifest_path).unwrap(), manifest);
+
+        // Plugin defines and vars are sorted by name regardless of plugin order
+        let text = String::from_utf8(output).unwrap();
+        let a = text.find("int alpha_x;").unwrap();
+        assert!(a < text.find("int alpha_y;").unwrap());
+        assert!(text.find("int alpha_y;").unwrap() < text.find("int zeta_x;").unwrap());
+        assert!(!text.contains("Generated at"));
+
+        let manifest = BuildManifest::load(&dir.path().join("dist"));
+        let entry = &manifest.outputs["Test.gpc"];
+        assert_eq!(entry.toolchain, manifest::toolchain());
+        assert_eq!(entry.defines["SLOTS"], "2");
+        assert!(entry.inputs.contains_key("game/main.gpc"));
+        let plugins: Vec<_> = entry.plugins.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(plugins, vec!["alpha", "zeta"]);
+    }
+
+    #[test]
+    fn test_build_manifest_relative_game_dir() {
+        // Created in the directory the tests run in, so it has a relative path
+        let cwd = std::env::current_dir().unwrap();
+        let dir = tempfile::tempdir_in(&cwd).unwrap();
+        let relative = dir.path().strip_prefix(&cwd).unwrap();
+        let game_dir = write_test_game(relative, "import helper;\nmain {\n}\n");
+        std::fs::write(game_dir.join("helper.gpc"), "int helper;\n").unwrap();
+
+        let result = build_game(&game_dir, relative, relative, false);
+        assert!(result.success);
+        let manifest = BuildManifest::load(&dir.path().join("dist"));
+        let inputs: Vec<_> = manifest.outputs["Test.gpc"].inputs.keys().cloned().collect();
+        assert_eq!(inputs, vec!["game/helper.gpc", "game/main.gpc"]);
+    }
+
+    #[test]
+    fn test_build_targets() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("game");
+        std::fs::create_dir_all(&game_dir).unwrap();
+        std::fs::write(
+            game_dir.join("game.json"),
+            r#"{"name":"Test","filename":"Test","version":1,"game_type":"fps","console_type":"ps5",
+                "targets":[
+                    {"name":"ps5"},
+                    {"name":"xbox","console_type":"xbox-series","filename":"{filename}-{console}",
+                     "defines":{"SLOTS":"4"}}]}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            game_dir.join("main.gpc"),
+            "#ifdef CONSOLE_XBOX_SERIES\nint xbox = SLOTS;\n#endif\n#ifdef TARGET_PS5\nint ps5;\n#endif\nmain {\n}\n",
+        )
+        .unwrap();
+        let build_all = || {
+            build_game_targets(&game_dir, dir.path(), dir.path(), false, None, &BuildOptions::default())
+        };
+
+        let matrix = build_all();
+        assert!(matrix.success);
+        assert_eq!(matrix.built(), 2);
+        let ps5 = std::fs::read_to_string(dir.path().join("dist/Test_ps5.gpc")).unwrap();
+        assert!(ps5.contains("int ps5;") && !ps5.contains("xbox"));
+        let xbox = std::fs::read_to_string(dir.path().join("dist/Test-xbox-series.gpc")).unwrap();
+        assert!(xbox.contains("int xbox = SLOTS;") && !xbox.contains("ps5"));
+        assert_eq!(matrix.targets[1].console_type.as_deref(), Some("xbox-series"));
+
+        // Each target keeps its own cache
+        let again = build_all();
+        assert!(again.targets.iter().all(|t| t.result.cache == CacheStatus::Hit));
+
+        // Targets writing the same file are rejected rather than overwriting each other
+        std::fs::write(
+            game_dir.join("game.json"),
+            r#"{"name":"Test","filename":"Test","version":1,"game_type":"fps","console_type":"ps5",
+                "targets":[{"name":"a","filename":"Same"},{"name":"b","filename":"Same"}]}"#,
+        )
+        .unwrap();
+        let clash = build_all();
+        assert!(!clash.success);
+        assert!(clash.targets[0].result.success);
+        assert_eq!(clash.targets[1].result.errors[0].code, codes::BUILD_CONFIG);
+
+        let options = BuildOptions {
+            target: Some("missing".to_string()),
+            ..Default::default()
+        };
+        let result = build_game_with_options(&game_dir, dir.path(), dir.path(), false, None, &options);
+        assert!(!result.success);
+    }
+
+    #[test]
+    fn test_build_cache_reuse() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = write_test_game(
+            dir.path(),
+            "import common/a;\nimport common/b;\nmain {\n}\n",
+        );
+        std::fs::create_dir_all(game_dir.join("common")).unwrap();
+        std::fs::write(game_dir.join("common/a.gpc"), "int a;\n").unwrap();
+        std::fs::write(game_dir.join("common/b.gpc"), "int b;\n").unwrap();
+
+        let first = build_game(&game_dir, dir.path(), dir.path(), false);
+        assert!(first.success, "{:?}", first.errors);
+        assert_eq!(first.cache, CacheStatus::Miss);
+        let output = std::fs::read_to_string(&first.output_path).unwrap();
+
+        let second = build_game(&game_dir, dir.path(), dir.path(), false);
+        assert_eq!(second.cache, CacheStatus::Hit);
+        assert_eq!(second.source_map_path, first.source_map_path);
+
+        // Only b.gpc changed, so a.gpc's fragment is reused
+        std::fs::write(game_dir.join("common/b.gpc"), "int b2;\n").unwrap();
+        let third = build_game(&game_dir, dir.path(), dir.path(), false);
+        assert_eq!(third.cache, CacheStatus::Partial);
+        let rebuilt = std::fs::read_to_string(&third.output_path).unwrap();
+        assert_eq!(rebuilt, output.replace("int b;", "int b2;"));
+        let map = SourceMap::load(Path::new(third.source_map_path.as_ref().unwrap())).unwrap();
+        let a_line = rebuilt.lines().position(|l| l == "int a;").unwrap() + 1;
+        assert!(map.resolve(a_line).unwrap().file.ends_with("a.gpc"));
+
+        // A deleted output is rebuilt even though the sources are unchanged
+        std::fs::remove_file(&third.output_path).unwrap();
+        let fourth = build_game(&game_dir, dir.path(), dir.path(), false);
+        assert_ne!(fourth.cache, CacheStatus::Hit);
+        assert!(Path::new(&fourth.output_path).exists());
+
+        let options = BuildOptions {
+            no_cache: true,
+            ..Default::default()
+        };
+        let fifth = build_game_with_options(&game_dir, dir.path(), dir.path(), false, None, &options);
+        assert_eq!(fifth.cache, CacheStatus::Disabled);
+    }
+
     #[test]
     fn test_build_real_game() {
         let project_root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../..");
@@ -0,0 +1,326 @@
+//! Incremental build cache, kept per game (and per build target) under
+//! `.zenforge/cache`.
+//!
+//! A `BuildRecord` remembers the last successful build: a key hashing everything
+//! that affects the output besides the sources, the hash of every source file
+//! read or looked for, and the output it produced. A build whose key, sources and
+//! output are all unchanged is served from the record without rebuilding.
+//!
+//! The `FragmentCache` holds the preprocessed output of imported files, keyed by
+//! the file, the preprocessor symbols and the files included before it, so a
+//! rebuild only preprocesses the imports that changed. Fragments whose files
+//! changed are ignored, and those a build did not use are dropped when it saves.
+//! Both are discarded whenever `CACHE_VERSION` changes.
+
+use crate::models::diagnostic::Diagnostic;
+use crate::pipeline::build::ImportEdge;
+use crate::pipeline::optimize::OptimizeReport;
+use crate::pipeline::treeshake::RemovedSymbol;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Bump when the cache layout or anything that affects build output changes,
+/// so caches written by older versions are ignored.
+const CACHE_VERSION: u32 = 3;
+
+const BUILD_RECORD_FILE: &str = "build.json";
+const FRAGMENTS_FILE: &str = "fragments.json";
+
+/// Cache directory of a game, or of one of its build targets.
+pub fn cache_dir(game_dir: &Path, target: Option<&str>) -> PathBuf {
+    let dir = game_dir.join(".zenforge").join("cache");
+    match target {
+        Some(name) => dir.join("targets").join(name),
+        None => dir,
+    }
+}
+
+/// Lowercase hex SHA-256 of `data`.
+pub fn hash_bytes(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Builds a cache key from labeled parts, so adjacent parts cannot run together.
+#[derive(Clone)]
+pub struct KeyHasher(Sha256);
+
+impl KeyHasher {
+    pub fn new() -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(CACHE_VERSION.to_le_bytes());
+        Self(hasher)
+    }
+
+    pub fn add(&mut self, label: &str, data: &[u8]) -> &mut Self {
+        for part in [label.as_bytes(), data] {
+            self.0.update((part.len() as u64).to_le_bytes());
+            self.0.update(part);
+        }
+        self
+    }
+
+    pub fn finish(self) -> String {
+        self.0
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+impl Default for KeyHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Content hash of a file at the time it was read. `hash` is `None` if the
+/// file did not exist, so creating it later invalidates the cache too.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileHash {
+    pub path: String,
+    pub hash: Option<String>,
+}
+
+impl FileHash {
+    pub fn of(path: &Path) -> Self {
+        Self {
+            path: path.to_string_lossy().to_string(),
+            hash: std::fs::read(path).ok().map(|data| hash_bytes(&data)),
+        }
+    }
+
+    pub fn is_current(&self) -> bool {
+        Self::of(Path::new(&self.path)).hash == self.hash
+    }
+}
+
+/// How a build used the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheStatus {
+    /// Inputs unchanged; the previous output was kept and nothing was rebuilt
+    Hit,
+    /// Rebuilt, reusing the preprocessed output of unchanged imports
+    Partial,
+    /// Rebuilt from scratch
+    Miss,
+    /// Caching was turned off for this build
+    Disabled,
+}
+
+/// Record of the last successful build of a game.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildRecord {
+    version: u32,
+    /// Hash of everything that affects the build besides `inputs`
+    pub key: String,
+    /// Every source file read (or looked for) while preprocessing
+    pub inputs: Vec<FileHash>,
+    pub output_path: String,
+    pub output_hash: String,
+    pub source_map_path: Option<String>,
+    pub warnings: Vec<Diagnostic>,
+    #[serde(default)]
+    pub removed: Vec<RemovedSymbol>,
+    /// What the optimizer changed, if it ran
+    #[serde(default)]
+    pub optimization: Option<OptimizeReport>,
+}
+
+impl BuildRecord {
+    pub fn new(
+        key: String,
+        inputs: Vec<FileHash>,
+        output_path: String,
+        output_hash: String,
+        source_map_path: Option<String>,
+        warnings: Vec<Diagnostic>,
+        removed: Vec<RemovedSymbol>,
+    ) -> Self {
+        Self {
+            version: CACHE_VERSION,
+            key,
+            inputs,
+            output_path,
+            output_hash,
+            source_map_path,
+            warnings,
+            removed,
+            optimization: None,
+        }
+    }
+
+    /// Load the record from a directory returned by `cache_dir`.
+    pub fn load(dir: &Path) -> Option<Self> {
+        let content = std::fs::read_to_string(dir.join(BUILD_RECORD_FILE)).ok()?;
+        serde_json::from_str::<Self>(&content)
+            .ok()
+            .filter(|r| r.version == CACHE_VERSION)
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), String> {
+        write_json(&dir.join(BUILD_RECORD_FILE), self)
+    }
+
+    pub fn remove(dir: &Path) {
+        let _ = std::fs::remove_file(dir.join(BUILD_RECORD_FILE));
+    }
+
+    /// Whether the recorded output is still what a build with `key` would produce.
+    /// Also checks the output itself, so edited or deleted dist files get rebuilt.
+    pub fn is_fresh(&self, key: &str, output_path: &Path) -> bool {
+        self.key == key
+            && self.output_path == output_path.to_string_lossy()
+            && std::fs::read(output_path)
+                .map(|data| hash_bytes(&data) == self.output_hash)
+                .unwrap_or(false)
+            && self
+                .source_map_path
+                .as_ref()
+                .map(|p| Path::new(p).exists())
+                .unwrap_or(true)
+            && self.inputs.iter().all(FileHash::is_current)
+    }
+}
+
+/// Output line of a cached fragment; `source` indexes `Fragment::sources`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FragmentLine {
+    pub source: usize,
+    pub line: usize,
+}
+
+/// Preprocessed output of one imported file together with everything it imported.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fragment {
+    pub content: String,
+    /// Display paths of the files referenced by `lines`
+    pub sources: Vec<String>,
+    pub lines: Vec<FragmentLine>,
+    /// Every file read or looked for inside the subtree
+    pub files: Vec<FileHash>,
+    /// Imports made inside the subtree
+    pub imports: Vec<ImportEdge>,
+}
+
+/// Preprocessed import subtrees from previous builds, keyed by
+/// file, preprocessor symbols and the set of files already included before it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FragmentCache {
+    version: u32,
+    entries: BTreeMap<String, Fragment>,
+    /// Keys looked up or stored during this build; everything else is dropped on save
+    #[serde(skip)]
+    used: HashSet<String>,
+    /// Number of fragments reused during this build
+    #[serde(skip)]
+    pub reused: usize,
+}
+
+impl FragmentCache {
+    /// Load the fragments from a directory returned by `cache_dir`.
+    pub fn load(dir: &Path) -> Self {
+        std::fs::read_to_string(dir.join(FRAGMENTS_FILE))
+            .ok()
+            .and_then(|content| serde_json::from_str::<Self>(&content).ok())
+            .filter(|c| c.version == CACHE_VERSION)
+            .unwrap_or_default()
+    }
+
+    /// Save the fragments used by this build, pruning stale ones.
+    pub fn save(&mut self, dir: &Path) -> Result<(), String> {
+        self.version = CACHE_VERSION;
+        let used = &self.used;
+        self.entries.retain(|key, _| used.contains(key));
+        write_json(&dir.join(FRAGMENTS_FILE), self)
+    }
+
+    /// Look up a fragment whose files are all unchanged.
+    pub fn get(&mut self, key: &str) -> Option<&Fragment> {
+        let fragment = self.entries.get(key)?;
+        if !fragment.files.iter().all(FileHash::is_current) {
+            return None;
+        }
+        self.used.insert(key.to_string());
+        self.reused += 1;
+        self.entries.get(key)
+    }
+
+    pub fn insert(&mut self, key: String, fragment: Fragment) {
+        self.used.insert(key.clone());
+        self.entries.insert(key, fragment);
+    }
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Could not create cache directory {}: {}", dir.display(), e))?;
+    }
+    let content =
+        serde_json::to_string(value).map_err(|e| format!("Could not serialize cache: {}", e))?;
+    std::fs::write(path, content)
+        .map_err(|e| format!("Could not write cache file {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_hasher_separates_parts() {
+        let mut a = KeyHasher::new();
+        a.add("x", b"ab").add("y", b"c");
+        let mut b = KeyHasher::new();
+        b.add("x", b"a").add("y", b"bc");
+        assert_ne!(a.finish(), b.finish());
+    }
+
+    #[test]
+    fn test_file_hash_tracks_creation_and_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.gpc");
+        let missing = FileHash::of(&path);
+        assert_eq!(missing.hash, None);
+        assert!(missing.is_current());
+
+        std::fs::write(&path, "int a;").unwrap();
+        assert!(!missing.is_current());
+        let present = FileHash::of(&path);
+        assert!(present.is_current());
+
+        std::fs::write(&path, "int b;").unwrap();
+        assert!(!present.is_current());
+    }
+
+    #[test]
+    fn test_fragment_cache_prunes_unused() {
+        let dir = tempfile::tempdir().unwrap();
+        let fragment = Fragment {
+            content: String::new(),
+            sources: Vec::new(),
+            lines: Vec::new(),
+            files: Vec::new(),
+            imports: Vec::new(),
+        };
+        let mut cache = FragmentCache::default();
+        cache.insert("a".to_string(), fragment.clone());
+        cache.insert("b".to_string(), fragment);
+        cache.save(dir.path()).unwrap();
+
+        let mut cache = FragmentCache::load(dir.path());
+        assert!(cache.get("a").is_some());
+        assert_eq!(cache.reused, 1);
+        cache.save(dir.path()).unwrap();
+
+        let mut cache = FragmentCache::load(dir.path());
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+    }
+}
@@ -0,0 +1,544 @@
+use std::collections::BTreeMap;
+
+/// A conditional compilation directive recognized by the preprocessor.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Directive {
+    If(String),
+    Ifdef(String),
+    Ifndef(String),
+    Elif(String),
+    Else,
+    Endif,
+}
+
+impl Directive {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Directive::If(_) => "#if",
+            Directive::Ifdef(_) => "#ifdef",
+            Directive::Ifndef(_) => "#ifndef",
+            Directive::Elif(_) => "#elif",
+            Directive::Else => "#else",
+            Directive::Endif => "#endif",
+        }
+    }
+}
+
+/// Parse a conditional directive line. Trailing `//` comments are ignored.
+///
+/// Supports:
+///   #if EXPR
+///   #ifdef SYMBOL / #ifndef SYMBOL
+///   #elif EXPR
+///   #else
+///   #endif
+pub fn parse_directive(line: &str) -> Option<Directive> {
+    let trimmed = line.trim();
+    let rest = trimmed.strip_prefix('#')?;
+    let rest = match rest.find("//") {
+        Some(pos) => &rest[..pos],
+        None => rest,
+    };
+    let keyword_end = rest
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(rest.len());
+    let (keyword, arg) = rest.split_at(keyword_end);
+    let arg = arg.trim().to_string();
+
+    match keyword {
+        "if" => Some(Directive::If(arg)),
+        "ifdef" => Some(Directive::Ifdef(arg)),
+        "ifndef" => Some(Directive::Ifndef(arg)),
+        "elif" => Some(Directive::Elif(arg)),
+        "else" if arg.is_empty() => Some(Directive::Else),
+        "endif" if arg.is_empty() => Some(Directive::Endif),
+        _ => None,
+    }
+}
+
+/// Why a conditional directive was rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConditionalError {
+    /// Directive out of place: no open block, or after `#else`
+    Mismatched(String),
+    /// `#if`/`#elif` expression that could not be parsed or evaluated
+    InvalidExpression(String),
+}
+
+impl std::fmt::Display for ConditionalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConditionalError::Mismatched(m) | ConditionalError::InvalidExpression(m) => {
+                write!(f, "{}", m)
+            }
+        }
+    }
+}
+
+/// One open `#if` block while walking a file.
+#[derive(Debug, Clone)]
+pub struct ConditionalFrame {
+    /// 1-indexed line of the opening directive
+    pub line: usize,
+    /// Whether the enclosing block is active
+    parent_active: bool,
+    /// Whether the current branch is active
+    active: bool,
+    /// Whether any branch of this block has been taken yet
+    taken: bool,
+    seen_else: bool,
+}
+
+/// Tracks nested `#if` blocks for a single file.
+#[derive(Debug, Default)]
+pub struct ConditionalStack {
+    frames: Vec<ConditionalFrame>,
+}
+
+impl ConditionalStack {
+    /// Whether lines at the current position should be kept.
+    pub fn is_active(&self) -> bool {
+        self.frames.last().map(|f| f.active).unwrap_or(true)
+    }
+
+    /// Apply a directive found at `line`, rejecting mismatched directives
+    /// and invalid expressions.
+    pub fn apply(
+        &mut self,
+        directive: &Directive,
+        line: usize,
+        symbols: &BTreeMap<String, String>,
+    ) -> Result<(), ConditionalError> {
+        use ConditionalError::{InvalidExpression, Mismatched};
+
+        match directive {
+            Directive::If(_) | Directive::Ifdef(_) | Directive::Ifndef(_) => {
+                let parent_active = self.is_active();
+                // Inactive blocks are not evaluated, so errors in them are not reported
+                let cond = if parent_active {
+                    evaluate_opening(directive, symbols).map_err(InvalidExpression)
+                } else {
+                    Ok(false)
+                };
+                // An invalid condition still opens a (false) block so its #endif matches
+                let taken = *cond.as_ref().unwrap_or(&false);
+                self.frames.push(ConditionalFrame {
+                    line,
+                    parent_active,
+                    active: parent_active && taken,
+                    taken,
+                    seen_else: false,
+                });
+                cond.map(|_| ())
+            }
+            Directive::Elif(expr) => {
+                let frame = self
+                    .frames
+                    .last_mut()
+                    .ok_or_else(|| Mismatched("#elif without matching #if".to_string()))?;
+                if frame.seen_else {
+                    return Err(Mismatched(format!(
+                        "#elif after #else (block opened at line {})",
+                        frame.line
+                    )));
+                }
+                if frame.taken || !frame.parent_active {
+                    frame.active = false;
+                } else {
+                    let cond = evaluate(expr, symbols).map_err(InvalidExpression)?;
+                    frame.active = cond;
+                    frame.taken = cond;
+                }
+                Ok(())
+            }
+            Directive::Else => {
+                let frame = self
+                    .frames
+                    .last_mut()
+                    .ok_or_else(|| Mismatched("#else without matching #if".to_string()))?;
+                if frame.seen_else {
+                    return Err(Mismatched(format!(
+                        "Duplicate #else (block opened at line {})",
+                        frame.line
+                    )));
+                }
+                frame.seen_else = true;
+                frame.active = frame.parent_active && !frame.taken;
+                frame.taken = true;
+                Ok(())
+            }
+            Directive::Endif => {
+                self.frames
+                    .pop()
+                    .map(|_| ())
+                    .ok_or_else(|| Mismatched("#endif without matching #if".to_string()))
+            }
+        }
+    }
+
+    /// Blocks still open at end of file, outermost first.
+    pub fn unterminated(&self) -> &[ConditionalFrame] {
+        &self.frames
+    }
+}
+
+fn evaluate_opening(directive: &Directive, symbols: &BTreeMap<String, String>) -> Result<bool, String> {
+    match directive {
+        Directive::If(expr) => evaluate(expr, symbols),
+        Directive::Ifdef(name) | Directive::Ifndef(name) => {
+            if !is_identifier(name) {
+                return Err(format!("{} expects a single symbol name, got '{}'", directive.name(), name));
+            }
+            let defined = symbols.contains_key(name);
+            Ok(if matches!(directive, Directive::Ifdef(_)) { defined } else { !defined })
+        }
+        _ => Ok(false),
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// ============================================================
+// Expression evaluation
+// ============================================================
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Num(f64),
+    Str(String),
+}
+
+impl Value {
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Num(n) => *n != 0.0,
+            Value::Str(s) => !s.is_empty(),
+        }
+    }
+
+    /// Symbol values that look numeric compare numerically, everything else as strings.
+    fn from_symbol(raw: &str) -> Value {
+        let raw = raw.trim();
+        if let Ok(n) = raw.parse::<f64>() {
+            return Value::Num(n);
+        }
+        let unquoted = raw
+            .strip_prefix('"')
+            .and_then(|r| r.strip_suffix('"'))
+            .unwrap_or(raw);
+        Value::Str(unquoted.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Ident(String),
+    Num(f64),
+    Str(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+fn lex(expr: &str) -> Result<Vec<Tok>, String> {
+    let bytes = expr.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if b.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        if b.is_ascii_alphabetic() || b == b'_' {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            tokens.push(Tok::Ident(expr[start..i].to_string()));
+            continue;
+        }
+        if b.is_ascii_digit() {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                i += 1;
+            }
+            let text = &expr[start..i];
+            let n = text
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid number '{}'", text))?;
+            tokens.push(Tok::Num(n));
+            continue;
+        }
+        if b == b'"' {
+            let start = i + 1;
+            i += 1;
+            while i < bytes.len() && bytes[i] != b'"' {
+                i += 1;
+            }
+            if i >= bytes.len() {
+                return Err("Unterminated string literal".to_string());
+            }
+            tokens.push(Tok::Str(expr[start..i].to_string()));
+            i += 1;
+            continue;
+        }
+        let two = expr.get(i..i + 2).unwrap_or("");
+        let op = match two {
+            "&&" => Some("&&"),
+            "||" => Some("||"),
+            "==" => Some("=="),
+            "!=" => Some("!="),
+            "<=" => Some("<="),
+            ">=" => Some(">="),
+            _ => None,
+        };
+        if let Some(op) = op {
+            tokens.push(Tok::Op(op));
+            i += 2;
+            continue;
+        }
+        match b {
+            b'!' => tokens.push(Tok::Op("!")),
+            b'<' => tokens.push(Tok::Op("<")),
+            b'>' => tokens.push(Tok::Op(">")),
+            b'(' => tokens.push(Tok::LParen),
+            b')' => tokens.push(Tok::RParen),
+            _ => return Err(format!("Unexpected character '{}'", b as char)),
+        }
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+struct ExprParser<'a> {
+    tokens: Vec<Tok>,
+    pos: usize,
+    symbols: &'a BTreeMap<String, String>,
+}
+
+impl ExprParser<'_> {
+    fn peek(&self) -> Option<&Tok> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Tok> {
+        let tok = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        tok
+    }
+
+    fn eat_op(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Tok::Op(o)) if *o == op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<Value, String> {
+        let mut left = self.and()?;
+        while self.eat_op("||") {
+            let right = self.and()?;
+            left = Value::Num((left.truthy() || right.truthy()) as i32 as f64);
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Value, String> {
+        let mut left = self.comparison()?;
+        while self.eat_op("&&") {
+            let right = self.comparison()?;
+            left = Value::Num((left.truthy() && right.truthy()) as i32 as f64);
+        }
+        Ok(left)
+    }
+
+    fn comparison(&mut self) -> Result<Value, String> {
+        let left = self.unary()?;
+        let op = match self.peek() {
+            Some(Tok::Op(op)) if matches!(*op, "==" | "!=" | "<" | ">" | "<=" | ">=") => *op,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let right = self.unary()?;
+        let result = match (&left, &right) {
+            (Value::Num(a), Value::Num(b)) => match op {
+                "==" => a == b,
+                "!=" => a != b,
+                "<" => a < b,
+                ">" => a > b,
+                "<=" => a <= b,
+                _ => a >= b,
+            },
+            (Value::Str(a), Value::Str(b)) => match op {
+                "==" => a.eq_ignore_ascii_case(b),
+                "!=" => !a.eq_ignore_ascii_case(b),
+                _ => return Err(format!("Operator '{}' cannot compare strings", op)),
+            },
+            _ => match op {
+                "==" => false,
+                "!=" => true,
+                _ => return Err(format!("Operator '{}' cannot compare a string with a number", op)),
+            },
+        };
+        Ok(Value::Num(result as i32 as f64))
+    }
+
+    fn unary(&mut self) -> Result<Value, String> {
+        if self.eat_op("!") {
+            let value = self.unary()?;
+            return Ok(Value::Num((!value.truthy()) as i32 as f64));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Value, String> {
+        match self.next() {
+            Some(Tok::Num(n)) => Ok(Value::Num(n)),
+            Some(Tok::Str(s)) => Ok(Value::Str(s)),
+            Some(Tok::LParen) => {
+                let value = self.or()?;
+                match self.next() {
+                    Some(Tok::RParen) => Ok(value),
+                    _ => Err("Expected ')'".to_string()),
+                }
+            }
+            Some(Tok::Ident(name)) if name == "defined" => {
+                let parenthesized = matches!(self.peek(), Some(Tok::LParen));
+                if parenthesized {
+                    self.pos += 1;
+                }
+                let symbol = match self.next() {
+                    Some(Tok::Ident(s)) => s,
+                    _ => return Err("defined() expects a symbol name".to_string()),
+                };
+                if parenthesized && self.next() != Some(Tok::RParen) {
+                    return Err("Expected ')' after defined(".to_string());
+                }
+                Ok(Value::Num(self.symbols.contains_key(&symbol) as i32 as f64))
+            }
+            // Undefined symbols evaluate to 0, as in the C preprocessor
+            Some(Tok::Ident(name)) => Ok(self
+                .symbols
+                .get(&name)
+                .map(|v| Value::from_symbol(v))
+                .unwrap_or(Value::Num(0.0))),
+            Some(tok) => Err(format!("Unexpected token {:?}", tok)),
+            None => Err("Unexpected end of expression".to_string()),
+        }
+    }
+}
+
+/// Evaluate a `#if`/`#elif` expression against the build symbols.
+///
+/// Supports `defined(NAME)`, `!`, `&&`, `||`, comparisons (`==`, `!=`, `<`, `>`,
+/// `<=`, `>=`), parentheses, numbers and double-quoted strings. String
+/// comparisons are case-insensitive.
+pub fn evaluate(expr: &str, symbols: &BTreeMap<String, String>) -> Result<bool, String> {
+    if expr.trim().is_empty() {
+        return Err("Missing expression".to_string());
+    }
+    let tokens = lex(expr)?;
+    let mut parser = ExprParser {
+        tokens,
+        pos: 0,
+        symbols,
+    };
+    let value = parser.or()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(format!("Unexpected token {:?}", parser.tokens[parser.pos]));
+    }
+    Ok(value.truthy())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbols() -> BTreeMap<String, String> {
+        let mut s = BTreeMap::new();
+        s.insert("CONSOLE_TYPE".to_string(), "ps5".to_string());
+        s.insert("GAME_VERSION".to_string(), "2.5".to_string());
+        s.insert("LITE".to_string(), "1".to_string());
+        s
+    }
+
+    #[test]
+    fn test_parse_directive() {
+        assert_eq!(parse_directive("#if LITE"), Some(Directive::If("LITE".into())));
+        assert_eq!(parse_directive("  #ifdef FOO // note"), Some(Directive::Ifdef("FOO".into())));
+        assert_eq!(parse_directive("#else"), Some(Directive::Else));
+        assert_eq!(parse_directive("#endif // LITE"), Some(Directive::Endif));
+        assert_eq!(parse_directive("#include \"x.gpc\""), None);
+        assert_eq!(parse_directive("int x;"), None);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let s = symbols();
+        assert!(evaluate("LITE", &s).unwrap());
+        assert!(!evaluate("FULL", &s).unwrap());
+        assert!(evaluate("defined(LITE) && !defined(FULL)", &s).unwrap());
+        assert!(evaluate("CONSOLE_TYPE == \"PS5\"", &s).unwrap());
+        assert!(evaluate("GAME_VERSION >= 2 && GAME_VERSION < 3", &s).unwrap());
+        assert!(evaluate("(CONSOLE_TYPE == \"xbox\") || LITE", &s).unwrap());
+        assert!(evaluate("LITE ==", &s).is_err());
+        assert!(evaluate("CONSOLE_TYPE < 3", &s).is_err());
+    }
+
+    #[test]
+    fn test_stack_branches() {
+        let s = symbols();
+        let mut stack = ConditionalStack::default();
+        stack.apply(&Directive::If("FULL".into()), 1, &s).unwrap();
+        assert!(!stack.is_active());
+        stack.apply(&Directive::Elif("LITE".into()), 3, &s).unwrap();
+        assert!(stack.is_active());
+        stack.apply(&Directive::Else, 5, &s).unwrap();
+        assert!(!stack.is_active());
+        stack.apply(&Directive::Endif, 7, &s).unwrap();
+        assert!(stack.is_active());
+        assert!(stack.unterminated().is_empty());
+    }
+
+    #[test]
+    fn test_stack_nested_inactive_parent() {
+        let s = symbols();
+        let mut stack = ConditionalStack::default();
+        stack.apply(&Directive::If("0".into()), 1, &s).unwrap();
+        stack.apply(&Directive::If("LITE".into()), 2, &s).unwrap();
+        assert!(!stack.is_active());
+        stack.apply(&Directive::Else, 3, &s).unwrap();
+        assert!(!stack.is_active());
+        stack.apply(&Directive::Endif, 4, &s).unwrap();
+        assert_eq!(stack.unterminated().len(), 1);
+        assert_eq!(stack.unterminated()[0].line, 1);
+    }
+
+    #[test]
+    fn test_stack_mismatched() {
+        let s = symbols();
+        let mut stack = ConditionalStack::default();
+        assert!(matches!(
+            stack.apply(&Directive::Endif, 1, &s),
+            Err(ConditionalError::Mismatched(_))
+        ));
+        assert!(matches!(
+            stack.apply(&Directive::If("1 +".into()), 1, &s),
+            Err(ConditionalError::InvalidExpression(_))
+        ));
+        assert!(!stack.is_active());
+        stack.apply(&Directive::Endif, 1, &s).unwrap();
+        assert!(stack.apply(&Directive::Else, 1, &s).is_err());
+        stack.apply(&Directive::Ifdef("LITE".into()), 2, &s).unwrap();
+        stack.apply(&Directive::Else, 3, &s).unwrap();
+        assert!(stack.apply(&Directive::Elif("1".into()), 4, &s).is_err());
+        assert!(stack.apply(&Directive::Else, 5, &s).is_err());
+    }
+}
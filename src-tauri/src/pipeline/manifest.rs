@@ -0,0 +1,152 @@
+//! `build-manifest.json`: what each output in `dist/` was built from.
+//!
+//! Builds are reproducible: the same sources, plugins and options always give
+//! byte-identical output. The manifest records those inputs next to the outputs
+//! (source hashes, plugin versions, toolchain, symbols and git commit) so a build
+//! can be checked or reproduced elsewhere. It holds no timestamps, and paths are
+//! relative to the workspace, so it only changes when a build's inputs do.
+
+use crate::pipeline::artifacts::PluginRef;
+use crate::pipeline::cache::FileHash;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const MANIFEST_FILE: &str = "build-manifest.json";
+
+/// Held while a manifest is read and rewritten; games built in parallel share `dist/`.
+static RECORD_LOCK: Mutex<()> = Mutex::new(());
+
+/// Manifest of a `dist/` directory, shared by every game building into it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BuildManifest {
+    /// Entries keyed by output file name
+    #[serde(default)]
+    pub outputs: BTreeMap<String, ManifestEntry>,
+}
+
+/// How one output file was built.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// SHA-256 of the output file
+    pub output_hash: String,
+    /// Version of the tool that built it, e.g. "zenforge 1.4.0"
+    pub toolchain: String,
+    pub target: Option<String>,
+    pub profile: Option<String>,
+    /// Commit checked out in the repository containing the game, if any
+    pub git_commit: Option<String>,
+    /// Symbols visible to `#if`/`#ifdef`
+    pub defines: BTreeMap<String, String>,
+    /// SHA-256 of every source file read, keyed by path relative to the workspace
+    pub inputs: BTreeMap<String, String>,
+    /// Enabled plugins, sorted by manifest path
+    pub plugins: Vec<PluginRef>,
+}
+
+/// Manifest path of a `dist/` directory.
+pub fn manifest_path(dist_dir: &Path) -> PathBuf {
+    dist_dir.join(MANIFEST_FILE)
+}
+
+/// Name and version of this build of ZenForge.
+pub fn toolchain() -> String {
+    format!("zenforge {}", env!("CARGO_PKG_VERSION"))
+}
+
+/// Hashes of the input files that exist, keyed by their path relative to `root`
+/// with `/` separators. Files outside `root` keep their full path.
+pub fn relative_inputs(root: &Path, inputs: &[FileHash]) -> BTreeMap<String, String> {
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    inputs
+        .iter()
+        .filter_map(|file| {
+            let hash = file.hash.clone()?;
+            let path = Path::new(&file.path);
+            let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+            let path = path.strip_prefix(&root).unwrap_or(&path);
+            let parts: Vec<_> = path.iter().map(|part| part.to_string_lossy()).collect();
+            Some((parts.join("/"), hash))
+        })
+        .collect()
+}
+
+impl BuildManifest {
+    /// Load the manifest of a `dist/` directory; empty if it is missing or unreadable.
+    pub fn load(dist_dir: &Path) -> Self {
+        std::fs::read_to_string(manifest_path(dist_dir))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// Whether the manifest describes `file_name` as having the content `output_hash`.
+    pub fn covers(&self, file_name: &str, output_hash: &str) -> bool {
+        self.outputs
+            .get(file_name)
+            .is_some_and(|entry| entry.output_hash == output_hash)
+    }
+}
+
+/// Record how `file_name` was built. The file is rewritten only if the entry changed.
+pub fn record(dist_dir: &Path, file_name: &str, entry: ManifestEntry) -> Result<(), String> {
+    let _guard = RECORD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut manifest = BuildManifest::load(dist_dir);
+    if manifest.outputs.get(file_name) == Some(&entry) {
+        return Ok(());
+    }
+    manifest.outputs.insert(file_name.to_string(), entry);
+    let mut content = serde_json::to_string_pretty(&manifest)
+        .map_err(|e| format!("Could not serialize build manifest: {}", e))?;
+    content.push('\n');
+    let path = manifest_path(dist_dir);
+    std::fs::write(&path, content)
+        .map_err(|e| format!("Could not write build manifest {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let game = dir.path().join("Games").join("R6S");
+        std::fs::create_dir_all(&game).unwrap();
+        std::fs::write(game.join("main.gpc"), "main {}").unwrap();
+        std::fs::write(game.join("helper.gpc"), "int x;").unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let inputs = vec![
+            FileHash::of(&root.join("Games/R6S/main.gpc")),
+            FileHash::of(&root.join("Games/R6S/missing.gpc")),
+            FileHash::of(&game.join("../R6S/helper.gpc")),
+        ];
+        let relative = relative_inputs(dir.path(), &inputs);
+        assert_eq!(
+            relative.keys().collect::<Vec<_>>(),
+            vec!["Games/R6S/helper.gpc", "Games/R6S/main.gpc"]
+        );
+    }
+
+    #[test]
+    fn test_record_and_covers() {
+        let dir = tempfile::tempdir().unwrap();
+        let entry = ManifestEntry {
+            output_hash: "abc".to_string(),
+            toolchain: toolchain(),
+            ..Default::default()
+        };
+        record(dir.path(), "r6s.gpc", entry.clone()).unwrap();
+        let manifest = BuildManifest::load(dir.path());
+        assert!(manifest.covers("r6s.gpc", "abc"));
+        assert!(!manifest.covers("r6s.gpc", "def"));
+        assert!(!manifest.covers("other.gpc", "abc"));
+
+        // Recording the same entry again leaves the file untouched
+        let path = manifest_path(dir.path());
+        std::fs::write(&path, std::fs::read_to_string(&path).unwrap() + " ").unwrap();
+        record(dir.path(), "r6s.gpc", entry).unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().ends_with("\n "));
+    }
+}
@@ -1,4 +1,22 @@
+pub mod artifacts;
 pub mod build;
+pub mod cache;
+pub mod conditional;
+pub mod manifest;
 pub mod modules;
 pub mod newgame;
 pub mod obfuscate;
+pub mod optimize;
+pub mod profile;
+pub mod queue;
+pub mod release;
+pub mod replay;
+pub mod resources;
+pub mod scripttest;
+pub mod sourcemap;
+pub mod syntax;
+pub mod template;
+#[cfg(test)]
+pub(crate) mod test_support;
+pub mod treeshake;
+pub mod watch;
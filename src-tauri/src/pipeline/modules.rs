@@ -177,3 +177,130 @@ pub fn resolve_dependencies(
 pub fn modules_dir(project_root: &Path) -> PathBuf {
     project_root.join("modules")
 }
+
+/// A problem found while validating module definition files.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ModuleIssue {
+    pub path: String,
+    pub module_id: Option<String>,
+    pub message: String,
+}
+
+/// Validate every module TOML in bundled modules/ and in each extra directory's modules/.
+///
+/// Unlike `load_all_modules_with_paths`, which logs and skips bad files, this reports
+/// parse errors, duplicate IDs, and conflicts that reference unknown modules.
+pub fn validate_modules(
+    project_root: &Path,
+    extra_dirs: &[PathBuf],
+) -> Result<Vec<ModuleIssue>, String> {
+    let bundled_dir = modules_dir(project_root);
+    if !bundled_dir.exists() {
+        return Err(format!(
+            "Modules directory not found: {}",
+            bundled_dir.display()
+        ));
+    }
+
+    let mut dirs = vec![bundled_dir];
+    dirs.extend(
+        extra_dirs
+            .iter()
+            .map(|d| d.join("modules"))
+            .filter(|d| d.exists()),
+    );
+
+    let mut issues = Vec::new();
+    let mut loaded: Vec<(PathBuf, ModuleDefinition)> = Vec::new();
+    let mut first_seen: HashMap<String, PathBuf> = HashMap::new();
+
+    for dir in &dirs {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read modules dir {}: {}", dir.display(), e))?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().and_then(|s| s.to_str()) == Some("toml"))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            match load_module(&path) {
+                Ok(module) => {
+                    if let Some(existing) = first_seen.get(&module.id) {
+                        issues.push(ModuleIssue {
+                            path: path.display().to_string(),
+                            module_id: Some(module.id.clone()),
+                            message: format!(
+                                "Duplicate module id '{}' (already defined in {})",
+                                module.id,
+                                existing.display()
+                            ),
+                        });
+                        continue;
+                    }
+                    first_seen.insert(module.id.clone(), path.clone());
+                    loaded.push((path, module));
+                }
+                Err(e) => issues.push(ModuleIssue {
+                    path: path.display().to_string(),
+                    module_id: None,
+                    message: e,
+                }),
+            }
+        }
+    }
+
+    for (path, module) in &loaded {
+        for conflict in &module.conflicts {
+            if conflict == &module.id {
+                issues.push(ModuleIssue {
+                    path: path.display().to_string(),
+                    module_id: Some(module.id.clone()),
+                    message: "Module lists itself as a conflict".to_string(),
+                });
+            } else if !first_seen.contains_key(conflict) {
+                issues.push(ModuleIssue {
+                    path: path.display().to_string(),
+                    module_id: Some(module.id.clone()),
+                    message: format!("Conflict references unknown module '{}'", conflict),
+                });
+            }
+        }
+    }
+
+    Ok(issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_modules_reports_issues() {
+        let dir = tempfile::tempdir().unwrap();
+        let modules = dir.path().join("modules");
+        std::fs::create_dir_all(&modules).unwrap();
+        std::fs::write(
+            modules.join("a.toml"),
+            "[a]\ndisplay_name = \"A\"\nid = \"a\"\ntype = \"fps\"\nconflicts = [\"b\", \"missing\"]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            modules.join("b.toml"),
+            "[b]\ndisplay_name = \"B\"\nid = \"b\"\ntype = \"fps\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            modules.join("c.toml"),
+            "[c]\ndisplay_name = \"C\"\nid = \"a\"\ntype = \"fps\"\n",
+        )
+        .unwrap();
+        std::fs::write(modules.join("broken.toml"), "[broken\n").unwrap();
+
+        let issues = validate_modules(dir.path(), &[]).unwrap();
+        let messages: Vec<&str> = issues.iter().map(|i| i.message.as_str()).collect();
+        assert_eq!(issues.len(), 3, "{:?}", messages);
+        assert!(issues[0].path.ends_with("broken.toml"));
+        assert!(messages[1].starts_with("Duplicate module id 'a'"));
+        assert_eq!(messages[2], "Conflict references unknown module 'missing'");
+    }
+}
@@ -64,6 +64,12 @@ pub fn create_game(
         tags: None,
         header_comments: params.header_comments.clone(),
         generate_module_info: None,
+        header_timestamp: None,
+        build_profiles: None,
+        resources: None,
+        tree_shake: None,
+        optimize: None,
+        targets: None,
     };
 
     let meta_content = serde_json::to_string_pretty(&meta)
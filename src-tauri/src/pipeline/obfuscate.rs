@@ -282,12 +282,14 @@ fn tokenize(source: &str) -> Vec<Token> {
             continue;
         }
 
-        // Punctuation (braces, parens, brackets, semicolons, commas, dots)
+        // Punctuation (braces, parens, brackets, semicolons, commas, dots); stray
+        // non-ASCII characters are kept whole so token text lines up with the source
+        let ch = source[i..].chars().next().unwrap_or(b as char);
         tokens.push(Token {
             kind: TokenKind::Punctuation,
-            text: (b as char).to_string(),
+            text: ch.to_string(),
         });
-        i += 1;
+        i += ch.len_utf8();
     }
 
     tokens
@@ -0,0 +1,967 @@
+//! Optimizer for built GPC scripts.
+//!
+//! Module and flow code generation leaves expressions such as
+//! `ANTIRECOIL_INIT_DELAY * 10 / 10` and defines that are used only once. This pass
+//! folds constant integer expressions with the wrapping 32-bit arithmetic of the
+//! device, substitutes defines used once and removes their declarations, and
+//! replaces `if` statements with a constant condition by the branch taken.
+
+use crate::gpc::is_keyword;
+use crate::gpc::lexer::{lex, TokenKind};
+use crate::pipeline::sourcemap::{compose_traces, LineIndex, LineTrace};
+use crate::pipeline::treeshake::apply_edits;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// What the optimizer changed in a build output.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OptimizeReport {
+    /// Constant expressions replaced by their value
+    pub folded: usize,
+    /// Defines used once whose value was substituted and declaration removed
+    pub inlined: Vec<String>,
+    /// `if` statements with a constant condition replaced by the branch taken
+    pub branches: usize,
+    pub bytes_saved: usize,
+    pub lines_saved: usize,
+}
+
+pub struct Optimized {
+    pub text: String,
+    /// For every output line, the input line it came from
+    pub(crate) lines: Vec<LineTrace>,
+    pub report: OptimizeReport,
+}
+
+/// Optimize `source`.
+///
+/// `extra_roots` are names used by code outside `source` (e.g. appended plugin
+/// code); defines among them are never inlined.
+pub fn optimize(source: &str, extra_roots: &HashSet<String>) -> Optimized {
+    let mut report = OptimizeReport::default();
+
+    let folded = Code::new(source);
+    let consts = folded.constants();
+    let edits = folded.fold(&consts, extra_roots, &mut report);
+    let (mut text, mut lines) = apply_edits(source, &folded.index, &edits);
+
+    // Removing an `if` can leave the `if` of its `else` at the start of a statement
+    loop {
+        let simplified = Code::new(&text);
+        let edits = simplified.simplify_branches(&consts, &mut report);
+        if edits.is_empty() {
+            break;
+        }
+        let (next, next_lines) = apply_edits(&text, &simplified.index, &edits);
+        lines = compose_traces(&lines, &next_lines);
+        text = next;
+    }
+
+    report.bytes_saved = source.len().saturating_sub(text.len());
+    report.lines_saved = source.lines().count().saturating_sub(text.lines().count());
+    Optimized {
+        text,
+        lines,
+        report,
+    }
+}
+
+/// Names with a value known before anything is declared.
+const BUILTIN_CONSTANTS: [(&str, i32); 2] = [("TRUE", 1), ("FALSE", 0)];
+
+/// Tokenized source, with comments and whitespace left out of `sig`.
+struct Code<'a> {
+    source: &'a str,
+    index: LineIndex,
+    /// Significant tokens
+    sig: Vec<&'a str>,
+    kinds: Vec<TokenKind>,
+    /// Byte range of each significant token
+    spans: Vec<(usize, usize)>,
+    defines: Vec<DefineDecl>,
+}
+
+/// `define NAME = value;`, in significant token indices.
+struct DefineDecl {
+    name: String,
+    /// Index of the `define` token
+    start: usize,
+    /// Index of the first value token
+    value: usize,
+    /// Index of the `;`
+    end: usize,
+}
+
+impl<'a> Code<'a> {
+    fn new(source: &'a str) -> Self {
+        let mut code = Code {
+            source,
+            index: LineIndex::new(source),
+            sig: Vec::new(),
+            kinds: Vec::new(),
+            spans: Vec::new(),
+            defines: Vec::new(),
+        };
+        for tok in lex(source).0.iter().filter(|t| !t.is_trivia()) {
+            code.sig.push(tok.text(source));
+            code.kinds.push(tok.kind);
+            code.spans.push((tok.span.start, tok.span.end));
+        }
+
+        let mut i = 0;
+        while i + 3 < code.sig.len() {
+            let is_define = code.sig[i] == "define"
+                && code.kinds[i + 1] == TokenKind::Ident
+                && code.sig[i + 2] == "=";
+            if !is_define {
+                i += 1;
+                continue;
+            }
+            let Some(end) = (i + 3..code.sig.len()).find(|&j| code.sig[j] == ";") else {
+                break;
+            };
+            code.defines.push(DefineDecl {
+                name: code.sig[i + 1].to_string(),
+                start: i,
+                value: i + 3,
+                end,
+            });
+            i = end + 1;
+        }
+        code
+    }
+
+    fn parser<'p>(&'p self, consts: &'p HashMap<String, i32>) -> Parser<'p> {
+        Parser {
+            sig: &self.sig,
+            kinds: &self.kinds,
+            consts,
+        }
+    }
+
+    /// Values of `TRUE`, `FALSE` and every define whose value is a constant expression.
+    fn constants(&self) -> HashMap<String, i32> {
+        let mut consts: HashMap<String, i32> = BUILTIN_CONSTANTS
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect();
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for decl in &self.defines {
+            *counts.entry(decl.name.as_str()).or_default() += 1;
+        }
+        // Defines may refer to ones declared later; repeat until nothing new is known
+        loop {
+            let mut changed = false;
+            for decl in &self.defines {
+                if counts[decl.name.as_str()] > 1 || consts.contains_key(&decl.name) {
+                    continue;
+                }
+                let parser = self.parser(&consts);
+                let value = parser
+                    .expression(decl.value, 0)
+                    .filter(|node| node.end == decl.end)
+                    .and_then(|node| node.value);
+                if let Some(value) = value {
+                    consts.insert(decl.name.clone(), value);
+                    changed = true;
+                }
+            }
+            if !changed {
+                return consts;
+            }
+        }
+    }
+
+    /// Edits that fold constant expressions and inline defines used once.
+    fn fold(
+        &self,
+        consts: &HashMap<String, i32>,
+        extra_roots: &HashSet<String>,
+        report: &mut OptimizeReport,
+    ) -> Vec<(usize, usize, String)> {
+        // Defines used exactly once, with the text that replaces their use
+        let declared_at: HashSet<usize> = self.defines.iter().map(|d| d.start + 1).collect();
+        let mut uses: HashMap<&str, usize> = HashMap::new();
+        for (i, kind) in self.kinds.iter().enumerate() {
+            if *kind == TokenKind::Ident && !declared_at.contains(&i) {
+                *uses.entry(self.sig[i]).or_default() += 1;
+            }
+        }
+        let mut declared: HashMap<&str, usize> = HashMap::new();
+        for decl in &self.defines {
+            *declared.entry(decl.name.as_str()).or_default() += 1;
+        }
+        let mut inline: HashMap<&str, Replacement> = HashMap::new();
+        for decl in &self.defines {
+            let name = decl.name.as_str();
+            if uses.get(name) != Some(&1) || declared[name] > 1 || extra_roots.contains(name) {
+                continue;
+            }
+            let replacement = match consts.get(name) {
+                Some(&value) => Replacement::Value(value),
+                // A single name or number that is not itself a define
+                None if decl.end == decl.value + 1
+                    && matches!(self.kinds[decl.value], TokenKind::Number | TokenKind::Ident)
+                    && !declared.contains_key(self.sig[decl.value]) =>
+                {
+                    Replacement::Text(self.sig[decl.value].to_string())
+                }
+                None => continue,
+            };
+            inline.insert(name, replacement);
+        }
+
+        let parser = self.parser(consts);
+        let mut emitter = Emitter {
+            code: self,
+            inline: &inline,
+            edits: Vec::new(),
+            consumed: HashSet::new(),
+            folded: 0,
+        };
+        let mut prev: Option<usize> = None;
+        let mut i = 0;
+        let mut defines = self.defines.iter().peekable();
+        while i < self.sig.len() {
+            // Define values are expressions; their names are not uses
+            while defines.next_if(|d| d.start < i).is_some() {}
+            if let Some(decl) = defines.next_if(|d| d.start == i) {
+                if let Some(node) = parser.expression(decl.value, 0) {
+                    emitter.emit(&node, false, false);
+                }
+                prev = Some(decl.end);
+                i = decl.end + 1;
+                continue;
+            }
+            if self.can_start_expression(prev, i) {
+                if let Some(node) = parser.expression(i, 0) {
+                    emitter.emit(&node, false, true);
+                    prev = Some(node.end - 1);
+                    i = node.end;
+                    continue;
+                }
+            }
+            prev = Some(i);
+            i += 1;
+        }
+
+        // Remove the declarations of defines whose only use was replaced, along with
+        // any edits made inside them
+        let mut removed: Vec<(usize, usize)> = Vec::new();
+        for decl in &self.defines {
+            if !emitter.consumed.contains(decl.name.as_str()) {
+                continue;
+            }
+            report.inlined.push(decl.name.clone());
+            removed.push((self.spans[decl.start].0, self.spans[decl.end].1));
+        }
+        let mut edits: Vec<(usize, usize, String)> = emitter
+            .edits
+            .into_iter()
+            .filter(|e| !removed.iter().any(|r| r.0 <= e.0 && e.1 <= r.1))
+            .collect();
+        report.folded = emitter.folded;
+        edits.extend(removed.into_iter().map(|(start, end)| (start, end, String::new())));
+        edits.sort_by_key(|e| e.0);
+        edits
+    }
+
+    /// Whether an expression can start at token `i` given the token before it, so
+    /// that nothing on its left binds more tightly than the expression itself.
+    fn can_start_expression(&self, prev: Option<usize>, i: usize) -> bool {
+        let name = self.kinds[i] == TokenKind::Ident && !is_keyword(self.sig[i]);
+        let starts = name
+            || matches!(self.kinds[i], TokenKind::Number)
+            || matches!(self.sig[i], "(" | "-" | "+" | "!" | "~");
+        let Some(prev) = prev else {
+            return starts;
+        };
+        let before = self.sig[prev];
+        if matches!(before, "(" | "[" | "," | ";" | "{" | "}" | "?" | ":")
+            || assignment_op(before)
+            || is_keyword(before)
+        {
+            return starts;
+        }
+        // A new statement after `if (...)` or a type name
+        (before == ")" || self.kinds[prev] == TokenKind::Ident)
+            && (name || self.kinds[i] == TokenKind::Number)
+    }
+
+    /// Edits that replace `if` statements with a constant condition by the branch taken.
+    fn simplify_branches(
+        &self,
+        consts: &HashMap<String, i32>,
+        report: &mut OptimizeReport,
+    ) -> Vec<(usize, usize, String)> {
+        let parser = self.parser(consts);
+        let mut edits = Vec::new();
+        let mut i = 0;
+        while i < self.sig.len() {
+            // An `if` right after `else` is that `else`'s whole branch
+            let after_else = i > 0 && self.sig[i - 1] == "else";
+            let at_statement = i == 0 || after_else || matches!(self.sig[i - 1], ";" | "{" | "}");
+            if self.sig[i] != "if" || !at_statement {
+                i += 1;
+                continue;
+            }
+            let Some(stmt) = self.if_statement(i) else {
+                i += 1;
+                continue;
+            };
+            let condition = parser
+                .expression(i + 2, 0)
+                .filter(|node| node.end == stmt.close_paren)
+                .and_then(|node| node.value);
+            let Some(condition) = condition else {
+                i += 1;
+                continue;
+            };
+
+            let start = self.spans[i].0;
+            let end = self.spans[stmt.end - 1].1;
+            let (then_first, then_last) = stmt.then_branch;
+            let closes = |j: usize| self.sig[j] == "}";
+            if after_else && condition == 0 && i >= 2 && closes(i - 2) && closes(then_last - 1) {
+                // `} else if (FALSE) { .. }`: the block's own `}` takes the place of the
+                // one before the `else`, so what follows it stays on its line
+                self.cut(self.spans[i - 2].0, self.spans[then_last - 1].0, &mut edits);
+                report.branches += 1;
+                i = then_last;
+                continue;
+            }
+            let taken = if condition != 0 {
+                Some((then_first, then_last))
+            } else {
+                stmt.else_branch
+            };
+            match taken {
+                Some((first, last)) => {
+                    // Keep the branch's statements, without the braces of a block
+                    // unless they are still needed as the branch of an `else`
+                    let block = self.sig[first] == "{" && !after_else;
+                    let keep_start = if block { self.spans[first].1 } else { self.spans[first].0 };
+                    let keep_end = if block {
+                        self.spans[last - 1].0
+                    } else {
+                        self.spans[last - 1].1
+                    };
+                    self.cut(start, keep_start, &mut edits);
+                    edits.push((keep_end, end, String::new()));
+                    // Statements in the kept branch may be simplified too. An `if` kept
+                    // from an `else` only starts a statement once this pass is applied.
+                    i = first + 1;
+                }
+                None => {
+                    let empty = if after_else { "{}" } else { "" };
+                    edits.push((start, end, empty.to_string()));
+                    i = stmt.end;
+                }
+            }
+            report.branches += 1;
+        }
+        edits.sort_by_key(|e| e.0);
+        edits
+    }
+
+    /// Edits removing `start..keep`. If the kept code starts on a later line and
+    /// nothing else precedes `start` on its line, the kept code gets that indentation.
+    fn cut(&self, start: usize, keep: usize, edits: &mut Vec<(usize, usize, String)>) {
+        let line = self.line_start(start);
+        let keep_line = self.line_start(keep);
+        if keep_line > start && self.source[line..start].trim().is_empty() {
+            let indent = self.source[line..start].to_string();
+            edits.push((start, keep_line, String::new()));
+            edits.push((keep_line, keep, indent));
+        } else {
+            edits.push((start, keep, String::new()));
+        }
+    }
+
+    /// Byte offset of the start of the line containing `pos`.
+    fn line_start(&self, pos: usize) -> usize {
+        self.source[..pos].rfind('\n').map(|p| p + 1).unwrap_or(0)
+    }
+
+    /// The `if` statement starting at token `i`, if its branches are blocks or
+    /// simple statements.
+    fn if_statement(&self, i: usize) -> Option<IfStatement> {
+        if self.sig.get(i + 1) != Some(&"(") {
+            return None;
+        }
+        let close_paren = self.matching(i + 1)?;
+        let then_end = self.branch_end(close_paren + 1)?;
+        let mut stmt = IfStatement {
+            close_paren,
+            then_branch: (close_paren + 1, then_end),
+            else_branch: None,
+            end: then_end,
+        };
+        if self.sig.get(then_end) == Some(&"else") {
+            let else_end = match self.sig.get(then_end + 1) {
+                Some(&"if") => self.if_statement(then_end + 1)?.end,
+                _ => self.branch_end(then_end + 1)?,
+            };
+            stmt.else_branch = Some((then_end + 1, else_end));
+            stmt.end = else_end;
+        }
+        Some(stmt)
+    }
+
+    /// End (exclusive) of the block or simple statement starting at token `i`.
+    fn branch_end(&self, i: usize) -> Option<usize> {
+        match *self.sig.get(i)? {
+            "{" => self.matching(i).map(|close| close + 1),
+            "if" | "else" | "while" | "for" | "do" | "switch" | ";" | "}" => None,
+            _ => {
+                let mut depth = 0usize;
+                for j in i..self.sig.len() {
+                    match self.sig[j] {
+                        "(" | "[" => depth += 1,
+                        ")" | "]" => depth = depth.checked_sub(1)?,
+                        "{" | "}" => return None,
+                        ";" if depth == 0 => return Some(j + 1),
+                        _ => {}
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    /// Index of the bracket closing the one at `open`.
+    fn matching(&self, open: usize) -> Option<usize> {
+        let mut depth = 0usize;
+        for j in open..self.sig.len() {
+            match self.sig[j] {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => {
+                    depth = depth.checked_sub(1)?;
+                    if depth == 0 {
+                        return Some(j);
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+/// An `if` statement in significant token indices; branches are `(first, end)`.
+struct IfStatement {
+    close_paren: usize,
+    then_branch: (usize, usize),
+    else_branch: Option<(usize, usize)>,
+    /// Index just past the statement
+    end: usize,
+}
+
+/// What replaces the use of an inlined define.
+enum Replacement {
+    Value(i32),
+    Text(String),
+}
+
+/// Turns parsed expressions into edits.
+struct Emitter<'c> {
+    code: &'c Code<'c>,
+    inline: &'c HashMap<&'c str, Replacement>,
+    edits: Vec<(usize, usize, String)>,
+    /// Inlined defines whose use was replaced
+    consumed: HashSet<&'c str>,
+    folded: usize,
+}
+
+impl<'c> Emitter<'c> {
+    /// `operand`: the node is an operand of an operator, so a negative value
+    /// needs parentheses. `root`: the node is a whole expression, whose outer
+    /// parentheses may belong to `if (...)` and must stay.
+    fn emit(&mut self, node: &Node, operand: bool, root: bool) {
+        let code = self.code;
+        let span = (code.spans[node.start].0, code.spans[node.end - 1].1);
+        let keep_parens = root && node.kind == NodeKind::Paren;
+        if let Some(value) = node.value.filter(|_| node.has_operator() && !keep_parens) {
+            if value != i32::MIN {
+                self.edits.push((span.0, span.1, format_value(value, operand)));
+                self.consume(node);
+                self.folded += 1;
+                return;
+            }
+        }
+        if node.kind == NodeKind::Name {
+            if let Some(replacement) = self.inline.get(code.sig[node.start]) {
+                let text = match replacement {
+                    Replacement::Value(value) => format_value(*value, operand),
+                    Replacement::Text(text) => text.clone(),
+                };
+                self.edits.push((span.0, span.1, text));
+                self.consume(node);
+                return;
+            }
+        }
+        let child_operand = matches!(node.kind, NodeKind::Unary | NodeKind::Binary);
+        for (i, child) in node.children.iter().enumerate() {
+            // The left side of an assignment is a name; its value is not read
+            if node.kind == NodeKind::Assign && i == 0 {
+                continue;
+            }
+            self.emit(child, child_operand, false);
+        }
+    }
+
+    fn consume(&mut self, node: &Node) {
+        if node.kind == NodeKind::Name {
+            let name = self.code.sig[node.start];
+            if let Some((&key, _)) = self.inline.get_key_value(name) {
+                self.consumed.insert(key);
+            }
+        }
+        for child in &node.children {
+            self.consume(child);
+        }
+    }
+}
+
+fn format_value(value: i32, operand: bool) -> String {
+    if value < 0 && operand {
+        format!("({})", value)
+    } else {
+        value.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NodeKind {
+    /// Number, string or character literal
+    Literal,
+    Name,
+    Unary,
+    Binary,
+    Paren,
+    Assign,
+    /// Calls, indexing and increments
+    Other,
+}
+
+#[derive(Debug)]
+struct Node {
+    kind: NodeKind,
+    /// Significant token range
+    start: usize,
+    end: usize,
+    value: Option<i32>,
+    children: Vec<Node>,
+}
+
+impl Node {
+    /// Whether folding the node replaces any computation (`-5` is just a literal).
+    fn has_operator(&self) -> bool {
+        match self.kind {
+            NodeKind::Binary => true,
+            NodeKind::Unary => self.children[0].kind != NodeKind::Literal,
+            NodeKind::Paren => self.children[0].has_operator(),
+            _ => false,
+        }
+    }
+}
+
+fn assignment_op(op: &str) -> bool {
+    matches!(
+        op,
+        "=" | "+=" | "-=" | "*=" | "/=" | "%=" | "&=" | "|=" | "^=" | "<<=" | ">>="
+    )
+}
+
+/// Binding power of a binary operator, as in C.
+fn binding_power(op: &str) -> Option<u8> {
+    Some(match op {
+        "||" => 3,
+        "&&" => 4,
+        "|" => 5,
+        "^" => 6,
+        "&" => 7,
+        "==" | "!=" => 8,
+        "<" | ">" | "<=" | ">=" => 9,
+        "<<" | ">>" => 10,
+        "+" | "-" => 11,
+        "*" | "/" | "%" => 12,
+        _ => return None,
+    })
+}
+
+const ASSIGN_POWER: u8 = 1;
+const UNARY_POWER: u8 = 13;
+
+/// Integer literal as a 32-bit value; larger literals are left alone.
+fn parse_number(text: &str) -> Option<i32> {
+    let value = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => text.parse::<i64>().ok()?,
+    };
+    i32::try_from(value).ok()
+}
+
+fn eval_unary(op: &str, value: i32) -> Option<i32> {
+    Some(match op {
+        "-" => value.wrapping_neg(),
+        "+" => value,
+        "!" => (value == 0) as i32,
+        "~" => !value,
+        _ => return None,
+    })
+}
+
+/// `a op b` with the device's wrapping 32-bit arithmetic. Division by zero and
+/// shifts outside 0..32 are not folded.
+fn eval_binary(op: &str, a: i32, b: i32) -> Option<i32> {
+    Some(match op {
+        "+" => a.wrapping_add(b),
+        "-" => a.wrapping_sub(b),
+        "*" => a.wrapping_mul(b),
+        "/" if b != 0 => a.wrapping_div(b),
+        "%" if b != 0 => a.wrapping_rem(b),
+        "<<" if (0..32).contains(&b) => a.wrapping_shl(b as u32),
+        ">>" if (0..32).contains(&b) => a >> b,
+        "&" => a & b,
+        "|" => a | b,
+        "^" => a ^ b,
+        "==" => (a == b) as i32,
+        "!=" => (a != b) as i32,
+        "<" => (a < b) as i32,
+        ">" => (a > b) as i32,
+        "<=" => (a <= b) as i32,
+        ">=" => (a >= b) as i32,
+        "&&" => (a != 0 && b != 0) as i32,
+        "||" => (a != 0 || b != 0) as i32,
+        _ => return None,
+    })
+}
+
+/// Precedence-climbing parser over significant tokens. It stops at the first token
+/// that cannot continue an expression and fails on tokens it does not understand.
+struct Parser<'p> {
+    sig: &'p [&'p str],
+    kinds: &'p [TokenKind],
+    consts: &'p HashMap<String, i32>,
+}
+
+impl<'p> Parser<'p> {
+    fn expression(&self, start: usize, min_power: u8) -> Option<Node> {
+        let mut left = self.operand(start)?;
+        while let Some(&op) = self.sig.get(left.end) {
+            let (power, kind) = if assignment_op(op) {
+                (ASSIGN_POWER, NodeKind::Assign)
+            } else {
+                match binding_power(op) {
+                    Some(power) => (power, NodeKind::Binary),
+                    None => break,
+                }
+            };
+            if power < min_power {
+                break;
+            }
+            // Assignments are right-associative, everything else left-associative
+            let next_power = if kind == NodeKind::Assign { power } else { power + 1 };
+            let right = self.expression(left.end + 1, next_power)?;
+            let value = match (kind, left.value, right.value) {
+                (NodeKind::Binary, Some(a), Some(b)) => eval_binary(op, a, b),
+                _ => None,
+            };
+            left = Node {
+                kind,
+                start: left.start,
+                end: right.end,
+                value,
+                children: vec![left, right],
+            };
+        }
+        Some(left)
+    }
+
+    fn operand(&self, start: usize) -> Option<Node> {
+        let text = *self.sig.get(start)?;
+        let mut node = match self.kinds[start] {
+            TokenKind::Number => Node {
+                kind: NodeKind::Literal,
+                start,
+                end: start + 1,
+                value: parse_number(text),
+                children: Vec::new(),
+            },
+            TokenKind::Str | TokenKind::Char => Node {
+                kind: NodeKind::Literal,
+                start,
+                end: start + 1,
+                value: None,
+                children: Vec::new(),
+            },
+            TokenKind::Ident if !is_keyword(text) => Node {
+                kind: NodeKind::Name,
+                start,
+                end: start + 1,
+                value: self.consts.get(text).copied(),
+                children: Vec::new(),
+            },
+            _ => match text {
+                "(" => {
+                    let inner = self.expression(start + 1, 0)?;
+                    if self.sig.get(inner.end) != Some(&")") {
+                        return None;
+                    }
+                    Node {
+                        kind: NodeKind::Paren,
+                        start,
+                        end: inner.end + 1,
+                        value: inner.value,
+                        children: vec![inner],
+                    }
+                }
+                "-" | "+" | "!" | "~" => {
+                    let operand = self.expression(start + 1, UNARY_POWER)?;
+                    Node {
+                        kind: NodeKind::Unary,
+                        start,
+                        end: operand.end,
+                        value: operand.value.and_then(|v| eval_unary(text, v)),
+                        children: vec![operand],
+                    }
+                }
+                "++" | "--" => {
+                    let operand = self.expression(start + 1, UNARY_POWER)?;
+                    Node {
+                        kind: NodeKind::Other,
+                        start,
+                        end: operand.end,
+                        value: None,
+                        children: vec![operand],
+                    }
+                }
+                _ => return None,
+            },
+        };
+
+        // Calls, indexing and postfix increments
+        loop {
+            match self.sig.get(node.end).copied() {
+                Some("(") if node.kind == NodeKind::Name => {
+                    let mut children = vec![node];
+                    let mut pos = children[0].end + 1;
+                    if self.sig.get(pos) != Some(&")") {
+                        loop {
+                            let arg = self.expression(pos, 0)?;
+                            pos = arg.end + 1;
+                            let sep = self.sig.get(arg.end).copied();
+                            children.push(arg);
+                            match sep {
+                                Some(",") => continue,
+                                Some(")") => break,
+                                _ => return None,
+                            }
+                        }
+                    } else {
+                        pos += 1;
+                    }
+                    node = Node {
+                        kind: NodeKind::Other,
+                        start,
+                        end: pos,
+                        value: None,
+                        children,
+                    };
+                }
+                Some("[") => {
+                    let index = self.expression(node.end + 1, 0)?;
+                    if self.sig.get(index.end) != Some(&"]") {
+                        return None;
+                    }
+                    node = Node {
+                        kind: NodeKind::Other,
+                        start,
+                        end: index.end + 1,
+                        value: None,
+                        children: vec![node, index],
+                    };
+                }
+                Some("++") | Some("--") => {
+                    node = Node {
+                        kind: NodeKind::Other,
+                        start,
+                        end: node.end + 1,
+                        value: None,
+                        children: vec![node],
+                    };
+                }
+                _ => return Some(node),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str) -> Optimized {
+        optimize(source, &HashSet::new())
+    }
+
+    #[test]
+    fn test_fold_constant_expressions() {
+        let source = "\
+define DELAY = 40;
+define STEP = 3;
+int a, b;
+main {
+    a = DELAY * 10 / 10;
+    b = a + 2 * 3;
+    a = b - (1 - 5);
+    wait(DELAY + STEP);
+    b = 7 / 0;
+    a = 2147483647 + 1;
+    b = -7 / 2 + -7 % 2;
+    a = 1 << 31 >> 31;
+    b <<= 2 + 1;
+}
+";
+        let optimized = run(source);
+        let text = &optimized.text;
+        assert!(text.contains("a = 40;"), "{}", text);
+        assert!(text.contains("b = a + 6;"));
+        assert!(text.contains("a = b - (-4);"));
+        assert!(text.contains("b <<= 3;"), "{}", text);
+        assert!(text.contains("wait(43);"));
+        assert!(text.contains("b = 7 / 0;"));
+        // The result would need a literal GPC cannot write
+        assert!(text.contains("a = 2147483647 + 1;"));
+        assert!(text.contains("b = -4;"));
+        assert!(text.contains("a = -1;"));
+        // Defines used more than once stay
+        assert!(text.contains("define DELAY = 40;"));
+        assert_eq!(optimized.report.inlined, vec!["STEP"]);
+        assert!(!text.contains("STEP"));
+    }
+
+    #[test]
+    fn test_left_context_is_respected() {
+        let source = "\
+int a, b;
+main {
+    a = (a ? b : 1) - 2 + 3;
+    b = a - 2 + 3;
+    a = b * 2 + 3;
+}
+";
+        let text = run(source).text;
+        // Nothing here is a constant expression on its own
+        assert!(text.contains("b = a - 2 + 3;"));
+        assert!(text.contains("a = b * 2 + 3;"));
+        assert!(!text.contains(") 1"));
+    }
+
+    #[test]
+    fn test_inline_single_use_defines() {
+        let source = "\
+define FIRE = PS5_R2;
+define SPEED = -5;
+define ONCE = TWICE;
+define TWICE = 2;
+define KEPT_FOR_PLUGIN = 9;
+int a;
+main {
+    if (get_val(FIRE)) a = a - SPEED;
+    a = ONCE + TWICE;
+    a = KEPT_FOR_PLUGIN;
+}
+";
+        let mut roots = HashSet::new();
+        roots.insert("KEPT_FOR_PLUGIN".to_string());
+        let optimized = optimize(source, &roots);
+        let text = &optimized.text;
+        assert!(text.contains("get_val(PS5_R2)"), "{}", text);
+        assert!(text.contains("a = a - (-5);"));
+        assert!(text.contains("a = 4;"));
+        assert!(text.contains("define KEPT_FOR_PLUGIN = 9;"));
+        assert!(text.contains("define TWICE = 2;"));
+        assert_eq!(optimized.report.inlined, vec!["FIRE", "SPEED", "ONCE"]);
+    }
+
+    #[test]
+    fn test_simplify_constant_branches() {
+        let source = "\
+define DEBUG = FALSE;
+int a;
+main {
+    if (TRUE) {
+        a = 1;
+    }
+    if (DEBUG) {
+        a = 2;
+    } else {
+        a = 3;
+    }
+    if (FALSE) a = 4;
+    if (1 + 1 == 3) {
+        a = 5;
+    } else if (a) {
+        a = 6;
+    }
+    if (a) {
+        if (TRUE) a = 7;
+    }
+}
+";
+        let optimized = run(source);
+        assert_eq!(
+            optimized.text,
+            "int a;\nmain {\n        a = 1;\n        a = 3;\n    if (a) {\n        a = 6;\n    }\n    if (a) {\n        a = 7;\n    }\n}\n"
+        );
+        assert_eq!(optimized.report.branches, 5);
+        assert_eq!(optimized.report.inlined, vec!["DEBUG"]);
+        assert_eq!(optimized.lines.len(), optimized.text.lines().count());
+        assert_eq!(optimized.lines[0].line, 1);
+        assert_eq!(optimized.lines[2].line, 4);
+        assert!(optimized.report.bytes_saved > 0);
+        assert_eq!(
+            optimized.report.lines_saved,
+            source.lines().count() - optimized.text.lines().count()
+        );
+    }
+
+    #[test]
+    fn test_simplify_else_if_chains() {
+        let source = "\
+int a;
+main {
+    if (FALSE) {
+        a = 1;
+    } else if (TRUE) {
+        a = 2;
+    } else {
+        a = 3;
+    }
+    if (a) {
+        a = 4;
+    } else if (1 == 2) {
+        a = 5;
+    } else if (2 == 2) {
+        a = 6;
+    }
+    if (a) a = 7; else if (FALSE) a = 8;
+    if (a) {
+        a = 9;
+    } else if (FALSE) {
+        a = 10;
+    }
+}
+";
+        let optimized = run(source);
+        assert_eq!(
+            optimized.text,
+            "int a;\nmain {\n        a = 2;\n    if (a) {\n        a = 4;\n    } else {\n        a = 6;\n    }\n    if (a) a = 7; else {}\n    if (a) {\n        a = 9;\n    }\n}\n"
+        );
+        assert_eq!(optimized.report.branches, 6);
+        assert!(crate::gpc::parse(&optimized.text).errors.is_empty());
+        assert_eq!(optimized.lines.len(), optimized.text.lines().count());
+    }
+}
@@ -0,0 +1,417 @@
+//! Per-tick performance profile of a script.
+//!
+//! The script runs headlessly on the GPC virtual machine, idle or driven by an input
+//! trace, while every operation is attributed to the `main`, `init`, combo or function
+//! frame it ran in. The most expensive tick is compared with what the device can run
+//! in one tick at each VM speed `vm_tctrl` can select, since a `main` that cannot
+//! finish in time makes combos and timings drift at low speeds.
+//!
+//! Operation counts are those of the simulator: one per statement and expression. The
+//! device's throughput is an estimate; `ops_per_ms` can be tuned to measurements.
+
+use crate::gpc::profile::StackOps;
+use crate::gpc::vm::Vm;
+use crate::pipeline::build::BuildOptions;
+use crate::pipeline::replay::{InputTrace, Script, TracePlayer};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Tick lengths in milliseconds the device can run at.
+pub const VM_SPEEDS: [u64; 5] = [10, 8, 4, 2, 1];
+
+/// Estimated operations the device runs per millisecond.
+pub const DEFAULT_OPS_PER_MS: u64 = 1000;
+
+/// Ticks to run a script for when no trace drives it.
+pub const DEFAULT_IDLE_TICKS: u64 = 1000;
+
+/// Share of a tick's budget, in percent, a frame may use by itself before it is
+/// flagged as a hot spot.
+const HOT_SPOT_PERCENT: u64 = 25;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileOptions {
+    /// Tick length the report is checked against, one of `VM_SPEEDS`
+    pub vm_speed_ms: u64,
+    pub ops_per_ms: u64,
+    /// Ticks to run; defaults to the length of the trace, or `DEFAULT_IDLE_TICKS`
+    pub ticks: Option<u64>,
+}
+
+impl Default for ProfileOptions {
+    fn default() -> Self {
+        Self {
+            vm_speed_ms: 10,
+            ops_per_ms: DEFAULT_OPS_PER_MS,
+            ticks: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileReport {
+    /// Whether every tick fit the budget of the chosen VM speed
+    pub success: bool,
+    pub ticks: u64,
+    pub vm_speed_ms: u64,
+    pub ops_per_ms: u64,
+    /// Operations the device can run in one tick at the chosen speed
+    pub budget_ops: u64,
+    /// Operations of `init`, which runs once and is left out of tick costs
+    pub init_ops: u64,
+    /// Operations per `main` iteration, including the functions it calls
+    pub main: IterationStats,
+    pub worst_tick: WorstTick,
+    /// The worst tick against every VM speed
+    pub speeds: Vec<SpeedBudget>,
+    /// Frames by operations in the worst tick they ran in, most expensive first
+    pub frames: Vec<FrameProfile>,
+    /// Runtime error that stopped the run early, with its location
+    pub error: Option<String>,
+    /// Folded stacks of the whole run for flamegraph tools
+    #[serde(skip)]
+    pub folded: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IterationStats {
+    pub average_ops: u64,
+    pub max_ops: u64,
+    /// Tick of the most expensive iteration
+    pub max_tick: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WorstTick {
+    pub tick: u64,
+    pub ops: u64,
+    /// Percentage of the chosen speed's budget
+    pub budget_percent: u64,
+    /// Call stacks that ran operations in that tick, as `frame;frame`
+    pub stacks: Vec<FoldedStack>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FoldedStack {
+    pub stack: String,
+    pub ops: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SpeedBudget {
+    pub tick_ms: u64,
+    pub budget_ops: u64,
+    /// The worst tick as a percentage of this budget
+    pub worst_percent: u64,
+    pub ticks_over_budget: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FrameProfile {
+    pub name: String,
+    /// `main`, `init`, `combo` or `function`
+    pub kind: String,
+    pub calls: u64,
+    /// Operations in the frame itself over the whole run
+    pub self_ops: u64,
+    /// Most operations in one tick, including the frames it called
+    pub max_tick_ops: u64,
+    /// Most operations in one tick in the frame itself
+    pub max_tick_self_ops: u64,
+    /// The frame by itself used more than a quarter of a tick's budget
+    pub hot: bool,
+}
+
+/// Most expensive tick of each frame, collected while the script runs.
+#[derive(Default)]
+struct FrameStats {
+    calls: u64,
+    self_ops: u64,
+    max_tick_ops: u64,
+    max_tick_self_ops: u64,
+}
+
+/// Run a script with profiling and report its per-tick cost.
+pub fn profile(
+    mut vm: Vm,
+    trace: Option<&InputTrace>,
+    options: &ProfileOptions,
+    locate: &dyn Fn(usize) -> String,
+) -> Result<ProfileReport, String> {
+    if !VM_SPEEDS.contains(&options.vm_speed_ms) {
+        return Err(format!(
+            "VM speed must be one of 10, 8, 4, 2 or 1 ms, got {}",
+            options.vm_speed_ms
+        ));
+    }
+    let mut player = trace.map(TracePlayer::new).transpose()?;
+    vm.enable_profiling();
+
+    let mut ticks_over: Vec<u64> = vec![0; VM_SPEEDS.len()];
+    let mut frames: BTreeMap<Rc<str>, FrameStats> = BTreeMap::new();
+    let mut main = IterationStats::default();
+    let mut main_total = 0;
+    let mut init_ops = 0;
+    let mut worst = WorstTick::default();
+    let mut error = None;
+    let idle = [0; crate::gpc::constants::IO_COUNT];
+    loop {
+        let tick = vm.ticks();
+        let done = match (options.ticks, &player) {
+            (Some(ticks), _) => tick >= ticks,
+            (None, Some(player)) => vm.time_ms() >= player.end_ms,
+            (None, None) => tick >= DEFAULT_IDLE_TICKS,
+        };
+        if done {
+            break;
+        }
+        let time_ms = vm.time_ms();
+        let input = match &mut player {
+            Some(player) => player.input_at(time_ms),
+            None => &idle,
+        };
+        let result = vm.tick(input);
+        if let Err(e) = result {
+            error = Some(format!(
+                "tick {}: {} ({})",
+                tick,
+                e.message,
+                locate(e.span.start)
+            ));
+            break;
+        }
+
+        let stacks = vm.profile().map(|p| p.stacks()).unwrap_or_default();
+        let mut tick_ops = 0;
+        let mut inclusive: BTreeMap<&str, u64> = BTreeMap::new();
+        let mut exclusive: BTreeMap<&str, u64> = BTreeMap::new();
+        for stack in stacks.iter().filter(|s| s.tick_ops > 0) {
+            if &*stack.frames[0] == "init" {
+                init_ops += stack.tick_ops;
+                continue;
+            }
+            tick_ops += stack.tick_ops;
+            let mut seen: Vec<&str> = Vec::new();
+            for frame in &stack.frames {
+                // A recursive frame counts once per stack
+                if !seen.contains(&&**frame) {
+                    seen.push(frame);
+                    *inclusive.entry(frame).or_default() += stack.tick_ops;
+                }
+            }
+            if let Some(frame) = stack.frames.last() {
+                *exclusive.entry(frame).or_default() += stack.tick_ops;
+            }
+        }
+        for (frame, ops) in &inclusive {
+            let stats = frames.entry(Rc::from(*frame)).or_default();
+            stats.max_tick_ops = stats.max_tick_ops.max(*ops);
+            stats.max_tick_self_ops = stats
+                .max_tick_self_ops
+                .max(exclusive.get(frame).copied().unwrap_or(0));
+        }
+        let main_ops = inclusive.get("main").copied().unwrap_or(0);
+        main_total += main_ops;
+        if main_ops > main.max_ops {
+            main.max_ops = main_ops;
+            main.max_tick = tick;
+        }
+        if tick_ops > worst.ops {
+            worst = WorstTick {
+                tick,
+                ops: tick_ops,
+                budget_percent: 0,
+                stacks: folded(&stacks, |s| s.tick_ops)
+                    .into_iter()
+                    .filter(|s| !s.stack.starts_with("init"))
+                    .collect(),
+            };
+        }
+        for (index, speed) in VM_SPEEDS.iter().enumerate() {
+            if tick_ops > speed * options.ops_per_ms {
+                ticks_over[index] += 1;
+            }
+        }
+    }
+
+    let ticks = vm.ticks();
+    let profile = vm.profile();
+    let stacks = profile.map(|p| p.stacks()).unwrap_or_default();
+    for stack in &stacks {
+        if let Some(frame) = stack.frames.last() {
+            let stats = frames.entry(Rc::clone(frame)).or_default();
+            stats.calls += stack.calls;
+            stats.self_ops += stack.ops;
+        }
+    }
+    main.average_ops = main_total.checked_div(ticks).unwrap_or(0);
+
+    let budget_ops = options.vm_speed_ms * options.ops_per_ms;
+    worst.budget_percent = percent(worst.ops, budget_ops);
+    let speeds: Vec<SpeedBudget> = VM_SPEEDS
+        .iter()
+        .zip(&ticks_over)
+        .map(|(speed, over)| SpeedBudget {
+            tick_ms: *speed,
+            budget_ops: speed * options.ops_per_ms,
+            worst_percent: percent(worst.ops, speed * options.ops_per_ms),
+            ticks_over_budget: *over,
+        })
+        .collect();
+    let over_budget = worst.ops > budget_ops;
+
+    let combos: Vec<&str> = vm.combo_names().collect();
+    let mut frames: Vec<FrameProfile> = frames
+        .into_iter()
+        .map(|(name, stats)| FrameProfile {
+            kind: match &*name {
+                "main" | "init" => name.to_string(),
+                _ if combos.contains(&&*name) => "combo".to_string(),
+                _ => "function".to_string(),
+            },
+            name: name.to_string(),
+            calls: stats.calls,
+            self_ops: stats.self_ops,
+            max_tick_ops: stats.max_tick_ops,
+            max_tick_self_ops: stats.max_tick_self_ops,
+            hot: percent(stats.max_tick_self_ops, budget_ops) >= HOT_SPOT_PERCENT,
+        })
+        .collect();
+    frames.sort_by(|a, b| {
+        b.max_tick_ops
+            .cmp(&a.max_tick_ops)
+            .then(a.name.cmp(&b.name))
+    });
+
+    Ok(ProfileReport {
+        success: error.is_none() && !over_budget,
+        ticks,
+        vm_speed_ms: options.vm_speed_ms,
+        ops_per_ms: options.ops_per_ms,
+        budget_ops,
+        init_ops,
+        main,
+        worst_tick: worst,
+        speeds,
+        frames,
+        error,
+        folded: profile.map(|p| p.folded()).unwrap_or_default(),
+    })
+}
+
+fn percent(ops: u64, budget: u64) -> u64 {
+    (ops * 100).checked_div(budget).unwrap_or(0)
+}
+
+fn folded(stacks: &[StackOps], ops: impl Fn(&StackOps) -> u64) -> Vec<FoldedStack> {
+    stacks
+        .iter()
+        .filter(|s| ops(s) > 0)
+        .map(|s| FoldedStack {
+            stack: s.frames.join(";"),
+            ops: ops(s),
+        })
+        .collect()
+}
+
+/// Profile a game directory, which is built first, or a script file.
+pub fn profile_path(
+    path: &Path,
+    project_root: &Path,
+    dist_base: &Path,
+    workspace_path: Option<&str>,
+    build_options: &BuildOptions,
+    trace: Option<&InputTrace>,
+    options: &ProfileOptions,
+) -> Result<ProfileReport, String> {
+    let script = Script::load(path, project_root, dist_base, workspace_path, build_options)?;
+    let location = |offset: usize| script.locate(offset);
+    profile(Vm::new(script.program.clone()), trace, options, &location)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"
+int Total, i;
+init { for (i = 0; i < 10; i++) Total = Total + i; }
+main {
+    if (get_val(PS5_R2)) {
+        combo_run(Pulse);
+        sum(40);
+    }
+}
+function sum(n) {
+    Total = 0;
+    for (i = 0; i < n; i++) Total = Total + i;
+    return Total;
+}
+combo Pulse {
+    set_val(PS5_R2, 100);
+    wait(20);
+    set_val(PS5_R2, 0);
+}
+"#;
+
+    fn run(trace: Option<&InputTrace>, options: &ProfileOptions) -> ProfileReport {
+        profile(Vm::load(SCRIPT).unwrap(), trace, options, &|_| {
+            String::new()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_profile_idle_and_with_trace() {
+        let idle = run(None, &ProfileOptions::default());
+        assert_eq!(idle.ticks, DEFAULT_IDLE_TICKS);
+        assert!(idle.success && idle.init_ops > 0);
+        assert_eq!(idle.main.max_ops, idle.worst_tick.ops);
+        assert!(idle.frames.iter().all(|f| f.name != "sum"));
+
+        let trace = InputTrace::from_csv("0,PS5_R2,100\n50,PS5_R2,0").unwrap();
+        let options = ProfileOptions {
+            vm_speed_ms: 1,
+            ops_per_ms: 100,
+            ticks: None,
+        };
+        let report = run(Some(&trace), &options);
+        assert_eq!((report.ticks, report.budget_ops), (6, 100));
+        assert!(!report.success);
+        assert_eq!(report.worst_tick.tick, 0);
+        assert!(report.main.max_ops < report.worst_tick.ops);
+        assert!(report.worst_tick.budget_percent > 100);
+        let stacks: Vec<&str> = report
+            .worst_tick
+            .stacks
+            .iter()
+            .map(|s| s.stack.as_str())
+            .collect();
+        assert_eq!(stacks, vec!["main", "main;sum", "Pulse"]);
+        assert_eq!(report.speeds.len(), VM_SPEEDS.len());
+        assert_eq!(report.speeds[4].ticks_over_budget, 5);
+        assert_eq!(report.speeds[0].ticks_over_budget, 0);
+
+        let sum = report.frames.iter().find(|f| f.name == "sum").unwrap();
+        assert_eq!(
+            (sum.kind.as_str(), sum.calls, sum.hot),
+            ("function", 5, true)
+        );
+        let pulse = report.frames.iter().find(|f| f.name == "Pulse").unwrap();
+        assert_eq!((pulse.kind.as_str(), pulse.hot), ("combo", false));
+        assert_eq!(report.frames[0].name, "main");
+        assert!(report.folded.contains("\nmain;sum "));
+        assert!(report.folded.starts_with("init "));
+
+        let invalid = ProfileOptions {
+            vm_speed_ms: 3,
+            ..Default::default()
+        };
+        let error = profile(Vm::load(SCRIPT).unwrap(), None, &invalid, &|_| {
+            String::new()
+        });
+        assert!(error.unwrap_err().contains("VM speed"));
+    }
+}
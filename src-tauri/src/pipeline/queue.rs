@@ -0,0 +1,400 @@
+//! Build queue: several games built concurrently on a pool of worker threads.
+//!
+//! Each job builds every target of one game (see `build_game_targets`). Progress
+//! of every job is reported through a callback, any job or the whole queue can be
+//! cancelled through a `QueueHandle` while it runs, and the queue ends with a
+//! summary of all jobs.
+
+use crate::pipeline::build::{
+    build_game_targets, BuildMonitor, BuildOptions, BuildPhase, MatrixBuildResult,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// A game to build.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BuildJob {
+    pub game_path: String,
+    /// Workspace whose dist/ receives the output and whose plugins apply
+    #[serde(default)]
+    pub workspace_path: Option<String>,
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub defines: BTreeMap<String, String>,
+    #[serde(default)]
+    pub no_cache: bool,
+}
+
+/// Where a job stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+/// Progress of one job, reported whenever it starts a phase or a file.
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildProgress {
+    /// Index of the job in the queue
+    pub job_id: usize,
+    pub game_path: String,
+    pub status: JobStatus,
+    /// Phase of a running job
+    pub phase: Option<BuildPhase>,
+    /// File being processed or written, if the phase works on one
+    pub file: Option<String>,
+    /// How far the job is, 0-100
+    pub percent: u8,
+    /// How far the whole queue is, 0-100
+    pub queue_percent: u8,
+}
+
+/// Outcome of one job.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobSummary {
+    pub job_id: usize,
+    pub game_path: String,
+    pub status: JobStatus,
+    /// `None` if the job was cancelled before it started or its game does not exist
+    pub result: Option<MatrixBuildResult>,
+    /// Why the job did not build, when there is no `result` to explain it
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// Outcome of a whole queue.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueSummary {
+    /// Jobs in queue order
+    pub jobs: Vec<JobSummary>,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub duration_ms: u64,
+}
+
+/// Cancels jobs of a running queue from another thread.
+#[derive(Debug)]
+pub struct QueueHandle {
+    monitors: Vec<BuildMonitor>,
+    cancelled: AtomicBool,
+}
+
+impl QueueHandle {
+    /// Cancel one job: a queued job is skipped, a running one stops at its next phase.
+    pub fn cancel_job(&self, job_id: usize) -> Result<(), String> {
+        let monitor = self
+            .monitors
+            .get(job_id)
+            .ok_or_else(|| format!("No build job {} in the queue", job_id))?;
+        monitor.cancel();
+        Ok(())
+    }
+
+    /// Cancel every job that has not finished yet.
+    pub fn cancel_all(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        for monitor in &self.monitors {
+            monitor.cancel();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+type ProgressSink = dyn Fn(&BuildProgress) + Send + Sync;
+
+/// A queue of build jobs, ready to run.
+pub struct BuildQueue {
+    jobs: Vec<BuildJob>,
+    handle: Arc<QueueHandle>,
+    /// Percent done of each job, for `BuildProgress::queue_percent`
+    percents: Arc<Mutex<Vec<u8>>>,
+    on_progress: Arc<ProgressSink>,
+}
+
+impl BuildQueue {
+    /// Queue `jobs` in order, reporting their progress to `on_progress` from the
+    /// worker threads. A game may only be queued once, since two builds of the same
+    /// game would write the same files.
+    pub fn new(
+        jobs: Vec<BuildJob>,
+        on_progress: impl Fn(&BuildProgress) + Send + Sync + 'static,
+    ) -> Result<Self, String> {
+        let mut seen = HashSet::new();
+        for job in &jobs {
+            let path = Path::new(&job.game_path);
+            if !seen.insert(path.canonicalize().unwrap_or_else(|_| path.to_path_buf())) {
+                return Err(format!("Game {} is queued more than once", job.game_path));
+            }
+        }
+
+        let on_progress: Arc<ProgressSink> = Arc::new(on_progress);
+        let percents = Arc::new(Mutex::new(vec![0; jobs.len()]));
+        // Monitors exist before the queue runs so any job can be cancelled before it starts
+        let monitors = jobs
+            .iter()
+            .enumerate()
+            .map(|(job_id, job)| {
+                let sink = on_progress.clone();
+                let percents = percents.clone();
+                let game_path = job.game_path.clone();
+                BuildMonitor::new(move |phase, file| {
+                    let update = Update {
+                        job_id,
+                        game_path: &game_path,
+                        status: JobStatus::Running,
+                        phase: Some(phase),
+                        file,
+                        percent: phase.percent(),
+                    };
+                    sink(&update.apply(&percents));
+                })
+            })
+            .collect();
+        Ok(Self {
+            jobs,
+            handle: Arc::new(QueueHandle {
+                monitors,
+                cancelled: AtomicBool::new(false),
+            }),
+            percents,
+            on_progress,
+        })
+    }
+
+    /// Handle for cancelling jobs while the queue runs.
+    pub fn handle(&self) -> Arc<QueueHandle> {
+        self.handle.clone()
+    }
+
+    /// Build every job on up to `workers` threads and wait for all of them.
+    pub fn run(self, project_root: &Path, workers: usize) -> QueueSummary {
+        let started = Instant::now();
+        for (job_id, job) in self.jobs.iter().enumerate() {
+            self.report(job_id, &job.game_path, JobStatus::Queued, 0);
+        }
+        let next = AtomicUsize::new(0);
+        let summaries: Mutex<Vec<Option<JobSummary>>> = Mutex::new(vec![None; self.jobs.len()]);
+        let workers = workers.clamp(1, self.jobs.len().max(1));
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let job_id = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(job) = self.jobs.get(job_id) else {
+                        break;
+                    };
+                    let summary = self.run_job(job_id, job, project_root);
+                    summaries.lock().unwrap_or_else(|e| e.into_inner())[job_id] = Some(summary);
+                });
+            }
+        });
+
+        let jobs: Vec<JobSummary> = summaries
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner())
+            .into_iter()
+            .flatten()
+            .collect();
+        let count = |status: JobStatus| jobs.iter().filter(|j| j.status == status).count();
+        QueueSummary {
+            succeeded: count(JobStatus::Succeeded),
+            failed: count(JobStatus::Failed),
+            cancelled: count(JobStatus::Cancelled),
+            jobs,
+            duration_ms: started.elapsed().as_millis() as u64,
+        }
+    }
+
+    /// Report a change of a job's status outside of its build phases.
+    fn report(&self, job_id: usize, game_path: &str, status: JobStatus, percent: u8) {
+        let update = Update {
+            job_id,
+            game_path,
+            status,
+            phase: None,
+            file: None,
+            percent,
+        };
+        (self.on_progress)(&update.apply(&self.percents));
+    }
+
+    fn run_job(&self, job_id: usize, job: &BuildJob, project_root: &Path) -> JobSummary {
+        let started = Instant::now();
+        let monitor = &self.handle.monitors[job_id];
+        let report = |status: JobStatus, percent: u8| {
+            self.report(job_id, &job.game_path, status, percent);
+        };
+        let finish = |status: JobStatus, result: Option<MatrixBuildResult>, error: Option<String>| {
+            // Finished jobs count as done towards the queue, whatever their outcome
+            report(status, 100);
+            JobSummary {
+                job_id,
+                game_path: job.game_path.clone(),
+                status,
+                result,
+                error,
+                duration_ms: started.elapsed().as_millis() as u64,
+            }
+        };
+
+        if monitor.is_cancelled() {
+            return finish(JobStatus::Cancelled, None, None);
+        }
+        let game_dir = PathBuf::from(&job.game_path);
+        if !game_dir.exists() {
+            let error = format!("Game directory not found: {}", job.game_path);
+            return finish(JobStatus::Failed, None, Some(error));
+        }
+        report(JobStatus::Running, 0);
+
+        let dist_base = job
+            .workspace_path
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| project_root.to_path_buf());
+        let options = BuildOptions {
+            profile: job.profile.clone(),
+            defines: job.defines.clone(),
+            no_cache: job.no_cache,
+            monitor: monitor.clone(),
+            ..Default::default()
+        };
+        let result = build_game_targets(
+            &game_dir,
+            project_root,
+            &dist_base,
+            false,
+            job.workspace_path.as_deref(),
+            &options,
+        );
+        let status = if monitor.is_cancelled() {
+            JobStatus::Cancelled
+        } else if result.success {
+            JobStatus::Succeeded
+        } else {
+            JobStatus::Failed
+        };
+        finish(status, Some(result), None)
+    }
+}
+
+/// Number of worker threads to use by default: one per core, at most `jobs`.
+pub fn default_workers(jobs: usize) -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(jobs.max(1))
+}
+
+/// A change in a job's progress.
+struct Update<'a> {
+    job_id: usize,
+    game_path: &'a str,
+    status: JobStatus,
+    phase: Option<BuildPhase>,
+    file: Option<&'a str>,
+    percent: u8,
+}
+
+impl Update<'_> {
+    /// Record the job's percent among `percents` and describe its progress.
+    fn apply(self, percents: &Mutex<Vec<u8>>) -> BuildProgress {
+        let mut percents = percents.lock().unwrap_or_else(|e| e.into_inner());
+        percents[self.job_id] = self.percent;
+        let total: usize = percents.iter().map(|&p| p as usize).sum();
+        BuildProgress {
+            job_id: self.job_id,
+            game_path: self.game_path.to_string(),
+            status: self.status,
+            phase: self.phase,
+            file: self.file.map(str::to_string),
+            percent: self.percent,
+            queue_percent: (total / percents.len().max(1)) as u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(dir: &Path, name: &str, main: &str) -> BuildJob {
+        let game_dir = dir.join(name);
+        std::fs::create_dir_all(&game_dir).unwrap();
+        std::fs::write(
+            game_dir.join("game.json"),
+            format!(
+                r#"{{"name":"{name}","filename":"{name}","version":1,"game_type":"fps",
+                    "console_type":"ps5"}}"#
+            ),
+        )
+        .unwrap();
+        std::fs::write(game_dir.join("main.gpc"), main).unwrap();
+        BuildJob {
+            game_path: game_dir.to_string_lossy().to_string(),
+            workspace_path: Some(dir.to_string_lossy().to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_queue_builds_and_summarizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let jobs = vec![
+            game(dir.path(), "A", "main {\n}\n"),
+            game(dir.path(), "B", "main {\n"),
+            game(dir.path(), "C", "int c;\nmain {\n}\n"),
+        ];
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let queue = BuildQueue::new(jobs, move |p| sink.lock().unwrap().push(p.clone())).unwrap();
+        let summary = queue.run(dir.path(), 2);
+
+        assert_eq!((summary.succeeded, summary.failed, summary.cancelled), (2, 1, 0));
+        let statuses: Vec<JobStatus> = summary.jobs.iter().map(|j| j.status).collect();
+        assert_eq!(statuses, vec![JobStatus::Succeeded, JobStatus::Failed, JobStatus::Succeeded]);
+        assert!(dir.path().join("dist/A.gpc").exists());
+        assert!(dir.path().join("dist/C.gpc").exists());
+
+        let events = events.lock().unwrap();
+        assert!(events.iter().any(|p| p.phase == Some(BuildPhase::Writing)));
+        assert!(events
+            .iter()
+            .any(|p| p.file.as_deref().is_some_and(|f| f.ends_with("main.gpc"))));
+        assert_eq!(events.iter().map(|p| p.queue_percent).max(), Some(100));
+    }
+
+    #[test]
+    fn test_queue_cancellation() {
+        let dir = tempfile::tempdir().unwrap();
+        let jobs = vec![game(dir.path(), "A", "main {\n}\n"), game(dir.path(), "B", "main {\n}\n")];
+        let queue = BuildQueue::new(jobs, |_| {}).unwrap();
+        queue.handle().cancel_job(1).unwrap();
+        assert!(queue.handle().cancel_job(2).is_err());
+        let summary = queue.run(dir.path(), 1);
+        assert_eq!(summary.jobs[0].status, JobStatus::Succeeded);
+        assert_eq!(summary.jobs[1].status, JobStatus::Cancelled);
+        assert!(!dir.path().join("dist/B.gpc").exists());
+
+        let jobs = vec![game(dir.path(), "C", "main {\n}\n")];
+        let queue = BuildQueue::new(jobs, |_| {}).unwrap();
+        queue.handle().cancel_all();
+        assert!(queue.handle().is_cancelled());
+        assert_eq!(queue.run(dir.path(), 1).cancelled, 1);
+
+        let a = game(dir.path(), "A", "main {\n}\n");
+        assert!(BuildQueue::new(vec![a.clone(), a], |_| {}).is_err());
+    }
+}
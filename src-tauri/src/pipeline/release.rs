@@ -0,0 +1,789 @@
+//! Release bundles: a built game packaged for publishing.
+//!
+//! A release builds every target of a game and zips the outputs together with a
+//! README generated from the game's menu, modules and button layout, the game's
+//! `CHANGELOG.md`, the outputs' entries of `build-manifest.json` and a `SHA256SUMS`
+//! file. Bundles are written to
+//! `<dist_base>/dist/releases/<game>-v<version>.zip`.
+
+use crate::commands::flow::load_flow_project;
+use crate::models::config::GameConfig;
+use crate::models::flow::{FlowGraph, FlowNode, FlowProject};
+use crate::models::game_meta::GameMeta;
+use crate::pipeline::artifacts;
+use crate::pipeline::manifest::{self, BuildManifest};
+use crate::pipeline::build::{build_game_targets, BuildOptions, MatrixBuildResult};
+use crate::pipeline::cache::hash_bytes;
+use crate::pipeline::modules;
+use crate::pipeline::template;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const CHANGELOG_FILE: &str = "CHANGELOG.md";
+
+/// Options for a release beyond those of the build itself.
+#[derive(Debug, Clone, Default)]
+pub struct ReleaseOptions {
+    /// Increase the game's version before building (1.4 -> 1.5, 2 -> 3)
+    pub bump_version: bool,
+    /// Notes for this version, added as a new entry at the top of CHANGELOG.md
+    pub notes: Option<String>,
+    pub build: BuildOptions,
+}
+
+/// A file in a release bundle.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReleaseFile {
+    pub name: String,
+    pub sha256: String,
+}
+
+/// Outcome of a release.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReleaseResult {
+    /// Whether the game built and the bundle was written
+    pub success: bool,
+    /// Version the release was built as
+    pub version: f64,
+    pub bundle_path: Option<String>,
+    /// Files in the bundle with their checksums, as listed in SHA256SUMS
+    pub files: Vec<ReleaseFile>,
+    pub build: MatrixBuildResult,
+}
+
+/// Directory release bundles are written to.
+pub fn releases_dir(dist_base: &Path) -> PathBuf {
+    dist_base.join("dist").join("releases")
+}
+
+/// The version after `version`: its last decimal digit goes up by one.
+pub fn next_version(version: f64) -> f64 {
+    let text = version.to_string();
+    match text.split_once('.') {
+        Some((_, decimals)) => {
+            let scale = 10f64.powi(decimals.len() as i32);
+            ((version * scale).round() + 1.0) / scale
+        }
+        None => version + 1.0,
+    }
+}
+
+/// The game's metadata file and its parsed version. Legacy config-based games keep
+/// their version in config.toml.
+enum VersionSource {
+    Meta(PathBuf),
+    Config(PathBuf),
+}
+
+impl VersionSource {
+    fn of(game_dir: &Path) -> Result<Self, String> {
+        let meta = game_dir.join("game.json");
+        if meta.exists() {
+            return Ok(Self::Meta(meta));
+        }
+        let config = game_dir.join("config.toml");
+        if config.exists() {
+            return Ok(Self::Config(config));
+        }
+        Err(format!("No game.json or config.toml in {}", game_dir.display()))
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            Self::Meta(path) | Self::Config(path) => path,
+        }
+    }
+
+    fn version(&self) -> Result<f64, String> {
+        let content = std::fs::read_to_string(self.path())
+            .map_err(|e| format!("Could not read {}: {}", self.path().display(), e))?;
+        match self {
+            Self::Meta(_) => serde_json::from_str::<GameMeta>(&content)
+                .map(|m| m.version)
+                .map_err(|e| format!("Could not parse game.json: {}", e)),
+            Self::Config(_) => toml::from_str::<GameConfig>(&content)
+                .map(|c| c.version)
+                .map_err(|e| format!("Could not parse config.toml: {}", e)),
+        }
+    }
+
+    /// Rewrite only the version. The number in game.json is replaced where it stands
+    /// and config.toml is edited as a document, so every other field, the key order
+    /// and the file's formatting are kept.
+    fn set_version(&self, version: f64) -> Result<(), String> {
+        let content = std::fs::read_to_string(self.path())
+            .map_err(|e| format!("Could not read {}: {}", self.path().display(), e))?;
+        let updated = match self {
+            Self::Meta(_) => {
+                let updated = replace_top_level_number(&content, "version", &version.to_string())
+                    .ok_or_else(|| "Could not find the version in game.json".to_string())?;
+                serde_json::from_str::<GameMeta>(&updated)
+                    .map_err(|e| format!("Could not parse game.json: {}", e))?;
+                updated
+            }
+            Self::Config(_) => {
+                let mut doc = content
+                    .parse::<toml_edit::DocumentMut>()
+                    .map_err(|e| format!("Could not parse config.toml: {}", e))?;
+                doc["version"] = toml_edit::value(version);
+                doc.to_string()
+            }
+        };
+        std::fs::write(self.path(), updated)
+            .map_err(|e| format!("Could not write {}: {}", self.path().display(), e))
+    }
+}
+
+/// `content` with the number under `key` of its top-level JSON object replaced by
+/// `value`, leaving the rest of the text as it was.
+fn replace_top_level_number(content: &str, key: &str, value: &str) -> Option<String> {
+    let bytes = content.as_bytes();
+    let quoted = format!("\"{}\"", key);
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            b'"' => {
+                let start = i;
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                // A key is a string followed by `:`
+                let after = content.get(i + 1..).unwrap_or("").trim_start();
+                let is_key = after.starts_with(':');
+                if depth == 1 && is_key && content.get(start..=i) == Some(quoted.as_str()) {
+                    let number = after[1..].trim_start();
+                    let value_start = content.len() - number.len();
+                    let len = number
+                        .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+                        .unwrap_or(number.len());
+                    if len == 0 {
+                        return None;
+                    }
+                    let mut updated = content.to_string();
+                    updated.replace_range(value_start..value_start + len, value);
+                    return Some(updated);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Build a game and package it as a release bundle.
+///
+/// With `bump_version` the new version is written before building so it appears in the
+/// output header, and put back if the build fails or the bundle cannot be written.
+/// Returns an error only when the game cannot be read or the bundle cannot be
+/// written; build failures are reported in the result.
+pub fn release_game(
+    game_dir: &Path,
+    project_root: &Path,
+    dist_base: &Path,
+    workspace_path: Option<&str>,
+    options: &ReleaseOptions,
+) -> Result<ReleaseResult, String> {
+    let source = VersionSource::of(game_dir)?;
+    let mut version = source.version()?;
+    let previous = std::fs::read(source.path())
+        .map_err(|e| format!("Could not read {}: {}", source.path().display(), e))?;
+    if options.bump_version {
+        version = next_version(version);
+        source.set_version(version)?;
+    }
+    let restore_version = || {
+        if !options.bump_version {
+            return Ok(());
+        }
+        std::fs::write(source.path(), &previous)
+            .map_err(|e| format!("Could not restore {}: {}", source.path().display(), e))
+    };
+
+    let build = build_game_targets(
+        game_dir,
+        project_root,
+        dist_base,
+        true,
+        workspace_path,
+        &options.build,
+    );
+    if !build.success {
+        restore_version()?;
+        return Ok(ReleaseResult {
+            success: false,
+            version,
+            bundle_path: None,
+            files: Vec::new(),
+            build,
+        });
+    }
+
+    let changelog_path = game_dir.join(CHANGELOG_FILE);
+    let previous_changelog = std::fs::read(&changelog_path).ok();
+    let notes = options.notes.as_deref().filter(|n| !n.trim().is_empty());
+    let bundle = write_bundle(game_dir, project_root, dist_base, &build, version, notes);
+    let (bundle_path, files) = match bundle {
+        Ok(bundle) => bundle,
+        Err(e) => {
+            // Leave the game as it was so the release can simply be run again
+            restore_version()?;
+            if notes.is_some() {
+                match previous_changelog {
+                    Some(content) => std::fs::write(&changelog_path, content),
+                    None => std::fs::remove_file(&changelog_path),
+                }
+                .map_err(|e| format!("Could not restore {}: {}", changelog_path.display(), e))?;
+            }
+            return Err(e);
+        }
+    };
+
+    Ok(ReleaseResult {
+        success: true,
+        version,
+        bundle_path: Some(bundle_path.to_string_lossy().to_string()),
+        files,
+        build,
+    })
+}
+
+/// Add the changelog entry and zip the outputs of a successful `build` with the
+/// release's other files. Returns the bundle's path and its checksummed files.
+fn write_bundle(
+    game_dir: &Path,
+    project_root: &Path,
+    dist_base: &Path,
+    build: &MatrixBuildResult,
+    version: f64,
+    notes: Option<&str>,
+) -> Result<(PathBuf, Vec<ReleaseFile>), String> {
+    if let Some(notes) = notes {
+        add_changelog_entry(game_dir, version, notes)?;
+    }
+
+    let mut entries: Vec<(String, Vec<u8>)> = Vec::new();
+    for target in &build.targets {
+        let path = Path::new(&target.result.output_path);
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .ok_or_else(|| format!("Invalid output path: {}", path.display()))?;
+        let content = std::fs::read(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        label_artifact(game_dir, &name, &content, version);
+        entries.push((name, content));
+    }
+    entries.push(("README.md".to_string(), generate_readme(game_dir, project_root).into_bytes()));
+    if let Ok(changelog) = std::fs::read(game_dir.join(CHANGELOG_FILE)) {
+        entries.push((CHANGELOG_FILE.to_string(), changelog));
+    }
+    // Only the released outputs' entries; other games may share dist/
+    let mut released = BuildManifest::load(&dist_base.join("dist"));
+    released.outputs.retain(|name, _| {
+        build
+            .targets
+            .iter()
+            .any(|t| Path::new(&t.result.output_path).file_name() == Some(name.as_ref()))
+    });
+    let released = serde_json::to_string_pretty(&released)
+        .map_err(|e| format!("Could not serialize build manifest: {}", e))?;
+    entries.push((manifest::MANIFEST_FILE.to_string(), released.into_bytes()));
+
+    let files: Vec<ReleaseFile> = entries
+        .iter()
+        .map(|(name, content)| ReleaseFile {
+            name: name.clone(),
+            sha256: hash_bytes(content),
+        })
+        .collect();
+    let sums: String = files
+        .iter()
+        .map(|f| format!("{}  {}\n", f.sha256, f.name))
+        .collect();
+    entries.push(("SHA256SUMS".to_string(), sums.into_bytes()));
+
+    let dir = releases_dir(dist_base);
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Could not create release directory {}: {}", dir.display(), e))?;
+    let game_name = game_dir
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "game".to_string());
+    let bundle_path = dir.join(format!("{}-v{}.zip", game_name, version));
+    write_zip(&bundle_path, &entries)?;
+    Ok((bundle_path, files))
+}
+
+fn write_zip(path: &Path, entries: &[(String, Vec<u8>)]) -> Result<(), String> {
+    let file = std::fs::File::create(path)
+        .map_err(|e| format!("Could not create {}: {}", path.display(), e))?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+    for (name, content) in entries {
+        zip.start_file(name.as_str(), options)
+            .map_err(|e| format!("Could not add {} to bundle: {}", name, e))?;
+        zip.write_all(content)
+            .map_err(|e| format!("Could not write {} to bundle: {}", name, e))?;
+    }
+    zip.finish()
+        .map_err(|e| format!("Could not finish bundle {}: {}", path.display(), e))?;
+    Ok(())
+}
+
+/// Label the recorded artifact of a released output, so the release stands out in
+/// the game's build history.
+fn label_artifact(game_dir: &Path, file_name: &str, content: &[u8], version: f64) {
+    let hash = hash_bytes(content);
+    let recorded = artifacts::list(game_dir)
+        .into_iter()
+        .find(|a| a.file_name == file_name && a.output_hash == hash);
+    if let Some(artifact) = recorded {
+        let label = Some(format!("Release v{}", version));
+        if let Err(e) = artifacts::set_label(game_dir, &artifact.id, label) {
+            log::warn!("Could not label release artifact {}: {}", artifact.id, e);
+        }
+    }
+}
+
+/// Add an entry for `version` at the top of the game's CHANGELOG.md, below its title.
+fn add_changelog_entry(game_dir: &Path, version: f64, notes: &str) -> Result<(), String> {
+    let path = game_dir.join(CHANGELOG_FILE);
+    let existing = std::fs::read_to_string(&path).unwrap_or_default();
+    let entry = format!("## v{} - {}\n\n{}\n", version, template::today(), notes.trim());
+    let (title, rest) = match existing.strip_prefix("# ") {
+        Some(_) => existing.split_once('\n').unwrap_or((existing.as_str(), "")),
+        None if existing.is_empty() => ("# Changelog", ""),
+        None => ("", existing.as_str()),
+    };
+    let mut content = String::new();
+    if !title.is_empty() {
+        content.push_str(title);
+        content.push_str("\n\n");
+    }
+    content.push_str(&entry);
+    let rest = rest.trim_start_matches('\n');
+    if !rest.is_empty() {
+        content.push('\n');
+        content.push_str(rest);
+    }
+    std::fs::write(&path, content)
+        .map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
+
+// ============================================================
+// README generation
+// ============================================================
+
+/// Controller button constant as players know it: PS5_DPAD_UP -> D-Pad Up.
+fn format_button(button: &str) -> String {
+    let name = ["PS4_", "PS5_", "XB1_"]
+        .iter()
+        .find_map(|prefix| button.strip_prefix(prefix))
+        .unwrap_or(button);
+    let words = title_case(name);
+    match words.strip_prefix("Dpad ") {
+        Some(rest) => format!("D-Pad {}", rest),
+        None => words,
+    }
+}
+
+/// Keyboard key constant as players know it: KEY_BACKSPACE -> Backspace.
+fn format_key(key: &str) -> String {
+    title_case(key.strip_prefix("KEY_").unwrap_or(key))
+}
+
+fn title_case(name: &str) -> String {
+    name.split('_')
+        .filter(|w| !w.is_empty())
+        .map(|word| {
+            let lower = word.to_lowercase();
+            let mut chars = lower.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Generate the README of a release: game details, menu tree, modules and controls.
+pub fn generate_readme(game_dir: &Path, project_root: &Path) -> String {
+    let game_path = game_dir.to_string_lossy().to_string();
+    let meta = std::fs::read_to_string(game_dir.join("game.json"))
+        .ok()
+        .and_then(|content| serde_json::from_str::<GameMeta>(&content).ok());
+    let config = std::fs::read_to_string(game_dir.join("config.toml"))
+        .ok()
+        .and_then(|content| toml::from_str::<GameConfig>(&content).ok());
+
+    let mut out = String::new();
+    match (&meta, &config) {
+        (Some(m), _) => {
+            out.push_str(&format!("# {} v{}\n\n", m.name, m.version));
+            out.push_str(&format!("- Console: {}\n- Game type: {}\n", m.console_type, m.game_type));
+            if let Some(ref author) = m.username {
+                out.push_str(&format!("- Author: {}\n", author));
+            }
+        }
+        (None, Some(c)) => {
+            let name = c.name.clone().unwrap_or_else(|| c.filename.clone());
+            out.push_str(&format!("# {} v{}\n\n", name, c.version));
+            if let Some(ref console) = c.console_type {
+                out.push_str(&format!("- Console: {}\n", console));
+            }
+        }
+        (None, None) => out.push_str("# Release\n"),
+    }
+
+    match load_flow_project(game_path) {
+        Ok(Some(project)) => flow_sections(&project, &mut out),
+        Ok(None) => {
+            if let Some(ref c) = config {
+                config_sections(c, project_root, &mut out);
+            }
+        }
+        Err(e) => log::warn!("Could not read flows for README: {}", e),
+    }
+    out
+}
+
+fn flow_sections(project: &FlowProject, out: &mut String) {
+    let menu = project.flows.iter().find(|f| f.flow_type == "menu");
+
+    if let Some(menu) = menu {
+        let root = menu
+            .nodes
+            .iter()
+            .find(|n| n.r#type == "home")
+            .or_else(|| menu.nodes.iter().find(|n| n.r#type == "menu"));
+        if let Some(root) = root {
+            let mut tree = String::new();
+            let mut visited = HashSet::new();
+            menu_tree(menu, root, 0, &mut visited, &mut tree);
+            if !tree.is_empty() {
+                out.push_str("\n## Menu\n\n");
+                out.push_str(&tree);
+            }
+        }
+    }
+
+    let module_nodes: Vec<&FlowNode> = project
+        .flows
+        .iter()
+        .filter(|f| f.flow_type == "gameplay" || f.flow_type == "data")
+        .flat_map(|f| f.nodes.iter())
+        .filter(|n| n.r#type == "module" && n.module_data.is_some())
+        .collect();
+    if !module_nodes.is_empty() {
+        out.push_str("\n## Modules\n\n");
+        for node in module_nodes {
+            let data = node.module_data.as_ref().expect("filtered above");
+            out.push_str(&format!("- **{}**", node.label));
+            if let Some(desc) = data.short_description.as_deref().filter(|d| !d.is_empty()) {
+                out.push_str(&format!(": {}", desc));
+            }
+            if let Some(toggle) = data.quick_toggle.as_ref().filter(|t| !t.is_empty()) {
+                let keys: Vec<String> = toggle
+                    .iter()
+                    .map(|b| if b.starts_with("KEY_") { format_key(b) } else { format_button(b) })
+                    .collect();
+                out.push_str(&format!(" (toggle: {})", keys.join(" + ")));
+            }
+            out.push('\n');
+        }
+    }
+
+    if let Some(menu) = menu {
+        out.push_str("\n## Controls\n\n| Action | Button | Key |\n| --- | --- | --- |\n");
+        if let Some((buttons, key)) = open_menu_binding(menu) {
+            out.push_str(&format!("| Open menu | {} | {} |\n", buttons, key));
+        }
+        let mapping = &menu.settings.button_mapping;
+        let keys = menu.settings.keyboard_mapping.as_ref();
+        let rows = [
+            ("Select", &mapping.confirm, keys.and_then(|k| k.confirm.as_ref())),
+            ("Back", &mapping.cancel, keys.and_then(|k| k.cancel.as_ref())),
+            ("Up", &mapping.up, keys.and_then(|k| k.up.as_ref())),
+            ("Down", &mapping.down, keys.and_then(|k| k.down.as_ref())),
+            ("Decrease value", &mapping.left, keys.and_then(|k| k.left.as_ref())),
+            ("Increase value", &mapping.right, keys.and_then(|k| k.right.as_ref())),
+        ];
+        for (action, button, key) in rows {
+            let key = key.map(|k| format_key(k)).unwrap_or_default();
+            out.push_str(&format!("| {} | {} | {} |\n", action, format_button(button), key));
+        }
+    }
+}
+
+/// Buttons and key of the transition out of the idle state (intro, screensaver or home).
+fn open_menu_binding(menu: &FlowGraph) -> Option<(String, String)> {
+    let idle = ["intro", "screensaver", "home"]
+        .iter()
+        .find_map(|t| menu.nodes.iter().find(|n| n.r#type == *t))?;
+    let edge = menu
+        .edges
+        .iter()
+        .find(|e| e.source_node_id == idle.id && e.condition.r#type == "button_press")?;
+    let condition = &edge.condition;
+    let mut buttons: Vec<String> = condition
+        .modifiers
+        .iter()
+        .flatten()
+        .map(|b| format_button(b))
+        .collect();
+    buttons.extend(condition.button.as_deref().map(format_button));
+    let key = condition.keyboard_key.as_deref().map(format_key).unwrap_or_default();
+    Some((buttons.join(" + "), key))
+}
+
+/// Nested list of the interactive items of a menu node, following item transitions
+/// into submenus.
+fn menu_tree(
+    menu: &FlowGraph,
+    node: &FlowNode,
+    depth: usize,
+    visited: &mut HashSet<String>,
+    out: &mut String,
+) {
+    if !visited.insert(node.id.clone()) {
+        return;
+    }
+    let mut items: Vec<_> = node
+        .sub_nodes
+        .iter()
+        .filter(|s| s.interactive && !s.hidden.unwrap_or(false))
+        .collect();
+    items.sort_by_key(|s| s.order);
+    for item in items {
+        let label = item
+            .display_text
+            .as_deref()
+            .filter(|t| !t.trim().is_empty())
+            .unwrap_or(&item.label);
+        let kind = match item.r#type.as_str() {
+            "toggle-item" => " (on/off)",
+            "value-item" => " (value)",
+            _ => "",
+        };
+        out.push_str(&format!("{}- {}{}\n", "  ".repeat(depth), label.trim(), kind));
+        let target = menu
+            .edges
+            .iter()
+            .filter(|e| e.source_node_id == node.id)
+            .filter(|e| e.source_sub_node_id.as_deref() == Some(item.id.as_str()))
+            .find_map(|e| menu.nodes.iter().find(|n| n.id == e.target_node_id));
+        if let Some(target) = target {
+            menu_tree(menu, target, depth + 1, visited, out);
+        }
+    }
+}
+
+fn config_sections(config: &GameConfig, project_root: &Path, out: &mut String) {
+    if !config.menu.is_empty() {
+        out.push_str("\n## Menu\n\n");
+        for item in &config.menu {
+            out.push_str(&format!("- {}\n", item.name));
+            for option in item.options.iter().flatten() {
+                out.push_str(&format!("  - {}\n", option.name));
+            }
+        }
+    }
+
+    let definitions = modules::load_all_modules(project_root).unwrap_or_default();
+    let used: Vec<_> = config
+        .menu
+        .iter()
+        .filter_map(|item| Some((item, item.module.as_ref()?)))
+        .collect();
+    if !used.is_empty() {
+        out.push_str("\n## Modules\n\n");
+        for (item, id) in used {
+            out.push_str(&format!("- **{}**", item.name));
+            let description = definitions
+                .iter()
+                .find(|d| &d.id == id)
+                .and_then(|d| d.description.as_deref());
+            if let Some(desc) = description.filter(|d| !d.is_empty()) {
+                out.push_str(&format!(": {}", desc));
+            }
+            out.push('\n');
+        }
+    }
+
+    let b = &config.buttons;
+    out.push_str("\n## Controls\n\n| Action | Button |\n| --- | --- |\n");
+    out.push_str(&format!(
+        "| Open menu | {} + {} |\n",
+        format_button(&b.menu_mod),
+        format_button(&b.menu_btn)
+    ));
+    for (action, button) in [
+        ("Select", &b.confirm),
+        ("Back", &b.cancel),
+        ("Up", &b.up),
+        ("Down", &b.down),
+        ("Decrease value", &b.left),
+        ("Increase value", &b.right),
+    ] {
+        out.push_str(&format!("| {} | {} |\n", action, format_button(button)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_version() {
+        assert_eq!(next_version(1.4), 1.5);
+        assert_eq!(next_version(1.9), 2.0);
+        assert_eq!(next_version(1.25), 1.26);
+        assert_eq!(next_version(2.0), 3.0);
+    }
+
+    #[test]
+    fn test_replace_top_level_number() {
+        let json = r#"{
+  "meta": { "version": 1 },
+  "name": "version",
+  "version": 2.5
+}
+"#;
+        assert_eq!(
+            replace_top_level_number(json, "version", "2.6").unwrap(),
+            json.replace("2.5", "2.6")
+        );
+        assert!(replace_top_level_number(r#"{"version": "1"}"#, "version", "2").is_none());
+    }
+
+    #[test]
+    fn test_release_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("Game");
+        std::fs::create_dir_all(&game_dir).unwrap();
+        let game_json = r#"{"name":"Game","filename":"Game","version":1.4,"game_type":"fps",
+            "console_type":"ps5", "custom_field":true}"#;
+        std::fs::write(game_dir.join("game.json"), game_json).unwrap();
+        std::fs::write(game_dir.join("main.gpc"), "main {\n}\n").unwrap();
+        let changelog = "# Changelog\n\n## v1.4\n\nFirst.\n";
+        std::fs::write(game_dir.join(CHANGELOG_FILE), changelog).unwrap();
+
+        let options = ReleaseOptions {
+            bump_version: true,
+            notes: Some("- Faster menu".to_string()),
+            ..Default::default()
+        };
+        let result = release_game(&game_dir, dir.path(), dir.path(), None, &options).unwrap();
+        assert!(result.success);
+        assert_eq!(result.version, 1.5);
+        // Only the number changes; key order and formatting stay as written
+        let meta = std::fs::read_to_string(game_dir.join("game.json")).unwrap();
+        assert_eq!(meta, game_json.replace("1.4", "1.5"));
+
+        let changelog = std::fs::read_to_string(game_dir.join(CHANGELOG_FILE)).unwrap();
+        assert!(changelog.starts_with("# Changelog\n\n## v1.5 - "));
+        assert!(changelog.contains("- Faster menu\n\n## v1.4"));
+
+        let names: Vec<&str> = result.files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["Game.gpc", "README.md", "CHANGELOG.md", manifest::MANIFEST_FILE]
+        );
+        let bundle = std::fs::File::open(result.bundle_path.unwrap()).unwrap();
+        let mut archive = zip::ZipArchive::new(bundle).unwrap();
+        let mut sums = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name("SHA256SUMS").unwrap(), &mut sums)
+            .unwrap();
+        let gpc = std::fs::read(dir.path().join("dist/Game.gpc")).unwrap();
+        assert!(sums.starts_with(&format!("{}  Game.gpc\n", hash_bytes(&gpc))));
+        let mut released = String::new();
+        let mut entry = archive.by_name(manifest::MANIFEST_FILE).unwrap();
+        std::io::Read::read_to_string(&mut entry, &mut released).unwrap();
+        let released: BuildManifest = serde_json::from_str(&released).unwrap();
+        assert!(released.covers("Game.gpc", &hash_bytes(&gpc)));
+
+        // A failed build puts the version back
+        std::fs::write(game_dir.join("main.gpc"), "main {\n").unwrap();
+        let failed = release_game(&game_dir, dir.path(), dir.path(), None, &options).unwrap();
+        assert!(!failed.success && failed.bundle_path.is_none());
+        assert!(std::fs::read_to_string(game_dir.join("game.json")).unwrap().contains("1.5"));
+
+        // So does a bundle that cannot be written, along with the changelog entry
+        std::fs::write(game_dir.join("main.gpc"), "main {\n}\n").unwrap();
+        let releases = releases_dir(dir.path());
+        std::fs::remove_dir_all(&releases).unwrap();
+        std::fs::write(&releases, "").unwrap();
+        assert!(release_game(&game_dir, dir.path(), dir.path(), None, &options).is_err());
+        let meta = std::fs::read_to_string(game_dir.join("game.json")).unwrap();
+        assert_eq!(meta, game_json.replace("1.4", "1.5"));
+        let after = std::fs::read_to_string(game_dir.join(CHANGELOG_FILE)).unwrap();
+        assert_eq!(after, changelog);
+    }
+
+    #[test]
+    fn test_readme_from_flows() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("game.json"),
+            r#"{"name":"Game","filename":"Game","version":2,"game_type":"fps","console_type":"ps5"}"#,
+        )
+        .unwrap();
+        let edge = |id: &str, from: &str, to: &str, sub: Option<&str>, cond: &str| {
+            let condition: serde_json::Value = serde_json::from_str(cond).unwrap();
+            serde_json::json!({"id": id, "sourceNodeId": from, "targetNodeId": to,
+                "sourceSubNodeId": sub, "condition": condition})
+        };
+        let item = |id: &str, kind: &str, label: &str, order: u32| {
+            serde_json::json!({"id": id, "type": kind, "label": label, "order": order,
+                "interactive": true})
+        };
+        let node = |id: &str, kind: &str, subs: Vec<serde_json::Value>| {
+            serde_json::json!({"id": id, "type": kind, "label": id,
+                "position": {"x": 0, "y": 0}, "subNodes": subs})
+        };
+        let settings = serde_json::json!({
+            "buttonMapping": {"confirm": "PS5_CROSS", "up": "PS5_DPAD_UP"},
+            "keyboardMapping": {"confirm": "KEY_ENTER"}});
+        let project = serde_json::json!({"version": 1, "flows": [
+            {"id": "m", "name": "Menu", "version": 1, "flowType": "menu", "settings": settings,
+             "nodes": [
+                node("intro", "intro", vec![]),
+                node("home", "home", vec![
+                    item("a", "toggle-item", "Anti Recoil", 1),
+                    item("s", "menu-item", "Settings", 2)]),
+                node("settings", "submenu", vec![item("v", "value-item", "Strength", 0)])],
+             "edges": [
+                edge("e1", "intro", "home", None,
+                    r#"{"type":"button_press","button":"PS5_OPTIONS","modifiers":["PS5_L2"]}"#),
+                edge("e2", "home", "settings", Some("s"), r#"{"type":"button_press"}"#)]},
+            {"id": "g", "name": "Gameplay", "version": 1, "flowType": "gameplay",
+             "settings": {}, "edges": [],
+             "nodes": [{"id": "r", "type": "module", "label": "Rapid Fire",
+                "position": {"x": 0, "y": 0},
+                "moduleData": {"moduleId": "rapidfire", "moduleName": "Rapid Fire",
+                    "shortDescription": "Fires semi-autos faster",
+                    "quickToggle": ["PS5_L2", "PS5_LEFT"]}}]}]});
+        std::fs::write(dir.path().join("flows.json"), project.to_string()).unwrap();
+
+        let readme = generate_readme(dir.path(), dir.path());
+        assert!(readme.starts_with("# Game v2\n"));
+        let menu = "## Menu\n\n- Anti Recoil (on/off)\n- Settings\n  - Strength (value)\n";
+        assert!(readme.contains(menu));
+        assert!(readme.contains("- **Rapid Fire**: Fires semi-autos faster (toggle: L2 + Left)\n"));
+        assert!(readme.contains("| Open menu | L2 + Options |  |\n"));
+        assert!(readme.contains("| Select | Cross | Enter |\n"));
+        assert!(readme.contains("| Up | D-Pad Up |  |\n"));
+    }
+}
@@ -0,0 +1,612 @@
+//! Input traces: controller input recorded once, for example in the flow emulator, or
+//! written by hand, and replayed on the GPC virtual machine against any build of a
+//! game. Replaying records the script's output stream so two builds can be compared
+//! output for output.
+//!
+//! A trace is a list of timed changes. Each change sets one identifier to a value at a
+//! time in milliseconds, and the value holds until it is changed again. Traces are
+//! stored as JSON:
+//!
+//! ```json
+//! { "version": 1, "duration_ms": 1000, "events": [[0, "PS5_R2", 100], [480, "PS5_R2", 0]] }
+//! ```
+//!
+//! or as CSV, with `#` comments carrying the same metadata:
+//!
+//! ```text
+//! # duration_ms=1000
+//! time_ms,input,value
+//! 0,PS5_R2,100
+//! 480,PS5_R2,0
+//! ```
+
+use crate::gpc::constants::{self, IO_COUNT};
+use crate::gpc::vm::{Program, Vm};
+use crate::pipeline::build::{build_game_with_options, BuildOptions};
+use crate::pipeline::scripttest::{load_program, locate, TickDiff};
+use crate::pipeline::sourcemap::SourceMap;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Trace format version written by this version of ZenForge.
+pub const TRACE_VERSION: u32 = 1;
+
+const CSV_HEADER: &str = "time_ms,input,value";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputTrace {
+    #[serde(default = "default_version")]
+    pub version: u32,
+    /// How long a replay runs; defaults to just past the last event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    pub events: Vec<TraceEvent>,
+}
+
+/// One input change, stored as `[time_ms, input, value]`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "(u64, String, i32)", into = "(u64, String, i32)")]
+pub struct TraceEvent {
+    pub time_ms: u64,
+    /// Identifier name, e.g. `PS5_R2`
+    pub input: String,
+    pub value: i32,
+}
+
+impl From<(u64, String, i32)> for TraceEvent {
+    fn from((time_ms, input, value): (u64, String, i32)) -> Self {
+        Self {
+            time_ms,
+            input,
+            value,
+        }
+    }
+}
+
+impl From<TraceEvent> for (u64, String, i32) {
+    fn from(event: TraceEvent) -> Self {
+        (event.time_ms, event.input, event.value)
+    }
+}
+
+fn default_version() -> u32 {
+    TRACE_VERSION
+}
+
+impl InputTrace {
+    pub fn from_json(content: &str) -> Result<Self, String> {
+        let trace: InputTrace =
+            serde_json::from_str(content).map_err(|e| format!("Invalid input trace: {}", e))?;
+        trace.check_version()?;
+        Ok(trace)
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string(self).map_err(|e| format!("Could not serialize input trace: {}", e))
+    }
+
+    pub fn from_csv(content: &str) -> Result<Self, String> {
+        let mut trace = InputTrace {
+            version: TRACE_VERSION,
+            duration_ms: None,
+            events: Vec::new(),
+        };
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            let invalid = |what: &str| format!("Invalid input trace line {}: {}", index + 1, what);
+            if line.is_empty() || line == CSV_HEADER {
+                continue;
+            }
+            if let Some(comment) = line.strip_prefix('#') {
+                if let Some((key, value)) = comment.split_once('=') {
+                    let value = value.trim();
+                    match key.trim() {
+                        "version" => trace.version = value.parse().map_err(|_| invalid(line))?,
+                        "duration_ms" => {
+                            trace.duration_ms = Some(value.parse().map_err(|_| invalid(line))?)
+                        }
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [time_ms, input, value] = fields[..] else {
+                return Err(invalid("expected time_ms,input,value"));
+            };
+            trace.events.push(TraceEvent {
+                time_ms: time_ms
+                    .parse()
+                    .map_err(|_| invalid(&format!("invalid time '{}'", time_ms)))?,
+                input: input.to_string(),
+                value: value
+                    .parse()
+                    .map_err(|_| invalid(&format!("invalid value '{}'", value)))?,
+            });
+        }
+        trace.check_version()?;
+        Ok(trace)
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut out = format!("# version={}\n", self.version);
+        if let Some(duration) = self.duration_ms {
+            out.push_str(&format!("# duration_ms={}\n", duration));
+        }
+        out.push_str(CSV_HEADER);
+        out.push('\n');
+        for event in &self.events {
+            out.push_str(&format!(
+                "{},{},{}\n",
+                event.time_ms, event.input, event.value
+            ));
+        }
+        out
+    }
+
+    /// Read a trace, as CSV if the file ends in `.csv` and as JSON otherwise.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        if is_csv(path) {
+            Self::from_csv(&content)
+        } else {
+            Self::from_json(&content)
+        }
+        .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = if is_csv(path) {
+            self.to_csv()
+        } else {
+            self.to_json()?
+        };
+        std::fs::write(path, content)
+            .map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
+    fn check_version(&self) -> Result<(), String> {
+        if self.version > TRACE_VERSION {
+            return Err(format!(
+                "Input trace version {} is newer than supported version {}",
+                self.version, TRACE_VERSION
+            ));
+        }
+        Ok(())
+    }
+
+    /// Time the replay stops at.
+    fn end_ms(&self) -> u64 {
+        self.duration_ms
+            .unwrap_or_else(|| self.events.iter().map(|e| e.time_ms + 1).max().unwrap_or(0))
+    }
+
+    /// Console prefix used by the trace's identifiers, `PS5_` if it has none.
+    fn prefix(&self) -> &'static str {
+        self.events
+            .iter()
+            .find_map(|e| constants::io_prefix(&e.input))
+            .unwrap_or("PS5_")
+    }
+}
+
+fn is_csv(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"))
+}
+
+/// An output that changed value at the end of a tick.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OutputChange {
+    pub tick: u64,
+    pub time_ms: u64,
+    pub output: String,
+    pub value: i32,
+}
+
+/// The outputs a script sent while a trace was replayed, as the changes between
+/// ticks; every output starts at 0.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OutputStream {
+    pub ticks: u64,
+    pub time_ms: u64,
+    pub changes: Vec<OutputChange>,
+    /// Runtime error that stopped the replay early, with its location
+    pub error: Option<String>,
+}
+
+/// Result of replaying one trace against two builds.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayComparison {
+    pub identical: bool,
+    /// Ticks at which at least one output differed
+    pub differing_ticks: u64,
+    /// Where an output started to differ or changed while differing; `expected` is the
+    /// first build's value and `actual` the second's
+    pub diffs: Vec<TickDiff>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayReport {
+    pub success: bool,
+    pub stream: OutputStream,
+    /// Output of the build compared against, if any
+    pub against: Option<OutputStream>,
+    pub comparison: Option<ReplayComparison>,
+}
+
+/// Feed a trace into a script and record its outputs.
+///
+/// Events take effect at the first tick that starts at or after their time, so a
+/// replay follows `vm_tctrl` changes of the tick length. `locate` turns a byte offset
+/// of the script into a location for runtime errors.
+pub fn replay(
+    mut vm: Vm,
+    trace: &InputTrace,
+    locate: &dyn Fn(usize) -> String,
+) -> Result<OutputStream, String> {
+    let mut player = TracePlayer::new(trace)?;
+    let prefix = trace.prefix();
+    let mut stream = OutputStream {
+        ticks: 0,
+        time_ms: 0,
+        changes: Vec::new(),
+        error: None,
+    };
+    let mut previous = [0; IO_COUNT];
+    while vm.time_ms() < player.end_ms {
+        let (tick, time_ms) = (vm.ticks(), vm.time_ms());
+        let output = match vm.tick(player.input_at(time_ms)) {
+            Ok(output) => *output,
+            Err(e) => {
+                stream.error = Some(format!(
+                    "tick {}: {} ({})",
+                    tick,
+                    e.message,
+                    locate(e.span.start)
+                ));
+                break;
+            }
+        };
+        for io in (0..IO_COUNT).filter(|io| output[*io] != previous[*io]) {
+            stream.changes.push(OutputChange {
+                tick,
+                time_ms,
+                output: output_name(prefix, io),
+                value: output[io],
+            });
+        }
+        previous = output;
+    }
+    stream.ticks = vm.ticks();
+    stream.time_ms = vm.time_ms();
+    Ok(stream)
+}
+
+/// Controller input of a trace as time moves forward.
+pub(crate) struct TracePlayer {
+    /// Time, identifier and value of each change, in time order
+    events: Vec<(u64, usize, i32)>,
+    next: usize,
+    input: [i32; IO_COUNT],
+    /// Time the replay stops at
+    pub end_ms: u64,
+}
+
+impl TracePlayer {
+    pub(crate) fn new(trace: &InputTrace) -> Result<Self, String> {
+        let mut events = Vec::with_capacity(trace.events.len());
+        for event in &trace.events {
+            let io = constants::io_index(&event.input)
+                .ok_or_else(|| format!("Unknown controller identifier '{}'", event.input))?;
+            events.push((event.time_ms, io, event.value));
+        }
+        events.sort_by_key(|(time, _, _)| *time);
+        Ok(Self {
+            events,
+            next: 0,
+            input: [0; IO_COUNT],
+            end_ms: trace.end_ms(),
+        })
+    }
+
+    /// Input of a tick starting at `time_ms`, which must not go back in time.
+    pub(crate) fn input_at(&mut self, time_ms: u64) -> &[i32; IO_COUNT] {
+        while let Some((_, io, value)) = self.events.get(self.next).filter(|e| e.0 <= time_ms) {
+            self.input[*io] = *value;
+            self.next += 1;
+        }
+        &self.input
+    }
+}
+
+fn output_name(prefix: &str, io: usize) -> String {
+    constants::io_name(prefix, io).unwrap_or_else(|| format!("{}", io))
+}
+
+/// Compare the outputs of two replays of the same trace tick by tick.
+pub fn compare(first: &OutputStream, second: &OutputStream) -> ReplayComparison {
+    let mut comparison = ReplayComparison {
+        identical: true,
+        differing_ticks: 0,
+        diffs: Vec::new(),
+    };
+    let (mut a, mut b) = (
+        first.changes.iter().peekable(),
+        second.changes.iter().peekable(),
+    );
+    let mut values: Vec<(String, i32, i32)> = Vec::new();
+    let mut reported: Vec<(i32, i32)> = Vec::new();
+    for tick in 0..first.ticks.max(second.ticks) {
+        for (changes, side) in [(&mut a, 0), (&mut b, 1)] {
+            while let Some(change) = changes.next_if(|c| c.tick == tick) {
+                let index = match values.iter().position(|(name, ..)| *name == change.output) {
+                    Some(index) => index,
+                    None => {
+                        values.push((change.output.clone(), 0, 0));
+                        reported.push((0, 0));
+                        values.len() - 1
+                    }
+                };
+                if side == 0 {
+                    values[index].1 = change.value;
+                } else {
+                    values[index].2 = change.value;
+                }
+            }
+        }
+        let mut differs = false;
+        for (index, (name, expected, actual)) in values.iter().enumerate() {
+            if expected != actual {
+                differs = true;
+                if reported[index] != (*expected, *actual) {
+                    comparison.diffs.push(TickDiff {
+                        tick,
+                        subject: name.clone(),
+                        expected: expected.to_string(),
+                        actual: actual.to_string(),
+                    });
+                }
+            }
+            reported[index] = (*expected, *actual);
+        }
+        if differs {
+            comparison.differing_ticks += 1;
+        }
+    }
+    if first.ticks != second.ticks {
+        comparison.diffs.push(TickDiff {
+            tick: first.ticks.min(second.ticks),
+            subject: "ticks".to_string(),
+            expected: first.ticks.to_string(),
+            actual: second.ticks.to_string(),
+        });
+    }
+    comparison.identical = comparison.diffs.is_empty();
+    comparison
+}
+
+/// A script loaded into the simulator, with what is needed to locate runtime errors.
+pub(crate) struct Script {
+    pub program: Program,
+    output: String,
+    source_map: Option<SourceMap>,
+}
+
+impl Script {
+    /// Load a game directory, which is built first, or a script file.
+    pub(crate) fn load(
+        path: &Path,
+        project_root: &Path,
+        dist_base: &Path,
+        workspace_path: Option<&str>,
+        options: &BuildOptions,
+    ) -> Result<Self, String> {
+        let (script, source_map) = if path.is_dir() {
+            let build = build_game_with_options(
+                path,
+                project_root,
+                dist_base,
+                false,
+                workspace_path,
+                options,
+            );
+            if !build.success {
+                let errors: Vec<String> = build.errors.iter().map(|e| e.to_string()).collect();
+                return Err(format!(
+                    "Build of {} failed:\n{}",
+                    path.display(),
+                    errors.join("\n")
+                ));
+            }
+            let source_map = build
+                .source_map_path
+                .as_ref()
+                .and_then(|map| SourceMap::load(Path::new(map)).ok());
+            (Path::new(&build.output_path).to_path_buf(), source_map)
+        } else {
+            (path.to_path_buf(), None)
+        };
+        let output = std::fs::read_to_string(&script)
+            .map_err(|e| format!("Could not read {}: {}", script.display(), e))?;
+        let program = load_program(&output, source_map.as_ref())?;
+        Ok(Self {
+            program,
+            output,
+            source_map,
+        })
+    }
+
+    /// `file:line` of a byte offset in the script.
+    pub(crate) fn locate(&self, offset: usize) -> String {
+        locate(&self.output, offset, self.source_map.as_ref())
+    }
+}
+
+/// Replay a trace against a game directory, which is built first, or a script file.
+pub fn replay_path(
+    path: &Path,
+    project_root: &Path,
+    dist_base: &Path,
+    workspace_path: Option<&str>,
+    options: &BuildOptions,
+    trace: &InputTrace,
+) -> Result<OutputStream, String> {
+    let script = Script::load(path, project_root, dist_base, workspace_path, options)?;
+    let location = |offset: usize| script.locate(offset);
+    replay(Vm::new(script.program.clone()), trace, &location)
+}
+
+/// Replay a trace against a build and, if given, a second build to compare it with.
+pub fn replay_and_compare(
+    path: &Path,
+    against: Option<&Path>,
+    project_root: &Path,
+    dist_base: &Path,
+    workspace_path: Option<&str>,
+    options: &BuildOptions,
+    trace: &InputTrace,
+) -> Result<ReplayReport, String> {
+    let run = |path: &Path| {
+        replay_path(
+            path,
+            project_root,
+            dist_base,
+            workspace_path,
+            options,
+            trace,
+        )
+    };
+    let stream = run(path)?;
+    let against = against.map(run).transpose()?;
+    let comparison = against.as_ref().map(|other| compare(&stream, other));
+    let success = stream.error.is_none()
+        && against.iter().all(|other| other.error.is_none())
+        && comparison.iter().all(|c| c.identical);
+    Ok(ReplayReport {
+        success,
+        stream,
+        against,
+        comparison,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"
+main {
+    if (get_val(PS5_R2)) combo_run(Pulse);
+}
+combo Pulse {
+    set_val(PS5_R2, 100);
+    wait(20);
+    set_val(PS5_R2, 0);
+    wait(20);
+}
+"#;
+
+    fn trace() -> InputTrace {
+        InputTrace {
+            version: TRACE_VERSION,
+            duration_ms: Some(100),
+            events: vec![
+                TraceEvent {
+                    time_ms: 0,
+                    input: "PS5_R2".to_string(),
+                    value: 100,
+                },
+                TraceEvent {
+                    time_ms: 45,
+                    input: "PS5_R2".to_string(),
+                    value: 0,
+                },
+            ],
+        }
+    }
+
+    fn run(script: &str, trace: &InputTrace) -> OutputStream {
+        replay(Vm::load(script).unwrap(), trace, &|_| String::new()).unwrap()
+    }
+
+    #[test]
+    fn test_trace_formats() {
+        let trace = trace();
+        let json = trace.to_json().unwrap();
+        assert_eq!(
+            json,
+            r#"{"version":1,"duration_ms":100,"events":[[0,"PS5_R2",100],[45,"PS5_R2",0]]}"#
+        );
+        assert_eq!(InputTrace::from_json(&json).unwrap(), trace);
+        let csv = trace.to_csv();
+        assert_eq!(
+            csv,
+            "# version=1\n# duration_ms=100\ntime_ms,input,value\n0,PS5_R2,100\n45,PS5_R2,0\n"
+        );
+        assert_eq!(InputTrace::from_csv(&csv).unwrap(), trace);
+
+        let events = InputTrace::from_json(r#"{"events":[[5,"XB1_RT",100]]}"#).unwrap();
+        assert_eq!(
+            (events.version, events.duration_ms, events.end_ms()),
+            (1, None, 6)
+        );
+        assert!(InputTrace::from_csv("0,PS5_R2")
+            .unwrap_err()
+            .contains("line 1"));
+        assert!(InputTrace::from_csv("x,PS5_R2,1")
+            .unwrap_err()
+            .contains("invalid time"));
+        assert!(InputTrace::from_json(r#"{"version":2,"events":[]}"#).is_err());
+    }
+
+    #[test]
+    fn test_replay_records_output_changes() {
+        let stream = run(SCRIPT, &trace());
+        assert_eq!(
+            (stream.ticks, stream.time_ms, stream.error),
+            (10, 100, None)
+        );
+        let changes: Vec<(u64, &str, i32)> = stream
+            .changes
+            .iter()
+            .map(|c| (c.tick, c.output.as_str(), c.value))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (0, "PS5_R2", 100),
+                (2, "PS5_R2", 0),
+                (4, "PS5_R2", 100),
+                (5, "PS5_R2", 0)
+            ]
+        );
+
+        let unknown = InputTrace::from_csv("0,PS5_NOPE,1").unwrap();
+        let error = replay(Vm::load(SCRIPT).unwrap(), &unknown, &|_| String::new());
+        assert_eq!(
+            error.unwrap_err(),
+            "Unknown controller identifier 'PS5_NOPE'"
+        );
+    }
+
+    #[test]
+    fn test_compare_builds() {
+        let trace = trace();
+        let first = run(SCRIPT, &trace);
+        assert!(compare(&first, &first.clone()).identical);
+
+        let slower = run(&SCRIPT.replace("wait(20)", "wait(30)"), &trace);
+        let comparison = compare(&first, &slower);
+        assert!(!comparison.identical);
+        assert_eq!(comparison.differing_ticks, 2);
+        let diffs: Vec<(u64, &str, &str)> = comparison
+            .diffs
+            .iter()
+            .map(|d| (d.tick, d.expected.as_str(), d.actual.as_str()))
+            .collect();
+        assert_eq!(diffs, vec![(2, "0", "100"), (4, "100", "0")]);
+    }
+}
@@ -0,0 +1,566 @@
+//! Resource usage of built GPC scripts, checked against device firmware limits.
+//!
+//! Scripts that exceed a limit only fail once loaded in Zen Studio, so the build
+//! counts what the final output uses and reports it next to the limits of the
+//! target firmware. Counts come from a token scan, not a compiler, so sizes are
+//! estimates. The built-in limits are estimates as well; games that know the
+//! real limits of their firmware set them in game.json.
+
+use crate::models::diagnostic::{codes, Diagnostic, Severity};
+use crate::models::game_meta::ResourceSettings;
+use crate::gpc::lexer::{lex, Token, TokenKind};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+
+/// Firmware used when game.json does not name one.
+pub const DEFAULT_FIRMWARE: &str = "zen";
+
+const DEFAULT_WARN_PERCENT: u32 = 90;
+const DEFAULT_ERROR_PERCENT: u32 = 100;
+
+/// Rough bytecode cost of one operand or operator inside a code block.
+const BYTES_PER_CODE_TOKEN: u64 = 2;
+
+/// A limited device resource.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resource {
+    GlobalSlots,
+    ConstBytes,
+    StringBytes,
+    Combos,
+    Functions,
+    SpvarSlots,
+    BytecodeBytes,
+}
+
+impl Resource {
+    pub const ALL: [Resource; 7] = [
+        Resource::GlobalSlots,
+        Resource::ConstBytes,
+        Resource::StringBytes,
+        Resource::Combos,
+        Resource::Functions,
+        Resource::SpvarSlots,
+        Resource::BytecodeBytes,
+    ];
+
+    /// Name used for limit overrides in game.json.
+    pub fn key(self) -> &'static str {
+        match self {
+            Resource::GlobalSlots => "global_slots",
+            Resource::ConstBytes => "const_bytes",
+            Resource::StringBytes => "string_bytes",
+            Resource::Combos => "combos",
+            Resource::Functions => "functions",
+            Resource::SpvarSlots => "spvar_slots",
+            Resource::BytecodeBytes => "bytecode_bytes",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Resource::GlobalSlots => "Global variable slots",
+            Resource::ConstBytes => "Const data bytes",
+            Resource::StringBytes => "String bytes",
+            Resource::Combos => "Combos",
+            Resource::Functions => "Functions",
+            Resource::SpvarSlots => "User SPVAR slots",
+            Resource::BytecodeBytes => "Estimated bytecode bytes",
+        }
+    }
+}
+
+/// Limits of one firmware family.
+#[derive(Debug, Clone, Copy)]
+pub struct FirmwareLimits {
+    pub name: &'static str,
+    pub global_slots: u64,
+    pub const_bytes: u64,
+    pub string_bytes: u64,
+    pub combos: u64,
+    pub functions: u64,
+    pub spvar_slots: u64,
+    pub bytecode_bytes: u64,
+}
+
+impl FirmwareLimits {
+    pub fn get(&self, resource: Resource) -> u64 {
+        match resource {
+            Resource::GlobalSlots => self.global_slots,
+            Resource::ConstBytes => self.const_bytes,
+            Resource::StringBytes => self.string_bytes,
+            Resource::Combos => self.combos,
+            Resource::Functions => self.functions,
+            Resource::SpvarSlots => self.spvar_slots,
+            Resource::BytecodeBytes => self.bytecode_bytes,
+        }
+    }
+}
+
+/// Estimated limits of each firmware family.
+///
+/// No published specification lists these limits, so the values are conservative
+/// guesses rather than measured ones. Reports mark them as estimated unless the
+/// game overrides them through `resources.limits` in game.json.
+pub const FIRMWARE_LIMITS: &[FirmwareLimits] = &[
+    FirmwareLimits {
+        name: "zen",
+        global_slots: 4096,
+        const_bytes: 16384,
+        string_bytes: 8192,
+        combos: 256,
+        functions: 512,
+        spvar_slots: 64,
+        bytecode_bytes: 32768,
+    },
+    FirmwareLimits {
+        name: "zen-1",
+        global_slots: 1024,
+        const_bytes: 8192,
+        string_bytes: 4096,
+        combos: 128,
+        functions: 256,
+        spvar_slots: 64,
+        bytecode_bytes: 16384,
+    },
+];
+
+pub fn firmware_limits(name: &str) -> Option<&'static FirmwareLimits> {
+    FIRMWARE_LIMITS.iter().find(|f| f.name.eq_ignore_ascii_case(name))
+}
+
+/// What a script uses, as counted from its source.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ResourceCounts {
+    /// Declared global variables (an array counts once)
+    pub globals: u64,
+    /// Global variable slots (an array counts once per element)
+    pub global_slots: u64,
+    /// Global variables and const arrays declared as int8/uint8
+    pub int8: u64,
+    /// Global variables and const arrays declared as int16/uint16
+    pub int16: u64,
+    /// Global variables and const arrays declared as int, int32 or uint32
+    pub int32: u64,
+    /// Bytes of const arrays and `data(...)`
+    pub const_bytes: u64,
+    /// Bytes of string literals, including terminators
+    pub string_bytes: u64,
+    pub combos: u64,
+    pub functions: u64,
+    /// Highest `SPVAR_<n>` referenced
+    pub spvar_slots: u64,
+    pub bytecode_bytes: u64,
+}
+
+impl ResourceCounts {
+    pub fn get(&self, resource: Resource) -> u64 {
+        match resource {
+            Resource::GlobalSlots => self.global_slots,
+            Resource::ConstBytes => self.const_bytes,
+            Resource::StringBytes => self.string_bytes,
+            Resource::Combos => self.combos,
+            Resource::Functions => self.functions,
+            Resource::SpvarSlots => self.spvar_slots,
+            Resource::BytecodeBytes => self.bytecode_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResourceUsage {
+    pub resource: Resource,
+    pub used: u64,
+    pub limit: u64,
+    /// Whether `limit` is a built-in estimate rather than a value set in game.json
+    pub estimated: bool,
+}
+
+/// Resource usage of a built script against the limits of its firmware.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResourceReport {
+    pub firmware: String,
+    pub counts: ResourceCounts,
+    pub usage: Vec<ResourceUsage>,
+}
+
+/// Count the resources used by a preprocessed GPC script.
+pub fn analyze(source: &str) -> ResourceCounts {
+    let tokens: Vec<Token> = lex(source).0.into_iter().filter(|t| !t.is_trivia()).collect();
+
+    let mut counts = ResourceCounts::default();
+    let mut defines: HashMap<&str, u64> = HashMap::new();
+    let mut spvars: BTreeSet<u64> = BTreeSet::new();
+    let mut code_tokens: u64 = 0;
+    let mut depth = 0usize;
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let tok = &tokens[i];
+        let text = tok.text(source);
+        if depth == 0 && tok.kind == TokenKind::Ident {
+            let next = tokens.get(i + 1).map(|t| t.text(source));
+            match text {
+                "define" => {
+                    if let (Some(name), Some("="), Some(value)) = (
+                        tokens.get(i + 1),
+                        tokens.get(i + 2).map(|t| t.text(source)),
+                        tokens.get(i + 3).and_then(|t| parse_number(t.text(source))),
+                    ) {
+                        defines.insert(name.text(source), value);
+                    }
+                }
+                "combo" => counts.combos += 1,
+                "function" => counts.functions += 1,
+                "const" => {
+                    i = count_const_array(source, &tokens, i + 1, &mut counts);
+                    continue;
+                }
+                "data" if next == Some("(") => {
+                    i = count_data_section(source, &tokens, i + 1, &mut counts);
+                    continue;
+                }
+                ty if int_width(ty).is_some() => {
+                    i = count_globals(source, &tokens, i, &defines, &mut counts);
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        match tok.kind {
+            TokenKind::Str => counts.string_bytes += string_size(text),
+            TokenKind::Ident => {
+                if let Some(slot) = text.strip_prefix("SPVAR_").and_then(parse_number) {
+                    spvars.insert(slot);
+                }
+            }
+            TokenKind::Punct if text == "{" => depth += 1,
+            TokenKind::Punct if text == "}" => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        if depth > 0 && !matches!(text, "{" | "}" | ";" | ",") {
+            code_tokens += 1;
+        }
+        i += 1;
+    }
+
+    counts.spvar_slots = spvars.last().copied().unwrap_or(0);
+    counts.bytecode_bytes =
+        code_tokens * BYTES_PER_CODE_TOKEN + counts.const_bytes + counts.string_bytes;
+    counts
+}
+
+/// Compare `counts` against the firmware limits selected by `settings`.
+///
+/// Returns the report and a warning or error for each resource at or above the
+/// configured thresholds. Fails if the firmware is unknown.
+pub fn check(
+    counts: ResourceCounts,
+    settings: Option<&ResourceSettings>,
+) -> Result<(ResourceReport, Vec<Diagnostic>), String> {
+    let firmware = settings
+        .and_then(|s| s.firmware.as_deref())
+        .unwrap_or(DEFAULT_FIRMWARE);
+    let limits = firmware_limits(firmware).ok_or_else(|| {
+        let known: Vec<&str> = FIRMWARE_LIMITS.iter().map(|f| f.name).collect();
+        format!(
+            "Unknown firmware '{}' in game.json resources (known: {})",
+            firmware,
+            known.join(", ")
+        )
+    })?;
+    let warn_percent = settings
+        .and_then(|s| s.warn_percent)
+        .unwrap_or(DEFAULT_WARN_PERCENT);
+    let error_percent = settings
+        .and_then(|s| s.error_percent)
+        .unwrap_or(DEFAULT_ERROR_PERCENT);
+
+    if let Some(s) = settings {
+        if let Some(key) = s
+            .limits
+            .keys()
+            .find(|k| !Resource::ALL.iter().any(|r| r.key() == k.as_str()))
+        {
+            return Err(format!("Unknown resource '{}' in game.json resource limits", key));
+        }
+    }
+
+    let mut usage = Vec::new();
+    let mut diagnostics = Vec::new();
+    for resource in Resource::ALL {
+        let configured = settings.and_then(|s| s.limits.get(resource.key()).copied());
+        let limit = configured.unwrap_or_else(|| limits.get(resource));
+        let used = counts.get(resource);
+        let percent = (used * 100).checked_div(limit).unwrap_or(u64::MAX);
+        let severity = if used > 0 && percent >= u64::from(error_percent) {
+            Some(Severity::Error)
+        } else if used > 0 && percent >= u64::from(warn_percent) {
+            Some(Severity::Warning)
+        } else {
+            None
+        };
+        if let Some(severity) = severity {
+            diagnostics.push(Diagnostic::new(
+                codes::RESOURCE_LIMIT,
+                severity,
+                format!(
+                    "{}: {} of {}{} used ({}%) on firmware '{}'",
+                    resource.label(),
+                    used,
+                    if configured.is_some() { "" } else { "an estimated " },
+                    limit,
+                    percent.min(999),
+                    limits.name
+                ),
+            ));
+        }
+        usage.push(ResourceUsage {
+            resource,
+            used,
+            limit,
+            estimated: configured.is_none(),
+        });
+    }
+
+    let report = ResourceReport {
+        firmware: limits.name.to_string(),
+        counts,
+        usage,
+    };
+    Ok((report, diagnostics))
+}
+
+/// Storage width in bytes of an integer type keyword.
+fn int_width(ty: &str) -> Option<u64> {
+    match ty {
+        "int8" | "uint8" => Some(1),
+        "int16" | "uint16" => Some(2),
+        "int" | "int32" | "uint32" => Some(4),
+        _ => None,
+    }
+}
+
+fn tally_width(counts: &mut ResourceCounts, width: u64) {
+    match width {
+        1 => counts.int8 += 1,
+        2 => counts.int16 += 1,
+        _ => counts.int32 += 1,
+    }
+}
+
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Stored size of a string literal token: its characters plus a terminator.
+fn string_size(literal: &str) -> u64 {
+    let inner = literal.trim_start_matches('"').trim_end_matches('"');
+    let mut size = 1;
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        }
+        size += 1;
+    }
+    size
+}
+
+/// Index just past the `;` ending the statement that contains `start`.
+fn statement_end(source: &str, tokens: &[Token], start: usize) -> usize {
+    tokens[start..]
+        .iter()
+        .position(|t| t.text(source) == ";")
+        .map(|p| start + p + 1)
+        .unwrap_or(tokens.len())
+}
+
+/// `const [type] name[]... = { ... };` starting after `const`.
+fn count_const_array(
+    source: &str,
+    tokens: &[Token],
+    start: usize,
+    counts: &mut ResourceCounts,
+) -> usize {
+    let end = statement_end(source, tokens, start);
+    let ty = tokens.get(start).map(|t| t.text(source)).unwrap_or("");
+    let is_string = ty == "string";
+    let width = int_width(ty);
+    if let Some(w) = width {
+        tally_width(counts, w);
+    }
+    let width = width.unwrap_or(4);
+
+    let init = tokens[start..end].iter().position(|t| t.text(source) == "=");
+    let mut elements = 0;
+    let mut in_item = false;
+    for tok in init.map(|p| &tokens[start + p + 1..end]).unwrap_or(&[]) {
+        match tok.text(source) {
+            "{" => in_item = false,
+            "," | "}" | ";" => {
+                if in_item {
+                    elements += 1;
+                }
+                in_item = false;
+            }
+            _ => {
+                if tok.kind == TokenKind::Str {
+                    counts.string_bytes += string_size(tok.text(source));
+                }
+                in_item = true;
+            }
+        }
+    }
+    if !is_string {
+        counts.const_bytes += elements * width;
+    }
+    end
+}
+
+/// `data(...);` starting at the `(`; numbers take one byte, strings their length.
+fn count_data_section(
+    source: &str,
+    tokens: &[Token],
+    start: usize,
+    counts: &mut ResourceCounts,
+) -> usize {
+    let end = statement_end(source, tokens, start);
+    for tok in &tokens[start..end] {
+        match tok.kind {
+            TokenKind::Number | TokenKind::Char => counts.const_bytes += 1,
+            TokenKind::Str => counts.const_bytes += string_size(tok.text(source)),
+            _ => {}
+        }
+    }
+    end
+}
+
+/// `type a, b[N], c = expr;` starting at the type keyword.
+fn count_globals(
+    source: &str,
+    tokens: &[Token],
+    start: usize,
+    defines: &HashMap<&str, u64>,
+    counts: &mut ResourceCounts,
+) -> usize {
+    let end = statement_end(source, tokens, start);
+    let width = int_width(tokens[start].text(source)).unwrap_or(4);
+    let mut expect_name = true;
+    let mut elements = 1u64;
+    let mut nesting = 0usize;
+    let mut in_dims = false;
+
+    for tok in &tokens[start + 1..end] {
+        let text = tok.text(source);
+        match text {
+            "(" | "{" => nesting += 1,
+            ")" | "}" => nesting = nesting.saturating_sub(1),
+            "[" if nesting == 0 => in_dims = true,
+            "]" if nesting == 0 => in_dims = false,
+            "," | ";" if nesting == 0 => {
+                if !expect_name {
+                    counts.global_slots += elements;
+                }
+                expect_name = true;
+                elements = 1;
+            }
+            _ if in_dims => {
+                let size = parse_number(text).or_else(|| defines.get(text).copied());
+                elements *= size.unwrap_or(1).max(1);
+            }
+            _ if expect_name && tok.kind == TokenKind::Ident => {
+                counts.globals += 1;
+                tally_width(counts, width);
+                expect_name = false;
+            }
+            _ => {}
+        }
+    }
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_analyze_counts() {
+        let source = r#"
+define SLOTS = 4;
+const int8 ASCII[] = { 48, 49, 50 };
+const int16 TABLE[][2] = { { 1, 2 }, { 3, 4 } };
+const string NAMES[] = { "ab", "c" };
+data(1, 2, "xy");
+int a, b[SLOTS], c = 5;
+int8 small;
+combo Jump { set_val(PS4_CROSS, 100); wait(40); }
+function f(x) { return set_pvar(SPVAR_12, x, 0, 10, 0); }
+main {
+    if (get_pvar(SPVAR_3, 0, 10, 0)) combo_run(Jump);
+}
+"#;
+        let counts = analyze(source);
+        assert_eq!(counts.globals, 4);
+        assert_eq!(counts.global_slots, 7);
+        assert_eq!(counts.int8, 2);
+        assert_eq!(counts.int16, 1);
+        assert_eq!(counts.int32, 3);
+        // 3 int8 + 4 int16 + data: 2 numbers and "xy"
+        assert_eq!(counts.const_bytes, 3 + 8 + 2 + 3);
+        assert_eq!(counts.string_bytes, 3 + 2);
+        assert_eq!(counts.combos, 1);
+        assert_eq!(counts.functions, 1);
+        assert_eq!(counts.spvar_slots, 12);
+        assert!(counts.bytecode_bytes > counts.const_bytes + counts.string_bytes);
+    }
+
+    #[test]
+    fn test_check_thresholds() {
+        let counts = ResourceCounts {
+            combos: 9,
+            functions: 10,
+            ..Default::default()
+        };
+        let mut limits = BTreeMap::new();
+        limits.insert("combos".to_string(), 10);
+        limits.insert("functions".to_string(), 10);
+        let settings = ResourceSettings {
+            firmware: None,
+            warn_percent: Some(80),
+            error_percent: None,
+            limits,
+        };
+        let (report, diagnostics) = check(counts, Some(&settings)).unwrap();
+        assert_eq!(report.firmware, DEFAULT_FIRMWARE);
+        assert_eq!(report.usage.len(), Resource::ALL.len());
+        let severities: Vec<Severity> = diagnostics.iter().map(|d| d.severity).collect();
+        assert_eq!(severities, vec![Severity::Warning, Severity::Error]);
+        assert!(diagnostics[0].message.starts_with("Combos: 9 of 10"));
+        let combos = &report.usage[3];
+        assert_eq!((combos.resource, combos.estimated), (Resource::Combos, false));
+        assert!(report.usage[0].estimated);
+
+        let over = ResourceCounts {
+            functions: 600,
+            ..Default::default()
+        };
+        let (_, diagnostics) = check(over, None).unwrap();
+        assert_eq!(
+            diagnostics[0].message,
+            "Functions: 600 of an estimated 512 used (117%) on firmware 'zen'"
+        );
+
+        let unknown = ResourceSettings {
+            firmware: Some("max".to_string()),
+            ..settings
+        };
+        assert!(check(ResourceCounts::default(), Some(&unknown)).is_err());
+    }
+}
@@ -0,0 +1,532 @@
+//! Script unit tests: timed controller input run through the built game on the GPC
+//! virtual machine, checked against expected outputs, variables and OLED text.
+//!
+//! Tests live in the game's `tests/` directory, any number per TOML file:
+//!
+//! ```toml
+//! [[test]]
+//! name = "rapid fire pulses R2 while aiming"
+//! pvars = { SPVAR_1 = 1 }
+//!
+//! [[test.input]]
+//! at = 0                     # tick the values are applied at
+//! until = 20                 # optional tick they are released at
+//! set = { PS5_L2 = 100, PS5_R2 = 100 }
+//!
+//! [[test.expect]]
+//! at = 5
+//! output = { PS5_R2 = 0 }
+//! vars = { RapidFireStatus = 1, "Slots[2]" = 4 }
+//! combos = { RapidFire = true }
+//! oled = "Rapid Fire"        # text some string on the display contains
+//! ```
+//!
+//! Ticks are VM ticks counted from 0; input values hold until they are set again or
+//! released. Expectations are checked after their tick has run.
+
+use crate::gpc::constants::{self, IO_COUNT};
+use crate::gpc::vm::{Program, RuntimeError, Vm};
+use crate::pipeline::build::{build_game_with_options, BuildOptions, BuildResult};
+use crate::pipeline::sourcemap::SourceMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Directory of a game that holds its test files.
+pub const TESTS_DIR: &str = "tests";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestFile {
+    #[serde(default, rename = "test")]
+    pub tests: Vec<TestCase>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestCase {
+    pub name: String,
+    /// Ticks to run; defaults to one past the last input change or expectation
+    pub ticks: Option<u64>,
+    /// Persistent variables before the first tick, by slot name (`SPVAR_1`) or number
+    #[serde(default)]
+    pub pvars: BTreeMap<String, i32>,
+    #[serde(default)]
+    pub input: Vec<InputStep>,
+    #[serde(default)]
+    pub expect: Vec<Expectation>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputStep {
+    pub at: u64,
+    pub until: Option<u64>,
+    /// Values by identifier name, e.g. `PS5_R2 = 100`
+    pub set: BTreeMap<String, i32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expectation {
+    pub at: u64,
+    #[serde(default)]
+    pub output: BTreeMap<String, i32>,
+    /// Global variables by name, or array elements as `name[index]`
+    #[serde(default)]
+    pub vars: BTreeMap<String, i32>,
+    /// Whether each combo is running
+    #[serde(default)]
+    pub combos: BTreeMap<String, bool>,
+    pub oled: Option<String>,
+}
+
+/// A value that differs from what a test expected.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TickDiff {
+    pub tick: u64,
+    /// What was checked: an identifier, `var <name>`, `combo <name>` or `oled`
+    pub subject: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TestResult {
+    /// Test file name within the tests directory
+    pub file: String,
+    pub name: String,
+    pub passed: bool,
+    pub diffs: Vec<TickDiff>,
+    /// Why the test could not run to the end, e.g. a runtime error with its location
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TestRunReport {
+    pub success: bool,
+    pub passed: usize,
+    pub failed: usize,
+    pub tests: Vec<TestResult>,
+    /// Set when the built script cannot be run by the simulator
+    pub error: Option<String>,
+    pub build: BuildResult,
+}
+
+/// Read every `tests/*.toml` of a game, sorted by file name.
+pub fn load_test_files(game_dir: &Path) -> Result<Vec<(String, TestFile)>, String> {
+    let dir = game_dir.join(TESTS_DIR);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let entries =
+        std::fs::read_dir(&dir).map_err(|e| format!("Could not read {}: {}", dir.display(), e))?;
+    let mut paths: Vec<_> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+
+    let mut files = Vec::new();
+    for path in paths {
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let file: TestFile = toml::from_str(&content)
+            .map_err(|e| format!("Invalid test file {}: {}", path.display(), e))?;
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        files.push((name, file));
+    }
+    Ok(files)
+}
+
+/// Build a game and run its tests whose name contains `filter`.
+pub fn run_tests(
+    game_dir: &Path,
+    project_root: &Path,
+    dist_base: &Path,
+    workspace_path: Option<&str>,
+    options: &BuildOptions,
+    filter: Option<&str>,
+) -> Result<TestRunReport, String> {
+    let files = load_test_files(game_dir)?;
+    let build = build_game_with_options(
+        game_dir,
+        project_root,
+        dist_base,
+        false,
+        workspace_path,
+        options,
+    );
+    let mut report = TestRunReport {
+        success: false,
+        passed: 0,
+        failed: 0,
+        tests: Vec::new(),
+        error: None,
+        build,
+    };
+    if !report.build.success {
+        return Ok(report);
+    }
+
+    let output = std::fs::read_to_string(&report.build.output_path)
+        .map_err(|e| format!("Could not read {}: {}", report.build.output_path, e))?;
+    let source_map = report
+        .build
+        .source_map_path
+        .as_ref()
+        .and_then(|path| SourceMap::load(Path::new(path)).ok());
+    let location = |offset: usize| locate(&output, offset, source_map.as_ref());
+    let program = match load_program(&output, source_map.as_ref()) {
+        Ok(program) => program,
+        Err(error) => {
+            report.error = Some(error);
+            return Ok(report);
+        }
+    };
+
+    for (file, tests) in &files {
+        for test in &tests.tests {
+            if filter.is_some_and(|f| !test.name.contains(f)) {
+                continue;
+            }
+            let mut result = run_test(Vm::new(program.clone()), test, &location);
+            result.file = file.clone();
+            if result.passed {
+                report.passed += 1;
+            } else {
+                report.failed += 1;
+            }
+            report.tests.push(result);
+        }
+    }
+    report.success = report.failed == 0;
+    Ok(report)
+}
+
+/// Load a built script into the simulator, with load errors at their source lines.
+pub(crate) fn load_program(
+    output: &str,
+    source_map: Option<&SourceMap>,
+) -> Result<Program, String> {
+    Program::load(output).map_err(|errors| {
+        let messages: Vec<String> = errors
+            .iter()
+            .map(|e| {
+                format!(
+                    "{}: {}",
+                    locate(output, e.span.start, source_map),
+                    e.message
+                )
+            })
+            .collect();
+        format!(
+            "The simulator cannot run this script:\n{}",
+            messages.join("\n")
+        )
+    })
+}
+
+/// `file:line` in the game's sources of a byte offset in the built output.
+pub(crate) fn locate(output: &str, offset: usize, source_map: Option<&SourceMap>) -> String {
+    let line = output[..offset.min(output.len())].matches('\n').count() + 1;
+    match source_map.and_then(|map| map.resolve(line)) {
+        Some(location) => format!("{}:{}", location.file, location.line),
+        None => format!("line {}", line),
+    }
+}
+
+fn io_index(name: &str) -> Result<usize, String> {
+    constants::io_index(name).ok_or_else(|| format!("Unknown controller identifier '{}'", name))
+}
+
+/// Run one test on a freshly loaded script.
+pub fn run_test(mut vm: Vm, test: &TestCase, locate: &dyn Fn(usize) -> String) -> TestResult {
+    let mut result = TestResult {
+        file: String::new(),
+        name: test.name.clone(),
+        passed: false,
+        diffs: Vec::new(),
+        error: None,
+    };
+    match execute(&mut vm, test, &mut result.diffs) {
+        Ok(()) => {}
+        Err(Failure::Test(message)) => result.error = Some(message),
+        Err(Failure::Runtime(tick, error)) => {
+            result.error = Some(format!(
+                "tick {}: {} ({})",
+                tick,
+                error.message,
+                locate(error.span.start)
+            ));
+        }
+    }
+    result.passed = result.error.is_none() && result.diffs.is_empty();
+    result
+}
+
+enum Failure {
+    /// The test itself is invalid
+    Test(String),
+    Runtime(u64, RuntimeError),
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Failure::Test(message)
+    }
+}
+
+fn execute(vm: &mut Vm, test: &TestCase, diffs: &mut Vec<TickDiff>) -> Result<(), Failure> {
+    for (slot, value) in &test.pvars {
+        let number = constants::constant(slot).or_else(|| slot.parse().ok());
+        let number = number.ok_or_else(|| format!("Unknown persistent variable '{}'", slot))?;
+        vm.set_pvar(number, *value);
+    }
+    let mut changes: BTreeMap<u64, Vec<(usize, i32)>> = BTreeMap::new();
+    for step in &test.input {
+        for (name, value) in &step.set {
+            let io = io_index(name)?;
+            changes.entry(step.at).or_default().push((io, *value));
+            if let Some(until) = step.until {
+                changes.entry(until).or_default().push((io, 0));
+            }
+        }
+    }
+    let last = changes
+        .keys()
+        .chain(test.expect.iter().map(|e| &e.at))
+        .max()
+        .map_or(1, |tick| tick + 1);
+    let ticks = test.ticks.unwrap_or(last);
+
+    let mut input = [0; IO_COUNT];
+    for tick in 0..ticks {
+        for (io, value) in changes.get(&tick).into_iter().flatten() {
+            input[*io] = *value;
+        }
+        vm.tick(&input).map_err(|e| Failure::Runtime(tick, e))?;
+        for expectation in test.expect.iter().filter(|e| e.at == tick) {
+            check(vm, expectation, diffs)?;
+        }
+    }
+    Ok(())
+}
+
+fn check(vm: &Vm, expect: &Expectation, diffs: &mut Vec<TickDiff>) -> Result<(), String> {
+    let mut diff = |subject: String, expected: String, actual: String| {
+        if expected != actual {
+            diffs.push(TickDiff {
+                tick: expect.at,
+                subject,
+                expected,
+                actual,
+            });
+        }
+    };
+    for (name, expected) in &expect.output {
+        let actual = vm.output()[io_index(name)?];
+        diff(name.clone(), expected.to_string(), actual.to_string());
+    }
+    for (name, expected) in &expect.vars {
+        let (var, index) = match name.strip_suffix(']').and_then(|n| n.split_once('[')) {
+            Some((var, index)) => {
+                let index = index.trim().parse::<usize>();
+                (
+                    var.trim(),
+                    index.map_err(|_| format!("Invalid index in '{}'", name))?,
+                )
+            }
+            None => (name.as_str(), 0),
+        };
+        let values = vm
+            .variable(var)
+            .ok_or_else(|| format!("Unknown variable '{}'", var))?;
+        let actual = values
+            .get(index)
+            .ok_or_else(|| format!("Index {} out of bounds for '{}'", index, var))?;
+        diff(
+            format!("var {}", name),
+            expected.to_string(),
+            actual.to_string(),
+        );
+    }
+    for (name, expected) in &expect.combos {
+        if !vm.combo_names().any(|combo| combo == name) {
+            return Err(format!("Unknown combo '{}'", name));
+        }
+        let actual = vm.combo_running(name);
+        diff(
+            format!("combo {}", name),
+            expected.to_string(),
+            actual.to_string(),
+        );
+    }
+    if let Some(expected) = &expect.oled {
+        let text = vm.oled().text_content();
+        if !vm
+            .oled()
+            .text
+            .iter()
+            .any(|t| t.text.contains(expected.as_str()))
+        {
+            diffs.push(TickDiff {
+                tick: expect.at,
+                subject: "oled".to_string(),
+                expected: format!("{:?}", expected),
+                actual: format!("{:?}", text),
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::test_support::write_test_game;
+
+    const GAME: &str = r#"
+int Shots, Status;
+const string LABELS[] = { "Off", "Rapid Fire" };
+init { Status = get_pvar(SPVAR_1, 0, 1, 0); }
+main {
+    if (event_press(PS5_R2)) Shots++;
+    if (Status && get_val(PS5_R2)) combo_run(Rapid);
+    cls_oled(OLED_BLACK);
+    print(0, 0, OLED_FONT_SMALL, OLED_WHITE, LABELS[Status]);
+}
+combo Rapid {
+    set_val(PS5_R2, 100);
+    wait(20);
+    set_val(PS5_R2, 0);
+    wait(20);
+}
+"#;
+
+    const TESTS: &str = r#"
+[[test]]
+name = "rapid fire pulses R2"
+pvars = { SPVAR_1 = 1 }
+
+[[test.input]]
+at = 0
+until = 6
+set = { PS5_R2 = 100 }
+
+[[test.expect]]
+at = 1
+output = { PS5_R2 = 100 }
+vars = { Shots = 1 }
+combos = { Rapid = true }
+oled = "Rapid"
+
+[[test.expect]]
+at = 2
+output = { PS5_R2 = 0 }
+
+[[test]]
+name = "off by default"
+
+[[test.input]]
+at = 0
+set = { PS5_R2 = 100 }
+
+[[test.expect]]
+at = 2
+output = { PS5_R2 = 0, PS5_L2 = 0 }
+vars = { Status = 1 }
+oled = "Rapid"
+"#;
+
+    fn game(tests: &str) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = write_test_game(dir.path(), GAME);
+        std::fs::create_dir_all(game_dir.join(TESTS_DIR)).unwrap();
+        std::fs::write(game_dir.join(TESTS_DIR).join("rapid.toml"), tests).unwrap();
+        dir
+    }
+
+    fn run(dir: &tempfile::TempDir, filter: Option<&str>) -> TestRunReport {
+        let game_dir = dir.path().join("game");
+        let options = BuildOptions::default();
+        run_tests(&game_dir, dir.path(), dir.path(), None, &options, filter).unwrap()
+    }
+
+    #[test]
+    fn test_run_reports_passes_and_diffs() {
+        let dir = game(TESTS);
+        let report = run(&dir, None);
+        assert!(report.build.success, "{:?}", report.build.errors);
+        assert_eq!((report.passed, report.failed), (1, 1));
+        assert!(!report.success);
+
+        let failed = &report.tests[1];
+        assert_eq!(
+            (failed.file.as_str(), failed.name.as_str()),
+            ("rapid.toml", "off by default")
+        );
+        assert_eq!(
+            failed.diffs,
+            vec![
+                TickDiff {
+                    tick: 2,
+                    subject: "PS5_R2".to_string(),
+                    expected: "0".to_string(),
+                    actual: "100".to_string(),
+                },
+                TickDiff {
+                    tick: 2,
+                    subject: "var Status".to_string(),
+                    expected: "1".to_string(),
+                    actual: "0".to_string(),
+                },
+                TickDiff {
+                    tick: 2,
+                    subject: "oled".to_string(),
+                    expected: "\"Rapid\"".to_string(),
+                    actual: "\"Off\"".to_string(),
+                },
+            ]
+        );
+
+        let report = run(&dir, Some("pulses"));
+        assert_eq!((report.passed, report.failed), (1, 0));
+        assert!(report.success);
+    }
+
+    #[test]
+    fn test_invalid_tests_and_runtime_errors() {
+        let dir = game("[[test]]\nname = \"x\"\n[[test.input]]\nat = 0\nset = { PS5_NOPE = 1 }\n");
+        let report = run(&dir, None);
+        assert_eq!(
+            report.tests[0].error.as_deref(),
+            Some("Unknown controller identifier 'PS5_NOPE'")
+        );
+
+        let dir = game("[[test]]\nname = \"x\"\nticks = 1\nbogus = 1\n");
+        let game_dir = dir.path().join("game");
+        let error = load_test_files(&game_dir).unwrap_err();
+        assert!(error.starts_with("Invalid test file"), "{}", error);
+
+        let dir = game("[[test]]\nname = \"x\"\nticks = 1\n");
+        let game_dir = dir.path().join("game");
+        std::fs::write(
+            game_dir.join("main.gpc"),
+            "int a[2];\nmain {\n    a[a[0] + 5] = 1;\n}\n",
+        )
+        .unwrap();
+        let report = run(&dir, None);
+        let error = report.tests[0].error.as_deref().unwrap();
+        assert!(
+            error.starts_with("tick 0: Index 5 out of bounds for 'a' of size 2 ("),
+            "{}",
+            error
+        );
+        assert!(error.ends_with("main.gpc:3)"), "{}", error);
+    }
+}
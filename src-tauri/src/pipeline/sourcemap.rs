@@ -0,0 +1,309 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Current source map format version.
+const SOURCE_MAP_VERSION: u32 = 1;
+
+/// Line-level source map written next to a built file as `<output>.map`.
+///
+/// `lines[n]` describes where line `n + 1` of the built file came from.
+/// Generated lines (header comments, plugin code without a backing file) are `None`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SourceMap {
+    pub version: u32,
+    /// File name of the built output this map describes
+    pub file: String,
+    /// Absolute paths of every source file referenced by `lines`
+    pub sources: Vec<String>,
+    pub lines: Vec<Option<LineOrigin>>,
+}
+
+/// Origin of a single output line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LineOrigin {
+    /// Index into `SourceMap::sources`
+    pub source: usize,
+    /// 1-indexed line in the source file
+    pub line: usize,
+    /// Macros whose expansion produced this line, outermost first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub macros: Vec<String>,
+}
+
+/// A resolved location in an original source file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub macros: Vec<String>,
+}
+
+impl SourceMap {
+    pub fn new(file: &str) -> Self {
+        Self {
+            version: SOURCE_MAP_VERSION,
+            file: file.to_string(),
+            sources: Vec::new(),
+            lines: Vec::new(),
+        }
+    }
+
+    /// Resolve a 1-indexed output line back to its original file and line.
+    pub fn resolve(&self, line: usize) -> Option<SourceLocation> {
+        let origin = self.lines.get(line.checked_sub(1)?)?.as_ref()?;
+        Some(SourceLocation {
+            file: self.sources.get(origin.source)?.clone(),
+            line: origin.line,
+            macros: origin.macros.clone(),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read source map {}: {}", path.display(), e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Could not parse source map {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = serde_json::to_string(self)
+            .map_err(|e| format!("Could not serialize source map: {}", e))?;
+        std::fs::write(path, content)
+            .map_err(|e| format!("Could not write source map {}: {}", path.display(), e))
+    }
+}
+
+/// Path of the source map for a built output file (`dist/Foo.gpc` -> `dist/Foo.gpc.map`).
+pub fn map_path_for(output_path: &Path) -> std::path::PathBuf {
+    let mut name = output_path.as_os_str().to_os_string();
+    name.push(".map");
+    std::path::PathBuf::from(name)
+}
+
+/// Interns source file paths so each output line only stores an index.
+#[derive(Debug, Clone, Default)]
+pub struct SourceTable {
+    pub sources: Vec<String>,
+    index: HashMap<String, usize>,
+}
+
+impl SourceTable {
+    pub fn id(&mut self, path: &str) -> usize {
+        if let Some(&id) = self.index.get(path) {
+            return id;
+        }
+        let id = self.sources.len();
+        self.sources.push(path.to_string());
+        self.index.insert(path.to_string(), id);
+        id
+    }
+}
+
+// ============================================================
+// Line tracking through text rewrites
+// ============================================================
+
+/// Byte offsets of line starts, for mapping a byte position to a 0-indexed line.
+pub(crate) struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub(crate) fn new(text: &str) -> Self {
+        let mut starts = vec![0];
+        starts.extend(text.bytes().enumerate().filter(|(_, b)| *b == b'\n').map(|(i, _)| i + 1));
+        Self { starts }
+    }
+
+    pub(crate) fn line_of(&self, pos: usize) -> usize {
+        self.starts.partition_point(|&s| s <= pos) - 1
+    }
+
+    /// 1-indexed column of a byte position.
+    pub(crate) fn column_of(&self, pos: usize) -> usize {
+        pos - self.starts[self.line_of(pos)] + 1
+    }
+}
+
+/// Where a line of rewritten text came from in the text it was rewritten from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LineTrace {
+    /// 0-indexed line in the input text
+    pub line: usize,
+    /// Macro expansions that produced the line, outermost first
+    pub macros: Vec<String>,
+}
+
+/// String builder that records, for every output line, which input line produced it.
+///
+/// A line is attributed to wherever its terminating newline came from, so text
+/// copied verbatim keeps its own line while generated text takes the line of
+/// the construct that generated it. A line containing any macro-generated text
+/// records that macro.
+pub(crate) struct TracedString<'a> {
+    pub text: String,
+    pub lines: Vec<LineTrace>,
+    input: &'a LineIndex,
+    /// Macros that contributed to the current (unterminated) line
+    pending_macros: Vec<String>,
+}
+
+impl<'a> TracedString<'a> {
+    pub(crate) fn new(input: &'a LineIndex, capacity: usize) -> Self {
+        Self {
+            text: String::with_capacity(capacity),
+            lines: Vec::new(),
+            input,
+            pending_macros: Vec::new(),
+        }
+    }
+
+    /// Append `text`, which was copied verbatim from the input starting at byte `input_pos`.
+    pub(crate) fn push_copied(&mut self, text: &str, input_pos: usize) {
+        for (i, b) in text.bytes().enumerate() {
+            if b == b'\n' {
+                self.lines.push(LineTrace {
+                    line: self.input.line_of(input_pos + i),
+                    macros: std::mem::take(&mut self.pending_macros),
+                });
+            }
+        }
+        self.text.push_str(text);
+    }
+
+    /// Append generated `text` attributed to the input byte `input_pos`.
+    pub(crate) fn push_generated(&mut self, text: &str, input_pos: usize, macro_name: Option<&str>) {
+        let line = self.input.line_of(input_pos);
+        let mark = |macros: &mut Vec<String>| {
+            if let Some(m) = macro_name {
+                if !macros.iter().any(|existing| existing == m) {
+                    macros.push(m.to_string());
+                }
+            }
+        };
+        for b in text.bytes() {
+            if b == b'\n' {
+                let mut macros = std::mem::take(&mut self.pending_macros);
+                mark(&mut macros);
+                self.lines.push(LineTrace { line, macros });
+            }
+        }
+        if !text.ends_with('\n') && !text.is_empty() {
+            mark(&mut self.pending_macros);
+        }
+        self.text.push_str(text);
+    }
+
+    /// Append generated `text` attributed to the input byte `input_pos`, where line `i`
+    /// of `text` was produced by the macros in `chains[i]`, outermost first.
+    pub(crate) fn push_expansion(&mut self, text: &str, input_pos: usize, chains: &[Vec<String>]) {
+        let line = self.input.line_of(input_pos);
+        let pieces = text.split('\n').count();
+        for (idx, piece) in text.split('\n').enumerate() {
+            let terminated = idx + 1 < pieces;
+            if let Some(chain) = chains.get(idx).filter(|_| terminated || !piece.is_empty()) {
+                for name in chain {
+                    if !self.pending_macros.contains(name) {
+                        self.pending_macros.push(name.clone());
+                    }
+                }
+            }
+            if terminated {
+                self.lines.push(LineTrace {
+                    line,
+                    macros: std::mem::take(&mut self.pending_macros),
+                });
+            }
+        }
+        self.text.push_str(text);
+    }
+
+    /// Finish the text, attributing a trailing unterminated line to `input_end`.
+    pub(crate) fn finish(mut self, input_end: usize) -> (String, Vec<LineTrace>) {
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.lines.push(LineTrace {
+                line: self.input.line_of(input_end),
+                macros: std::mem::take(&mut self.pending_macros),
+            });
+        }
+        (self.text, self.lines)
+    }
+}
+
+/// Compose two line traces: `outer` maps output lines to lines of an intermediate
+/// text, `inner` maps those intermediate lines to the original input.
+pub(crate) fn compose_traces(inner: &[LineTrace], outer: &[LineTrace]) -> Vec<LineTrace> {
+    outer
+        .iter()
+        .map(|o| {
+            let mut trace = inner.get(o.line).cloned().unwrap_or(LineTrace {
+                line: o.line,
+                macros: Vec::new(),
+            });
+            trace.macros.extend(o.macros.iter().cloned());
+            trace
+        })
+        .collect()
+}
+
+/// Identity trace for a text: every line maps to itself.
+pub(crate) fn identity_trace(text: &str) -> Vec<LineTrace> {
+    (0..text.lines().count())
+        .map(|line| LineTrace { line, macros: Vec::new() })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_index() {
+        let idx = LineIndex::new("ab\ncd\n\nef");
+        assert_eq!(idx.line_of(0), 0);
+        assert_eq!(idx.line_of(2), 0);
+        assert_eq!(idx.line_of(3), 1);
+        assert_eq!(idx.line_of(6), 2);
+        assert_eq!(idx.line_of(7), 3);
+        assert_eq!(idx.column_of(4), 2);
+    }
+
+    #[test]
+    fn test_traced_string_generated_lines_take_call_site() {
+        let input = "a;\nm()!;\nb;\n";
+        let idx = LineIndex::new(input);
+        let mut out = TracedString::new(&idx, input.len());
+        out.push_copied("a;\n", 0);
+        out.push_generated("x;\ny;", 3, Some("m"));
+        out.push_copied(";\nb;\n", 7);
+        let (text, lines) = out.finish(input.len());
+        assert_eq!(text, "a;\nx;\ny;;\nb;\n");
+        let lines: Vec<usize> = lines.iter().map(|l| l.line).collect();
+        assert_eq!(lines, vec![0, 1, 1, 2]);
+    }
+
+    #[test]
+    fn test_compose_traces_accumulates_macros() {
+        let inner = vec![
+            LineTrace { line: 4, macros: vec![] },
+            LineTrace { line: 9, macros: vec!["outer".to_string()] },
+        ];
+        let outer = vec![LineTrace { line: 1, macros: vec!["inner".to_string()] }];
+        let composed = compose_traces(&inner, &outer);
+        assert_eq!(composed[0].line, 9);
+        assert_eq!(composed[0].macros, vec!["outer", "inner"]);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut map = SourceMap::new("Out.gpc");
+        map.sources.push("/games/x/main.gpc".to_string());
+        map.lines = vec![None, Some(LineOrigin { source: 0, line: 7, macros: vec![] })];
+        assert_eq!(map.resolve(1), None);
+        assert_eq!(map.resolve(2).unwrap().line, 7);
+        assert_eq!(map.resolve(0), None);
+        assert_eq!(map.resolve(3), None);
+    }
+}
@@ -0,0 +1,166 @@
+//! Syntax check for built GPC scripts.
+//!
+//! Imports, macros and plugin hooks all work on plain text, so a bad plugin
+//! snippet or macro body can leave unbalanced braces or half a statement in the
+//! output. This pass parses the final script with `gpc::parse`, the grammar the
+//! rest of ZenForge uses, and reports where it stops making sense, so the build
+//! fails instead of the upload.
+
+use crate::gpc::{self, Span};
+use crate::pipeline::sourcemap::LineIndex;
+
+/// A range on one line of the checked text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextRange {
+    /// 0-indexed line
+    pub line: usize,
+    /// 1-indexed character columns; `end_col` is exclusive
+    pub start_col: usize,
+    pub end_col: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub message: String,
+    pub range: TextRange,
+    /// Another location that explains the error, e.g. the bracket left unclosed
+    pub related: Option<(TextRange, String)>,
+}
+
+/// Check that `source` is a syntactically valid GPC script.
+pub fn check(source: &str) -> Vec<SyntaxError> {
+    let index = LineIndex::new(source);
+    gpc::parse(source)
+        .errors
+        .into_iter()
+        .map(|e| SyntaxError {
+            message: e.message,
+            range: text_range(source, &index, e.span),
+            related: e
+                .related
+                .map(|(span, message)| (text_range(source, &index, span), message)),
+        })
+        .collect()
+}
+
+fn text_range(source: &str, index: &LineIndex, span: Span) -> TextRange {
+    let Span { start, end } = span;
+    let line = index.line_of(start);
+    let line_start = start + 1 - index.column_of(start);
+    let line_end = source[start..].find('\n').map_or(source.len(), |p| start + p);
+    let start_col = source[line_start..start].chars().count() + 1;
+    let end_col = start_col + source[start..end.min(line_end)].chars().count().max(1);
+    TextRange {
+        line,
+        start_col,
+        end_col,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(source: &str) -> Vec<(usize, usize, String)> {
+        check(source)
+            .into_iter()
+            .map(|e| (e.range.line, e.range.start_col, e.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_script() {
+        let source = r#"
+define FIRE = 40;
+define NEG = -1;
+data(20, 42, FIRE);
+remap PS5_CROSS -> PS5_SQUARE;
+unmap PS5_TRIANGLE;
+enum { MODE_A, MODE_B = 5, };
+int a, b = 2, c[10];
+const int8 TABLE[] = { 1, -2, 0x7F };
+const string NAMES[] = { "one", "two" };
+const int8 GRID[][] = { { 1, 2 }, { 3, 4 } };
+image logo = { 8, 8, 0xFF };
+fix32 ratio = 1.5;
+
+init { a = 0; }
+
+main {
+    if (get_val(PS5_R2) > 50 && !b) {
+        combo_run(Fire);
+    } else if (a) a++; else { a -= 1; }
+    for (i = 0; i < 10; i++) c[i] <<= 1;
+    while (a) { a = a >> 1; if (a == 3) break; else continue; }
+    do { a--; } while (a > 0);
+    switch (a) { case 1: b = 1; break; default: b = (fix32)a * ratio; }
+    c[0] |= ~a & 0xF;
+    printf("%d", a); // trailing comment
+}
+
+combo Fire {
+    set_val(PS5_R2, 100);
+    wait(FIRE);
+}
+
+function add(x, int y) { return x + y * (x - 1) % 3; }
+function nothing() { return; }
+"#;
+        assert_eq!(messages(source), Vec::new());
+    }
+
+    #[test]
+    fn test_unbalanced_brackets() {
+        assert_eq!(
+            messages("main {\n    if (a) {\n        b = 1;\n}\n"),
+            vec![(0, 6, "'{' is never closed".to_string())]
+        );
+        let errors = check("main {\n    f(a];\n}\n");
+        assert_eq!(errors[0].message, "Mismatched ']': expected ')' to close '('");
+        assert_eq!((errors[0].range.line, errors[0].range.start_col), (1, 8));
+        let (related, note) = errors[0].related.clone().unwrap();
+        assert_eq!((related.line, related.start_col, note.as_str()), (1, 6, "'(' opened here"));
+        assert_eq!(messages("main { }\n}\n"), vec![(1, 1, "Unmatched '}'".to_string())]);
+    }
+
+    #[test]
+    fn test_malformed_items_are_each_reported() {
+        let source = "int a = ;\nmain {\n    a = 1\n    b = 2;\n}\nfunction f(1) { }\nint ok;\n";
+        assert_eq!(
+            messages(source),
+            vec![
+                (0, 9, "Expected an expression, found ';'".to_string()),
+                (2, 9, "Expected ';' after '1'".to_string()),
+                (5, 12, "Expected a parameter name, found '1'".to_string()),
+            ]
+        );
+        assert_eq!(
+            messages("main { if (a) { } }\nelse { }\n"),
+            vec![(
+                1,
+                1,
+                "Expected a declaration, 'init', 'main', 'combo' or 'function', found 'else'"
+                    .to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_literals() {
+        assert_eq!(
+            messages("main { printf(\"abc); }\n"),
+            vec![(0, 15, "Unterminated string literal".to_string())]
+        );
+        assert_eq!(
+            messages("int a; /* never closed\nmain { }\n"),
+            vec![(0, 8, "Unterminated block comment".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_columns_count_characters() {
+        let errors = check("// é\nint é;\n");
+        assert_eq!(errors[0].message, "Expected a variable name, found 'é'");
+        assert_eq!((errors[0].range.start_col, errors[0].range.end_col), (5, 6));
+    }
+}
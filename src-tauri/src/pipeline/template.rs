@@ -0,0 +1,375 @@
+//! Placeholder templates for output filenames and header comments.
+//!
+//! `{name}` inserts a value and `{name|upper}` runs it through filters (`upper`,
+//! `lower`, `slug`, `abbr`). `{#name}...{/name}` keeps its contents only when the
+//! value is non-empty and `{^name}...{/name}` only when it is empty. `{{` is a
+//! literal brace. Unknown placeholders, filters and unbalanced sections are
+//! reported as warnings and render as nothing.
+
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Values available to a template, plus the date/time and git placeholders which
+/// are looked up only when a template uses them.
+pub struct TemplateContext {
+    values: BTreeMap<String, String>,
+    /// Seconds since the Unix epoch for `{date}`, `{time}` and friends
+    now: u64,
+    /// Directory whose git repository `{git_hash}` and `{git_branch}` describe
+    git_dir: Option<PathBuf>,
+    git: OnceCell<Option<GitInfo>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct GitInfo {
+    hash: String,
+    branch: String,
+}
+
+/// Part of a parsed template.
+#[derive(Debug, PartialEq)]
+enum Node {
+    Text(String),
+    Value { name: String, filters: Vec<String> },
+    Section { name: String, inverted: bool, children: Vec<Node> },
+}
+
+impl Default for TemplateContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TemplateContext {
+    pub fn new() -> Self {
+        Self {
+            values: BTreeMap::new(),
+            now: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            git_dir: None,
+            git: OnceCell::new(),
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: impl Into<String>) -> &mut Self {
+        self.values.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// Describe the git repository containing `dir` in `{git_hash}` and `{git_branch}`.
+    pub fn with_git(mut self, dir: &Path) -> Self {
+        self.git_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Render date and time placeholders for `secs` since the Unix epoch instead of now.
+    pub fn with_time(mut self, secs: u64) -> Self {
+        self.now = secs;
+        self
+    }
+
+    fn lookup(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.values.get(name) {
+            return Some(value.clone());
+        }
+        let (year, month, day) = civil_from_days((self.now / 86_400) as i64);
+        let (hour, minute) = ((self.now % 86_400) / 3600, (self.now % 3600) / 60);
+        let value = match name {
+            "date" => format!("{:04}-{:02}-{:02}", year, month, day),
+            "time" => format!("{:02}:{:02}", hour, minute),
+            "datetime" => {
+                format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, hour, minute)
+            }
+            "year" => format!("{:04}", year),
+            "month" => format!("{:02}", month),
+            "day" => format!("{:02}", day),
+            "timestamp" => self.now.to_string(),
+            "git_hash" | "git_branch" => {
+                let git = self
+                    .git
+                    .get_or_init(|| self.git_dir.as_deref().and_then(git_info));
+                // Outside a repository these are empty rather than unknown
+                match (git, name) {
+                    (Some(git), "git_hash") => git.hash.clone(),
+                    (Some(git), _) => git.branch.clone(),
+                    (None, _) => String::new(),
+                }
+            }
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Render a template. Returns the text and a warning per unknown placeholder,
+    /// unknown filter or unbalanced section.
+    pub fn render(&self, template: &str) -> (String, Vec<String>) {
+        let mut warnings = BTreeSet::new();
+        let nodes = parse(template, &mut warnings);
+        let mut out = String::with_capacity(template.len());
+        self.render_nodes(&nodes, &mut out, &mut warnings);
+        (out, warnings.into_iter().collect())
+    }
+
+    fn render_nodes(&self, nodes: &[Node], out: &mut String, warnings: &mut BTreeSet<String>) {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Value { name, filters } => {
+                    let Some(mut value) = self.lookup(name) else {
+                        warnings.insert(format!("Unknown placeholder {{{}}}", name));
+                        continue;
+                    };
+                    for filter in filters {
+                        match apply_filter(filter, &value) {
+                            Some(filtered) => value = filtered,
+                            None => {
+                                let warning = format!("Unknown filter '{}' on {{{}}}", filter, name);
+                                warnings.insert(warning);
+                            }
+                        }
+                    }
+                    out.push_str(&value);
+                }
+                Node::Section { name, inverted, children } => {
+                    let value = self.lookup(name).unwrap_or_else(|| {
+                        warnings.insert(format!("Unknown placeholder {{{}}}", name));
+                        String::new()
+                    });
+                    if value.is_empty() == *inverted {
+                        self.render_nodes(children, out, warnings);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn apply_filter(filter: &str, value: &str) -> Option<String> {
+    let filtered = match filter {
+        "upper" => value.to_uppercase(),
+        "lower" => value.to_lowercase(),
+        "slug" => slug(value),
+        "abbr" => abbreviate(value),
+        _ => return None,
+    };
+    Some(filtered)
+}
+
+/// Lowercase words joined by dashes: "Rainbow Six: Siege" -> "rainbow-six-siege".
+pub fn slug(value: &str) -> String {
+    value
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// A value with everything but letters, digits, `-` and `_` removed, for filenames.
+pub fn abbreviate(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+        .collect()
+}
+
+/// Length of a tag like `{name}`, `{#name}` or `{name|upper|slug}` at the start of `s`.
+fn tag_len(s: &str) -> Option<usize> {
+    let end = s.find('}')?;
+    let inner = &s[1..end];
+    let inner = inner.strip_prefix(['#', '^', '/']).unwrap_or(inner);
+    let is_ident = |part: &str| {
+        let mut chars = part.chars();
+        chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    inner.split('|').all(is_ident).then_some(end + 1)
+}
+
+fn parse(template: &str, warnings: &mut BTreeSet<String>) -> Vec<Node> {
+    // Open sections with the nodes collected before each of them
+    let mut stack: Vec<(String, bool, Vec<Node>)> = Vec::new();
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut text = String::new();
+    let mut rest = template;
+
+    while let Some(pos) = rest.find('{') {
+        text.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        if let Some(after) = rest.strip_prefix("{{") {
+            text.push('{');
+            rest = after;
+            continue;
+        }
+        let Some(len) = tag_len(rest) else {
+            text.push('{');
+            rest = &rest[1..];
+            continue;
+        };
+        let tag = &rest[1..len - 1];
+        rest = &rest[len..];
+        if !text.is_empty() {
+            nodes.push(Node::Text(std::mem::take(&mut text)));
+        }
+
+        if let Some(name) = tag.strip_prefix('#').or_else(|| tag.strip_prefix('^')) {
+            let inverted = tag.starts_with('^');
+            stack.push((name.to_string(), inverted, std::mem::take(&mut nodes)));
+        } else if let Some(name) = tag.strip_prefix('/') {
+            match stack.last() {
+                Some((open, _, _)) if open == name => {
+                    let (name, inverted, parent) = stack.pop().expect("checked above");
+                    let children = std::mem::replace(&mut nodes, parent);
+                    nodes.push(Node::Section { name, inverted, children });
+                }
+                _ => {
+                    warnings.insert(format!("{{/{}}} does not close an open section", name));
+                }
+            }
+        } else {
+            let mut parts = tag.split('|');
+            let name = parts.next().unwrap_or_default().to_string();
+            let filters = parts.map(String::from).collect();
+            nodes.push(Node::Value { name, filters });
+        }
+    }
+    text.push_str(rest);
+    if !text.is_empty() {
+        nodes.push(Node::Text(text));
+    }
+
+    // Close whatever is still open at the end of the template
+    while let Some((name, inverted, parent)) = stack.pop() {
+        warnings.insert(format!("Section {{#{}}} is never closed", name));
+        let children = std::mem::replace(&mut nodes, parent);
+        nodes.push(Node::Section { name, inverted, children });
+    }
+    nodes
+}
+
+/// Short commit hash and branch of the git repository containing `dir`.
+fn git_info(dir: &Path) -> Option<GitInfo> {
+    let (hash, branch) = git_head(dir)?;
+    Some(GitInfo {
+        hash: hash.chars().take(7).collect(),
+        branch,
+    })
+}
+
+/// Full hash of the commit checked out in the git repository containing `dir`.
+pub fn git_commit(dir: &Path) -> Option<String> {
+    git_head(dir).map(|(hash, _)| hash)
+}
+
+/// Commit hash and branch (empty when detached) of the git repository containing
+/// `dir`, read straight from `.git` so git does not need to be installed.
+fn git_head(dir: &Path) -> Option<(String, String)> {
+    let git_dir = dir.ancestors().map(|d| d.join(".git")).find(|d| d.is_dir())?;
+    let head = std::fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    let Some(reference) = head.strip_prefix("ref: ") else {
+        // Detached HEAD holds the commit itself
+        return Some((head.to_string(), String::new()));
+    };
+    let branch = reference.strip_prefix("refs/heads/").unwrap_or(reference).to_string();
+    let hash = match std::fs::read_to_string(git_dir.join(reference)) {
+        Ok(hash) => hash.trim().to_string(),
+        // Refs packed by `git gc` live in packed-refs as "<hash> <ref>"
+        Err(_) => std::fs::read_to_string(git_dir.join("packed-refs"))
+            .ok()?
+            .lines()
+            .find_map(|line| {
+                let (hash, name) = line.split_once(' ')?;
+                (name == reference).then(|| hash.to_string())
+            })?,
+    };
+    Some((hash, branch))
+}
+
+/// Today's UTC date as YYYY-MM-DD.
+pub fn today() -> String {
+    let (text, _) = TemplateContext::new().render("{date}");
+    text
+}
+
+/// Proleptic Gregorian date of a day count since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> TemplateContext {
+        let mut ctx = TemplateContext::new().with_time(1_792_226_700);
+        ctx.set("name", "Rainbow Six: Siege").set("version", "1.4").set("username", "");
+        ctx
+    }
+
+    #[test]
+    fn test_values_and_filters() {
+        let (text, warnings) = context().render("{name|slug}-v{version} {name|abbr|upper}");
+        assert_eq!(text, "rainbow-six-siege-v1.4 RAINBOWSIXSIEGE");
+        assert!(warnings.is_empty());
+
+        let (text, _) = context().render("{date} {time} {{name} {not a tag} {}");
+        assert_eq!(text, "2026-10-17 08:45 {name} {not a tag} {}");
+    }
+
+    #[test]
+    fn test_sections() {
+        let template =
+            "{name}{#username} by {username}{/username}{^username} (anonymous){/username}";
+        assert_eq!(context().render(template).0, "Rainbow Six: Siege (anonymous)");
+        let mut ctx = context();
+        ctx.set("username", "masshiro");
+        assert_eq!(ctx.render(template).0, "Rainbow Six: Siege by masshiro");
+    }
+
+    #[test]
+    fn test_unknown_placeholders_warn() {
+        let (text, warnings) = context().render("a{nope}b{name|shout}{#open}c");
+        assert_eq!(text, "abRainbow Six: Siege");
+        assert_eq!(
+            warnings,
+            vec![
+                "Section {#open} is never closed",
+                "Unknown filter 'shout' on {name}",
+                "Unknown placeholder {nope}",
+                "Unknown placeholder {open}",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_git_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let git = dir.path().join(".git");
+        std::fs::create_dir_all(git.join("refs/heads")).unwrap();
+        std::fs::write(git.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        std::fs::write(git.join("packed-refs"), "# pack-refs\n0123456789abcdef refs/heads/main\n")
+            .unwrap();
+        let game = dir.path().join("Games/R6S");
+        std::fs::create_dir_all(&game).unwrap();
+        let ctx = TemplateContext::new().with_git(&game);
+        assert_eq!(ctx.render("{git_branch}@{git_hash}").0, "main@0123456");
+
+        std::fs::write(git.join("refs/heads/main"), "fedcba9876543210\n").unwrap();
+        assert_eq!(git_info(&game).unwrap().hash, "fedcba9");
+        assert_eq!(git_commit(&game).as_deref(), Some("fedcba9876543210"));
+    }
+}
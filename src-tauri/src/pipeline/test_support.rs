@@ -0,0 +1,16 @@
+//! Fixtures shared by the tests of several modules.
+
+use std::path::{Path, PathBuf};
+
+/// Write a PS5 game with `main_src` as its main.gpc to `dir/game`.
+pub(crate) fn write_test_game(dir: &Path, main_src: &str) -> PathBuf {
+    let game_dir = dir.join("game");
+    std::fs::create_dir_all(&game_dir).unwrap();
+    std::fs::write(
+        game_dir.join("game.json"),
+        r#"{"name":"Test","filename":"Test","version":1,"game_type":"fps","console_type":"ps5"}"#,
+    )
+    .unwrap();
+    std::fs::write(game_dir.join("main.gpc"), main_src).unwrap();
+    game_dir
+}
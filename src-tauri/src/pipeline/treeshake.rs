@@ -0,0 +1,608 @@
+//! Dead-code elimination for built GPC scripts.
+//!
+//! Games import whole library files but usually call only a few of their
+//! functions. This pass builds a reference graph of top-level declarations,
+//! starting from `main`, `init` and any other top-level code, and removes the
+//! functions, combos, defines, consts and globals that cannot be reached.
+//! A `// @keep` comment on or just above a declaration keeps it.
+
+use crate::gpc::lexer::{lex, Token, TokenKind};
+use crate::pipeline::sourcemap::{LineIndex, LineTrace, SourceLocation, TracedString};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Comment annotation that opts a declaration out of removal.
+pub const KEEP_ANNOTATION: &str = "@keep";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymbolKind {
+    Function,
+    Combo,
+    Define,
+    Const,
+    Variable,
+}
+
+impl SymbolKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SymbolKind::Function => "function",
+            SymbolKind::Combo => "combo",
+            SymbolKind::Define => "define",
+            SymbolKind::Const => "const",
+            SymbolKind::Variable => "variable",
+        }
+    }
+}
+
+/// A declaration removed from the build output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemovedSymbol {
+    pub kind: SymbolKind,
+    pub name: String,
+    /// Where the declaration came from in the original sources
+    pub location: Option<SourceLocation>,
+}
+
+/// An unreachable declaration, located in the text passed to `shake`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unreachable {
+    pub kind: SymbolKind,
+    pub name: String,
+    /// 0-indexed line of the declaration in the input
+    pub line: usize,
+}
+
+pub struct Shaken {
+    pub text: String,
+    /// For every output line, the input line it came from
+    pub(crate) lines: Vec<LineTrace>,
+    pub removed: Vec<Unreachable>,
+}
+
+/// A top-level declaration and the names it refers to.
+struct Decl {
+    kind: SymbolKind,
+    name: String,
+    /// Byte range of the whole declaration
+    start: usize,
+    end: usize,
+    /// Start of the comment block directly above, removed along with it
+    comment_start: usize,
+    refs: HashSet<String>,
+    keep: bool,
+    /// Index into `VarStatement`s for variable declarators
+    statement: Option<usize>,
+}
+
+/// `type a, b[2] = {...}, c;` with one `Decl` per declarator.
+struct VarStatement {
+    start: usize,
+    end: usize,
+    comment_start: usize,
+    /// Text from the start of the statement up to the first declarator (e.g. "int ")
+    type_prefix: String,
+    decls: Vec<usize>,
+}
+
+/// Remove unreachable top-level declarations from `source`.
+///
+/// `extra_roots` are names used by code outside `source` (e.g. appended plugin
+/// code) that must be kept.
+pub fn shake(source: &str, extra_roots: &HashSet<String>) -> Shaken {
+    let tokens = lex(source).0;
+    let offsets = token_offsets(source, &tokens);
+
+    let index = LineIndex::new(source);
+    let scan = scan_declarations(source, &tokens, &offsets, &index);
+
+    // Walk the reference graph from everything that is not a removable declaration
+    let mut by_name: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, decl) in scan.decls.iter().enumerate() {
+        by_name.entry(decl.name.as_str()).or_default().push(i);
+    }
+    let mut reachable = vec![false; scan.decls.len()];
+    let mut queue: Vec<usize> = scan
+        .decls
+        .iter()
+        .enumerate()
+        .filter(|(_, d)| d.keep)
+        .map(|(i, _)| i)
+        .collect();
+    for name in scan.root_refs.iter().chain(extra_roots) {
+        queue.extend(by_name.get(name.as_str()).into_iter().flatten());
+    }
+    while let Some(i) = queue.pop() {
+        if std::mem::replace(&mut reachable[i], true) {
+            continue;
+        }
+        for name in &scan.decls[i].refs {
+            queue.extend(by_name.get(name.as_str()).into_iter().flatten());
+        }
+    }
+
+    // Edits as (start, end, replacement), non-overlapping
+    let mut edits: Vec<(usize, usize, String)> = Vec::new();
+    let mut removed = Vec::new();
+    for (i, decl) in scan.decls.iter().enumerate() {
+        if reachable[i] {
+            continue;
+        }
+        removed.push(Unreachable {
+            kind: decl.kind,
+            name: decl.name.clone(),
+            line: index.line_of(decl.start),
+        });
+        if decl.statement.is_none() {
+            edits.push((decl.comment_start, decl.end, String::new()));
+        }
+    }
+    for statement in &scan.statements {
+        let kept: Vec<&str> = statement
+            .decls
+            .iter()
+            .filter(|&&d| reachable[d])
+            .map(|&d| source[scan.decls[d].start..scan.decls[d].end].trim())
+            .collect();
+        if kept.len() == statement.decls.len() {
+            continue;
+        }
+        if kept.is_empty() {
+            edits.push((statement.comment_start, statement.end, String::new()));
+        } else {
+            let replacement = format!("{}{};", statement.type_prefix, kept.join(", "));
+            edits.push((statement.start, statement.end, replacement));
+        }
+    }
+    edits.sort_by_key(|e| e.0);
+
+    let (text, lines) = apply_edits(source, &index, &edits);
+    Shaken {
+        text,
+        lines,
+        removed,
+    }
+}
+
+/// A top-level declaration, as found by the same scan tree shaking uses.
+#[derive(Debug, Clone)]
+pub(crate) struct Declaration {
+    pub kind: SymbolKind,
+    pub name: String,
+    /// 0-indexed line of the declaration
+    pub line: usize,
+    /// The declaration's tokens without comments or whitespace, for comparing definitions
+    pub normalized: String,
+}
+
+/// Every top-level function, combo, define, const and global declared in `source`.
+pub(crate) fn declarations(source: &str) -> Vec<Declaration> {
+    let tokens = lex(source).0;
+    let offsets = token_offsets(source, &tokens);
+
+    let index = LineIndex::new(source);
+    let scan = scan_declarations(source, &tokens, &offsets, &index);
+    scan.decls
+        .iter()
+        .map(|decl| {
+            let prefix = decl
+                .statement
+                .map(|s| scan.statements[s].type_prefix.as_str())
+                .unwrap_or("");
+            let text = format!("{} {}", prefix, &source[decl.start..decl.end]);
+            let normalized = lex(&text)
+                .0
+                .iter()
+                .filter(|t| !t.is_trivia())
+                .map(|t| t.text(&text))
+                .collect::<Vec<_>>()
+                .join(" ");
+            Declaration {
+                kind: decl.kind,
+                name: decl.name.clone(),
+                line: index.line_of(decl.start),
+                normalized,
+            }
+        })
+        .collect()
+}
+
+/// Every identifier used in `source`, for code that is added after shaking.
+pub fn referenced_names(source: &str) -> HashSet<String> {
+    identifiers(source, &lex(source).0, "")
+}
+
+struct Scan {
+    decls: Vec<Decl>,
+    statements: Vec<VarStatement>,
+    /// Names referenced by top-level code that is not a removable declaration
+    root_refs: HashSet<String>,
+}
+
+/// Byte offset of every token, plus the end of the text.
+fn token_offsets(source: &str, tokens: &[Token]) -> Vec<usize> {
+    let mut offsets: Vec<usize> = tokens.iter().map(|t| t.span.start).collect();
+    offsets.push(source.len());
+    offsets
+}
+
+fn is_var_type(text: &str) -> bool {
+    matches!(
+        text,
+        "int" | "int8" | "int16" | "int32" | "uint8" | "uint16" | "uint32"
+    )
+}
+
+fn scan_declarations(
+    source: &str,
+    tokens: &[Token],
+    offsets: &[usize],
+    index: &LineIndex,
+) -> Scan {
+    let mut scan = Scan {
+        decls: Vec::new(),
+        statements: Vec::new(),
+        root_refs: HashSet::new(),
+    };
+    // Line of the most recent `@keep` comment at the top level
+    let mut keep_line: Option<usize> = None;
+    // Line on which the last top-level construct ended
+    let mut last_code_line: Option<usize> = None;
+    // Start of the run of comment lines since the last construct, and the line it ends on
+    let mut comment_block: Option<(usize, usize)> = None;
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let tok = &tokens[i];
+        let is_comment = matches!(tok.kind, TokenKind::LineComment | TokenKind::BlockComment);
+        if is_comment && last_code_line != Some(index.line_of(offsets[i])) {
+            let end_line = index.line_of(offsets[i + 1].saturating_sub(1));
+            comment_block = match comment_block {
+                // Blank lines separate comment blocks
+                Some((start, line)) if line + 1 >= index.line_of(offsets[i]) => {
+                    Some((start, end_line))
+                }
+                _ => Some((offsets[i], end_line)),
+            };
+        }
+        if is_comment && tok.text(source).contains(KEEP_ANNOTATION) {
+            let line = index.line_of(offsets[i]);
+            if last_code_line == Some(line) {
+                // A trailing `// @keep` applies to the declarations it follows
+                for decl in scan.decls.iter_mut().rev() {
+                    if index.line_of(decl.start) != line {
+                        break;
+                    }
+                    decl.keep = true;
+                }
+            } else {
+                keep_line = Some(index.line_of(offsets[i + 1].saturating_sub(1)));
+            }
+        }
+        if tok.is_trivia() {
+            i += 1;
+            continue;
+        }
+
+        let start_line = index.line_of(offsets[i]);
+        let keep = keep_line.is_some_and(|l| l == start_line || l + 1 == start_line);
+        let comment_start = match comment_block.take() {
+            Some((start, line)) if line + 1 == start_line => start,
+            _ => offsets[i],
+        };
+        let next = next_significant(tokens, i + 1);
+        let name = next
+            .filter(|&n| tokens[n].kind == TokenKind::Ident)
+            .map(|n| tokens[n].text(source).to_string());
+
+        let kind = match tok.text(source) {
+            "function" if name.is_some() => Some(SymbolKind::Function),
+            "combo" if name.is_some() => Some(SymbolKind::Combo),
+            "define" if name.is_some() => Some(SymbolKind::Define),
+            "const" => Some(SymbolKind::Const),
+            t if is_var_type(t) => Some(SymbolKind::Variable),
+            _ => None,
+        };
+
+        match kind {
+            Some(SymbolKind::Function) | Some(SymbolKind::Combo) => {
+                let end = block_end(source, tokens, i);
+                let name = name.unwrap_or_default();
+                scan.decls.push(Decl {
+                    kind: kind.unwrap(),
+                    refs: identifiers(source, &tokens[next.unwrap() + 1..end], &name),
+                    name,
+                    start: offsets[i],
+                    end: offsets[end],
+                    comment_start,
+                    keep,
+                    statement: None,
+                });
+                i = end;
+            }
+            Some(SymbolKind::Define) => {
+                let end = statement_end(source, tokens, i);
+                let name = name.unwrap_or_default();
+                scan.decls.push(Decl {
+                    kind: SymbolKind::Define,
+                    refs: identifiers(source, &tokens[next.unwrap() + 1..end], &name),
+                    name,
+                    start: offsets[i],
+                    end: offsets[end],
+                    comment_start,
+                    keep,
+                    statement: None,
+                });
+                i = end;
+            }
+            Some(SymbolKind::Const) => {
+                let end = statement_end(source, tokens, i);
+                // The name is the identifier right before the first `[` or `=`
+                let name_idx = (i + 1..end)
+                    .take_while(|&j| !matches!(tokens[j].text(source), "[" | "="))
+                    .filter(|&j| tokens[j].kind == TokenKind::Ident)
+                    .last();
+                match name_idx {
+                    Some(n) => {
+                        let name = tokens[n].text(source).to_string();
+                        scan.decls.push(Decl {
+                            kind: SymbolKind::Const,
+                            refs: identifiers(source, &tokens[n + 1..end], &name),
+                            name,
+                            start: offsets[i],
+                            end: offsets[end],
+                            comment_start,
+                            keep,
+                            statement: None,
+                        });
+                    }
+                    None => scan.root_refs.extend(identifiers(source, &tokens[i..end], "")),
+                }
+                i = end;
+            }
+            Some(SymbolKind::Variable) => {
+                let end = statement_end(source, tokens, i);
+                scan_var_statement(source, tokens, offsets, i..end, comment_start, keep, &mut scan);
+                i = end;
+            }
+            _ => {
+                // Other top-level code (main, init, data, ...) is always kept
+                let end = if next.is_some_and(|n| tokens[n].text(source) == "{")
+                    || tok.text(source) == "{"
+                {
+                    block_end(source, tokens, i)
+                } else {
+                    i + 1
+                };
+                if tok.kind == TokenKind::Ident {
+                    scan.root_refs.insert(tok.text(source).to_string());
+                }
+                scan.root_refs.extend(identifiers(source, &tokens[i..end], ""));
+                i = end;
+            }
+        }
+        last_code_line = Some(index.line_of(offsets[i].saturating_sub(1)));
+    }
+    scan
+}
+
+/// Split `type a, b = x, c[2];` into one declaration per declarator.
+fn scan_var_statement(
+    source: &str,
+    tokens: &[Token],
+    offsets: &[usize],
+    range: std::ops::Range<usize>,
+    comment_start: usize,
+    keep: bool,
+    scan: &mut Scan,
+) {
+    let (start, end) = (range.start, range.end);
+    let statement_idx = scan.statements.len();
+    let mut statement = VarStatement {
+        start: offsets[start],
+        end: offsets[end],
+        comment_start,
+        type_prefix: String::new(),
+        decls: Vec::new(),
+    };
+
+    let mut nesting = 0usize;
+    let mut decl_start = start + 1;
+    for j in start + 1..end {
+        let text = tokens[j].text(source);
+        match text {
+            "(" | "[" | "{" => nesting += 1,
+            ")" | "]" | "}" => nesting = nesting.saturating_sub(1),
+            _ => {}
+        }
+        let boundary = nesting == 0 && (text == "," || text == ";");
+        if !boundary {
+            continue;
+        }
+        let declarator = &tokens[decl_start..j];
+        if let Some(name_tok) = declarator.iter().find(|t| t.kind == TokenKind::Ident) {
+            let name = name_tok.text(source);
+            if statement.decls.is_empty() {
+                let first = decl_start
+                    + declarator
+                        .iter()
+                        .position(|t| !t.is_trivia())
+                        .unwrap_or(0);
+                statement.type_prefix = source[offsets[start]..offsets[first]].to_string();
+            }
+            statement.decls.push(scan.decls.len());
+            scan.decls.push(Decl {
+                kind: SymbolKind::Variable,
+                name: name.to_string(),
+                start: offsets[decl_start],
+                end: offsets[j],
+                comment_start: offsets[decl_start],
+                refs: identifiers(source, declarator, name),
+                keep,
+                statement: Some(statement_idx),
+            });
+        }
+        decl_start = j + 1;
+    }
+    scan.statements.push(statement);
+}
+
+fn next_significant(tokens: &[Token], from: usize) -> Option<usize> {
+    (from..tokens.len()).find(|&j| !tokens[j].is_trivia())
+}
+
+/// Index just past the `;` ending the statement starting at `start`.
+fn statement_end(source: &str, tokens: &[Token], start: usize) -> usize {
+    let mut nesting = 0usize;
+    for (j, tok) in tokens.iter().enumerate().skip(start) {
+        match tok.text(source) {
+            "(" | "[" | "{" => nesting += 1,
+            ")" | "]" | "}" => nesting = nesting.saturating_sub(1),
+            ";" if nesting == 0 => return j + 1,
+            _ => {}
+        }
+    }
+    tokens.len()
+}
+
+/// Index just past the `}` closing the first block that opens at or after `start`.
+fn block_end(source: &str, tokens: &[Token], start: usize) -> usize {
+    let mut depth = 0usize;
+    for (j, tok) in tokens.iter().enumerate().skip(start) {
+        match tok.text(source) {
+            "{" => depth += 1,
+            "}" => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    return j + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    tokens.len()
+}
+
+fn identifiers(source: &str, tokens: &[Token], except: &str) -> HashSet<String> {
+    tokens
+        .iter()
+        .filter(|t| t.kind == TokenKind::Ident)
+        .map(|t| t.text(source))
+        .filter(|&text| text != except)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Apply `edits` line by line, dropping lines that an edit left blank.
+pub(crate) fn apply_edits(
+    source: &str,
+    index: &LineIndex,
+    edits: &[(usize, usize, String)],
+) -> (String, Vec<LineTrace>) {
+    let mut result = TracedString::new(index, source.len());
+    let mut first_edit = 0;
+    let mut line_start = 0;
+
+    while line_start < source.len() {
+        let line_end = source[line_start..]
+            .find('\n')
+            .map(|p| line_start + p + 1)
+            .unwrap_or(source.len());
+        while first_edit < edits.len() && edits[first_edit].1 <= line_start {
+            first_edit += 1;
+        }
+
+        let mut text = String::new();
+        let mut touched = false;
+        let mut pos = line_start;
+        for (start, end, replacement) in edits[first_edit..]
+            .iter()
+            .take_while(|e| e.0 < line_end)
+        {
+            touched = true;
+            if *start > pos {
+                text.push_str(&source[pos..*start]);
+            }
+            if *start >= line_start {
+                text.push_str(replacement);
+            }
+            pos = pos.max((*end).min(line_end));
+        }
+        if pos < line_end {
+            text.push_str(&source[pos..line_end]);
+        }
+
+        if !touched {
+            result.push_copied(&text, line_start);
+        } else if !text.trim().is_empty() {
+            if source[..line_end].ends_with('\n') && !text.ends_with('\n') {
+                text.push('\n');
+            }
+            result.push_generated(&text, line_start, None);
+        }
+        line_start = line_end;
+    }
+    result.finish(source.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(shaken: &Shaken) -> Vec<&str> {
+        shaken.removed.iter().map(|r| r.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_shake_removes_unreachable() {
+        let source = "\
+define USED = 1;
+define UNUSED = 2;
+const int8 TABLE[] = { 1, 2 };
+int a, b, c = USED;
+function helper() { return TABLE[0]; }
+// Not called anywhere
+function unused() { return b; }
+combo Unused { wait(10); }
+main {
+    a = helper() + c;
+}
+";
+        let shaken = shake(source, &HashSet::new());
+        assert_eq!(names(&shaken), vec!["UNUSED", "b", "unused", "Unused"]);
+        assert_eq!(
+            shaken.text,
+            "define USED = 1;\nconst int8 TABLE[] = { 1, 2 };\nint a, c = USED;\nfunction helper() { return TABLE[0]; }\nmain {\n    a = helper() + c;\n}\n"
+        );
+        let lines: Vec<usize> = shaken.lines.iter().map(|t| t.line).collect();
+        assert_eq!(lines, vec![0, 2, 3, 4, 8, 9, 10]);
+        assert_eq!(shaken.removed[2].line, 6);
+    }
+
+    #[test]
+    fn test_shake_keep_and_extra_roots() {
+        let source = "\
+// @keep
+function kept() {
+    return 1;
+}
+int debug; // @keep
+function from_plugin() { return 2; }
+function after_trailing_keep() { return 4; }
+function dropped() {
+    return 3;
+}
+main {
+}
+";
+        let mut roots = HashSet::new();
+        roots.insert("from_plugin".to_string());
+        let shaken = shake(source, &roots);
+        assert_eq!(names(&shaken), vec!["after_trailing_keep", "dropped"]);
+        assert!(shaken.text.contains("function kept()"));
+        assert!(shaken.text.contains("int debug;"));
+        assert!(!shaken.text.contains("return 3"));
+        assert_eq!(shaken.lines.len(), shaken.text.lines().count());
+    }
+}
@@ -0,0 +1,282 @@
+//! Auto build: rebuild a game whenever a file its build depends on changes.
+//!
+//! The files watched are the game's include closure as recorded by its last build
+//! (including imports found outside the game directory, and imports that were
+//! looked for but missing), its configuration, the workspace configuration and the
+//! enabled plugins' manifests and includes. Changes are debounced, and rebuilds use
+//! the build cache so only changed imports are preprocessed again.
+
+use crate::models::workspace::WORKSPACE_CONFIG_FILE;
+use crate::pipeline::build::{build_game_with_options, BuildOptions, BuildResult};
+use crate::pipeline::cache::{self, BuildRecord};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How long files must stay unchanged before a rebuild starts.
+pub const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Longest a watcher waits for file events before checking whether it was stopped.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Outcome of one automatic build.
+#[derive(Debug, Clone, Serialize)]
+pub struct AutoBuildResult {
+    pub game_path: String,
+    /// Files whose change started the build; empty for the first build
+    pub changed: Vec<String>,
+    /// Whether the output file now differs from before the build
+    pub output_changed: bool,
+    pub result: BuildResult,
+}
+
+/// Files a build of a game depends on, as absolute paths.
+pub fn watch_set(
+    game_dir: &Path,
+    dist_base: &Path,
+    workspace_path: Option<&str>,
+    target: Option<&str>,
+) -> BTreeSet<PathBuf> {
+    let mut files = BTreeSet::new();
+    for name in ["main.gpc", "game.json", "config.toml"] {
+        files.insert(absolute(&game_dir.join(name)));
+    }
+    files.insert(absolute(&dist_base.join(WORKSPACE_CONFIG_FILE)));
+    if let Some(record) = BuildRecord::load(&cache::cache_dir(game_dir, target)) {
+        files.extend(record.inputs.iter().map(|input| absolute(Path::new(&input.path))));
+    }
+    if let Some(ws) = workspace_path {
+        files.insert(absolute(&Path::new(ws).join("plugins").join(".enabled.json")));
+        for manifest in crate::commands::plugins::enabled_manifest_paths(ws) {
+            files.insert(absolute(&manifest));
+        }
+        let hooks = crate::commands::plugins::collect_enabled_hooks(ws);
+        for include in hooks.includes.unwrap_or_default() {
+            files.insert(absolute(Path::new(&include)));
+        }
+    }
+    // The build's own scratch file changes on every build
+    files.remove(&absolute(&game_dir.join(".main_build.gpc")));
+    files
+}
+
+/// Directories to watch so that changes to `files` are seen, including files that
+/// do not exist yet: the nearest existing ancestor of each file.
+pub fn watch_dirs(files: &BTreeSet<PathBuf>) -> BTreeSet<PathBuf> {
+    files
+        .iter()
+        .filter_map(|file| file.ancestors().skip(1).find(|dir| dir.is_dir()))
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+/// Canonical form of a path if it exists, so paths from file events compare equal
+/// to recorded ones; missing files keep the path as given.
+fn absolute(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Collects file changes until none have arrived for a while.
+#[derive(Debug)]
+pub struct Debouncer {
+    delay: Duration,
+    changed: BTreeSet<PathBuf>,
+    last_change: Option<Instant>,
+}
+
+impl Debouncer {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            changed: BTreeSet::new(),
+            last_change: None,
+        }
+    }
+
+    pub fn change(&mut self, path: PathBuf, now: Instant) {
+        self.changed.insert(path);
+        self.last_change = Some(now);
+    }
+
+    /// How long to wait for more events before `take_due` could return changes.
+    pub fn wait(&self, now: Instant) -> Duration {
+        match self.last_change {
+            Some(last) => (last + self.delay).saturating_duration_since(now),
+            None => POLL_INTERVAL,
+        }
+    }
+
+    /// The changed files, once `delay` has passed since the last change.
+    pub fn take_due(&mut self, now: Instant) -> Option<Vec<PathBuf>> {
+        let last = self.last_change?;
+        if now.duration_since(last) < self.delay {
+            return None;
+        }
+        self.last_change = None;
+        Some(std::mem::take(&mut self.changed).into_iter().collect())
+    }
+}
+
+/// A game being rebuilt whenever its files change.
+pub struct AutoBuild {
+    game_dir: PathBuf,
+    project_root: PathBuf,
+    dist_base: PathBuf,
+    workspace_path: Option<String>,
+    options: BuildOptions,
+    watched: BTreeSet<PathBuf>,
+    /// Output of the last build and its hash, to tell whether a build changed it
+    last_output: Option<(PathBuf, Option<String>)>,
+}
+
+impl AutoBuild {
+    pub fn new(
+        game_dir: &Path,
+        project_root: &Path,
+        dist_base: &Path,
+        workspace_path: Option<String>,
+        options: BuildOptions,
+    ) -> Self {
+        let target = options.target.as_deref();
+        let last_output = BuildRecord::load(&cache::cache_dir(game_dir, target))
+            .map(|record| PathBuf::from(record.output_path))
+            .map(|path| {
+                let hash = hash_file(&path);
+                (path, hash)
+            });
+        let mut auto = Self {
+            game_dir: game_dir.to_path_buf(),
+            project_root: project_root.to_path_buf(),
+            dist_base: dist_base.to_path_buf(),
+            workspace_path,
+            options,
+            watched: BTreeSet::new(),
+            last_output,
+        };
+        auto.refresh_watched();
+        auto
+    }
+
+    /// Files whose changes trigger a rebuild.
+    pub fn watched(&self) -> &BTreeSet<PathBuf> {
+        &self.watched
+    }
+
+    /// Whether a changed path is one the build depends on.
+    pub fn is_relevant(&self, path: &Path) -> bool {
+        self.watched.contains(path) || self.watched.contains(&absolute(path))
+    }
+
+    /// Build the game, then update the watched files from what the build read.
+    pub fn rebuild(&mut self, changed: Vec<PathBuf>) -> AutoBuildResult {
+        let result = build_game_with_options(
+            &self.game_dir,
+            &self.project_root,
+            &self.dist_base,
+            false,
+            self.workspace_path.as_deref(),
+            &self.options,
+        );
+
+        let mut output_changed = false;
+        if result.success {
+            let path = PathBuf::from(&result.output_path);
+            let hash = hash_file(&path);
+            output_changed = self.last_output.as_ref() != Some(&(path.clone(), hash.clone()));
+            self.last_output = Some((path, hash));
+        }
+        self.refresh_watched();
+
+        AutoBuildResult {
+            game_path: self.game_dir.to_string_lossy().to_string(),
+            changed: changed.iter().map(|p| p.to_string_lossy().to_string()).collect(),
+            output_changed,
+            result,
+        }
+    }
+
+    fn refresh_watched(&mut self) {
+        let target = self.options.target.as_deref();
+        let watched = watch_set(
+            &self.game_dir,
+            &self.dist_base,
+            self.workspace_path.as_deref(),
+            target,
+        );
+        // A failed build leaves no record of its imports; keep watching the last known ones
+        if BuildRecord::load(&cache::cache_dir(&self.game_dir, target)).is_some() {
+            self.watched = watched;
+        } else {
+            self.watched.extend(watched);
+        }
+    }
+}
+
+fn hash_file(path: &Path) -> Option<String> {
+    std::fs::read(path).ok().map(|data| cache::hash_bytes(&data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::test_support::write_test_game;
+
+    #[test]
+    fn test_debouncer() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(Duration::from_millis(100));
+        assert_eq!(debouncer.wait(start), POLL_INTERVAL);
+        assert!(debouncer.take_due(start).is_none());
+
+        debouncer.change(PathBuf::from("a.gpc"), start);
+        debouncer.change(PathBuf::from("b.gpc"), start + Duration::from_millis(50));
+        debouncer.change(PathBuf::from("a.gpc"), start + Duration::from_millis(60));
+        let now = start + Duration::from_millis(120);
+        assert_eq!(debouncer.wait(now), Duration::from_millis(40));
+        assert!(debouncer.take_due(now).is_none());
+
+        let due = debouncer.take_due(start + Duration::from_millis(160)).unwrap();
+        assert_eq!(due, vec![PathBuf::from("a.gpc"), PathBuf::from("b.gpc")]);
+        assert!(debouncer.take_due(start + Duration::from_secs(1)).is_none());
+    }
+
+    #[test]
+    fn test_auto_build_watches_include_closure() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = dir.path().join("shared");
+        std::fs::create_dir_all(&shared).unwrap();
+        let shared_file = shared.join("util.gpc");
+        std::fs::write(&shared_file, "int util;\n").unwrap();
+        let import = format!("import {};\n", shared_file.display());
+        let game_dir = write_test_game(dir.path(), &format!("{}main {{\n}}\n", import));
+
+        let mut auto =
+            AutoBuild::new(&game_dir, dir.path(), dir.path(), None, BuildOptions::default());
+        assert!(!auto.is_relevant(&shared_file));
+        let first = auto.rebuild(Vec::new());
+        assert!(first.result.success, "{:?}", first.result.errors);
+        assert!(first.output_changed);
+        assert!(auto.is_relevant(&shared_file));
+        assert!(auto.is_relevant(&game_dir.join("game.json")));
+        assert!(!auto.is_relevant(&game_dir.join(".main_build.gpc")));
+        assert!(watch_dirs(auto.watched()).contains(&shared.canonicalize().unwrap()));
+
+        // Rebuilding unchanged sources leaves the output alone
+        let output = PathBuf::from(&first.result.output_path);
+        let modified = std::fs::metadata(&output).unwrap().modified().unwrap();
+        let second = auto.rebuild(vec![shared_file.clone()]);
+        assert!(second.result.success && !second.output_changed);
+        assert_eq!(std::fs::metadata(&output).unwrap().modified().unwrap(), modified);
+
+        std::fs::write(&shared_file, "int util2;\n").unwrap();
+        let third = auto.rebuild(vec![shared_file.clone()]);
+        assert!(third.output_changed);
+        assert!(std::fs::read_to_string(&output).unwrap().contains("util2"));
+
+        // A broken build still watches the imports of the last good one
+        std::fs::write(&shared_file, "int broken\n").unwrap();
+        assert!(!auto.rebuild(vec![shared_file.clone()]).result.success);
+        assert!(auto.is_relevant(&shared_file));
+    }
+}
@@ -42,6 +42,8 @@ export interface MergedFlowOptions {
 	headerComments?: string;
 	/** Auto-generate module info block in header comments */
 	generateModuleInfo?: boolean;
+	/** Add the flows' last edit time to the header; off so unchanged flows generate identical code */
+	includeTimestamp?: boolean;
 }
 
 export function generateMergedFlowGpc(project: FlowProject, options?: MergedFlowOptions): MergedFlowResult {
@@ -181,7 +183,9 @@ export function generateMergedFlowGpc(project: FlowProject, options?: MergedFlow
 	if (options?.gameVersion != null) lines.push(`// Version: ${options.gameVersion}`);
 	if (options?.gameType) lines.push(`// Game type: ${options.gameType}`);
 	if (options?.consoleType) lines.push(`// Console: ${options.consoleType}`);
-	lines.push(`// Generated at: ${new Date(project.updatedAt).toLocaleString()}`);
+	if (options?.includeTimestamp) {
+		lines.push(`// Generated at: ${new Date(project.updatedAt).toLocaleString()}`);
+	}
 
 	if (profileCount > 1) {
 		lines.push(`// Profiles: ${profiles.map((p) => p.name).join(', ')}`);
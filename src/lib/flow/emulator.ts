@@ -1,6 +1,7 @@
 import type { FlowGraph, FlowNode, FlowEdge, SubNode, SubNodeRenderContext } from '$lib/types/flow';
 import { getSubNodeDef } from '$lib/flow/subnodes/registry';
 import { computeSubNodePixelY, getSortedSubNodes } from '$lib/flow/layout';
+import type { InputTrace, TraceEvent } from '$lib/tauri/commands';
 
 // ==================== Types ====================
 
@@ -47,6 +48,8 @@ export class FlowEmulator {
 	state: EmulatorState;
 	private heldButtons: Set<string> = new Set();
 	private pressedThisFrame: Set<string> = new Set();
+	private elapsedMs = 0;
+	private recording: { startMs: number; events: TraceEvent[] } | null = null;
 
 	constructor(graph: FlowGraph) {
 		this.graph = graph;
@@ -102,6 +105,8 @@ export class FlowEmulator {
 		this.state = this.createInitialState();
 		this.heldButtons.clear();
 		this.pressedThisFrame.clear();
+		this.elapsedMs = 0;
+		this.recording = null;
 	}
 
 	getCurrentNode(): FlowNode | undefined {
@@ -123,6 +128,7 @@ export class FlowEmulator {
 	// ==================== Input ====================
 
 	handleButtonPress(button: string): void {
+		if (!this.heldButtons.has(button)) this.record(button, 100);
 		this.heldButtons.add(button);
 		this.pressedThisFrame.add(button);
 		this.state.inputLog.push(button);
@@ -132,9 +138,39 @@ export class FlowEmulator {
 	}
 
 	handleButtonRelease(button: string): void {
+		if (this.heldButtons.has(button)) this.record(button, 0);
 		this.heldButtons.delete(button);
 	}
 
+	// ==================== Input Recording ====================
+
+	get isRecording(): boolean {
+		return this.recording !== null;
+	}
+
+	/** Start capturing button changes as an input trace, starting with held buttons. */
+	startRecording(): void {
+		this.recording = { startMs: this.elapsedMs, events: [] };
+		for (const button of this.heldButtons) this.record(button, 100);
+	}
+
+	/** Stop capturing and return the trace, replayable with `zenforge-cli replay`. */
+	stopRecording(): InputTrace {
+		const recording = this.recording ?? { startMs: this.elapsedMs, events: [] };
+		this.recording = null;
+		return {
+			version: 1,
+			duration_ms: Math.round(this.elapsedMs - recording.startMs),
+			events: recording.events,
+		};
+	}
+
+	private record(button: string, value: number): void {
+		if (!this.recording) return;
+		const time = Math.round(this.elapsedMs - this.recording.startMs);
+		this.recording.events.push([time, button, value]);
+	}
+
 	private eventPress(button: string): boolean {
 		return this.pressedThisFrame.has(button);
 	}
@@ -146,6 +182,7 @@ export class FlowEmulator {
 	 * deltaMs: time since last frame in ms (default 16ms = ~60fps)
 	 */
 	step(deltaMs: number = 16): void {
+		this.elapsedMs += deltaMs;
 		const node = this.getCurrentNode();
 		if (!node) return;
 
@@ -98,20 +98,419 @@ export async function createGame(params: CreateGameParams): Promise<CreateGameRe
 
 // === Build Commands ===
 
+export type DiagnosticSeverity = 'error' | 'warning' | 'info';
+
+/** 1-indexed line; 1-indexed character columns with an exclusive end */
+export interface DiagnosticSpan {
+	file: string;
+	line: number;
+	start_col: number;
+	end_col: number;
+}
+
+export interface RelatedSpan {
+	span: DiagnosticSpan;
+	message: string;
+}
+
+export interface DiagnosticFix {
+	message: string;
+	edits: { span: DiagnosticSpan; new_text: string }[];
+}
+
+export interface Diagnostic {
+	code: string;
+	severity: DiagnosticSeverity;
+	message: string;
+	span: DiagnosticSpan | null;
+	related: RelatedSpan[];
+	fixes: DiagnosticFix[];
+}
+
 export interface BuildResult {
 	output_path: string;
 	success: boolean;
-	errors: string[];
-	warnings: string[];
+	errors: Diagnostic[];
+	warnings: Diagnostic[];
+	source_map_path?: string | null;
+	/** How the build used the game's build cache; 'hit' means nothing was rebuilt */
+	cache?: BuildCacheStatus;
+	/** Resource usage of the output against the target firmware's limits */
+	resources?: ResourceReport | null;
+	/** Unused declarations removed by tree shaking */
+	removed?: RemovedSymbol[];
+	/** What the optimizer changed, if it ran */
+	optimization?: OptimizeReport | null;
+}
+
+export interface OptimizeReport {
+	/** Constant expressions replaced by their value */
+	folded: number;
+	/** Defines used once whose value was substituted and declaration removed */
+	inlined: string[];
+	/** `if` statements with a constant condition replaced by the branch taken */
+	branches: number;
+	bytes_saved: number;
+	lines_saved: number;
+}
+
+export interface RemovedSymbol {
+	kind: 'function' | 'combo' | 'define' | 'const' | 'variable';
+	name: string;
+	location: SourceLocation | null;
 }
 
-export async function buildGame(gamePath: string, workspacePath?: string): Promise<BuildResult> {
+export type ResourceKind =
+	| 'global_slots'
+	| 'const_bytes'
+	| 'string_bytes'
+	| 'combos'
+	| 'functions'
+	| 'spvar_slots'
+	| 'bytecode_bytes';
+
+export interface ResourceCounts {
+	globals: number;
+	global_slots: number;
+	int8: number;
+	int16: number;
+	int32: number;
+	const_bytes: number;
+	string_bytes: number;
+	combos: number;
+	functions: number;
+	spvar_slots: number;
+	bytecode_bytes: number;
+}
+
+export interface ResourceUsage {
+	resource: ResourceKind;
+	used: number;
+	limit: number;
+	estimated: boolean;
+}
+
+export interface ResourceReport {
+	firmware: string;
+	counts: ResourceCounts;
+	usage: ResourceUsage[];
+}
+
+export type BuildCacheStatus = 'hit' | 'partial' | 'miss' | 'disabled';
+
+export interface TargetBuildResult {
+	/** null when the game declares no targets and was built as-is */
+	target: string | null;
+	console_type: string | null;
+	result: BuildResult;
+}
+
+export interface MatrixBuildResult {
+	success: boolean;
+	targets: TargetBuildResult[];
+}
+
+export async function buildGame(
+	gamePath: string,
+	workspacePath?: string,
+	profile?: string,
+	defines?: Record<string, string>,
+	noCache?: boolean,
+	target?: string
+): Promise<BuildResult> {
 	return invoke<BuildResult>('build_game_cmd', {
 		gamePath,
-		workspacePath: workspacePath ?? null
+		workspacePath: workspacePath ?? null,
+		profile: profile ?? null,
+		defines: defines ?? null,
+		noCache: noCache ?? null,
+		target: target ?? null
+	});
+}
+
+/** Build every target in the game's game.json `targets` (or the game itself if it has none) */
+export async function buildGameTargets(
+	gamePath: string,
+	workspacePath?: string,
+	profile?: string,
+	defines?: Record<string, string>,
+	noCache?: boolean
+): Promise<MatrixBuildResult> {
+	return invoke<MatrixBuildResult>('build_game_targets_cmd', {
+		gamePath,
+		workspacePath: workspacePath ?? null,
+		profile: profile ?? null,
+		defines: defines ?? null,
+		noCache: noCache ?? null
 	});
 }
 
+/** A game for the build queue; every target of the game is built */
+export interface BuildJob {
+	game_path: string;
+	workspace_path?: string | null;
+	profile?: string | null;
+	defines?: Record<string, string>;
+	no_cache?: boolean;
+}
+
+export type BuildJobStatus = 'queued' | 'running' | 'succeeded' | 'failed' | 'cancelled';
+
+export type BuildPhase = 'preparing' | 'preprocessing' | 'expanding' | 'checking' | 'writing';
+
+/** Payload of the `build:progress` event */
+export interface BuildProgress {
+	/** Index of the job in the queue */
+	job_id: number;
+	game_path: string;
+	status: BuildJobStatus;
+	phase: BuildPhase | null;
+	/** File being processed or written */
+	file: string | null;
+	percent: number;
+	queue_percent: number;
+}
+
+export interface BuildJobSummary {
+	job_id: number;
+	game_path: string;
+	status: BuildJobStatus;
+	result: MatrixBuildResult | null;
+	error: string | null;
+	duration_ms: number;
+}
+
+export interface BuildQueueSummary {
+	jobs: BuildJobSummary[];
+	succeeded: number;
+	failed: number;
+	cancelled: number;
+	duration_ms: number;
+}
+
+/**
+ * Build several games concurrently in the backend. Progress arrives as `build:progress`
+ * events; the promise resolves once every job has finished or been cancelled.
+ * Flow games need their main.gpc generated first.
+ */
+export async function startBuildQueue(jobs: BuildJob[], workers?: number): Promise<BuildQueueSummary> {
+	return invoke<BuildQueueSummary>('start_build_queue', { jobs, workers: workers ?? null });
+}
+
+/** Cancel one job of the running build queue */
+export async function cancelBuildJob(jobId: number): Promise<void> {
+	return invoke<void>('cancel_build_job', { jobId });
+}
+
+/** Cancel every unfinished job of the running build queue */
+export async function cancelBuildQueue(): Promise<void> {
+	return invoke<void>('cancel_build_queue');
+}
+
+export interface ReleaseFile {
+	name: string;
+	sha256: string;
+}
+
+export interface ReleaseResult {
+	success: boolean;
+	/** Version the release was built as */
+	version: number;
+	bundle_path: string | null;
+	/** Files in the bundle with their checksums, as listed in SHA256SUMS */
+	files: ReleaseFile[];
+	build: MatrixBuildResult;
+}
+
+/**
+ * Build every target of a game and package the outputs with a generated README,
+ * CHANGELOG.md and SHA-256 checksums into dist/releases/<game>-v<version>.zip.
+ */
+export async function releaseGame(
+	gamePath: string,
+	workspacePath?: string,
+	profile?: string,
+	bumpVersion?: boolean,
+	notes?: string
+): Promise<ReleaseResult> {
+	return invoke<ReleaseResult>('release_game_cmd', {
+		gamePath,
+		workspacePath: workspacePath ?? null,
+		profile: profile ?? null,
+		bumpVersion: bumpVersion ?? null,
+		notes: notes ?? null
+	});
+}
+
+export interface TickDiff {
+	tick: number;
+	/** An identifier such as PS5_R2, `var <name>`, `combo <name>` or `oled` */
+	subject: string;
+	expected: string;
+	actual: string;
+}
+
+export interface ScriptTestResult {
+	/** Test file name within the game's tests/ directory */
+	file: string;
+	name: string;
+	passed: boolean;
+	diffs: TickDiff[];
+	error: string | null;
+}
+
+export interface TestRunReport {
+	success: boolean;
+	passed: number;
+	failed: number;
+	tests: ScriptTestResult[];
+	/** Set when the built script cannot be run by the simulator */
+	error: string | null;
+	build: BuildResult;
+}
+
+/**
+ * Build a game and run the script tests in its tests/ directory on the simulator,
+ * optionally only those whose name contains `filter`.
+ */
+export async function runScriptTests(
+	gamePath: string,
+	workspacePath?: string,
+	profile?: string,
+	filter?: string
+): Promise<TestRunReport> {
+	return invoke<TestRunReport>('run_script_tests_cmd', {
+		gamePath,
+		workspacePath: workspacePath ?? null,
+		profile: profile ?? null,
+		filter: filter ?? null
+	});
+}
+
+/** A timed input change: [time_ms, identifier, value] */
+export type TraceEvent = [number, string, number];
+
+export interface InputTrace {
+	version: number;
+	/** How long a replay runs; defaults to just past the last event */
+	duration_ms?: number;
+	events: TraceEvent[];
+}
+
+export interface OutputChange {
+	tick: number;
+	time_ms: number;
+	output: string;
+	value: number;
+}
+
+export interface OutputStream {
+	ticks: number;
+	time_ms: number;
+	changes: OutputChange[];
+	/** Runtime error that stopped the replay early */
+	error: string | null;
+}
+
+export interface ReplayReport {
+	success: boolean;
+	stream: OutputStream;
+	against: OutputStream | null;
+	comparison: {
+		identical: boolean;
+		differing_ticks: number;
+		/** `expected` is the first build's value, `actual` the second's */
+		diffs: TickDiff[];
+	} | null;
+}
+
+/**
+ * Replay an input trace against a game directory or built .gpc file on the simulator,
+ * optionally comparing the outputs with a second game or file.
+ */
+export async function replayTrace(
+	scriptPath: string,
+	tracePath: string,
+	againstPath?: string,
+	workspacePath?: string,
+	profile?: string
+): Promise<ReplayReport> {
+	return invoke<ReplayReport>('replay_trace_cmd', {
+		scriptPath,
+		tracePath,
+		againstPath: againstPath ?? null,
+		workspacePath: workspacePath ?? null,
+		profile: profile ?? null
+	});
+}
+
+export interface FrameProfile {
+	name: string;
+	kind: 'main' | 'init' | 'combo' | 'function';
+	calls: number;
+	/** Operations in the frame itself over the whole run */
+	self_ops: number;
+	/** Most operations in one tick, including the frames it called */
+	max_tick_ops: number;
+	max_tick_self_ops: number;
+	/** The frame by itself used more than a quarter of a tick's budget */
+	hot: boolean;
+}
+
+export interface ProfileReport {
+	/** Whether every tick fit the budget of the chosen VM speed */
+	success: boolean;
+	ticks: number;
+	vm_speed_ms: number;
+	ops_per_ms: number;
+	budget_ops: number;
+	init_ops: number;
+	main: { average_ops: number; max_ops: number; max_tick: number };
+	worst_tick: {
+		tick: number;
+		ops: number;
+		budget_percent: number;
+		stacks: { stack: string; ops: number }[];
+	};
+	speeds: {
+		tick_ms: number;
+		budget_ops: number;
+		worst_percent: number;
+		ticks_over_budget: number;
+	}[];
+	frames: FrameProfile[];
+	error: string | null;
+}
+
+/**
+ * Profile a game directory or built .gpc file on the simulator against a VM speed of
+ * 10, 8, 4, 2 or 1 ms, idle or driven by an input trace. `foldedPath` also writes
+ * folded stacks for flamegraph tools.
+ */
+export async function profileScript(
+	scriptPath: string,
+	tracePath?: string,
+	vmSpeedMs?: number,
+	foldedPath?: string,
+	workspacePath?: string,
+	profile?: string
+): Promise<ProfileReport> {
+	return invoke<ProfileReport>('profile_script_cmd', {
+		scriptPath,
+		tracePath: tracePath ?? null,
+		vmSpeedMs: vmSpeedMs ?? null,
+		opsPerMs: null,
+		foldedPath: foldedPath ?? null,
+		workspacePath: workspacePath ?? null,
+		profile: profile ?? null
+	});
+}
+
+/** Save an input trace as CSV if the path ends in .csv, JSON otherwise. */
+export async function saveInputTrace(path: string, trace: InputTrace): Promise<void> {
+	return invoke<void>('save_input_trace_cmd', { path, trace });
+}
+
 export async function getBuildOutputPath(gamePath: string, workspacePath?: string): Promise<string> {
 	return invoke<string>('get_build_output_path', {
 		gamePath,
@@ -119,6 +518,49 @@ export async function getBuildOutputPath(gamePath: string, workspacePath?: strin
 	});
 }
 
+export interface SourceLocation {
+	file: string;
+	line: number;
+	macros?: string[];
+}
+
+export async function resolveBuildLocation(
+	outputPath: string,
+	line: number
+): Promise<SourceLocation | null> {
+	return invoke<SourceLocation | null>('resolve_build_location', { outputPath, line });
+}
+
+export interface ExpandedRegion {
+	/** 1-indexed, inclusive lines of the preview text */
+	start_line: number;
+	end_line: number;
+	/** Line of the macro call; `macros` lists the expanding macros, outermost first */
+	call: SourceLocation;
+}
+
+export interface MacroPreview {
+	text: string;
+	regions: ExpandedRegion[];
+	diagnostics: Diagnostic[];
+}
+
+export async function previewMacroExpansion(
+	filePath: string,
+	gamePath?: string,
+	workspacePath?: string,
+	startLine?: number,
+	endLine?: number
+): Promise<MacroPreview> {
+	return invoke<MacroPreview>('preview_macro_expansion_cmd', {
+		filePath,
+		gamePath: gamePath ?? null,
+		workspacePath: workspacePath ?? null,
+		startLine: startLine ?? null,
+		endLine: endLine ?? null
+	});
+}
+
 // === Config Commands ===
 
 export async function saveGameConfig(gamePath: string, config: GameConfig): Promise<void> {
@@ -293,6 +735,34 @@ export async function unwatchDirectory(): Promise<void> {
 	return invoke<void>('unwatch_directory');
 }
 
+/** Result of a build started by auto build mode, emitted as 'build:auto' */
+export interface AutoBuildResult {
+	game_path: string;
+	/** Files whose change started the build; empty for the first build */
+	changed: string[];
+	/** Whether the output file differs from before the build */
+	output_changed: boolean;
+	result: BuildResult;
+}
+
+/** Rebuild a game whenever its sources, imports, config or enabled plugins change. */
+export async function startAutoBuild(
+	gamePath: string,
+	workspacePath?: string,
+	profile?: string,
+	target?: string
+): Promise<void> {
+	return invoke<void>('start_auto_build', { gamePath, workspacePath, profile, target });
+}
+
+export async function stopAutoBuild(gamePath: string): Promise<void> {
+	return invoke<void>('stop_auto_build', { gamePath });
+}
+
+export async function listAutoBuilds(): Promise<string[]> {
+	return invoke<string[]>('list_auto_builds');
+}
+
 // === Workspace Commands ===
 
 export async function pickWorkspaceDirectory(): Promise<string | null> {
@@ -516,6 +986,82 @@ export async function renameSnapshot(gamePath: string, snapshotId: string, label
 	return invoke<void>('rename_snapshot', { gamePath, snapshotId, label });
 }
 
+// === Build Artifact Commands ===
+
+export interface BuildArtifact {
+	id: string;
+	/** Seconds since the Unix epoch */
+	timestamp: number;
+	file_name: string;
+	output_hash: string;
+	size: number;
+	label: string | null;
+	target: string | null;
+	game_version: number;
+	console_type: string;
+	profile: string | null;
+	plugins: { id: string; version: string }[];
+	modules: string[];
+	/** Hash of the build's source files; equal for builds of identical sources */
+	source_snapshot: string;
+}
+
+export interface ArtifactDiff {
+	from: BuildArtifact;
+	to: BuildArtifact;
+	changes: string[];
+	from_content: string;
+	to_content: string;
+}
+
+export async function listArtifacts(gamePath: string): Promise<BuildArtifact[]> {
+	return invoke<BuildArtifact[]>('list_artifacts', { gamePath });
+}
+
+export async function readArtifact(gamePath: string, artifactId: string): Promise<string> {
+	return invoke<string>('read_artifact', { gamePath, artifactId });
+}
+
+export async function diffArtifacts(
+	gamePath: string,
+	fromId: string,
+	toId: string
+): Promise<ArtifactDiff> {
+	return invoke<ArtifactDiff>('diff_artifacts', { gamePath, fromId, toId });
+}
+
+export async function restoreArtifact(
+	gamePath: string,
+	artifactId: string,
+	workspacePath?: string
+): Promise<string> {
+	return invoke<string>('restore_artifact', {
+		gamePath,
+		artifactId,
+		workspacePath: workspacePath ?? null
+	});
+}
+
+export async function exportArtifact(
+	gamePath: string,
+	artifactId: string,
+	destPath: string
+): Promise<void> {
+	return invoke<void>('export_artifact', { gamePath, artifactId, destPath });
+}
+
+export async function labelArtifact(
+	gamePath: string,
+	artifactId: string,
+	label: string
+): Promise<void> {
+	return invoke<void>('label_artifact', { gamePath, artifactId, label });
+}
+
+export async function deleteArtifact(gamePath: string, artifactId: string): Promise<void> {
+	return invoke<void>('delete_artifact', { gamePath, artifactId });
+}
+
 // === Obfuscation Commands ===
 
 export interface ObfuscateStats {
@@ -1,4 +1,5 @@
 import { listen, type UnlistenFn } from '@tauri-apps/api/event';
+import type { AutoBuildResult, BuildProgress } from './commands';
 
 /**
  * Listen for LSP JSON-RPC messages emitted by the Rust backend.
@@ -33,6 +34,24 @@ export function onWorkspaceChange(callback: (event: FileChangeEvent) => void): P
     });
 }
 
+/**
+ * Listen for progress of the backend build queue.
+ */
+export function onBuildProgress(callback: (progress: BuildProgress) => void): Promise<UnlistenFn> {
+    return listen<BuildProgress>('build:progress', (event) => {
+        callback(event.payload);
+    });
+}
+
+/**
+ * Listen for builds made by auto build mode.
+ */
+export function onAutoBuild(callback: (result: AutoBuildResult) => void): Promise<UnlistenFn> {
+    return listen<AutoBuildResult>('build:auto', (event) => {
+        callback(event.payload);
+    });
+}
+
 // === Command Runner Events ===
 
 export function onRunnerStdout(callback: (line: string) => void): Promise<UnlistenFn> {
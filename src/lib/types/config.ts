@@ -98,4 +98,32 @@ export interface GameMeta {
 	tags?: string[];
 	header_comments?: string;
 	generate_module_info?: boolean;
+	/** Stamp flow-generated scripts with the last edit time (off for reproducible builds) */
+	header_timestamp?: boolean;
+	build_profiles?: Record<string, BuildProfile>;
+	resources?: ResourceSettings;
+	tree_shake?: boolean;
+	/** Fold constant expressions, inline defines used once and drop constant branches */
+	optimize?: boolean;
+	targets?: BuildTarget[];
+}
+
+export interface BuildProfile {
+	defines: Record<string, string>;
+}
+
+/** A build variant written to its own file in dist/, e.g. one per console */
+export interface BuildTarget {
+	name: string;
+	console_type?: string;
+	/** Filename template; also accepts {filename}, {target} and {console} */
+	filename?: string;
+	defines?: Record<string, string>;
+}
+
+export interface ResourceSettings {
+	firmware?: string;
+	warn_percent?: number;
+	error_percent?: number;
+	limits?: Record<string, number>;
 }
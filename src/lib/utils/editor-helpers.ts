@@ -1,5 +1,7 @@
 // Utility functions extracted from the main page
 
+import type { Diagnostic, DiagnosticSpan } from '$lib/tauri/commands';
+
 export interface DiffLine {
 	type: 'context' | 'added' | 'removed';
 	text: string;
@@ -58,25 +60,15 @@ export function computeLineDiff(oldLines: string[], newLines: string[]): DiffLin
 	return lines.reverse();
 }
 
-export interface BuildErrorLink {
-	path: string;
-	line?: number;
+export function formatDiagnosticLocation(span: DiagnosticSpan): string {
+	const name = span.file.split(/[\\/]/).pop() ?? span.file;
+	return `${name}:${span.line}:${span.start_col}`;
 }
 
-export function parseBuildErrorLink(error: string): BuildErrorLink | null {
-	// "Referenced from: /path/to/file:123"
-	const refMatch = error.match(/Referenced from:\s*(.+):(\d+)$/);
-	if (refMatch) return { path: refMatch[1], line: parseInt(refMatch[2]) };
-
-	// "File not found: /path/to/file"
-	const notFoundMatch = error.match(/File not found:\s*(.+)$/);
-	if (notFoundMatch) return { path: notFoundMatch[1] };
-
-	// "Could not read /path/to/file: ..."
-	const readMatch = error.match(/Could not read\s+(.+?):\s/);
-	if (readMatch) return { path: readMatch[1] };
-
-	return null;
+/** Wrap an error thrown outside the build pipeline (e.g. IPC failure) as a diagnostic.
+ * Code B000 is never emitted by the backend. */
+export function diagnosticFromMessage(message: string): Diagnostic {
+	return { code: 'B000', severity: 'error', message, span: null, related: [], fixes: [] };
 }
 
 export function getLanguageForFile(path: string): string {